@@ -0,0 +1,45 @@
+// Usage: cargo bench --bench quality
+//
+// The DSP load of the reference patch (a saw through Exciter -> Octaver ->
+// PlateReverb) at each quality. The low quality should take at least 30%
+// less time than the high quality.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    effects::{Exciter, Octaver, PlateReverb, SubShape},
+    quality::Quality,
+    units::{Hz, Ms},
+};
+
+const FS: f64 = 48000.0;
+
+fn render(quality: Quality, frames: usize) -> f64 {
+    let saw = signal::rate(FS).const_hz(110.0).saw().scale_amp(0.5);
+    let mut exciter = Exciter::new(saw, FS, Hz(3000.0), 4.0, 0.3);
+    exciter.set_quality(quality);
+    let mut octaver = Octaver::new(exciter, FS, SubShape::Sine, 0.5);
+    octaver.set_quality(quality);
+    let mut reverb = PlateReverb::new(octaver, FS, 0.7, 0.3, Ms(20.0));
+    reverb.set_quality(quality);
+    reverb.take(frames).sum()
+}
+
+fn quality(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reference patch (1 s)");
+    for quality in [Quality::Low, Quality::Normal, Quality::High] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{quality:?}")),
+            &quality,
+            |b, &quality| b.iter(|| render(quality, FS as usize)),
+        );
+    }
+    group.finish();
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = quality
+}
+criterion_main!(benches);
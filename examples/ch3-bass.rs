@@ -0,0 +1,93 @@
+// Usage: cargo run --example ch3-bass
+//
+// Plays the bass line of ch3-melody (TRACK2) as a saw through a low-pass, with
+// accents on the steps 1 and 5 (louder, and the filter opens) and a ratchet of
+// 3 on the step 7.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::{biquad::Biquad, polyblep::PolyBlepSaw},
+    envelope::Env,
+    humanize::Step,
+    runner::play,
+    units::{Frames, Hz, Ms},
+};
+
+#[rustfmt::skip]
+const TRACK2: [f64; 8] = [261.63, 196.00, 220.00, 164.81, 174.61, 130.81, 174.61, 196.00];
+
+// the steps (counted from 0) with the accent, and with the ratchet
+const ACCENTS: [usize; 2] = [0, 4];
+const RATCHETS: [(usize, usize); 1] = [(6, 3)];
+
+const LOOPS: usize = 4;
+const STEP: Ms = Ms(250.0);
+const ATTACK: Ms = Ms(3.0);
+const RELEASE: Ms = Ms(10.0);
+
+const CUTOFF: Hz = Hz(400.0);
+// how much the cutoff opens on the accents (at the peak of their envelope)
+const ACCENT_CUTOFF: f64 = 4.0;
+const Q: f64 = 4.0;
+const LEVEL: f64 = 0.3;
+
+fn steps(fs: f64) -> Vec<Step> {
+    let step_length = STEP.to_frames(fs).0;
+    (0..TRACK2.len() * LOOPS)
+        .map(|i| {
+            let step = Step::new(Frames(i * step_length), 1.0);
+            let i = i % TRACK2.len();
+            let ratchet = RATCHETS.iter().find(|(j, _)| *j == i).map_or(1, |r| r.1);
+            step.with_accent(ACCENTS.contains(&i)).with_ratchet(ratchet)
+        })
+        .collect()
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    play(move |config| {
+        let fs = config.sample_rate.0 as f64;
+
+        let step_length = STEP.to_frames(fs);
+        let steps = steps(fs);
+        let total = step_length.0 * steps.len();
+
+        let env = Env::scheduled(
+            steps.clone(),
+            step_length,
+            ATTACK.to_frames(fs),
+            RELEASE.to_frames(fs),
+        );
+        // the envelope of the accented notes only, for the filter
+        let accents = steps
+            .iter()
+            .filter(|step| step.accent)
+            .map(|step| Step::new(step.start, 1.0))
+            .collect();
+        let mut accent_env = Env::scheduled(
+            accents,
+            step_length,
+            ATTACK.to_frames(fs),
+            STEP.to_frames(fs),
+        );
+
+        let mut saw = PolyBlepSaw::new();
+        let mut lpf = Biquad::low_pass(fs, CUTOFF, Q);
+        let mut cur_frame: usize = 0;
+        let bass = signal::gen_mut(move || {
+            if cur_frame.is_multiple_of(step_length.0) {
+                let note = TRACK2[cur_frame / step_length.0 % TRACK2.len()];
+                saw.set_freq(fs, Hz(note));
+            }
+            cur_frame += 1;
+
+            let cutoff = CUTOFF.0 * (1.0 + ACCENT_CUTOFF * accent_env.next());
+            lpf.set_coefficients(&Biquad::low_pass(fs, Hz(cutoff), Q));
+            lpf.process(saw.next_sample())
+        });
+
+        bass.mul_amp(env)
+            .scale_amp(LEVEL)
+            .take(total)
+            .chain(signal::equilibrium().take(1000))
+    })
+}
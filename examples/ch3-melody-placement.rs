@@ -0,0 +1,132 @@
+// This code is derived from these examples on RustAudio:
+//
+// - https://github.com/RustAudio/dasp/blob/master/examples/synth.rs
+// - https://github.com/RustAudio/cpal/blob/master/examples/record_wav.rs
+//
+// Usage: cargo run --example ch3-melody-placement [--layout <stereo|quad>]
+//
+// The melody of ch3-melody with the tracks placed around the listener: TRACK1
+// stays front-left 1 m away, and TRACK2 starts back-right 4 m away and
+// circles the listener once over the sequence. With `--layout quad`, it's
+// rendered for the quadraphonic speakers.
+
+use dasp::{signal, Frame, Signal};
+use sound_programming_practice::{
+    envelope::Env,
+    oscillator::PhaseAccumOsc,
+    runner::{render, Layout, Player},
+    spatial::Placement,
+    units::{Frames, Ms},
+};
+
+#[rustfmt::skip]
+const SEQ: [bool; 8] = [true; 8];
+#[rustfmt::skip]
+const TRACK1: [f64; 8] = [659.26, 587.33, 523.25, 493.88, 440.00, 392.00, 440.00, 493.88];
+#[rustfmt::skip]
+const TRACK2: [f64; 8] = [261.63, 196.00, 220.00, 164.81, 174.61, 130.81, 174.61, 196.00];
+
+const ATTACK: Ms = Ms(20.0);
+const RELEASE: Ms = Ms(20.0);
+
+// the positions as (distance in meters, azimuth in degrees; 90 is the left)
+const TRACK1_POSITION: (f64, f64) = (1.0, 45.0);
+const TRACK2_POSITION: (f64, f64) = (4.0, -135.0);
+
+struct Track {
+    seq: Vec<f64>,
+    step_length: usize,
+    cur_frame: usize,
+    note: f64,
+}
+
+impl Track {
+    fn new(mut seq: Vec<f64>, step_length: Frames) -> Self {
+        let note = seq.pop().unwrap_or(0.0);
+
+        Self {
+            seq,
+            step_length: step_length.0,
+            cur_frame: 0,
+            note,
+        }
+    }
+}
+
+impl Signal for Track {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        self.cur_frame += 1;
+
+        // proceed to the next step
+        if self.cur_frame > self.step_length {
+            self.cur_frame -= self.step_length;
+            self.note = self.seq.pop().unwrap_or(0.0);
+        }
+
+        self.note
+    }
+}
+
+fn position(distance: f64, azimuth: f64) -> [f64; 2] {
+    let azimuth = azimuth.to_radians();
+    [distance * azimuth.cos(), distance * azimuth.sin()]
+}
+
+// The tracks and their positions are rendered beforehand, so that they have
+// the same types for both the layouts.
+type Mono = signal::FromIterator<std::vec::IntoIter<f64>>;
+type Position = signal::FromIterator<std::vec::IntoIter<[f64; 2]>>;
+
+fn melody<const N: usize>(
+    fs: f64,
+    place: fn(Mono, Position, f64) -> Placement<Mono, Position, N>,
+) -> impl Iterator<Item = [f64; N]>
+where
+    [f64; N]: Frame<Sample = f64>,
+{
+    let step_length = Ms(1000.0).to_frames(fs);
+    let total_frames = step_length.0 * SEQ.len();
+
+    let track = |notes: &[f64]| {
+        let env = Env::gated(
+            SEQ.to_vec(),
+            step_length,
+            ATTACK.to_frames(fs),
+            RELEASE.to_frames(fs),
+        );
+        let track = PhaseAccumOsc::new(Track::new(notes.to_vec(), step_length), fs)
+            .mul_amp(env)
+            .scale_amp(0.5);
+        signal::from_iter(render(track, Frames(total_frames)))
+    };
+
+    let (distance, azimuth) = TRACK1_POSITION;
+    let still = vec![position(distance, azimuth); total_frames];
+    let track1 = place(track(&TRACK1), signal::from_iter(still), fs);
+
+    // one round counterclockwise over the sequence
+    let (distance, start) = TRACK2_POSITION;
+    let circling = (0..total_frames)
+        .map(|i| position(distance, start + 360.0 * i as f64 / total_frames as f64))
+        .collect::<Vec<_>>();
+    let track2 = place(track(&TRACK2), signal::from_iter(circling), fs).with_pre_delay(distance);
+
+    track1
+        .add_amp(track2)
+        .take(total_frames)
+        .chain(signal::equilibrium().take(1000))
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let player = Player::from_args()?;
+    match player.layout() {
+        Layout::Stereo => {
+            player.play_frames(|config| melody(config.sample_rate.0 as f64, Placement::new))
+        }
+        Layout::Quad => {
+            player.play_frames(|config| melody(config.sample_rate.0 as f64, Placement::quad))
+        }
+    }
+}
@@ -0,0 +1,91 @@
+// This code is derived from these examples on RustAudio:
+//
+// - https://github.com/RustAudio/dasp/blob/master/examples/synth.rs
+// - https://github.com/RustAudio/cpal/blob/master/examples/record_wav.rs
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    effects::Tape,
+    envelope::Env,
+    oscillator::PhaseAccumOsc,
+    runner::play,
+    units::{Db, Frames, Hz, Ms},
+};
+
+#[rustfmt::skip]
+const SEQ: [bool; 8] = [true; 8];
+#[rustfmt::skip]
+const TRACK1: [f64; 8] = [659.26, 587.33, 523.25, 493.88, 440.00, 392.00, 440.00, 493.88];
+#[rustfmt::skip]
+const TRACK2: [f64; 8] = [261.63, 196.00, 220.00, 164.81, 174.61, 130.81, 174.61, 196.00];
+
+const ATTACK: Ms = Ms(20.0);
+const RELEASE: Ms = Ms(20.0);
+
+struct Track {
+    seq: Vec<f64>,
+    step_length: usize,
+    cur_frame: usize,
+    note: f64,
+}
+
+impl Track {
+    fn new(mut seq: Vec<f64>, step_length: Frames) -> Self {
+        let note = seq.pop().unwrap_or(0.0);
+        println!("note: {}", note);
+
+        Self {
+            seq,
+            step_length: step_length.0,
+            cur_frame: 0,
+            note,
+        }
+    }
+}
+
+impl Signal for Track {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        self.cur_frame += 1;
+
+        // proceed to the next step
+        if self.cur_frame > self.step_length {
+            self.cur_frame -= self.step_length;
+            self.note = self.seq.pop().unwrap_or(0.0);
+            println!("note: {}", self.note);
+        }
+
+        self.note
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    play(|config| {
+        let fs = config.sample_rate.0 as f64;
+
+        let step_length = Ms(1000.0).to_frames(fs);
+
+        let track1 = PhaseAccumOsc::new(Track::new(TRACK1.to_vec(), step_length), fs);
+
+        let track2 = PhaseAccumOsc::new(Track::new(TRACK2.to_vec(), step_length), fs);
+
+        let env = Env::gated(
+            SEQ.to_vec(),
+            step_length,
+            ATTACK.to_frames(fs),
+            RELEASE.to_frames(fs),
+        );
+
+        let mix = track1.add_amp(track2).mul_amp(env).scale_amp(0.5);
+
+        // a worn cassette
+        Tape::new(mix, fs)
+            .with_wow(Hz(0.5), 15.0)
+            .with_flutter(Hz(6.0), 5.0)
+            .with_saturation(2.0)
+            .with_hiss(Db(-50.0))
+            .take(step_length.0 * SEQ.len())
+            .chain(signal::equilibrium().take(1000))
+    })
+}
@@ -2,10 +2,22 @@
 //
 // - https://github.com/RustAudio/dasp/blob/master/examples/synth.rs
 // - https://github.com/RustAudio/cpal/blob/master/examples/record_wav.rs
-
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use dasp::{signal, Sample, Signal};
-use std::sync::mpsc;
+//
+// Usage: cargo run --example ch3-melody [-- --stems <dir>] [--humanize]
+//
+// With `--stems`, each track is written to a WAV file in the directory
+// (at 48 kHz) instead of playing. With `--humanize`, the timing and the
+// velocity of the steps vary a bit, as if played by a human.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    envelope::Env,
+    humanize::{Humanize, Step},
+    oscillator::PhaseAccumOsc,
+    runner::{play, positional_args},
+    units::{Frames, Ms},
+    wav,
+};
 
 #[rustfmt::skip]
 const SEQ: [bool; 8] = [true; 8];
@@ -14,85 +26,32 @@ const TRACK1: [f64; 8] = [659.26, 587.33, 523.25, 493.88, 440.00, 392.00, 440.00
 #[rustfmt::skip]
 const TRACK2: [f64; 8] = [261.63, 196.00, 220.00, 164.81, 174.61, 130.81, 174.61, 196.00];
 
-const ATTACK: usize = 1000;
-const RELEASE: usize = 1000;
-
-struct Env {
-    seq: Vec<bool>,
-    cur_frame: usize,
-    note_on: bool,
-    step_length: usize,
-    attack_frames: usize,
-    release_frames: usize,
-}
-
-impl Env {
-    fn new(
-        mut seq: Vec<bool>,
-        step_length: usize,
-        attack_frames: usize,
-        release_frames: usize,
-    ) -> Self {
-        let note_on = seq.pop().unwrap_or(false);
-        Self {
-            seq,
-            cur_frame: 0,
-            note_on,
-            step_length,
-            attack_frames,
-            release_frames,
-        }
-    }
-}
-
-impl Signal for Env {
-    type Frame = f64;
-
-    fn next(&mut self) -> Self::Frame {
-        self.cur_frame += 1;
-
-        // proceed to the next step
-        if self.cur_frame > self.step_length {
-            self.cur_frame -= self.step_length;
-            self.note_on = self.seq.pop().unwrap_or(false);
-        }
-
-        if !self.note_on {
-            return 0.0;
-        }
-
-        // release phase
-        if self.cur_frame > self.step_length - self.release_frames {
-            return (self.step_length - self.cur_frame) as f64 / self.release_frames as f64;
-        }
+const ATTACK: Ms = Ms(20.0);
+const RELEASE: Ms = Ms(20.0);
 
-        // attack phase
-        if self.cur_frame <= self.attack_frames {
-            return self.cur_frame as f64 / self.attack_frames as f64;
-        }
+// the sampling rate of the stems
+const STEM_RATE: f64 = 48000.0;
 
-        // sustain phase
-        1.0
-    }
-}
+const SEED: u64 = 1234;
+// the ranges of the humanization
+const HUMANIZE_TIMING: Ms = Ms(12.0);
+const HUMANIZE_VELOCITY: f64 = 0.1;
 
 struct Track {
     seq: Vec<f64>,
-    step_length: usize,
+    // the starts of the steps left, in the reverse order
+    starts: Vec<usize>,
     cur_frame: usize,
     note: f64,
 }
 
 impl Track {
-    fn new(mut seq: Vec<f64>, step_length: usize) -> Self {
-        let note = seq.pop().unwrap_or(0.0);
-        println!("note: {}", note);
-
+    fn new(seq: Vec<f64>, steps: &[Step]) -> Self {
         Self {
             seq,
-            step_length,
+            starts: steps.iter().rev().map(|step| step.start.0).collect(),
             cur_frame: 0,
-            note,
+            note: 0.0,
         }
     }
 }
@@ -101,98 +60,91 @@ impl Signal for Track {
     type Frame = f64;
 
     fn next(&mut self) -> Self::Frame {
-        self.cur_frame += 1;
-
         // proceed to the next step
-        if self.cur_frame > self.step_length {
-            self.cur_frame -= self.step_length;
+        if self.starts.last() == Some(&self.cur_frame) {
+            self.starts.pop();
             self.note = self.seq.pop().unwrap_or(0.0);
             println!("note: {}", self.note);
         }
+        self.cur_frame += 1;
 
         self.note
     }
 }
 
-fn main() -> Result<(), anyhow::Error> {
-    let host = cpal::default_host();
-    let device = host.default_output_device().unwrap();
-    let config = device.default_output_config()?;
-
-    println!("host: {}", host.id().name());
-
-    match config.sample_format() {
-        cpal::SampleFormat::F32 => run::<f32>(&device, &config.into())?,
-        cpal::SampleFormat::I16 => run::<i16>(&device, &config.into())?,
-        cpal::SampleFormat::U16 => run::<u16>(&device, &config.into())?,
+/// The steps, humanized if `humanize` is true.
+fn steps(fs: f64, humanize: bool) -> (Vec<Step>, Frames) {
+    let step_length = Ms(1000.0).to_frames(fs);
+    let mut h = Humanize::new(SEED);
+    if humanize {
+        h = h
+            .with_timing(HUMANIZE_TIMING.to_frames(fs))
+            .with_velocity(HUMANIZE_VELOCITY)
+            .with_lock_first(true);
     }
-
-    Ok(())
+    (h.steps(SEQ.len(), step_length), step_length)
 }
 
-fn run<T>(device: &cpal::Device, config: &cpal::StreamConfig) -> Result<(), anyhow::Error>
-where
-    T: cpal::Sample,
-{
-    println!("sample rate: {}", config.sample_rate.0);
-    println!("channels: {}", config.channels);
-
-    let step_length = config.sample_rate.0 as usize;
-
-    let track1 = signal::rate(config.sample_rate.0 as f64)
-        .hz(Track::new(TRACK1.to_vec(), step_length))
-        .sine();
-
-    let track2 = signal::rate(config.sample_rate.0 as f64)
-        .hz(Track::new(TRACK2.to_vec(), step_length))
-        .sine();
-
-    let env = Env::new(SEQ.to_vec(), step_length, ATTACK, RELEASE);
-
-    let mut frames = track1
-        .add_amp(track2)
-        .mul_amp(env)
-        .take(step_length * SEQ.len())
-        .chain(signal::equilibrium().take(1000));
+/// The envelope of the steps, which is applied to each track. The notes are
+/// shortened by the range of the timing, so that they never overlap.
+fn env(fs: f64, steps: &[Step], step_length: Frames, humanize: bool) -> impl Signal<Frame = f64> {
+    let margin = if humanize {
+        2 * HUMANIZE_TIMING.to_frames(fs).0
+    } else {
+        0
+    };
+    let steps = steps
+        .iter()
+        .zip(SEQ)
+        .filter_map(|(&step, on)| on.then_some(step))
+        .collect();
+    Env::scheduled(
+        steps,
+        Frames(step_length.0 - margin),
+        ATTACK.to_frames(fs),
+        RELEASE.to_frames(fs),
+    )
+}
 
-    let (complete_tx, complete_rx) = mpsc::sync_channel::<()>(1);
+fn main() -> Result<(), anyhow::Error> {
+    let args = positional_args();
+    let humanize = args.iter().any(|arg| arg == "--humanize");
+
+    if let Some(i) = args.iter().position(|arg| arg == "--stems") {
+        let dir = args
+            .get(i + 1)
+            .ok_or_else(|| anyhow::anyhow!("--stems needs a directory"))?;
+
+        let fs = STEM_RATE;
+        let (steps, step_length) = steps(fs, humanize);
+        let track = |seq: &[f64]| {
+            PhaseAccumOsc::new(Track::new(seq.to_vec(), &steps), fs).mul_amp(env(
+                fs,
+                &steps,
+                step_length,
+                humanize,
+            ))
+        };
+        let tracks = vec![("track1", track(&TRACK1)), ("track2", track(&TRACK2))];
+        for path in wav::render_stems(tracks, Frames(step_length.0 * SEQ.len()), fs, dir)? {
+            println!("wrote {}", path.display());
+        }
+        return Ok(());
+    }
 
-    let channels = config.channels as usize;
-    let stream = device.build_output_stream(
-        config,
-        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
-            write_data(data, channels, &complete_tx, &mut frames);
-        },
-        |err| eprintln!("{err}"),
-    )?;
+    play(move |config| {
+        let fs = config.sample_rate.0 as f64;
 
-    stream.play()?;
+        let (steps, step_length) = steps(fs, humanize);
 
-    complete_rx.recv().unwrap();
-    stream.pause()?;
+        let track1 = PhaseAccumOsc::new(Track::new(TRACK1.to_vec(), &steps), fs);
 
-    Ok(())
-}
+        let track2 = PhaseAccumOsc::new(Track::new(TRACK2.to_vec(), &steps), fs);
 
-fn write_data<T>(
-    output: &mut [T],
-    channels: usize,
-    complete_rx: &mpsc::SyncSender<()>,
-    frames: &mut dyn Iterator<Item = f64>,
-) where
-    T: cpal::Sample,
-{
-    for frame in output.chunks_mut(channels) {
-        let sample = match frames.next() {
-            Some(sample) => sample.to_sample::<f32>(),
-            None => {
-                complete_rx.try_send(()).ok();
-                0.0
-            }
-        };
-        let value: T = cpal::Sample::from::<f32>(&sample);
-        for sample in frame.iter_mut() {
-            *sample = value;
-        }
-    }
+        track1
+            .add_amp(track2)
+            .mul_amp(env(fs, &steps, step_length, humanize))
+            .take(step_length.0 * SEQ.len())
+            .chain(signal::equilibrium().take(1000))
+    })
 }
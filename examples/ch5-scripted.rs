@@ -0,0 +1,143 @@
+// Usage: cargo run --example ch5-scripted [--dump-controls out.csv]
+//
+// The filtered square of ch5-biquad-filter with an echo, performed for 10
+// seconds purely by the scheduled ramps: the filter opens, the echo rises,
+// and then everything fades out.
+//
+// With `--dump-controls`, this renders offline at 48 kHz instead of playing,
+// and writes the envelope, the cutoff, the echo mix, and the gain to the CSV
+// file every 64 frames.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::biquad::Biquad,
+    effects::DelayLine,
+    envelope::Env,
+    params::{AtomicF64, Probe},
+    runner::{ControlDump, Player},
+    schedule::Scheduler,
+    units::{Frames, Hz, Ms},
+};
+use std::sync::Arc;
+
+const ATTACK: Ms = Ms(20.0);
+const RELEASE: Ms = Ms(20.0);
+
+#[rustfmt::skip]
+const SEQ: [bool; 10] = [true; 10];
+
+const Q: f64 = 2.0;
+const ECHO: Ms = Ms(300.0);
+const FEEDBACK: f64 = 0.4;
+
+// the sampling rate of the offline rendering
+const OFFLINE_RATE: f64 = 48000.0;
+
+/// The values of the control signals on the current frame.
+struct Controls {
+    env: Arc<AtomicF64>,
+    cutoff: Arc<AtomicF64>,
+    mix: Arc<AtomicF64>,
+    gain: Arc<AtomicF64>,
+}
+
+impl Controls {
+    // the values before the first frame, as the first row of the dump
+    fn new() -> Self {
+        let control = |value| Arc::new(AtomicF64::new(value));
+        Self {
+            env: control(0.0),
+            cutoff: control(200.0),
+            mix: control(0.0),
+            gain: control(1.0),
+        }
+    }
+}
+
+fn step_length(fs: f64) -> Frames {
+    Ms(1000.0).to_frames(fs)
+}
+
+fn patch(fs: f64, controls: &Controls) -> impl Signal<Frame = f64> {
+    let at = |seconds: f64| Ms(seconds * 1000.0).to_frames(fs);
+
+    let mut scheduler = Scheduler::new();
+    let mut cutoff = scheduler.add("cutoff", 200.0);
+    let mut mix = scheduler.add("mix", 0.0);
+    let mut gain = scheduler.add("gain", 1.0);
+
+    let script = || -> Result<(), anyhow::Error> {
+        // the filter opens over the first 6 seconds
+        scheduler.exp_ramp_to("cutoff", at(6.0), 4000.0)?;
+        // the echo rises from 2 to 8 seconds
+        scheduler.set_value_at("mix", at(2.0), 0.0)?;
+        scheduler.linear_ramp_to("mix", at(8.0), 0.5)?;
+        // the fade-out over the last 2 seconds
+        scheduler.set_value_at("gain", at(8.0), 1.0)?;
+        scheduler.linear_ramp_to("gain", at(10.0), 0.0)?;
+        Ok(())
+    };
+    script().expect("the script should be valid");
+
+    let square = signal::rate(fs).const_hz(110.0).square();
+
+    let env = Env::gated(
+        SEQ.to_vec(),
+        step_length(fs),
+        ATTACK.to_frames(fs),
+        RELEASE.to_frames(fs),
+    );
+    let env = Probe::new(env, controls.env.clone());
+
+    let mut lpf = Biquad::low_pass(fs, Hz(200.0), Q);
+    let echo_frames = ECHO.to_frames(fs).0;
+    let mut echo = DelayLine::new(echo_frames);
+    let (cutoff_value, mix_value, gain_value) = (
+        controls.cutoff.clone(),
+        controls.mix.clone(),
+        controls.gain.clone(),
+    );
+    square.mul_amp(env).map(move |x| {
+        let (c, m, g) = (cutoff.next_value(), mix.next_value(), gain.next_value());
+        cutoff_value.set(c);
+        mix_value.set(m);
+        gain_value.set(g);
+
+        lpf.set_coefficients(&Biquad::low_pass(fs, Hz(c), Q));
+        let dry = lpf.process(x);
+
+        let wet = echo.tap(echo_frames);
+        echo.push(dry + FEEDBACK * wet);
+
+        g * 0.5 * (dry + m * wet)
+    })
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let player = Player::from_args()?;
+
+    if let Some(path) = player.dump_controls() {
+        let fs = OFFLINE_RATE;
+        let controls = Controls::new();
+        let mut dump = ControlDump::create(patch(fs, &controls), fs, path)?
+            .with_control("env", controls.env.clone())
+            .with_control("cutoff", controls.cutoff.clone())
+            .with_control("mix", controls.mix.clone())
+            .with_control("gain", controls.gain.clone());
+        // only the controls are written; the frames are discarded
+        (&mut dump)
+            .take(step_length(fs).0 * SEQ.len())
+            .for_each(drop);
+        dump.finish()?;
+        println!("wrote {}", path.display());
+        return Ok(());
+    }
+
+    player.play(|config| {
+        let fs = config.sample_rate.0 as f64;
+        patch(fs, &Controls::new())
+            .take(step_length(fs).0 * SEQ.len())
+            // To prevent click noise at the end, fill some silence
+            .chain(signal::equilibrium().take(1000))
+    })
+}
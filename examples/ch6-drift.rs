@@ -0,0 +1,83 @@
+// Usage: cargo run --example ch6-drift
+//
+// Plays the same chord of saws three times, with the analog drift of 0, 3,
+// and 8 cents. Each voice drifts independently, and its low-pass follows the
+// drift a little.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::{biquad::Biquad, drift::Drift, polyblep::PolyBlepSaw},
+    runner::play,
+    units::{Hz, Ms},
+};
+
+const SEED: u64 = 1234;
+
+#[rustfmt::skip]
+const CHORD: [Hz; 3] = [Hz(220.0), Hz(277.18), Hz(329.63)];
+
+// the depths of the drift of the three renderings, in cents
+const DEPTHS: [f64; 3] = [0.0, 3.0, 8.0];
+const RATE: Hz = Hz(0.5);
+
+const CUTOFF: Hz = Hz(1500.0);
+const Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+// how much the cutoff follows the drift of the pitch
+const CUTOFF_AMOUNT: f64 = 0.5;
+
+const LEVEL: f64 = 0.15;
+const NOTE: Ms = Ms(3000.0);
+const GAP: Ms = Ms(500.0);
+
+struct Voice {
+    freq: Hz,
+    saw: PolyBlepSaw,
+    lpf: Biquad,
+    drift: Drift,
+}
+
+impl Voice {
+    fn new(fs: f64, freq: Hz, depth: f64, seed: u64) -> Self {
+        Self {
+            freq,
+            saw: PolyBlepSaw::new(),
+            lpf: Biquad::low_pass(fs, CUTOFF, Q),
+            drift: Drift::new(fs, RATE, depth, seed),
+        }
+    }
+
+    fn process(&mut self, fs: f64) -> f64 {
+        let ratio = self.drift.next_ratio();
+        self.saw.set_freq(fs, Hz(self.freq.0 * ratio));
+        // skip recomputing the coefficients when nothing drifts
+        if ratio != 1.0 {
+            let cutoff = CUTOFF.0 * ratio.powf(CUTOFF_AMOUNT);
+            self.lpf
+                .set_coefficients(&Biquad::low_pass(fs, Hz(cutoff), Q));
+        }
+        self.lpf.process(self.saw.next_sample())
+    }
+}
+
+/// The chord with the drift of `depth` cents. Each voice has its own seed.
+fn chord(fs: f64, depth: f64) -> impl Iterator<Item = f64> {
+    let mut voices: Vec<Voice> = CHORD
+        .iter()
+        .enumerate()
+        .map(|(i, &freq)| Voice::new(fs, freq, depth, SEED + i as u64))
+        .collect();
+    signal::gen_mut(move || LEVEL * voices.iter_mut().map(|v| v.process(fs)).sum::<f64>())
+        .take(NOTE.to_frames(fs).0)
+        .chain(std::iter::repeat_n(0.0, GAP.to_frames(fs).0))
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    play(move |config| {
+        let fs = config.sample_rate.0 as f64;
+
+        DEPTHS.into_iter().flat_map(move |depth| {
+            println!("drift: {depth} cents");
+            chord(fs, depth)
+        })
+    })
+}
@@ -0,0 +1,51 @@
+// Usage: cargo run --example ch6-fm-feedback
+//
+// Plays a single FM operator feeding back to itself, with the feedback rising
+// from 0 (a sine) to the maximum (a saw-like tone) over the sequence.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::fm::{Operator, MAX_FEEDBACK},
+    envelope::Env,
+    runner::play,
+    units::{Hz, Ms},
+};
+
+const FREQ: Hz = Hz(220.0);
+const LEVEL: f64 = 0.5;
+const ATTACK: Ms = Ms(20.0);
+const RELEASE: Ms = Ms(20.0);
+
+#[rustfmt::skip]
+const SEQ: [bool; 8] = [true; 8];
+
+fn main() -> Result<(), anyhow::Error> {
+    play(|config| {
+        let fs = config.sample_rate.0 as f64;
+
+        let step_length = Ms(1000.0).to_frames(fs);
+        let total = step_length.0 * SEQ.len();
+
+        let mut op = Operator::new();
+        op.set_freq(fs, FREQ);
+        let mut i = 0;
+        let fm = signal::gen_mut(move || {
+            op.set_feedback(MAX_FEEDBACK * i as f64 / total as f64);
+            i += 1;
+            op.process(0.0)
+        });
+
+        let env = Env::gated(
+            SEQ.to_vec(),
+            step_length,
+            ATTACK.to_frames(fs),
+            RELEASE.to_frames(fs),
+        );
+
+        fm.scale_amp(LEVEL)
+            .mul_amp(env)
+            .take(total)
+            // To prevent click noise at the end, fill some silence
+            .chain(signal::equilibrium().take(1000))
+    })
+}
@@ -0,0 +1,73 @@
+// Usage: cargo run --example ch6-fm-locks
+//
+// Plays a two-operator FM voice with the modulator locked per step: the even
+// steps use the ratio of 1.41 (an inharmonic, bell-like tone following the
+// note), and the odd steps a fixed 900 Hz (a metallic tone whose sidebands
+// don't move with the note).
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::fm::{Lock, ModFreq, TwoOp},
+    envelope::Env,
+    runner::play,
+    units::{Hz, Ms},
+};
+
+const LEVEL: f64 = 0.5;
+const ATTACK: Ms = Ms(5.0);
+const RELEASE: Ms = Ms(100.0);
+const STEP: Ms = Ms(500.0);
+
+#[rustfmt::skip]
+const NOTES: [f64; 8] = [220.00, 261.63, 329.63, 293.66, 220.00, 196.00, 246.94, 220.00];
+// the index of the even and the odd steps
+const BELL_INDEX: f64 = 2.0;
+const METAL_INDEX: f64 = 3.0;
+
+/// The lock of the step.
+fn lock(step: usize) -> Lock {
+    if step.is_multiple_of(2) {
+        Lock {
+            mod_freq: Some(ModFreq::Ratio(1.41)),
+            index: Some(BELL_INDEX),
+        }
+    } else {
+        Lock {
+            mod_freq: Some(ModFreq::Fixed(Hz(900.0))),
+            index: Some(METAL_INDEX),
+        }
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    play(|config| {
+        let fs = config.sample_rate.0 as f64;
+
+        let step_length = STEP.to_frames(fs);
+        let mut voice = TwoOp::new(fs, Hz(NOTES[0]), ModFreq::Ratio(1.0), 0.0);
+        let mut i = 0;
+        let fm = signal::gen_mut(move || {
+            // the locks take effect at the start of the step
+            if i % step_length.0 == 0 {
+                let step = i / step_length.0;
+                voice.set_note(Hz(NOTES[step % NOTES.len()]));
+                voice.apply(&lock(step));
+            }
+            i += 1;
+            voice.next_sample()
+        });
+
+        let env = Env::gated(
+            vec![true; NOTES.len()],
+            step_length,
+            ATTACK.to_frames(fs),
+            RELEASE.to_frames(fs),
+        );
+
+        fm.scale_amp(LEVEL)
+            .mul_amp(env)
+            .take(step_length.0 * NOTES.len())
+            // To prevent click noise at the end, fill some silence
+            .chain(signal::equilibrium().take(1000))
+    })
+}
@@ -0,0 +1,142 @@
+// This code is derived from these examples on RustAudio:
+//
+// - https://github.com/RustAudio/dasp/blob/master/examples/synth.rs
+// - https://github.com/RustAudio/cpal/blob/master/examples/record_wav.rs
+//
+// Usage: cargo run --example ch6-karplus-body [--require-rate <Hz>] [body-impulse-response.wav]
+//
+// Plucks the string without the body first, and then with the body. Without
+// the argument, the body is a bank of resonant biquads; with it, the string
+// is convolved with the impulse response (truncated to 100 ms).
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    convolution::Convolver,
+    core::karplus,
+    effects::BodyResonator,
+    runner::{play, positional_args},
+    stereo::MonoEffect,
+    tail::{take_with_tail, HasTail},
+    units::{Frames, Hz, Ms},
+    wav,
+};
+
+const SEED: u64 = 1234;
+
+#[rustfmt::skip]
+const SEQ: [bool; 8] = [true; 8];
+
+// the capacity of the delay line, which is enough for 48 Hz at 48 kHz
+const MAX_DELAY: usize = 1024;
+
+// the maximum length of the impulse response of the body
+const MAX_IR_LENGTH: Ms = Ms(100.0);
+
+struct KarplusStrongWithBody {
+    cur_frame: usize,
+    plucks: usize,
+    fs: f64, // sampling rate
+    string: karplus::KarplusStrong<MAX_DELAY>,
+    body: Box<dyn MonoEffect + Send>,
+}
+
+impl KarplusStrongWithBody {
+    fn new(
+        fs: f64,
+        f0: Hz,
+        d: f64,
+        t60: Ms,
+        plucks: usize,
+        body: Box<dyn MonoEffect + Send>,
+    ) -> Self {
+        println!("central frequency: {}", f0.0);
+
+        let string = karplus::KarplusStrong::new(fs, f0, d, t60, SEED)
+            .expect("the parameters of the string should be valid");
+
+        Self {
+            cur_frame: 0,
+            plucks,
+            fs,
+            string,
+            body,
+        }
+    }
+}
+
+impl Signal for KarplusStrongWithBody {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        // trigger once per second
+        let fs = self.fs as usize;
+        let pluck = self.cur_frame / fs;
+        if self.cur_frame.is_multiple_of(fs) && pluck < self.plucks {
+            self.string.pluck();
+            if pluck == 0 {
+                println!("without the body");
+            } else if pluck == self.plucks / 2 {
+                println!("with the body");
+            }
+        }
+        self.cur_frame += 1;
+
+        // the body is always processed so that it is warmed up when switched
+        let dry = self.string.process();
+        let wet = self.body.process(dry);
+        if pluck < self.plucks / 2 {
+            dry
+        } else {
+            wet
+        }
+    }
+}
+
+impl HasTail for KarplusStrongWithBody {
+    fn has_tail(&self) -> bool {
+        true
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let wav = match positional_args().into_iter().next() {
+        Some(path) => {
+            let wav = wav::read(&path)?;
+            println!(
+                "impulse response of the body: {path} ({} frames)",
+                wav.len()
+            );
+            Some(wav)
+        }
+        None => None,
+    };
+
+    play(|config| {
+        let fs = config.sample_rate.0 as f64;
+
+        let body: Box<dyn MonoEffect + Send> = match wav {
+            Some(wav) => {
+                if wav.fs != fs {
+                    eprintln!(
+                        "warning: the sampling rate of the impulse response ({}) differs from the device's ({fs})",
+                        wav.fs
+                    );
+                }
+                let mut ir = wav.to_mono();
+                ir.truncate(MAX_IR_LENGTH.to_frames(fs).0);
+                Box::new(Convolver::from_ir(&ir, fs))
+            }
+            None => Box::new(BodyResonator::guitar(fs)),
+        };
+
+        let step_length = Ms(1000.0).to_frames(fs);
+
+        let ks = KarplusStrongWithBody::new(fs, Hz(110.0), 0.2, Ms(2000.0), SEQ.len(), body);
+
+        // taking the same number of samples as the sample rate = 1 second, and
+        // then let the string ring until it decays
+        take_with_tail(ks, Frames(step_length.0 * SEQ.len()), fs)
+            // To prevent click noise at the end, fill some silence
+            .chain(signal::equilibrium().take(1000))
+    })
+}
@@ -0,0 +1,132 @@
+// This code is derived from these examples on RustAudio:
+//
+// - https://github.com/RustAudio/dasp/blob/master/examples/synth.rs
+// - https://github.com/RustAudio/cpal/blob/master/examples/record_wav.rs
+//
+// Usage: cargo run --example ch6-karplus-room [--require-rate <Hz>] [--freeze] [impulse-response.wav]
+//
+// Without the argument, a synthetic impulse response of a small room is used.
+// With `--freeze`, the whole phrase is rendered before playing, so the
+// convolution costs nothing while playing.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    convolution::{exponential_decay_ir, Convolver},
+    core::karplus,
+    runner::{bounce, play, positional_args},
+    tail::{take_with_tail, HasTail},
+    units::{Frames, Hz, Ms},
+    wav,
+};
+
+const SEED: u64 = 1234;
+
+#[rustfmt::skip]
+const SEQ: [bool; 8] = [true; 8];
+
+// the capacity of the delay line, which is enough for 48 Hz at 48 kHz
+const MAX_DELAY: usize = 1024;
+
+// the level of the reverb relative to the dry signal
+const WET: f64 = 0.3;
+
+struct KarplusStrongInRoom {
+    cur_frame: usize,
+    plucks: usize,
+    fs: f64, // sampling rate
+    string: karplus::KarplusStrong<MAX_DELAY>,
+    room: Convolver,
+}
+
+impl KarplusStrongInRoom {
+    fn new(fs: f64, f0: Hz, d: f64, t60: Ms, plucks: usize, ir: &[f64]) -> Self {
+        println!("central frequency: {}", f0.0);
+
+        let string = karplus::KarplusStrong::new(fs, f0, d, t60, SEED)
+            .expect("the parameters of the string should be valid");
+
+        let room = Convolver::from_ir(ir, fs);
+        println!("block size of the convolution: {}", room.block_size());
+
+        Self {
+            cur_frame: 0,
+            plucks,
+            fs,
+            string,
+            room,
+        }
+    }
+}
+
+impl Signal for KarplusStrongInRoom {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        // trigger once per second
+        let fs = self.fs as usize;
+        if self.cur_frame.is_multiple_of(fs) && self.cur_frame / fs < self.plucks {
+            self.string.pluck();
+        }
+        self.cur_frame += 1;
+
+        // The reverb is delayed by the block size of the convolution, which
+        // is short enough to sound like a part of the room.
+        let dry = self.string.process();
+        dry + WET * self.room.process(dry)
+    }
+}
+
+impl HasTail for KarplusStrongInRoom {
+    fn has_tail(&self) -> bool {
+        true
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let (flags, args): (Vec<String>, Vec<String>) = positional_args()
+        .into_iter()
+        .partition(|arg| arg == "--freeze");
+    let freeze = !flags.is_empty();
+
+    let wav = match args.into_iter().next() {
+        Some(path) => {
+            let wav = wav::read(&path)?;
+            println!("impulse response: {path} ({} frames)", wav.len());
+            Some(wav)
+        }
+        None => None,
+    };
+
+    play(|config| {
+        let fs = config.sample_rate.0 as f64;
+
+        let ir = match wav {
+            Some(wav) => {
+                if wav.fs != fs {
+                    eprintln!(
+                        "warning: the sampling rate of the impulse response ({}) differs from the device's ({fs})",
+                        wav.fs
+                    );
+                }
+                wav.to_mono()
+            }
+            None => exponential_decay_ir(fs, Ms(400.0), SEED),
+        };
+
+        let step_length = Ms(1000.0).to_frames(fs);
+
+        let ks = KarplusStrongInRoom::new(fs, Hz(220.0), 0.05, Ms(2000.0), SEQ.len(), &ir);
+
+        // taking the same number of samples as the sample rate = 1 second, and
+        // then let the string and the room ring until they decay
+        let nominal_frames = Frames(step_length.0 * SEQ.len());
+        let frames: Box<dyn Iterator<Item = f64> + Send> = if freeze {
+            Box::new(bounce(ks, nominal_frames, fs).until_exhausted())
+        } else {
+            Box::new(take_with_tail(ks, nominal_frames, fs))
+        };
+
+        // To prevent click noise at the end, fill some silence
+        frames.chain(signal::equilibrium().take(1000))
+    })
+}
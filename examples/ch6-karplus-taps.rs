@@ -0,0 +1,92 @@
+// This code is derived from these examples on RustAudio:
+//
+// - https://github.com/RustAudio/dasp/blob/master/examples/synth.rs
+// - https://github.com/RustAudio/cpal/blob/master/examples/record_wav.rs
+//
+// Usage: cargo run --example ch6-karplus-taps
+//
+// The pluck is echoed by a rhythmic pattern of 3 taps: an eighth note on the
+// left, a dotted eighth on the right, and a darker half note at the center
+// which is fed back.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::karplus,
+    effects::{MultiTapDelay, Tap},
+    runner::play_stereo,
+    tail::{take_with_tail, HasTail},
+    units::{Frames, Hz, Ms},
+};
+
+const SEED: u64 = 1234;
+
+const BPM: f64 = 120.0;
+
+// a pluck on each bar (of 4 beats)
+const PLUCKS: usize = 4;
+
+// the capacity of the delay line, which is enough for 48 Hz at 48 kHz
+const MAX_DELAY: usize = 1024;
+
+struct Pluck {
+    cur_frame: usize,
+    bar_length: Frames,
+    string: karplus::KarplusStrong<MAX_DELAY>,
+}
+
+impl Pluck {
+    fn new(fs: f64, f0: Hz, d: f64, t60: Ms) -> Self {
+        let string = karplus::KarplusStrong::new(fs, f0, d, t60, SEED)
+            .expect("the parameters of the string should be valid");
+
+        Self {
+            cur_frame: 0,
+            bar_length: Ms::from_note(BPM, 1.0).to_frames(fs),
+            string,
+        }
+    }
+}
+
+impl Signal for Pluck {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let bar = self.bar_length.0;
+        if self.cur_frame.is_multiple_of(bar) && self.cur_frame / bar < PLUCKS {
+            self.string.pluck();
+        }
+        self.cur_frame += 1;
+
+        self.string.process()
+    }
+}
+
+impl HasTail for Pluck {
+    fn has_tail(&self) -> bool {
+        true
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    play_stereo(move |config| {
+        let fs = config.sample_rate.0 as f64;
+
+        let pluck = Pluck::new(fs, Hz(330.0), 0.05, Ms(800.0));
+        let length = Frames(Ms::from_note(BPM, 1.0).to_frames(fs).0 * PLUCKS);
+        // let the string ring until it decays; the silence follows
+        let pluck = take_with_tail(pluck, length, fs);
+
+        let note = |value| Ms::from_note(BPM, value).to_frames(fs);
+        let delay = MultiTapDelay::new(signal::from_iter(pluck), fs)
+            .with_tap(Tap::new(note(1.0 / 8.0), 0.5).with_pan(-1.0))
+            .with_tap(Tap::new(note(3.0 / 16.0), 0.4).with_pan(1.0))
+            .with_tap(Tap::new(note(1.0 / 2.0), 0.3).with_low_pass(Hz(2000.0)))
+            .with_feedback(2, 0.4);
+
+        // as long again for the echoes, and to prevent click noise at the end,
+        // fill some silence
+        delay
+            .take(2 * length.0)
+            .chain(signal::equilibrium().take(1000))
+    })
+}
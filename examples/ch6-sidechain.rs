@@ -0,0 +1,92 @@
+// Usage: cargo run --example ch6-sidechain
+//
+// A kick on every beat and a pad of saws, first as they are, and then with
+// the pad compressed by the kick (sidechain compression), so that the pad
+// ducks on each kick and swells back in between (pumping). The key is
+// high-passed a little, so that the detection follows the body of the kick
+// rather than its lowest end.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::{biquad::Biquad, polyblep::PolyBlepSaw},
+    effects::Compressor,
+    runner::play,
+    units::{Db, Hz, Ms},
+};
+use std::sync::Arc;
+
+const BPM: f64 = 120.0;
+const BARS: usize = 4;
+
+#[rustfmt::skip]
+const CHORD: [Hz; 4] = [Hz(130.81), Hz(196.0), Hz(261.63), Hz(311.13)];
+const PAD_LEVEL: f64 = 0.12;
+const PAD_CUTOFF: Hz = Hz(2000.0);
+
+const KICK_LEVEL: f64 = 0.7;
+
+const THRESHOLD: Db = Db(-30.0);
+const RATIO: f64 = 8.0;
+const ATTACK: Ms = Ms(2.0);
+const RELEASE: Ms = Ms(150.0);
+const KEY_HIGH_PASS: Hz = Hz(60.0);
+
+/// A beat of a kick: a sine dropping from 150 Hz to 50 Hz.
+fn kick(fs: f64) -> Arc<[f64]> {
+    let beat = Ms::from_note(BPM, 0.25).to_frames(fs).0;
+    (0..beat)
+        .map(|i| {
+            let t = i as f64 / fs;
+            let phase = 2.0 * std::f64::consts::PI * (50.0 * t + 2.0 * (1.0 - (-t / 0.02).exp()));
+            KICK_LEVEL * phase.sin() * (-t / 0.15).exp()
+        })
+        .collect()
+}
+
+/// The kick on every beat, forever.
+fn kicks(kick: Arc<[f64]>) -> impl Signal<Frame = f64> + Send {
+    let mut i = 0;
+    signal::gen_mut(move || {
+        let x = kick[i];
+        i = (i + 1) % kick.len();
+        x
+    })
+}
+
+/// A chord of low-passed saws, forever.
+fn pad(fs: f64) -> impl Signal<Frame = f64> + Send {
+    let mut saws: Vec<PolyBlepSaw> = CHORD
+        .iter()
+        .map(|&freq| {
+            let mut saw = PolyBlepSaw::new();
+            saw.set_freq(fs, freq);
+            saw
+        })
+        .collect();
+    let mut lpf = Biquad::low_pass(fs, PAD_CUTOFF, std::f64::consts::FRAC_1_SQRT_2);
+    signal::gen_mut(move || {
+        let x: f64 = saws.iter_mut().map(|saw| saw.next_sample()).sum();
+        PAD_LEVEL * lpf.process(x)
+    })
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    play(|config| {
+        let fs = config.sample_rate.0 as f64;
+        let kick = kick(fs);
+        let length = kick.len() * 4 * BARS;
+
+        let dry = pad(fs).add_amp(kicks(kick.clone())).take(length);
+
+        let pumped = Compressor::new(pad(fs), fs, THRESHOLD, RATIO)
+            .with_times(ATTACK, RELEASE)
+            .with_sidechain(kicks(kick.clone()))
+            .with_key_high_pass(KEY_HIGH_PASS)
+            .add_amp(kicks(kick))
+            .take(length);
+
+        dry.chain(pumped)
+            // To prevent click noise at the end, fill some silence
+            .chain(signal::equilibrium().take(1000))
+    })
+}
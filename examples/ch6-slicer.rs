@@ -0,0 +1,103 @@
+// Usage: cargo run --example ch6-slicer [loop.wav]
+//
+// Chops a one-bar drum loop into 8 slices and plays them in a new order,
+// with some of them reversed or pitched: first the loop as it is, and then
+// the resequenced bar twice. Without the argument, the loop is a kick, a
+// snare, and hats rendered here at 120 BPM.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::noise::Noise,
+    runner::{play, positional_args},
+    slicer::{SliceStep, Slicer},
+    units::{Frames, Ms},
+    wav,
+};
+
+const BPM: f64 = 120.0;
+const SLICES: usize = 8;
+const LEVEL: f64 = 0.5;
+
+/// The resequenced bar: the slices of the eighth notes in a new order, with
+/// a reversed snare and a pitched-down kick.
+fn steps() -> Vec<Option<SliceStep>> {
+    vec![
+        Some(SliceStep::new(0)),
+        Some(SliceStep::new(0).with_pitch(-5.0)),
+        Some(SliceStep::new(2).with_reverse(true)),
+        Some(SliceStep::new(5)),
+        Some(SliceStep::new(4)),
+        None,
+        Some(SliceStep::new(2)),
+        Some(SliceStep::new(7).with_pitch(7.0)),
+    ]
+}
+
+/// A bar of a kick on the beats 1 and 3, a snare on 2 and 4, and hats on the
+/// eighth notes.
+fn drum_loop(fs: f64) -> Vec<f64> {
+    let eighth = Ms::from_note(BPM, 0.125).to_frames(fs).0;
+    let mut samples = vec![0.0; eighth * 8];
+    let mut noise = Noise::new(1);
+
+    for (i, start) in (0..samples.len()).step_by(eighth).enumerate() {
+        let hit = &mut samples[start..start + eighth];
+        for (j, y) in hit.iter_mut().enumerate() {
+            let t = j as f64 / fs;
+            *y += 0.2 * noise.next_sample() * (-t / 0.01).exp();
+            match i {
+                // a sine dropping from 150 Hz to 50 Hz
+                0 | 4 => {
+                    let phase =
+                        2.0 * std::f64::consts::PI * (50.0 * t + 2.0 * (1.0 - (-t / 0.02).exp()));
+                    *y += 0.8 * phase.sin() * (-t / 0.15).exp();
+                }
+                // a burst of noise with a body of 200 Hz
+                2 | 6 => {
+                    let body = (2.0 * std::f64::consts::PI * 200.0 * t).sin();
+                    *y += (0.5 * noise.next_sample() + 0.3 * body) * (-t / 0.06).exp();
+                }
+                _ => {}
+            }
+        }
+    }
+    samples
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let wav = match positional_args().into_iter().next() {
+        Some(path) => {
+            let wav = wav::read(&path)?;
+            println!("loop: {path} ({} frames)", wav.len());
+            Some(wav)
+        }
+        None => None,
+    };
+
+    play(|config| {
+        let fs = config.sample_rate.0 as f64;
+
+        let slicer = match &wav {
+            Some(wav) => Slicer::new(wav),
+            None => Slicer::from_samples(drum_loop(fs), fs),
+        }
+        .with_equal_slices(SLICES);
+
+        // the length of the loop at the rate of the device
+        let len = slicer.slices().last().map_or(0, |&(_, end)| end.0);
+        let loop_rate = wav.as_ref().map_or(fs, |wav| wav.fs);
+        let bar = Frames((len as f64 * fs / loop_rate).round() as usize);
+        let step_length = Frames(bar.0 / SLICES);
+
+        let original = (0..SLICES).map(|i| Some(SliceStep::new(i)));
+        let resequenced = steps().into_iter().chain(steps());
+        let seq = original.chain(resequenced).collect();
+
+        slicer
+            .sequence(seq, step_length, fs)
+            .scale_amp(LEVEL)
+            .take(step_length.0 * SLICES * 3)
+            // To prevent click noise at the end, fill some silence
+            .chain(signal::equilibrium().take(1000))
+    })
+}
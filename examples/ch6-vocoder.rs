@@ -0,0 +1,162 @@
+// This code is derived from these examples on RustAudio:
+//
+// - https://github.com/RustAudio/dasp/blob/master/examples/synth.rs
+// - https://github.com/RustAudio/cpal/blob/master/examples/record_wav.rs
+//
+// Usage: cargo run --example ch6-vocoder [--require-rate <Hz>] [modulator.wav]
+//
+// The carrier is a chord of polyBLEP saws on TRACK2 with a bit of noise for
+// the consonants. Without the argument, the modulator is the melody of
+// TRACK1, which makes the chord sing along it. (The live input is not
+// supported yet, as the runner only has the output.)
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::{noise::Noise, polyblep},
+    effects::Vocoder,
+    envelope::Env,
+    oscillator::PhaseAccumOsc,
+    runner::{play, positional_args},
+    units::{Frames, Hz, Ms},
+    wav,
+};
+
+#[rustfmt::skip]
+const SEQ: [bool; 8] = [true; 8];
+#[rustfmt::skip]
+const TRACK1: [f64; 8] = [659.26, 587.33, 523.25, 493.88, 440.00, 392.00, 440.00, 493.88];
+#[rustfmt::skip]
+const TRACK2: [f64; 8] = [261.63, 196.00, 220.00, 164.81, 174.61, 130.81, 174.61, 196.00];
+
+// the root, the fifth and the octave
+const CHORD: [f64; 3] = [1.0, 1.5, 2.0];
+
+const BANDS: usize = 16;
+const NOISE_LEVEL: f64 = 0.1;
+const SEED: u64 = 1234;
+
+const ATTACK: Ms = Ms(20.0);
+const RELEASE: Ms = Ms(20.0);
+
+struct Track {
+    seq: Vec<f64>,
+    step_length: usize,
+    cur_frame: usize,
+    note: f64,
+}
+
+impl Track {
+    fn new(mut seq: Vec<f64>, step_length: Frames) -> Self {
+        let note = seq.pop().unwrap_or(0.0);
+        println!("note: {}", note);
+
+        Self {
+            seq,
+            step_length: step_length.0,
+            cur_frame: 0,
+            note,
+        }
+    }
+}
+
+impl Signal for Track {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        self.cur_frame += 1;
+
+        // proceed to the next step
+        if self.cur_frame > self.step_length {
+            self.cur_frame -= self.step_length;
+            self.note = self.seq.pop().unwrap_or(0.0);
+            println!("note: {}", self.note);
+        }
+
+        self.note
+    }
+}
+
+struct Carrier {
+    track: Track,
+    fs: f64, // sampling rate
+    saws: [polyblep::PolyBlepSaw; CHORD.len()],
+    noise: Noise,
+}
+
+impl Carrier {
+    fn new(track: Track, fs: f64) -> Self {
+        Self {
+            track,
+            fs,
+            saws: Default::default(),
+            noise: Noise::new(SEED),
+        }
+    }
+}
+
+impl Signal for Carrier {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let f0 = self.track.next();
+
+        let mut out = 0.0;
+        for (saw, ratio) in self.saws.iter_mut().zip(CHORD) {
+            saw.set_freq(self.fs, Hz(f0 * ratio));
+            out += saw.next_sample() / CHORD.len() as f64;
+        }
+
+        out + NOISE_LEVEL * self.noise.next_sample()
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let wav = match positional_args().into_iter().next() {
+        Some(path) => {
+            let wav = wav::read(&path)?;
+            println!("modulator: {path} ({} frames)", wav.len());
+            Some(wav)
+        }
+        None => None,
+    };
+
+    play(|config| {
+        let fs = config.sample_rate.0 as f64;
+
+        let step_length = Ms(1000.0).to_frames(fs);
+
+        let carrier = Carrier::new(Track::new(TRACK2.to_vec(), step_length), fs);
+
+        let modulator = match wav {
+            Some(wav) => {
+                if wav.fs != fs {
+                    eprintln!(
+                        "warning: the sampling rate of the modulator ({}) differs from the device's ({fs})",
+                        wav.fs
+                    );
+                }
+                wav.to_mono()
+            }
+            // rendered up front so that both are the same type
+            None => {
+                let env = Env::gated(
+                    SEQ.to_vec(),
+                    step_length,
+                    ATTACK.to_frames(fs),
+                    RELEASE.to_frames(fs),
+                );
+                let melody = PhaseAccumOsc::new(Track::new(TRACK1.to_vec(), step_length), fs);
+                melody
+                    .mul_amp(env)
+                    .take(step_length.0 * SEQ.len())
+                    .collect()
+            }
+        };
+        let len = modulator.len();
+
+        Vocoder::new(carrier, signal::from_iter(modulator), fs, BANDS)
+            .take(len)
+            // To prevent click noise at the end, fill some silence
+            .chain(signal::equilibrium().take(1000))
+    })
+}
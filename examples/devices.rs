@@ -0,0 +1,75 @@
+// Lists the hosts, the devices, and the stream configs they support, to find
+// the values for `--host` and `--require-rate`.
+
+use sound_programming_practice::runner::list_devices;
+
+fn format_rates(config: &cpal::SupportedStreamConfigRange) -> String {
+    let (min, max) = (config.min_sample_rate().0, config.max_sample_rate().0);
+    if min == max {
+        format!("{min} Hz")
+    } else {
+        format!("{min}-{max} Hz")
+    }
+}
+
+fn format_buffer_size(config: &cpal::SupportedStreamConfigRange) -> String {
+    match config.buffer_size() {
+        cpal::SupportedBufferSize::Range { min, max } => format!("{min}-{max} frames"),
+        cpal::SupportedBufferSize::Unknown => "unknown".to_string(),
+    }
+}
+
+fn main() {
+    for host in list_devices() {
+        println!("host: {}", host.id.name());
+
+        let devices = match host.devices {
+            Ok(devices) => devices,
+            Err(e) => {
+                println!("  (failed to list the devices: {e})\n");
+                continue;
+            }
+        };
+        if devices.is_empty() {
+            println!("  (no device)");
+        }
+
+        for device in devices {
+            let mut defaults = vec![];
+            if device.is_default_input {
+                defaults.push("default input");
+            }
+            if device.is_default_output {
+                defaults.push("default output");
+            }
+            if defaults.is_empty() {
+                println!("  device: {}", device.name);
+            } else {
+                println!("  device: {} ({})", device.name, defaults.join(", "));
+            }
+
+            if device.input_configs.is_empty() && device.output_configs.is_empty() {
+                println!("    (no supported config)");
+                continue;
+            }
+
+            println!(
+                "    {:<10}{:<10}{:<8}{:<18}buffer size",
+                "direction", "channels", "format", "sample rate"
+            );
+            let inputs = device.input_configs.iter().map(|c| ("input", c));
+            let outputs = device.output_configs.iter().map(|c| ("output", c));
+            for (direction, config) in inputs.chain(outputs) {
+                println!(
+                    "    {:<10}{:<10}{:<8}{:<18}{}",
+                    direction,
+                    config.channels(),
+                    format!("{:?}", config.sample_format()),
+                    format_rates(config),
+                    format_buffer_size(config)
+                );
+            }
+        }
+        println!();
+    }
+}
@@ -0,0 +1,212 @@
+// Usage: cargo run --example interactive-tui --features tui
+//
+// Plays a low-passed saw arpeggio with a live screen of the transport, the
+// level, and the parameters:
+//
+//   space    pause / resume (the output fades; the stream keeps running)
+//   + / -    the tempo
+//   arrows   the cutoff frequency of the low-pass
+//   p        the next preset
+//   q        fade out and quit
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::{biquad::Biquad, polyblep::PolyBlepSaw},
+    params::{AtomicF64, Clocked, FrameClock, Mute, ParamSet, PeakMeter, Smoothed},
+    runner::play,
+    tui::{layout, Action, Screen, Status, REFRESH},
+    units::{Hz, Ms},
+};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const Q: f64 = 2.0;
+const LEVEL: f64 = 0.3;
+
+// the time for the cutoff to glide to the new value
+const SMOOTHING: Ms = Ms(50.0);
+
+// the time for a note to decay by 60 dB
+const DECAY: Ms = Ms(300.0);
+
+const ROOT: Hz = Hz(110.0);
+// the notes of the arpeggio in semitones from the root, an eighth note each
+#[rustfmt::skip]
+const ARPEGGIO: [f64; 8] = [0.0, 3.0, 7.0, 10.0, 12.0, 10.0, 7.0, 3.0];
+
+// the name, the cutoff, and the BPM
+const PRESETS: [(&str, f64, f64); 3] = [
+    ("warm", 800.0, 100.0),
+    ("bright", 4000.0, 128.0),
+    ("dark", 300.0, 90.0),
+];
+
+// the steps of the keys
+const BPM_STEP: f64 = 2.0;
+const BPM_RANGE: (f64, f64) = (40.0, 240.0);
+const CUTOFF_RATIO: f64 = 1.122_462_048_309_373; // 2^(1/6), i.e. 2 semitones
+const CUTOFF_RANGE: (f64, f64) = (50.0, 20000.0);
+
+const BPM: &str = "bpm";
+const CUTOFF: &str = "cutoff";
+
+/// The state shared between the UI and the audio thread.
+struct Shared {
+    // bpm and cutoff
+    values: ParamSet,
+    preset: AtomicUsize,
+    clock: Arc<FrameClock>,
+    level: Arc<AtomicF64>,
+    fs: AtomicF64,
+    muted: Arc<AtomicBool>,
+    quit: AtomicBool,
+}
+
+impl Shared {
+    fn value(&self, name: &str) -> f64 {
+        self.values.get(name).map_or(0.0, |param| param.get())
+    }
+
+    fn load_preset(&self, index: usize) {
+        let (_, cutoff, bpm) = PRESETS[index];
+        self.preset.store(index, Ordering::Relaxed);
+        self.values.set(CUTOFF, cutoff);
+        self.values.set(BPM, bpm);
+    }
+
+    /// Applies the action, and returns false on `Quit`.
+    fn apply(&self, action: Action) -> bool {
+        let bpm = self.value(BPM);
+        let cutoff = self.value(CUTOFF);
+        match action {
+            Action::TogglePause => {
+                self.muted.fetch_xor(true, Ordering::Relaxed);
+            }
+            Action::BpmUp => {
+                self.values
+                    .set(BPM, (bpm + BPM_STEP).clamp(BPM_RANGE.0, BPM_RANGE.1));
+            }
+            Action::BpmDown => {
+                self.values
+                    .set(BPM, (bpm - BPM_STEP).clamp(BPM_RANGE.0, BPM_RANGE.1));
+            }
+            Action::CutoffUp => {
+                let cutoff = (cutoff * CUTOFF_RATIO).clamp(CUTOFF_RANGE.0, CUTOFF_RANGE.1);
+                self.values.set(CUTOFF, cutoff);
+            }
+            Action::CutoffDown => {
+                let cutoff = (cutoff / CUTOFF_RATIO).clamp(CUTOFF_RANGE.0, CUTOFF_RANGE.1);
+                self.values.set(CUTOFF, cutoff);
+            }
+            Action::NextPreset => {
+                self.load_preset((self.preset.load(Ordering::Relaxed) + 1) % PRESETS.len());
+            }
+            Action::Quit => {
+                // the audio thread ends after the fade-out
+                self.muted.store(true, Ordering::Relaxed);
+                self.quit.store(true, Ordering::Relaxed);
+                return false;
+            }
+        }
+        true
+    }
+
+    fn status(&self) -> Status {
+        let fs = self.fs.get();
+        let paused = self.muted.load(Ordering::Relaxed);
+        Status {
+            position: if fs > 0.0 {
+                self.clock.now() as f64 / fs
+            } else {
+                0.0
+            },
+            paused,
+            bpm: self.value(BPM),
+            cutoff: Hz(self.value(CUTOFF)),
+            preset: PRESETS[self.preset.load(Ordering::Relaxed)].0.to_string(),
+            level: if paused { 0.0 } else { self.level.get() },
+        }
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let mut values = ParamSet::new();
+    let bpm_param = values.add(BPM, 0.0);
+    let cutoff_param = values.add(CUTOFF, 0.0);
+    let shared = Arc::new(Shared {
+        values,
+        preset: AtomicUsize::new(0),
+        clock: Arc::new(FrameClock::new()),
+        level: Arc::new(AtomicF64::new(0.0)),
+        fs: AtomicF64::new(0.0),
+        muted: Arc::new(AtomicBool::new(false)),
+        quit: AtomicBool::new(false),
+    });
+    shared.load_preset(0);
+
+    let audio_shared = shared.clone();
+    let audio = std::thread::spawn(move || {
+        let shared = audio_shared;
+        play(move |config| {
+            let fs = config.sample_rate.0 as f64;
+            shared.fs.set(fs);
+
+            let mut saw = PolyBlepSaw::new();
+            let mut cutoff = Smoothed::new(cutoff_param.clone(), fs, SMOOTHING);
+            let mut lpf = Biquad::low_pass(fs, Hz(cutoff.value().min(fs * 0.45)), Q);
+
+            // the position in the current step (0.0 to 1.0), and the step
+            let mut phase = 0.0;
+            let mut step = 0;
+            let mut amp = 1.0;
+            let decay = 0.001_f64.powf(1.0 / DECAY.to_frames(fs).0 as f64);
+            saw.set_freq(fs, ROOT);
+
+            let synth = signal::gen_mut(move || {
+                // an eighth note is the half of a beat
+                phase += 2.0 * bpm_param.get() / 60.0 / fs;
+                if phase >= 1.0 {
+                    phase -= 1.0;
+                    step = (step + 1) % ARPEGGIO.len();
+                    saw.set_freq(fs, Hz(ROOT.0 * 2.0_f64.powf(ARPEGGIO[step] / 12.0)));
+                    amp = 1.0;
+                }
+                amp *= decay;
+
+                // recomputing the coefficients is costly, so only while gliding
+                if cutoff.is_gliding() {
+                    let cutoff = cutoff.next_value().min(fs * 0.45);
+                    lpf.set_coefficients(&Biquad::low_pass(fs, Hz(cutoff), Q));
+                }
+                LEVEL * amp * lpf.process(saw.next_sample())
+            });
+
+            // the meter is before the mute, so the screen hides it while paused
+            let synth = Clocked::new(
+                PeakMeter::new(synth, fs, shared.level.clone()),
+                shared.clock.clone(),
+            );
+            let mut synth = Mute::new(synth, fs, shared.muted.clone());
+            std::iter::from_fn(move || {
+                let quit = shared.quit.load(Ordering::Relaxed);
+                (!quit || !synth.is_silent()).then(|| synth.next())
+            })
+        })
+    });
+
+    {
+        let mut screen = Screen::new()?;
+        while !audio.is_finished() {
+            screen.draw(&layout(&shared.status(), screen.width()))?;
+            if let Some(action) = screen.poll_action(REFRESH)? {
+                if !shared.apply(action) {
+                    break;
+                }
+            }
+        }
+    }
+
+    audio
+        .join()
+        .map_err(|_| anyhow::anyhow!("the audio thread panicked"))?
+}
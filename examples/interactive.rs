@@ -0,0 +1,212 @@
+// Usage: cargo run --example interactive [automation.txt]
+//
+// Plays a low-passed saw and reads commands from stdin to change it while
+// playing:
+//
+//   freq <Hz>     the pitch of the saw
+//   cutoff <Hz>   the cutoff frequency of the low-pass
+//   play / stop   start / stop the sound (the stream keeps running)
+//   snap          save the current freq and cutoff on the undo stack
+//   undo          restore the last saved freq and cutoff
+//   ab            swap the current freq and cutoff with the last saved ones
+//   record        start recording the changes of freq and cutoff
+//   save <path>   stop recording and save the automation to the file
+//   quit          end the stream
+//
+// With the argument, the saved automation is replayed from the start.
+
+use dasp::{signal, Signal};
+use sound_programming_practice::{
+    core::{biquad::Biquad, polyblep::PolyBlepSaw, smooth::SmoothedParam},
+    params::{Automation, Clocked, FrameClock, History, ParamSet, Replay},
+    runner::{play, positional_args},
+    units::{Hz, Ms},
+};
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+const LEVEL: f64 = 0.3;
+
+// the time for the parameters to glide to the new values
+const SMOOTHING: Ms = Ms(100.0);
+
+const FREQ: &str = "freq";
+const CUTOFF: &str = "cutoff";
+
+/// The parameters shared between the stdin thread and the audio thread.
+struct Params {
+    // freq and cutoff
+    values: Arc<ParamSet>,
+    clock: Arc<FrameClock>,
+    playing: AtomicBool,
+    quit: AtomicBool,
+}
+
+impl Params {
+    fn new() -> Self {
+        let mut values = ParamSet::new();
+        values.add(FREQ, 220.0);
+        values.add(CUTOFF, 2000.0);
+        Self {
+            values: Arc::new(values),
+            clock: Arc::new(FrameClock::new()),
+            playing: AtomicBool::new(true),
+            quit: AtomicBool::new(false),
+        }
+    }
+}
+
+/// The low-passed saw following the parameters.
+fn synth(fs: f64, params: Arc<Params>) -> impl Signal<Frame = f64> {
+    let mut saw = PolyBlepSaw::new();
+
+    // the parameters glide to avoid clicks
+    let mut freq = params.values.smoothed(FREQ, fs, SMOOTHING).unwrap();
+    let mut cutoff = params.values.smoothed(CUTOFF, fs, SMOOTHING).unwrap();
+    let mut gain = SmoothedParam::new(fs, SMOOTHING, 0.0);
+    let mut lpf = Biquad::low_pass(fs, Hz(cutoff.value().min(fs * 0.45)), Q);
+
+    signal::gen_mut(move || {
+        // recomputing the coefficients is costly, so only while gliding
+        if cutoff.is_gliding() {
+            let cutoff = cutoff.next_value().min(fs * 0.45);
+            lpf.set_coefficients(&Biquad::low_pass(fs, Hz(cutoff), Q));
+        }
+        let playing = params.playing.load(Ordering::Relaxed);
+        gain.set_target(if playing { 1.0 } else { 0.0 });
+
+        saw.set_freq(fs, Hz(freq.next_value()));
+        LEVEL * gain.next_value() * lpf.process(saw.next_sample())
+    })
+}
+
+/// Handles a line of the commands, and returns false on `quit`.
+fn handle(params: &Params, history: &mut History, line: &str) -> bool {
+    let mut words = line.split_whitespace();
+    let value = |words: &mut std::str::SplitWhitespace| -> Option<f64> {
+        words.next()?.parse().ok().filter(|v: &f64| *v > 0.0)
+    };
+
+    match words.next() {
+        Some(name @ (FREQ | CUTOFF)) => match value(&mut words) {
+            Some(v) => {
+                params.values.set(name, v);
+            }
+            None => eprintln!("usage: {name} <Hz>"),
+        },
+        Some("play") => params.playing.store(true, Ordering::Relaxed),
+        Some("stop") => params.playing.store(false, Ordering::Relaxed),
+        Some("snap") => {
+            history.capture(&params.values);
+            eprintln!("saved ({} on the stack)", history.len());
+        }
+        Some("undo") => {
+            let undone = history.undo(&params.values);
+            if !undone {
+                eprintln!("nothing to undo");
+            }
+        }
+        Some("ab") => {
+            let swapped = history.swap(&params.values);
+            if !swapped {
+                eprintln!("nothing to compare with (use snap first)");
+            }
+        }
+        Some("record") => params.values.start_recording(params.clock.clone()),
+        Some("save") => match words.next() {
+            Some(path) => {
+                let automation = params.values.stop_recording();
+                match automation.save(path) {
+                    Ok(()) => eprintln!("saved {} changes", automation.events().len()),
+                    Err(e) => eprintln!("failed to save: {e}"),
+                }
+            }
+            None => eprintln!("usage: save <path>"),
+        },
+        Some("quit") => return false,
+        Some(cmd) => eprintln!(
+            "unknown command: {cmd} \
+             (freq, cutoff, play, stop, snap, undo, ab, record, save, or quit)"
+        ),
+        None => {}
+    }
+    true
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let automation = match positional_args().first() {
+        Some(path) => Automation::load(path)?,
+        None => Automation::default(),
+    };
+
+    let params = Arc::new(Params::new());
+
+    let stdin_params = params.clone();
+    std::thread::spawn(move || {
+        let mut history = History::new();
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if !handle(&stdin_params, &mut history, &line) {
+                break;
+            }
+        }
+        // also quit on the end of stdin
+        stdin_params.quit.store(true, Ordering::Relaxed);
+    });
+
+    play(move |config| {
+        let fs = config.sample_rate.0 as f64;
+        let synth = synth(fs, params.clone());
+
+        // the clock counts the frames for recording
+        let mut synth = Clocked::new(
+            Replay::new(synth, params.values.clone(), automation),
+            params.clock.clone(),
+        );
+        std::iter::from_fn(move || (!params.quit.load(Ordering::Relaxed)).then(|| synth.next()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sound_programming_practice::analysis::detect_pitch;
+
+    const FS: f64 = 48000.0;
+
+    #[test]
+    fn freq_command_changes_the_pitch() {
+        let params = Arc::new(Params::new());
+        let mut history = History::new();
+        let mut synth = synth(FS, params.clone());
+        // the pitch after the glide
+        let mut pitch = || {
+            let samples: Vec<f64> = (0..FS as usize / 2).map(|_| synth.next()).collect();
+            detect_pitch(&samples[samples.len() - 4096..], FS)
+                .unwrap()
+                .frequency
+                .0
+        };
+
+        assert!((pitch() - 220.0).abs() < 1.0);
+        assert!(handle(&params, &mut history, "freq 880"));
+        assert!((pitch() - 880.0).abs() < 4.0);
+        // invalid values are ignored
+        assert!(handle(&params, &mut history, "freq -1"));
+        assert_eq!(params.values.get(FREQ).unwrap().get(), 880.0);
+    }
+
+    #[test]
+    fn stop_and_quit_commands() {
+        let params = Arc::new(Params::new());
+        let mut history = History::new();
+        let mut synth = synth(FS, params.clone());
+
+        assert!(handle(&params, &mut history, "stop"));
+        let tail: Vec<f64> = (0..FS as usize).map(|_| synth.next()).collect();
+        assert!(tail[tail.len() - 100..].iter().all(|x| x.abs() < 1e-6));
+        assert!(!handle(&params, &mut history, "quit"));
+    }
+}
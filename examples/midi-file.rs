@@ -0,0 +1,88 @@
+// Usage: cargo run --example midi-file --features midi -- <file.mid>
+//
+// Plays the notes of a Standard MIDI File by a simple polyphonic saw synth.
+// All the channels sound the same (including the drums).
+
+use sound_programming_practice::{
+    core::multiosc::Waveform,
+    midi::{self, Note},
+    poly::{PolySynth, TimedEvent},
+    runner::{play, positional_args},
+    units::{Frames, Ms},
+};
+
+const ATTACK: Ms = Ms(5.0);
+const RELEASE: Ms = Ms(80.0);
+const LEVEL: f64 = 0.15;
+const VOICES: usize = 32;
+const BLOCK: usize = 512;
+
+/// Renders the notes block by block, each note starting and ending at its
+/// exact frame within the block.
+struct Synth {
+    notes: Vec<Note>,
+    synth: PolySynth,
+    events: Vec<TimedEvent>,
+    block: Vec<[f64; 2]>,
+    block_pos: usize,
+    cur_frame: usize,
+    end: usize,
+}
+
+impl Synth {
+    fn new(notes: Vec<Note>, fs: f64) -> Self {
+        let end = notes
+            .iter()
+            .map(|n| n.start.0 + n.length.0)
+            .max()
+            .unwrap_or(0)
+            + RELEASE.to_frames(fs).0;
+        Self {
+            notes,
+            synth: PolySynth::new(fs, Waveform::Saw, VOICES).with_envelope(ATTACK, RELEASE),
+            events: vec![],
+            block: vec![[0.0; 2]; BLOCK],
+            block_pos: BLOCK,
+            cur_frame: 0,
+            end,
+        }
+    }
+}
+
+impl Iterator for Synth {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.block_pos == BLOCK {
+            if self.cur_frame >= self.end {
+                return None;
+            }
+            let (from, to) = (Frames(self.cur_frame), Frames(self.cur_frame + BLOCK));
+            midi::note_events(&self.notes, from, to, &mut self.events);
+            self.synth.render_block(&mut self.block, &self.events);
+            self.block_pos = 0;
+            self.cur_frame += BLOCK;
+        }
+        // the voices are centered, so either channel is the mono mix
+        let [x, _] = self.block[self.block_pos];
+        self.block_pos += 1;
+        Some(LEVEL * std::f64::consts::SQRT_2 * x)
+    }
+}
+
+fn main() -> Result<(), anyhow::Error> {
+    let args = positional_args();
+    let path = args
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("usage: midi-file <file.mid>"))?;
+    // read once to report the errors before opening the stream
+    let notes = midi::read(path, 48000.0)?;
+    println!("notes: {}", notes.len());
+
+    let path = path.clone();
+    play(move |config| {
+        let fs = config.sample_rate.0 as f64;
+        let notes = midi::read(&path, fs).expect("the file should have been read once");
+        Synth::new(notes, fs).chain(std::iter::repeat_n(0.0, 1000))
+    })
+}
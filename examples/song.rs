@@ -0,0 +1,96 @@
+// Usage: cargo run --example song --features song -- [options] <song.toml>
+//
+// Plays a song file (see `songs/sweep.toml` and the module `song` for the
+// format): the tracks of patterns, with their parameters swept by the
+// automation lanes.
+//
+// With `--features live-reload`, the edits of the file are heard while
+// playing, from the next bar.
+//
+// With `--render <out.wav>`, the song is rendered offline at 48 kHz instead,
+// on the number of the threads of `--threads` (1 by default), each track on
+// its own thread.
+
+use dasp::Signal;
+use sound_programming_practice::{
+    runner::{play_stereo, positional_args},
+    song::{self, Song, SongPlayer},
+    wav::{self, Wav},
+};
+use std::sync::Arc;
+
+// the sampling rate of the offline rendering
+const RENDER_RATE: f64 = 48000.0;
+
+fn main() -> Result<(), anyhow::Error> {
+    // the options can come before or after the path
+    let mut path = None;
+    let mut render = None;
+    let mut threads = 1;
+    let mut args = positional_args().into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--render" => render = Some(option_value(&arg, &mut args)?),
+            "--threads" => threads = option_value(&arg, &mut args)?.parse()?,
+            _ if path.is_none() && !arg.starts_with("--") => path = Some(arg),
+            _ => {}
+        }
+    }
+    let path = path.ok_or_else(|| anyhow::anyhow!("usage: song <song.toml>"))?;
+    // read before opening the stream to report the errors
+    let song = Song::load(&path)?;
+    println!(
+        "{} bars at {} BPM, {} tracks, {} automation lanes",
+        song.bars,
+        song.bpm,
+        song.tracks.len(),
+        song.automation.len()
+    );
+
+    if let Some(out) = render {
+        let rendered = song::render(&song, RENDER_RATE, threads)?;
+        println!(
+            "rendered in {:.2} s on {} threads ({:.1}x)",
+            rendered.wall_time.as_secs_f64(),
+            rendered.threads,
+            rendered.speedup()
+        );
+        let wav = Wav {
+            fs: RENDER_RATE,
+            channels: 2,
+            samples: rendered.frames.concat(),
+            loop_points: None,
+            bext: None,
+        };
+        wav::write(&out, &wav)?;
+        println!("wrote {out}");
+        return Ok(());
+    }
+
+    // kept until the end of the song
+    #[cfg(feature = "live-reload")]
+    let mut _watcher = None;
+    play_stereo(|config| {
+        let fs = config.sample_rate.0 as f64;
+        let params = Arc::new(song.params());
+        let player =
+            SongPlayer::new(&song, fs, &params).expect("the song should have been validated");
+
+        #[cfg(feature = "live-reload")]
+        match song::watch(player.reloader(&path, song.clone(), params)) {
+            Ok(watcher) => _watcher = Some(watcher),
+            Err(e) => eprintln!("failed to watch the file: {e}"),
+        }
+
+        player.until_exhausted()
+    })
+}
+
+// the value following the option
+fn option_value(
+    name: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<String, anyhow::Error> {
+    args.next()
+        .ok_or_else(|| anyhow::anyhow!("{name} needs a value"))
+}
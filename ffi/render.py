@@ -0,0 +1,53 @@
+# Renders a few notes through the C API with ctypes. Build the library first:
+#
+#   cargo rustc --release --lib --crate-type cdylib --no-default-features --features ffi,song
+#
+# Usage: python3 ffi/render.py [path/to/libsound_programming_practice.so]
+
+import ctypes
+import sys
+
+SPP_OK = 0
+
+path = sys.argv[1] if len(sys.argv) > 1 else "target/release/libsound_programming_practice.so"
+lib = ctypes.CDLL(path)
+
+lib.spp_engine_new.argtypes = [ctypes.c_double]
+lib.spp_engine_new.restype = ctypes.c_uint32
+lib.spp_engine_free.argtypes = [ctypes.c_uint32]
+lib.spp_note_on.argtypes = [ctypes.c_uint32, ctypes.c_uint8, ctypes.c_uint8]
+lib.spp_note_off.argtypes = [ctypes.c_uint32, ctypes.c_uint8]
+lib.spp_set_param.argtypes = [ctypes.c_uint32, ctypes.c_char_p, ctypes.c_double]
+lib.spp_render.argtypes = [ctypes.c_uint32, ctypes.POINTER(ctypes.c_float), ctypes.c_size_t]
+for f in [lib.spp_engine_free, lib.spp_note_on, lib.spp_note_off, lib.spp_set_param, lib.spp_render]:
+    f.restype = ctypes.c_int32
+
+
+def check(code):
+    if code != SPP_OK:
+        raise RuntimeError(f"error code {code}")
+
+
+FS = 48000
+STEP = FS // 4
+
+engine = lib.spp_engine_new(FS)
+if engine == 0:
+    raise RuntimeError("failed to create the engine")
+
+check(lib.spp_set_param(engine, b"cutoff", 1200.0))
+
+buffer = (ctypes.c_float * STEP)()
+samples = []
+for key in [60, 64, 67, 72]:
+    check(lib.spp_note_on(engine, key, 100))
+    check(lib.spp_render(engine, buffer, STEP))
+    samples.extend(buffer)
+    check(lib.spp_note_off(engine, key))
+
+check(lib.spp_render(engine, buffer, STEP))
+samples.extend(buffer)
+check(lib.spp_engine_free(engine))
+
+# e.g. `IPython.display.Audio(samples, rate=FS)` in a notebook
+print(f"rendered {len(samples)} samples, peak {max(abs(x) for x in samples):.3f}")
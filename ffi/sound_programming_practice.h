@@ -0,0 +1,91 @@
+#ifndef SOUND_PROGRAMMING_PRACTICE_H
+#define SOUND_PROGRAMMING_PRACTICE_H
+
+/* Generated by cbindgen from src/ffi.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define SPP_OK 0
+
+/**
+ * A pointer argument is null.
+ */
+#define SPP_ERR_NULL -1
+
+/**
+ * The handle doesn't refer to a live engine.
+ */
+#define SPP_ERR_HANDLE -2
+
+/**
+ * A value is out of its range, a name is unknown, a string is not UTF-8, or
+ * a song is invalid.
+ */
+#define SPP_ERR_INVALID -3
+
+/**
+ * A bug: the function panicked.
+ */
+#define SPP_ERR_PANIC -4
+
+/**
+ * Creates an engine at the sampling rate, and returns its handle, or 0 if
+ * the rate is not positive.
+ */
+uint32_t spp_engine_new(double sample_rate);
+
+/**
+ * Frees the engine. The handle is invalid afterwards (until it's reused by
+ * `spp_engine_new()`).
+ */
+int32_t spp_engine_free(uint32_t handle);
+
+/**
+ * Starts a note of the MIDI key (0 to 127) and velocity (1 to 127). If
+ * another note is held, the pitch glides to the new key without
+ * retriggering.
+ */
+int32_t spp_note_on(uint32_t handle, uint8_t key, uint8_t velocity);
+
+/**
+ * Releases the key. If it's the one sounding, the pitch glides back to the
+ * previous key held, or the note ends if none is held.
+ */
+int32_t spp_note_off(uint32_t handle, uint8_t key);
+
+/**
+ * Sets a parameter by the name: `cutoff` (Hz), `q`, `volume` (gain),
+ * `attack`, `release`, and `glide` (ms), or one of the song loaded.
+ *
+ * # Safety
+ *
+ * `name` must be null or a NUL-terminated string.
+ */
+int32_t spp_set_param(uint32_t handle, const char *name, double value);
+
+/**
+ * Renders `frames` samples (mono) into `out`.
+ *
+ * # Safety
+ *
+ * `out` must be null or point to at least `frames` writable floats.
+ */
+int32_t spp_render(uint32_t handle, float *out, size_t frames);
+
+/**
+ * Loads a song (the TOML of a song file) to play from its start along with
+ * the voice, replacing the one loaded. After it ends, the engine renders
+ * only the voice. The parameters of the tracks (e.g. `bass.cutoff`) can be
+ * set by `spp_set_param()`. Only with the `song` feature.
+ *
+ * # Safety
+ *
+ * `toml` must be null or a NUL-terminated string.
+ */
+int32_t spp_load_song(uint32_t handle, const char *toml);
+
+#endif  /* SOUND_PROGRAMMING_PRACTICE_H */
@@ -0,0 +1,370 @@
+use crate::fft::{hann, Complex, Fft};
+use crate::units::{Frames, Hz, Ms};
+use std::collections::VecDeque;
+
+// the number of the past frames to average the novelty for the threshold
+const THRESHOLD_FRAMES: usize = 8;
+
+/// Detects onsets by the spectral flux, i.e. the sum of the increases of the
+/// magnitude of each bin from the previous STFT frame. A frame is an onset if
+/// its flux is a local maximum and exceeds `multiplier` times the average of
+/// the recent frames plus `delta`, so the threshold follows the level of the
+/// input.
+///
+/// The STFT uses the hop size of a quarter of the FFT size, which limits the
+/// time resolution of the detection.
+pub struct OnsetDetector {
+    fft: Fft,
+    window: Vec<f64>,
+    hop: usize,
+    input: Vec<f64>,
+    spectrum: Vec<Complex>,
+    prev_mag: Vec<f64>,
+    count: usize,
+    // the number of the frames pushed so far
+    pos: usize,
+    // the flux of the last two frames, and where the onset would be if the
+    // last one is an onset
+    prev_flux: f64,
+    cur_flux: f64,
+    cur_onset: usize,
+    history: VecDeque<f64>,
+    last_onset: Option<usize>,
+    min_interval: usize,
+    multiplier: f64,
+    delta: f64,
+}
+
+impl OnsetDetector {
+    /// `fft_size` must be a power of 2 and at least 4.
+    pub fn new(fs: f64, fft_size: usize) -> Self {
+        assert!(fft_size >= 4, "the FFT size must be at least 4");
+
+        Self {
+            fft: Fft::new(fft_size),
+            window: hann(fft_size),
+            hop: fft_size / 4,
+            input: vec![0.0; fft_size],
+            spectrum: vec![Complex::default(); fft_size],
+            prev_mag: vec![0.0; fft_size / 2 + 1],
+            count: 0,
+            pos: 0,
+            prev_flux: 0.0,
+            cur_flux: 0.0,
+            cur_onset: 0,
+            history: VecDeque::with_capacity(THRESHOLD_FRAMES),
+            last_onset: None,
+            min_interval: Ms(50.0).to_frames(fs).0,
+            multiplier: 1.5,
+            delta: 0.01,
+        }
+    }
+
+    /// The threshold is `multiplier` times the average flux of the recent
+    /// frames plus `delta`. The defaults are 1.5 and 0.01.
+    pub fn with_threshold(mut self, multiplier: f64, delta: f64) -> Self {
+        self.multiplier = multiplier;
+        self.delta = delta;
+        self
+    }
+
+    /// Onsets closer than this to the previous one are ignored. The default
+    /// is 50 ms.
+    pub fn with_min_interval(mut self, min_interval: Frames) -> Self {
+        self.min_interval = min_interval.0;
+        self
+    }
+
+    /// Pushes a sample, and returns the position of an onset (counted from
+    /// the first sample) if one is detected. The detection is delayed by
+    /// about the FFT size.
+    pub fn process(&mut self, x: f64) -> Option<Frames> {
+        let len = self.input.len();
+        self.input[len - self.hop + self.count] = x;
+        self.pos += 1;
+
+        self.count += 1;
+        if self.count < self.hop {
+            return None;
+        }
+        self.count = 0;
+
+        let flux = self.flux();
+        self.input.copy_within(self.hop.., 0);
+
+        // now that the next flux is known, the current one can be judged
+        let average = self.history.iter().sum::<f64>() / THRESHOLD_FRAMES as f64;
+        let is_peak = self.cur_flux > self.prev_flux && self.cur_flux >= flux;
+        let is_loud = self.cur_flux > self.multiplier * average + self.delta;
+        let is_apart = match self.last_onset {
+            Some(last) => self.cur_onset >= last + self.min_interval,
+            None => true,
+        };
+
+        let onset = if is_peak && is_loud && is_apart {
+            self.last_onset = Some(self.cur_onset);
+            Some(Frames(self.cur_onset))
+        } else {
+            None
+        };
+
+        if self.history.len() == THRESHOLD_FRAMES {
+            self.history.pop_front();
+        }
+        self.history.push_back(self.cur_flux);
+        self.prev_flux = self.cur_flux;
+        self.cur_flux = flux;
+        // The flux is the highest when the onset is slightly after the center
+        // of the frame, where the window rises the fastest. Report half a hop
+        // after the center, which is accurate within about half a hop.
+        self.cur_onset = (self.pos + self.hop / 2).saturating_sub(len / 2);
+
+        onset
+    }
+
+    fn flux(&mut self) -> f64 {
+        for ((c, &x), &w) in self.spectrum.iter_mut().zip(&self.input).zip(&self.window) {
+            *c = Complex::new(x * w, 0.0);
+        }
+        self.fft.forward(&mut self.spectrum);
+
+        // normalize so that a full-scale sine gives the magnitude of about 1
+        let scale = 4.0 / self.input.len() as f64;
+        let mut flux = 0.0;
+        for (prev, c) in self.prev_mag.iter_mut().zip(&self.spectrum) {
+            let mag = c.norm() * scale;
+            flux += (mag - *prev).max(0.0);
+            *prev = mag;
+        }
+        flux
+    }
+}
+
+/// Detects the onsets in `samples` with `OnsetDetector`.
+pub fn detect_onsets(samples: &[f64], fs: f64, fft_size: usize) -> Vec<Frames> {
+    let mut detector = OnsetDetector::new(fs, fft_size);
+    // pad with silence so that the onsets near the end are judged too
+    samples
+        .iter()
+        .chain(std::iter::repeat_n(&0.0, fft_size * 2))
+        .filter_map(|&x| detector.process(x))
+        .collect()
+}
+
+// the threshold of the cumulative mean normalized difference for YIN
+const YIN_THRESHOLD: f64 = 0.1;
+
+/// The result of `detect_pitch()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pitch {
+    pub frequency: Hz,
+    /// 0.0 (not periodic at all) to 1.0 (perfectly periodic)
+    pub confidence: f64,
+}
+
+/// Estimates the fundamental frequency of `samples` by the YIN algorithm.
+/// The lowest detectable frequency is `fs / (samples.len() / 2)`, so e.g.
+/// 2048 samples at 48 kHz can detect down to about 47 Hz.
+///
+/// Returns `None` if `samples` is too short or silent.
+///
+/// c.f. de Cheveigné, A., & Kawahara, H. (2002). YIN, a fundamental frequency
+/// estimator for speech and music.
+pub fn detect_pitch(samples: &[f64], fs: f64) -> Option<Pitch> {
+    let window = samples.len() / 2;
+    if window < 4 {
+        return None;
+    }
+
+    // the difference function, normalized by its cumulative mean
+    let mut cmndf = vec![1.0; window];
+    let mut cumsum = 0.0;
+    for tau in 1..window {
+        let d: f64 = samples[..window]
+            .iter()
+            .zip(&samples[tau..tau + window])
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum();
+        cumsum += d;
+        cmndf[tau] = if cumsum > 0.0 {
+            d * tau as f64 / cumsum
+        } else {
+            1.0
+        };
+    }
+    if cumsum == 0.0 {
+        return None;
+    }
+
+    // the first dip below the threshold, or the global minimum if none
+    let tau = match (2..window).find(|&tau| cmndf[tau] < YIN_THRESHOLD) {
+        Some(mut tau) => {
+            while tau + 1 < window && cmndf[tau + 1] < cmndf[tau] {
+                tau += 1;
+            }
+            tau
+        }
+        None => (2..window).min_by(|&a, &b| cmndf[a].total_cmp(&cmndf[b]))?,
+    };
+
+    // refine the period by the parabolic interpolation
+    let period = if tau + 1 < window {
+        let (a, b, c) = (cmndf[tau - 1], cmndf[tau], cmndf[tau + 1]);
+        let den = a - 2.0 * b + c;
+        if den > 0.0 {
+            tau as f64 + (a - c) / (2.0 * den)
+        } else {
+            tau as f64
+        }
+    } else {
+        tau as f64
+    };
+
+    Some(Pitch {
+        frequency: Hz(fs / period),
+        confidence: (1.0 - cmndf[tau]).clamp(0.0, 1.0),
+    })
+}
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// The name of the MIDI note number with the octave, e.g. "A4" for 69 and
+/// "C#5" for 73.
+pub fn note_name(note: i32) -> String {
+    let name = NOTE_NAMES[note.rem_euclid(12) as usize];
+    let octave = note.div_euclid(12) - 1;
+    format!("{name}{octave}")
+}
+
+/// The result of `tuner()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tuning {
+    /// The MIDI note number of the nearest note
+    pub note: i32,
+    /// The name of the nearest note, e.g. "A4"
+    pub name: String,
+    /// The deviation from the nearest note; positive means sharp
+    pub cents: f64,
+    pub pitch: Pitch,
+}
+
+/// Detects the pitch of `samples` and maps it to the nearest note in the
+/// equal temperament (A4 = 440 Hz). Returns `None` if no pitch is detected
+/// with enough confidence.
+pub fn tuner(samples: &[f64], fs: f64) -> Option<Tuning> {
+    let pitch = detect_pitch(samples, fs)?;
+    if pitch.confidence < 1.0 - YIN_THRESHOLD {
+        return None;
+    }
+
+    let midi_note = pitch.frequency.to_midi_note();
+    let note = midi_note.round() as i32;
+    Some(Tuning {
+        note,
+        name: note_name(note),
+        cents: (midi_note - note as f64) * 100.0,
+        pitch,
+    })
+}
+
+/// Runs `detect_pitch()` on a sliding window of a stream, every `hop`
+/// samples.
+pub struct PitchTracker {
+    fs: f64, // sampling rate
+    buf: Vec<f64>,
+    hop: usize,
+    count: usize,
+    latest: Option<Pitch>,
+}
+
+impl PitchTracker {
+    /// The lowest detectable frequency is `fs / (window / 2)`. The cost of
+    /// each detection is proportional to the square of `window`.
+    pub fn new(fs: f64, window: usize, hop: usize) -> Self {
+        Self {
+            fs,
+            buf: vec![0.0; window],
+            hop: hop.clamp(1, window),
+            count: 0,
+            latest: None,
+        }
+    }
+
+    /// Pushes a sample and returns the latest estimate.
+    pub fn process(&mut self, x: f64) -> Option<Pitch> {
+        let len = self.buf.len();
+        self.buf[len - self.hop + self.count] = x;
+
+        self.count += 1;
+        if self.count == self.hop {
+            self.count = 0;
+            self.latest = detect_pitch(&self.buf, self.fs);
+            self.buf.copy_within(self.hop.., 0);
+        }
+
+        self.latest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    #[test]
+    fn detects_clicks_of_a_click_train() {
+        // a short burst of a decaying 1 kHz sine every 0.25 s, from 0.1 s
+        let clicks = [4800, 16800, 28800, 40800];
+        let mut samples = vec![0.0; 48000];
+        for &start in &clicks {
+            for (i, x) in samples[start..start + 480].iter_mut().enumerate() {
+                let t = i as f64 / FS;
+                *x = (2.0 * std::f64::consts::PI * 1000.0 * t).sin() * (-t / 0.002).exp();
+            }
+        }
+
+        let onsets = detect_onsets(&samples, FS, 1024);
+        assert_eq!(onsets.len(), clicks.len(), "{onsets:?}");
+        for (onset, click) in onsets.iter().zip(clicks) {
+            // within half a hop
+            assert!(onset.0.abs_diff(click) <= 128, "{onset:?} for {click}");
+        }
+    }
+
+    fn sine(freq: f64, len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / FS).sin())
+            .collect()
+    }
+
+    #[test]
+    fn detects_pitch_of_sine_and_not_of_noise() {
+        let pitch = detect_pitch(&sine(440.0, 2048), FS).unwrap();
+        assert!((pitch.frequency.0 - 440.0).abs() < 1.0, "{pitch:?}");
+        assert!(pitch.confidence > 0.9, "{pitch:?}");
+
+        // white noise by a linear congruential generator
+        let mut state: u32 = 12345;
+        let noise: Vec<f64> = (0..2048)
+            .map(|_| {
+                state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                f64::from(state) / f64::from(u32::MAX) * 2.0 - 1.0
+            })
+            .collect();
+        let pitch = detect_pitch(&noise, FS).unwrap();
+        assert!(pitch.confidence < 0.5, "{pitch:?}");
+    }
+
+    #[test]
+    fn tuner_reports_slightly_sharp_a4() {
+        // 10 cents above A4
+        let freq = 440.0 * 2.0_f64.powf(10.0 / 1200.0);
+        let tuning = tuner(&sine(freq, 2048), FS).unwrap();
+        assert_eq!(tuning.name, "A4");
+        assert_eq!(tuning.note, 69);
+        assert!((tuning.cents - 10.0).abs() < 2.0, "{tuning:?}");
+    }
+}
@@ -0,0 +1,326 @@
+use crate::effects::{DelayLine, EarlyReflections};
+use crate::fft::{Complex, Fft};
+use crate::latency::Latency;
+use crate::stereo::MonoEffect;
+use crate::tail::HasTail;
+use crate::units::Ms;
+use dasp::Signal;
+
+/// A convolution engine using uniformly-partitioned overlap-save.
+///
+/// The impulse response is split into partitions of `block_size` frames, and
+/// each partition is convolved in the frequency domain with the spectrum of
+/// the corresponding past input block. Thus, the latency is `block_size`
+/// frames regardless of the length of the impulse response, which makes this
+/// suitable for real-time use with long impulse responses.
+pub struct Convolver {
+    block_size: usize,
+    fft: Fft,
+    // the spectra of the partitions of the impulse response
+    partitions: Vec<Vec<Complex>>,
+    // the spectra of the past input blocks (frequency-domain delay line)
+    spectra: Vec<Vec<Complex>>,
+    spectra_pos: usize,
+    // the last 2 blocks of the input
+    window: Vec<f64>,
+    // the sum of the products of the spectra, preallocated so that
+    // process_block() doesn't allocate
+    acc: Vec<Complex>,
+    // used by process()
+    block_in: Vec<f64>,
+    block_out: Vec<f64>,
+    pos: usize,
+}
+
+impl Convolver {
+    pub fn new(ir: &[f64], block_size: usize) -> Self {
+        assert!(
+            block_size.is_power_of_two(),
+            "the block size must be a power of 2"
+        );
+
+        let fft = Fft::new(block_size * 2);
+
+        let partitions: Vec<_> = if ir.is_empty() {
+            vec![fft.forward_real(&[])]
+        } else {
+            ir.chunks(block_size).map(|h| fft.forward_real(h)).collect()
+        };
+        let spectra = vec![vec![Complex::default(); block_size * 2]; partitions.len()];
+
+        Self {
+            block_size,
+            fft,
+            partitions,
+            spectra,
+            spectra_pos: 0,
+            window: vec![0.0; block_size * 2],
+            acc: vec![Complex::default(); block_size * 2],
+            block_in: vec![0.0; block_size],
+            block_out: vec![0.0; block_size],
+            pos: 0,
+        }
+    }
+
+    /// Chooses the block size from the sampling rate: the power of 2 closest
+    /// to 5 ms (e.g. 256 frames at 48 kHz). The cost per block is bounded by
+    /// the number of partitions, i.e. the length of the impulse response
+    /// divided by the block size.
+    pub fn from_ir(ir: &[f64], fs: f64) -> Self {
+        Self::new(ir, default_block_size(fs))
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Convolves one block; both `input` and `output` must have the length of
+    /// the block size. The output is not delayed.
+    pub fn process_block(&mut self, input: &[f64], output: &mut [f64]) {
+        let b = self.block_size;
+        assert_eq!(input.len(), b);
+        assert_eq!(output.len(), b);
+
+        // slide the window by one block
+        self.window.copy_within(b.., 0);
+        self.window[b..].copy_from_slice(input);
+
+        let p = self.partitions.len();
+        self.spectra_pos = (self.spectra_pos + 1) % p;
+        // the FFT of the window, in place of the oldest spectrum
+        let spectrum = &mut self.spectra[self.spectra_pos];
+        for (s, &x) in spectrum.iter_mut().zip(&self.window) {
+            *s = Complex::new(x, 0.0);
+        }
+        self.fft.forward(spectrum);
+
+        self.acc.fill(Complex::default());
+        for (k, h) in self.partitions.iter().enumerate() {
+            let x = &self.spectra[(self.spectra_pos + p - k) % p];
+            for ((a, &x), &h) in self.acc.iter_mut().zip(x).zip(h) {
+                *a += x * h;
+            }
+        }
+        self.fft.inverse(&mut self.acc);
+
+        // the first half is aliased by the circular convolution, so discard it
+        for (y, a) in output.iter_mut().zip(&self.acc[b..]) {
+            *y = a.re;
+        }
+    }
+
+    /// Convolves one sample. The output is delayed by the block size.
+    pub fn process(&mut self, x: f64) -> f64 {
+        let out = self.block_out[self.pos];
+        self.block_in[self.pos] = x;
+        self.pos += 1;
+
+        if self.pos == self.block_size {
+            let input = std::mem::take(&mut self.block_in);
+            let mut output = std::mem::take(&mut self.block_out);
+            self.process_block(&input, &mut output);
+            self.block_in = input;
+            self.block_out = output;
+            self.pos = 0;
+        }
+
+        out
+    }
+}
+
+impl MonoEffect for Convolver {
+    fn process(&mut self, x: f64) -> f64 {
+        Convolver::process(self, x)
+    }
+
+    fn latency(&self) -> usize {
+        self.block_size
+    }
+}
+
+/// Applies a `Convolver` to a `Signal`.
+pub struct Convolution<S: Signal<Frame = f64>> {
+    signal: S,
+    convolver: Convolver,
+    // the early reflections, and the delay of them by the latency
+    early: Option<(EarlyReflections, DelayLine)>,
+}
+
+impl<S: Signal<Frame = f64>> Convolution<S> {
+    pub fn new(signal: S, ir: &[f64], block_size: usize) -> Self {
+        Self {
+            signal,
+            convolver: Convolver::new(ir, block_size),
+            early: None,
+        }
+    }
+
+    /// See `Convolver::from_ir()`.
+    pub fn from_ir(signal: S, ir: &[f64], fs: f64) -> Self {
+        Self {
+            signal,
+            convolver: Convolver::from_ir(ir, fs),
+            early: None,
+        }
+    }
+
+    /// Adds the early reflections (each tap is (time, gain)) and the
+    /// pre-delay before the impulse response, as on `PlateReverb`, e.g. for a
+    /// synthetic impulse response which has neither. The reflections are
+    /// delayed by the latency too, so that they keep their times relative to
+    /// the tail.
+    pub fn with_early_reflections(mut self, fs: f64, pre_delay: Ms, taps: &[(Ms, f64)]) -> Self {
+        let early = EarlyReflections::new(fs, pre_delay).with_taps(taps);
+        let latency = self.convolver.latency();
+        self.early = Some((early, DelayLine::new(latency)));
+        self
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for Convolution<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        match &mut self.early {
+            Some((early, delay)) => {
+                let (reflections, x) = early.process(x);
+                let latency = self.convolver.latency();
+                delay.process(reflections, latency) + self.convolver.process(x)
+            }
+            None => self.convolver.process(x),
+        }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Latency for Convolution<S> {
+    fn latency_frames(&self) -> usize {
+        self.convolver.latency()
+    }
+}
+
+impl<S: Signal<Frame = f64>> HasTail for Convolution<S> {
+    fn has_tail(&self) -> bool {
+        true
+    }
+}
+
+/// The direct convolution in the time domain. This is slow, but handy as a
+/// reference.
+pub fn convolve(x: &[f64], h: &[f64]) -> Vec<f64> {
+    if x.is_empty() || h.is_empty() {
+        return Vec::new();
+    }
+
+    let mut y = vec![0.0; x.len() + h.len() - 1];
+    for (i, &x) in x.iter().enumerate() {
+        for (j, &h) in h.iter().enumerate() {
+            y[i + j] += x * h;
+        }
+    }
+    y
+}
+
+fn default_block_size(fs: f64) -> usize {
+    let frames = Ms(5.0).to_frames(fs).0.max(1);
+    let upper = frames.next_power_of_two();
+    let lower = upper / 2;
+    if lower > 0 && frames - lower < upper - frames {
+        lower
+    } else {
+        upper
+    }
+}
+
+/// A synthetic impulse response of a room: white noise decaying
+/// exponentially by 60 dB in `t60`. The energy is normalized to 1, so the
+/// level of the reverb is about the same as the dry signal.
+pub fn exponential_decay_ir(fs: f64, t60: Ms, seed: u64) -> Vec<f64> {
+    let len = t60.to_frames(fs).0;
+    // -60 dB = 10^-3 in the amplitude at the end
+    let rate = 3.0 * std::f64::consts::LN_10 / len.max(1) as f64;
+
+    let ir: Vec<f64> = dasp::signal::noise(seed)
+        .take(len)
+        .enumerate()
+        .map(|(n, x)| x * (-rate * n as f64).exp())
+        .collect();
+
+    let energy: f64 = ir.iter().map(|x| x * x).sum();
+    if energy > 0.0 {
+        let k = 1.0 / energy.sqrt();
+        ir.iter().map(|x| x * k).collect()
+    } else {
+        ir
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitioned_matches_direct_convolution() {
+        let x: Vec<f64> = dasp::signal::noise(1).take(2000).collect();
+        // longer than a partition, and not a multiple of it
+        let h: Vec<f64> = dasp::signal::noise(2).take(700).collect();
+        let expected = convolve(&x, &h);
+
+        for block_size in [1, 64, 256, 1024] {
+            let mut convolver = Convolver::new(&h, block_size);
+            let padded = x
+                .iter()
+                .chain(std::iter::repeat_n(&0.0, h.len() + block_size));
+            // the output is delayed by the block size
+            let y: Vec<f64> = padded
+                .map(|&x| convolver.process(x))
+                .skip(block_size)
+                .collect();
+            for (i, (y, e)) in y.iter().zip(&expected).enumerate() {
+                assert!(
+                    (y - e).abs() < 1e-9,
+                    "block {block_size}, frame {i}: {y} != {e}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unit_impulse_is_a_delayed_passthrough() {
+        let x: Vec<f64> = dasp::signal::noise(3).take(3000).collect();
+        let mut convolver = Convolver::from_ir(&[1.0], 48000.0);
+        let latency = convolver.latency();
+        let y: Vec<f64> = x
+            .iter()
+            .chain(std::iter::repeat_n(&0.0, latency))
+            .map(|&x| convolver.process(x))
+            .collect();
+        assert!(y[..latency].iter().all(|&y| y == 0.0));
+        for (i, (y, x)) in y[latency..].iter().zip(&x).enumerate() {
+            assert!((y - x).abs() < 1e-9, "frame {i}: {y} != {x}");
+        }
+    }
+
+    #[test]
+    fn early_reflections_precede_the_tail() {
+        let fs = 48000.0;
+        let impulse = dasp::signal::from_iter(std::iter::once(1.0).chain(std::iter::repeat(0.0)));
+        let taps = [(Ms(7.0), 0.5), (Ms(13.0), 0.3)];
+        let convolution =
+            Convolution::new(impulse, &[1.0], 64).with_early_reflections(fs, Ms(20.0), &taps);
+        let y: Vec<f64> = convolution.take(2000).collect();
+
+        // all delayed by the latency
+        let at = |time: Ms| time.to_frames(fs).0 + 64;
+        for (i, y) in y.iter().enumerate() {
+            let expected = match i {
+                _ if i == at(Ms(7.0)) => 0.5,
+                _ if i == at(Ms(13.0)) => 0.3,
+                // the impulse response after the pre-delay
+                _ if i == at(Ms(20.0)) => 1.0,
+                _ => 0.0,
+            };
+            assert!((y - expected).abs() < 1e-9, "frame {i}: {y}");
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use super::math;
+use crate::units::{Db, Hz};
+
+/// A biquad filter in the direct form I. The coefficients are normalized so
+/// that `a0` is 1.
+///
+/// c.f. https://webaudio.github.io/Audio-EQ-Cookbook/audio-eq-cookbook.html
+pub struct Biquad {
+    b0: f64,
+    b1: f64,
+    b2: f64,
+    a1: f64,
+    a2: f64,
+    // x1, y1: input and output of 1-step before
+    // x2, y2: input and output of 2-step before
+    x1: f64,
+    x2: f64,
+    y1: f64,
+    y2: f64,
+}
+
+impl Biquad {
+    pub fn new(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    /// Takes the coefficients of `other` while keeping the state, so that the
+    /// filter can be modulated without resetting it (and clicking).
+    pub fn set_coefficients(&mut self, other: &Biquad) {
+        self.b0 = other.b0;
+        self.b1 = other.b1;
+        self.b2 = other.b2;
+        self.a1 = other.a1;
+        self.a2 = other.a2;
+    }
+
+    pub fn low_pass(fs: f64, fc: Hz, q: f64) -> Self {
+        let omega0 = 2.0 * core::f64::consts::PI * fc.normalized(fs);
+        let cos = math::cos(omega0);
+        let alpha = math::sin(omega0) / 2.0 / q;
+
+        Self::new(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn high_pass(fs: f64, fc: Hz, q: f64) -> Self {
+        let omega0 = 2.0 * core::f64::consts::PI * fc.normalized(fs);
+        let cos = math::cos(omega0);
+        let alpha = math::sin(omega0) / 2.0 / q;
+
+        Self::new(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    /// The band-pass with the constant peak gain of 0 dB.
+    pub fn band_pass(fs: f64, fc: Hz, q: f64) -> Self {
+        let omega0 = 2.0 * core::f64::consts::PI * fc.normalized(fs);
+        let cos = math::cos(omega0);
+        let alpha = math::sin(omega0) / 2.0 / q;
+
+        Self::new(alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// Raw-unit convenience form of `low_pass()`, taking `fc` as a bare
+    /// `f64` in hertz. Prefer `low_pass()`.
+    pub fn low_pass_raw(fs: f64, fc: f64, q: f64) -> Self {
+        Self::low_pass(fs, Hz(fc), q)
+    }
+
+    /// Raw-unit convenience form of `high_pass()`. Prefer `high_pass()`.
+    pub fn high_pass_raw(fs: f64, fc: f64, q: f64) -> Self {
+        Self::high_pass(fs, Hz(fc), q)
+    }
+
+    /// Raw-unit convenience form of `band_pass()`. Prefer `band_pass()`.
+    pub fn band_pass_raw(fs: f64, fc: f64, q: f64) -> Self {
+        Self::band_pass(fs, Hz(fc), q)
+    }
+
+    /// The high shelf, which boosts (or cuts) above `fc` by `gain`. The ones
+    /// with `gain` and `-gain` (and the same `fc` and `q`) cancel each other.
+    pub fn high_shelf(fs: f64, fc: Hz, q: f64, gain: Db) -> Self {
+        let a = math::powf(10.0, gain.0 / 40.0);
+        let omega0 = 2.0 * core::f64::consts::PI * fc.normalized(fs);
+        let cos = math::cos(omega0);
+        let alpha = math::sin(omega0) / 2.0 / q;
+        let k = 2.0 * math::sqrt(a) * alpha;
+
+        Self::new(
+            a * ((a + 1.0) + (a - 1.0) * cos + k),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - k),
+            (a + 1.0) - (a - 1.0) * cos + k,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - k,
+        )
+    }
+
+    // the normalized coefficients `[b0, b1, b2, a1, a2]`, to test other
+    // implementations against this
+    #[cfg(test)]
+    pub(crate) fn coefficients(&self) -> [f64; 5] {
+        [self.b0, self.b1, self.b2, self.a1, self.a2]
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+
+        y
+    }
+}
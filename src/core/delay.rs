@@ -0,0 +1,37 @@
+/// A delay line of a fixed capacity `N`, which doesn't need an allocator. The
+/// actual delay can be shorter than the capacity.
+pub struct FixedDelayLine<const N: usize> {
+    buf: [f64; N],
+    len: usize,
+    pos: usize,
+}
+
+impl<const N: usize> FixedDelayLine<N> {
+    /// Panics if `delay` is 0 or larger than `N`.
+    pub fn new(delay: usize) -> Self {
+        assert!(delay > 0 && delay <= N, "delay must be within 1..={N}");
+        Self {
+            buf: [0.0; N],
+            len: delay,
+            pos: 0,
+        }
+    }
+
+    pub fn delay(&self) -> usize {
+        self.len
+    }
+
+    /// The sample that the next `process()` returns, i.e. the one pushed
+    /// `delay` frames before.
+    pub fn delayed(&self) -> f64 {
+        self.buf[self.pos]
+    }
+
+    /// Pushes `x` and returns the sample pushed `delay` frames before.
+    pub fn process(&mut self, x: f64) -> f64 {
+        let out = self.buf[self.pos];
+        self.buf[self.pos] = x;
+        self.pos = (self.pos + 1) % self.len;
+        out
+    }
+}
@@ -0,0 +1,113 @@
+use super::math;
+use super::noise::Noise;
+use crate::units::Hz;
+
+// the bound in the standard deviations of the walk; the walk is limited at
+// this, which it reaches only rarely
+const BOUND_SIGMAS: f64 = 3.0;
+
+/// A slow random walk in cents, emulating the pitch instability of analog
+/// oscillators. Give each voice its own seed, so that the voices drift
+/// independently (which is what makes a chord sound alive).
+///
+/// This is a white noise through a one-pole low-pass (an Ornstein-Uhlenbeck
+/// process), so the correlation between two moments decays by
+/// `exp(-2π rate t)`: `rate` is how fast it wanders. The standard deviation is
+/// a third of `depth`, and the walk is limited to ± `depth`. With the depth of
+/// 0, it's exactly 0.
+pub struct Drift {
+    noise: Noise,
+    coef: f64,
+    // the gain of the noise for the standard deviation of 1
+    input_gain: f64,
+    depth: f64,
+    state: f64,
+}
+
+impl Drift {
+    /// `depth` is in cents.
+    pub fn new(fs: f64, rate: Hz, depth: f64, seed: u64) -> Self {
+        let coef = math::exp(-2.0 * core::f64::consts::PI * rate.normalized(fs));
+        // the variance of the uniform noise is 1/3
+        let input_gain = math::sqrt(3.0 * (1.0 - coef * coef));
+
+        let mut noise = Noise::new(seed);
+        // start from a random point of the walk rather than from the center
+        let state = BOUND_SIGMAS * noise.next_sample() / 2.0;
+        Self {
+            noise,
+            coef,
+            input_gain,
+            depth,
+            state,
+        }
+    }
+
+    /// Proceeds a frame and returns the deviation in cents.
+    pub fn next_cents(&mut self) -> f64 {
+        self.state = self.coef * self.state + self.input_gain * self.noise.next_sample();
+        let limited = (self.state / BOUND_SIGMAS).clamp(-1.0, 1.0);
+        self.depth * limited
+    }
+
+    /// Proceeds a frame and returns the ratio to multiply a frequency by.
+    pub fn next_ratio(&mut self) -> f64 {
+        math::powf(2.0, self.next_cents() / 1200.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // slow enough a rate for the time constant to be many frames
+    const FS: f64 = 1000.0;
+    const RATE: Hz = Hz(2.0);
+
+    #[test]
+    fn stays_within_the_depth() {
+        let mut drift = Drift::new(FS, RATE, 8.0, 1);
+        let mut peak: f64 = 0.0;
+        for _ in 0..1_000_000 {
+            let cents = drift.next_cents();
+            assert!(cents.abs() <= 8.0, "{cents}");
+            peak = peak.max(cents.abs());
+        }
+        // and wanders across most of it
+        assert!(peak > 4.0, "{peak}");
+    }
+
+    #[test]
+    fn depth_of_zero_is_still() {
+        let mut drift = Drift::new(FS, RATE, 0.0, 1);
+        for _ in 0..10_000 {
+            assert_eq!(drift.next_ratio(), 1.0);
+        }
+    }
+
+    #[test]
+    fn time_constant_follows_the_rate() {
+        // the autocorrelation at the lag of the time constant, from two walks
+        // of the same seed, one ahead of the other
+        let tau = FS / (2.0 * core::f64::consts::PI * RATE.0);
+        let lag = tau.round() as usize;
+        let mut drift = Drift::new(FS, RATE, 8.0, 7);
+        let mut ahead = Drift::new(FS, RATE, 8.0, 7);
+        for _ in 0..lag {
+            ahead.next_cents();
+        }
+        let (mut product, mut power) = (0.0, 0.0);
+        for _ in 0..1_000_000 {
+            let x = drift.next_cents();
+            product += x * ahead.next_cents();
+            power += x * x;
+        }
+        let correlation = product / power;
+        // exp(-lag / measured) = correlation
+        let measured = -(lag as f64) / (math::log2(correlation) * core::f64::consts::LN_2);
+        assert!(
+            measured > tau / 2.0 && measured < tau * 2.0,
+            "{measured} frames vs {tau}"
+        );
+    }
+}
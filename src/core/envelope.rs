@@ -0,0 +1,119 @@
+use super::math;
+use crate::units::{Frames, Ms};
+
+/// An envelope consisting of attack, sustain, and release phases. The length
+/// of a note is fixed, so the release phase starts `release_frames` before the
+/// end of the note.
+///
+/// This is the state machine of the forms in `crate::envelope` (with std):
+///
+/// - `Env::finite()`: an `Iterator` that plays a single note and terminates
+/// - `Env::scheduled()`: a `Signal` that plays a note from the start of each
+///   step
+///
+/// and `Env::gated()` plays the same notes by a gate (`Env::triggered()`).
+///
+/// Without std, call `next_level()` directly.
+pub struct Env {
+    cur_frame: usize,
+    note_length: usize,
+    attack_frames: usize,
+    release_frames: usize,
+}
+
+impl Env {
+    pub fn new(note_length: Frames, attack_frames: Frames, release_frames: Frames) -> Self {
+        Self {
+            cur_frame: 0,
+            note_length: note_length.0,
+            attack_frames: attack_frames.0,
+            release_frames: release_frames.0,
+        }
+    }
+
+    /// Raw-unit convenience form of `new()`, taking the lengths as bare
+    /// numbers of frames. Prefer `new()`.
+    pub fn new_raw(note_length: usize, attack_frames: usize, release_frames: usize) -> Self {
+        Self::new(
+            Frames(note_length),
+            Frames(attack_frames),
+            Frames(release_frames),
+        )
+    }
+
+    /// Changes the length of the note, e.g. before retriggering it for a
+    /// note of another length.
+    pub fn set_note_length(&mut self, note_length: Frames) {
+        self.note_length = note_length.0;
+    }
+
+    /// Restarts the note from the beginning of the attack phase.
+    pub fn retrigger(&mut self) {
+        self.cur_frame = 0;
+    }
+
+    /// Returns the level of the next frame, or `None` if the note has ended.
+    pub fn next_level(&mut self) -> Option<f64> {
+        self.cur_frame += 1;
+
+        // already ended
+        if self.cur_frame > self.note_length {
+            return None;
+        }
+
+        // release phase
+        if self.cur_frame > self.note_length.saturating_sub(self.release_frames) {
+            return Some((self.note_length - self.cur_frame) as f64 / self.release_frames as f64);
+        }
+
+        // attack phase
+        if self.cur_frame <= self.attack_frames {
+            return Some(self.cur_frame as f64 / self.attack_frames as f64);
+        }
+
+        // sustain phase
+        Some(1.0)
+    }
+}
+
+/// Follows the level of a signal with separate attack and release times, by
+/// a one-pole low-pass whose coefficient depends on whether the input is
+/// rising or falling. The input should be a level (e.g. the absolute value
+/// or the square of the signal), not the signal itself.
+pub struct EnvelopeFollower {
+    attack: f64,
+    release: f64,
+    level: f64,
+}
+
+impl EnvelopeFollower {
+    /// The time constants are the times to reach about 63% of a step.
+    pub fn new(fs: f64, attack: Ms, release: Ms) -> Self {
+        Self {
+            attack: one_pole_coef(fs, attack),
+            release: one_pole_coef(fs, release),
+            level: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let coef = if x > self.level {
+            self.attack
+        } else {
+            self.release
+        };
+        self.level = x + coef * (self.level - x);
+        self.level
+    }
+}
+
+/// The coefficient of a one-pole low-pass with the time constant `time`; 0.0
+/// (no smoothing) if `time` is 0.
+fn one_pole_coef(fs: f64, time: Ms) -> f64 {
+    let frames = time.0 * fs / 1000.0;
+    if frames > 0.0 {
+        math::exp(-1.0 / frames)
+    } else {
+        0.0
+    }
+}
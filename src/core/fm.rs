@@ -0,0 +1,307 @@
+use super::math;
+use super::phasor::Phasor;
+use super::smooth::{SmoothedParam, SMOOTHING};
+use crate::units::Hz;
+
+/// The maximum of the feedback, in radians of the phase per unit of the
+/// output. Around this, the feedback gives a saw-like tone; beyond it, the
+/// tone gets noisy and then chaotic.
+pub const MAX_FEEDBACK: f64 = 1.5;
+
+/// A sine operator of FM (phase modulation, as on the DX synths), whose
+/// previous output can be fed back to its own phase. The feedback turns the
+/// sine into a saw-like tone as it increases.
+///
+/// By default, the feedback is the average of the last two outputs, which
+/// keeps it from oscillating by itself at the Nyquist frequency on the high
+/// settings (the standard trick of the DX synths).
+pub struct Operator {
+    phasor: Phasor,
+    feedback: f64,
+    averaged: bool,
+    // the last two outputs
+    prev: [f64; 2],
+}
+
+impl Operator {
+    pub fn new() -> Self {
+        Self {
+            phasor: Phasor::new(),
+            feedback: 0.0,
+            averaged: true,
+            prev: [0.0; 2],
+        }
+    }
+
+    pub fn set_freq(&mut self, fs: f64, freq: Hz) {
+        self.phasor.set_freq(fs, freq);
+    }
+
+    /// Sets the feedback, which is clamped to 0 to `MAX_FEEDBACK`. 0 gives
+    /// the plain sine. This takes effect at once; `TwoOp::set_feedback()`
+    /// glides.
+    pub fn set_feedback(&mut self, feedback: f64) {
+        self.feedback = feedback.clamp(0.0, MAX_FEEDBACK);
+    }
+
+    /// Feeds back the last output only instead of the average of the last
+    /// two, which sounds brighter but gets unstable sooner.
+    pub fn set_averaged(&mut self, averaged: bool) {
+        self.averaged = averaged;
+    }
+
+    /// Returns the current sample with the phase modulated by `modulation`
+    /// (in radians, e.g. the output of another operator scaled by the index),
+    /// and advances the phase.
+    pub fn process(&mut self, modulation: f64) -> f64 {
+        let fed_back = if self.averaged {
+            (self.prev[0] + self.prev[1]) / 2.0
+        } else {
+            self.prev[0]
+        };
+        let phase = 2.0 * core::f64::consts::PI * self.phasor.phase();
+        let y = math::sin(phase + modulation + self.feedback * fed_back);
+        self.phasor.advance();
+
+        self.prev = [y, self.prev[0]];
+        y
+    }
+}
+
+impl Default for Operator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The frequency of a modulator.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModFreq {
+    /// The ratio to the frequency of the note; integers give harmonic tones,
+    /// and the others inharmonic ones like bells.
+    Ratio(f64),
+    /// A fixed frequency regardless of the note, like the drums of the DX
+    /// synths.
+    Fixed(Hz),
+}
+
+/// The parameter locks of a step of a sequence: the parameters to change on
+/// the step. `None` keeps the current value.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Lock {
+    pub mod_freq: Option<ModFreq>,
+    pub index: Option<f64>,
+}
+
+/// A voice of two operators, a modulator modulating the phase of a carrier
+/// at the frequency of the note.
+///
+/// Changing the parameters keeps the phases of the operators continuous, so
+/// that it doesn't click. The index and the feedback glide to the new values
+/// over 20 ms, while the frequencies change at once; change them at the start
+/// of a note (e.g. by `apply()` on each step).
+pub struct TwoOp {
+    fs: f64, // sampling rate
+    carrier: Operator,
+    modulator: Operator,
+    note: Hz,
+    mod_freq: ModFreq,
+    // the peak deviation of the phase of the carrier, in radians
+    index: SmoothedParam,
+    feedback: SmoothedParam,
+}
+
+impl TwoOp {
+    pub fn new(fs: f64, note: Hz, mod_freq: ModFreq, index: f64) -> Self {
+        let mut voice = Self {
+            fs,
+            carrier: Operator::new(),
+            modulator: Operator::new(),
+            note,
+            mod_freq,
+            index: SmoothedParam::new(fs, SMOOTHING, index),
+            feedback: SmoothedParam::new(fs, SMOOTHING, 0.0),
+        };
+        voice.update_freqs();
+        voice
+    }
+
+    pub fn set_note(&mut self, note: Hz) {
+        self.note = note;
+        self.update_freqs();
+    }
+
+    pub fn set_mod_freq(&mut self, mod_freq: ModFreq) {
+        self.mod_freq = mod_freq;
+        self.update_freqs();
+    }
+
+    pub fn set_index(&mut self, index: f64) {
+        self.index.set_target(index);
+    }
+
+    /// Changes the parameters locked on the step.
+    pub fn apply(&mut self, lock: &Lock) {
+        if let Some(mod_freq) = lock.mod_freq {
+            self.set_mod_freq(mod_freq);
+        }
+        if let Some(index) = lock.index {
+            self.set_index(index);
+        }
+    }
+
+    /// The feedback of the modulator (see `Operator::set_feedback()`).
+    pub fn set_feedback(&mut self, feedback: f64) {
+        self.feedback.set_target(feedback.clamp(0.0, MAX_FEEDBACK));
+    }
+
+    /// The current frequency of the modulator.
+    pub fn modulator_freq(&self) -> Hz {
+        match self.mod_freq {
+            ModFreq::Ratio(ratio) => Hz(self.note.0 * ratio),
+            ModFreq::Fixed(freq) => freq,
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f64 {
+        if self.feedback.is_gliding() {
+            self.modulator.set_feedback(self.feedback.next_value());
+        }
+        let modulation = self.index.next_value() * self.modulator.process(0.0);
+        self.carrier.process(modulation)
+    }
+
+    fn update_freqs(&mut self) {
+        self.carrier.set_freq(self.fs, self.note);
+        self.modulator.set_freq(self.fs, self.modulator_freq());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a period of 128 frames, so that a period of the output holds the
+    // harmonics exactly
+    const FS: f64 = 48000.0;
+    const FREQ: Hz = Hz(375.0);
+    const PERIOD: usize = 128;
+
+    // the spectral centroid of a period of the operator, in harmonics
+    fn centroid(feedback: f64) -> f64 {
+        let mut op = Operator::new();
+        op.set_freq(FS, FREQ);
+        op.set_feedback(feedback);
+        // settle the feedback
+        for _ in 0..10 * PERIOD {
+            op.process(0.0);
+        }
+        let mut period = [0.0; PERIOD];
+        for x in &mut period {
+            *x = op.process(0.0);
+        }
+
+        let (mut weighted, mut total) = (0.0, 0.0);
+        for k in 1..PERIOD / 2 {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (n, x) in period.iter().enumerate() {
+                let w = 2.0 * core::f64::consts::PI * (k * n) as f64 / PERIOD as f64;
+                re += x * math::cos(w);
+                im -= x * math::sin(w);
+            }
+            let magnitude = math::sqrt(re * re + im * im);
+            weighted += k as f64 * magnitude;
+            total += magnitude;
+        }
+        weighted / total
+    }
+
+    #[test]
+    fn no_feedback_is_the_plain_sine() {
+        let mut op = Operator::new();
+        op.set_freq(FS, FREQ);
+        let mut phasor = Phasor::new();
+        phasor.set_freq(FS, FREQ);
+        for _ in 0..4800 {
+            let expected = math::sin(2.0 * core::f64::consts::PI * phasor.phase());
+            assert_eq!(op.process(0.0), expected);
+            phasor.advance();
+        }
+    }
+
+    #[test]
+    fn feedback_brightens_the_tone() {
+        assert!((centroid(0.0) - 1.0).abs() < 1e-6);
+        // over the low half of the range
+        let mut prev = centroid(0.0);
+        for i in 1..=5 {
+            let c = centroid(MAX_FEEDBACK / 2.0 * i as f64 / 5.0);
+            assert!(c > prev, "feedback {i}/10: {c} after {prev}");
+            prev = c;
+        }
+    }
+
+    #[test]
+    fn feedback_is_clamped() {
+        let mut op = Operator::new();
+        op.set_feedback(10.0);
+        assert_eq!(op.feedback, MAX_FEEDBACK);
+        op.set_feedback(-1.0);
+        assert_eq!(op.feedback, 0.0);
+    }
+
+    // the amplitude of the component at `freq` of a whole number of its periods
+    fn amplitude(x: &[f64], freq: f64) -> f64 {
+        let (mut re, mut im) = (0.0, 0.0);
+        for (n, x) in x.iter().enumerate() {
+            let w = 2.0 * core::f64::consts::PI * freq * n as f64 / FS;
+            re += x * math::cos(w);
+            im -= x * math::sin(w);
+        }
+        2.0 * math::sqrt(re * re + im * im) / x.len() as f64
+    }
+
+    #[test]
+    fn sidebands_follow_the_locks() {
+        // 0.1 s, which holds a whole number of periods of all the components
+        const STEP: usize = 4800;
+        let carrier = 1000.0;
+        let mut voice = TwoOp::new(FS, Hz(carrier), ModFreq::Ratio(1.41), 1.0);
+        let locks = [
+            Lock::default(),
+            Lock {
+                mod_freq: Some(ModFreq::Fixed(Hz(900.0))),
+                ..Lock::default()
+            },
+        ];
+        let mut max_step: f64 = 0.0;
+        let mut prev: Option<f64> = None;
+        // with a frequency between the sidebands (on the bins of a step)
+        for (lock, (modulator, between)) in locks.iter().zip([(1410.0, 1700.0), (900.0, 1450.0)]) {
+            voice.apply(lock);
+            assert_eq!(voice.modulator_freq(), Hz(modulator));
+            let mut out = [0.0; STEP];
+            for x in &mut out {
+                *x = voice.next_sample();
+                // the phases are continuous over the change, so no click
+                if let Some(prev) = prev {
+                    max_step = max_step.max((*x - prev).abs());
+                }
+                prev = Some(*x);
+            }
+
+            // the carrier and the first sidebands, and nothing in between
+            for k in [0.0, 1.0, -1.0] {
+                let freq = (carrier + k * modulator).abs();
+                assert!(amplitude(&out, freq) > 0.2, "{freq} Hz of {modulator} Hz");
+            }
+            assert!(
+                amplitude(&out, between) < 1e-3,
+                "{between} Hz of {modulator} Hz"
+            );
+        }
+        // within the slope of the highest sideband
+        let highest = 2.0 * core::f64::consts::PI * (carrier + 3.0 * 1410.0) / FS;
+        assert!(max_step < highest, "{max_step}");
+    }
+}
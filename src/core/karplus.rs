@@ -0,0 +1,303 @@
+use super::delay::FixedDelayLine;
+use super::math;
+use super::noise::Noise;
+use crate::units::{Hz, Ms};
+
+/// The error on an invalid parameter of `KarplusStrong`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KarplusStrongError {
+    /// T60 must be positive and finite.
+    InvalidT60(Ms),
+    /// The frequency must be positive, below the Nyquist frequency, and high
+    /// enough for the delay line to hold a period.
+    InvalidFrequency(Hz),
+}
+
+impl core::fmt::Display for KarplusStrongError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidT60(t60) => write!(f, "invalid T60: {} ms", t60.0),
+            Self::InvalidFrequency(f0) => write!(f, "invalid frequency: {} Hz", f0.0),
+        }
+    }
+}
+
+#[cfg(feature = "dsp")]
+impl std::error::Error for KarplusStrongError {}
+
+const DEFAULT_RELEASE_T60: Ms = Ms(80.0);
+
+/// The gain of each round of the loop to decay by 60 dB in `t60`, compensating
+/// the gain of the loss filter at f0.
+fn loop_gain(f0: f64, loss_gain: f64, t60: Ms) -> f64 {
+    let num = math::powf(10.0, -3.0 / (f0 * t60.to_seconds()));
+    (num / loss_gain).clamp(0.0, 1.0)
+}
+
+/// A plucked string by the Karplus-Strong algorithm, with a fractional delay
+/// by an all-pass filter for the accurate tuning. `N` is the capacity of the
+/// delay line, which limits the lowest note to about `fs / N`.
+///
+/// `note_off()` damps the string like palm muting, so that it decays with
+/// the release T60 (80 ms by default; see `with_release()`) instead of
+/// ringing through the rests. The next `pluck()` restores the decay.
+pub struct KarplusStrong<const N: usize> {
+    noise_source: Noise,
+    g: f64,
+    c: f64,
+    d: f64,
+    f0: f64,
+    // the gain of the loss filter at f0
+    loss_gain: f64,
+    sustain_c: f64,
+    release_c: f64,
+    let_ring: bool,
+    delay_line: FixedDelayLine<N>,
+    last_delayed_sample: f64,
+    last_all_passed_feedback: f64,
+    // the number of the frames to excite the string
+    excite_remaining: usize,
+}
+
+impl<const N: usize> KarplusStrong<N> {
+    /// `d` is the weight of the loss filter (0.0 to 1.0), and `t60` is the time
+    /// for the string to decay by 60 dB.
+    pub fn new(fs: f64, f0: Hz, d: f64, t60: Ms, seed: u64) -> Result<Self, KarplusStrongError> {
+        if !(t60.0 > 0.0 && t60.0.is_finite()) {
+            return Err(KarplusStrongError::InvalidT60(t60));
+        }
+        if !(f0.0 > 0.0 && f0.0 < fs / 2.0) {
+            return Err(KarplusStrongError::InvalidFrequency(f0));
+        }
+
+        let omega = 2.0 * core::f64::consts::PI * f0.normalized(fs);
+        let f0 = f0.0;
+
+        // The signal goes around the loop f0 times per second, so, to decay by
+        // 60 dB (= 10^-3) in t60 seconds, the gain of each round must be
+        // 10^(-3 / (f0 * t60)). The loss filter has the gain of `loss_gain` at f0,
+        // so c compensates it. c is clamped to 1 so that the loop never grows;
+        // if the loss filter alone decays faster than that, T60 gets shorter.
+        let loss_gain =
+            math::sqrt((1.0 - d) * (1.0 - d) + d * d + 2.0 * d * (1.0 - d) * math::cos(omega));
+        let c = loop_gain(f0, loss_gain, t60);
+
+        // The loop consists of the delay line, the fractional all-pass, and the
+        // loss filter. Subtract the phase delay of the loss filter at f0 (which
+        // is only approximately `d`, and the difference gets larger for the
+        // higher notes) so that the total loop delay is exactly fs / f0.
+        let loss_filter_delay =
+            math::atan2(d * math::sin(omega), (1.0 - d) + d * math::cos(omega)) / omega;
+        let delay = fs / f0 - loss_filter_delay;
+
+        // Keep the delay of the all-pass within [0.5, 1.5) so that g is never
+        // close to 1 (i.e. the pole is not close to the unit circle).
+        let delay_line_length = math::floor(delay - 0.5) as usize;
+        if delay_line_length == 0 || delay_line_length > N {
+            return Err(KarplusStrongError::InvalidFrequency(Hz(f0)));
+        }
+        let e = delay - delay_line_length as f64;
+
+        // The all-pass coefficient that gives the phase delay of exactly e at
+        // f0, instead of the low-frequency approximation (1 - e) / (1 + e).
+        let g = math::sin(omega * (1.0 - e) / 2.0) / math::sin(omega * (1.0 + e) / 2.0);
+
+        Ok(Self {
+            noise_source: Noise::new(seed),
+            g,
+            c,
+            d,
+            f0,
+            loss_gain,
+            sustain_c: c,
+            release_c: loop_gain(f0, loss_gain, DEFAULT_RELEASE_T60),
+            let_ring: false,
+            delay_line: FixedDelayLine::new(delay_line_length),
+            last_delayed_sample: 0.0,
+            last_all_passed_feedback: 0.0,
+            excite_remaining: 0,
+        })
+    }
+
+    /// Sets the time for the string to decay by 60 dB after `note_off()`.
+    pub fn with_release(mut self, t60: Ms) -> Result<Self, KarplusStrongError> {
+        if !(t60.0 > 0.0 && t60.0.is_finite()) {
+            return Err(KarplusStrongError::InvalidT60(t60));
+        }
+        self.release_c = loop_gain(self.f0, self.loss_gain, t60);
+        Ok(self)
+    }
+
+    /// If true, `note_off()` does nothing and the string keeps ringing with
+    /// its T60, as a string without any damping.
+    pub fn with_let_ring(mut self, let_ring: bool) -> Self {
+        self.let_ring = let_ring;
+        self
+    }
+
+    pub fn delay_line_length(&self) -> usize {
+        self.delay_line.delay()
+    }
+
+    /// Excites the string with a burst of noise as long as the delay line.
+    pub fn pluck(&mut self) {
+        self.c = self.sustain_c;
+        self.excite_remaining = self.delay_line.delay();
+    }
+
+    /// Damps the string so that it decays with the release T60, unless
+    /// `with_let_ring(true)`.
+    pub fn note_off(&mut self) {
+        if !self.let_ring {
+            self.c = self.release_c;
+            self.excite_remaining = 0;
+        }
+    }
+
+    // the all-pass coefficient, the loop gain, and the weight of the loss
+    // filter, to test other implementations against this
+    #[cfg(test)]
+    pub(crate) fn coefficients(&self) -> (f64, f64, f64) {
+        (self.g, self.c, self.d)
+    }
+
+    pub fn process(&mut self) -> f64 {
+        let cur_delayed_sample = self.delay_line.delayed();
+
+        let all_passed_feedback = -self.g * self.last_all_passed_feedback
+            + self.g * cur_delayed_sample
+            + self.last_delayed_sample;
+
+        let orig_noise = if self.excite_remaining > 0 {
+            self.excite_remaining -= 1;
+            self.noise_source.next_sample()
+        } else {
+            0.0
+        };
+
+        let out = orig_noise
+            + self.c
+                * ((1.0 - self.d) * all_passed_feedback + self.d * self.last_all_passed_feedback);
+
+        self.last_all_passed_feedback = all_passed_feedback;
+        self.last_delayed_sample = cur_delayed_sample;
+        self.delay_line.process(out);
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    /// The frequency of the component near `f0`, from the advance of its
+    /// phase between two windows of the output.
+    fn measure_frequency(samples: &[f64], f0: f64) -> f64 {
+        let window = (FS * 0.1) as usize;
+        let phase_at = |start: usize| {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (i, x) in samples[start..start + window].iter().enumerate() {
+                let hann =
+                    0.5 - 0.5 * (2.0 * core::f64::consts::PI * i as f64 / window as f64).cos();
+                let theta = 2.0 * core::f64::consts::PI * f0 * (start + i) as f64 / FS;
+                re += hann * x * theta.cos();
+                im -= hann * x * theta.sin();
+            }
+            im.atan2(re)
+        };
+        let (start1, start2) = (window, 3 * window);
+        let mut dphase = phase_at(start2) - phase_at(start1);
+        while dphase > core::f64::consts::PI {
+            dphase -= 2.0 * core::f64::consts::PI;
+        }
+        while dphase < -core::f64::consts::PI {
+            dphase += 2.0 * core::f64::consts::PI;
+        }
+        f0 + dphase / (2.0 * core::f64::consts::PI * (start2 - start1) as f64 / FS)
+    }
+
+    #[test]
+    fn tuned_within_3_cents() {
+        for f0 in [55.0, 110.0, 220.0, 440.0] {
+            for d in [0.2, 0.5, 0.8] {
+                let mut ks = KarplusStrong::<2048>::new(FS, Hz(f0), d, Ms(3000.0), 1).unwrap();
+                ks.pluck();
+                let samples: Vec<f64> = (0..(FS * 0.5) as usize).map(|_| ks.process()).collect();
+                let cents = 1200.0 * (measure_frequency(&samples, f0) / f0).log2();
+                assert!(cents.abs() < 3.0, "{f0} Hz, d = {d}: {cents} cents");
+            }
+        }
+    }
+
+    /// The time for the envelope (the RMS of each period) to fall by 60 dB,
+    /// from the slope of the decay between `from` and `to` (in seconds),
+    /// after the higher partials have died away.
+    fn measure_t60(samples: &[f64], period: usize, from: f64, to: f64) -> f64 {
+        let db_at = |t: f64| {
+            let start = (t * FS) as usize;
+            let c = &samples[start..start + period];
+            10.0 * (c.iter().map(|x| x * x).sum::<f64>() / period as f64).log10()
+        };
+        60.0 * (to - from) / (db_at(from) - db_at(to))
+    }
+
+    #[test]
+    fn decays_by_60_db_in_t60() {
+        for f0 in [110.0, 440.0] {
+            for t60 in [500.0, 2000.0] {
+                let mut ks = KarplusStrong::<2048>::new(FS, Hz(f0), 0.5, Ms(t60), 1).unwrap();
+                ks.pluck();
+                let seconds = t60 / 1000.0;
+                let samples: Vec<f64> =
+                    (0..(FS * seconds) as usize).map(|_| ks.process()).collect();
+                let period = (FS / f0).round() as usize;
+                let measured =
+                    measure_t60(&samples, period, seconds * 0.25, seconds * 0.75) * 1000.0;
+                assert!(
+                    (measured / t60 - 1.0).abs() < 0.15,
+                    "{f0} Hz: {measured} ms for {t60} ms"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_t60() {
+        for t60 in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let ks = KarplusStrong::<2048>::new(FS, Hz(440.0), 0.5, Ms(t60), 1);
+            assert!(matches!(ks, Err(KarplusStrongError::InvalidT60(_))));
+        }
+    }
+
+    /// The drop in dB of the level (the RMS of a period) from the note off to
+    /// 100 ms after it.
+    fn drop_after_note_off(mut ks: KarplusStrong<2048>, period: usize) -> f64 {
+        let rms = |ks: &mut KarplusStrong<2048>, frames: usize| {
+            let sum: f64 = (0..frames).map(|_| ks.process()).map(|x| x * x).sum();
+            math::sqrt(sum / frames as f64)
+        };
+        ks.pluck();
+        rms(&mut ks, (FS * 0.3) as usize);
+        let before = rms(&mut ks, period);
+        ks.note_off();
+        rms(&mut ks, (FS * 0.1) as usize - period);
+        let after = rms(&mut ks, period);
+        20.0 * (after / before).log10()
+    }
+
+    #[test]
+    fn note_off_damps_the_string() {
+        let f0 = 220.0;
+        let period = (FS / f0).round() as usize;
+        let new = || KarplusStrong::<2048>::new(FS, Hz(f0), 0.5, Ms(2000.0), 1).unwrap();
+
+        let damped = drop_after_note_off(new().with_release(Ms(80.0)).unwrap(), period);
+        assert!(damped < -60.0, "{damped} dB");
+
+        let ringing = drop_after_note_off(new().with_let_ring(true), period);
+        assert!(ringing > -10.0, "{ringing} dB");
+    }
+}
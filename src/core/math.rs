@@ -0,0 +1,60 @@
+//! The float functions that are the methods of `f64` with std, and come from
+//! libm without it.
+
+#[cfg(feature = "dsp")]
+mod imp {
+    pub fn sin(x: f64) -> f64 {
+        x.sin()
+    }
+
+    pub fn cos(x: f64) -> f64 {
+        x.cos()
+    }
+
+    pub fn sqrt(x: f64) -> f64 {
+        x.sqrt()
+    }
+
+    pub fn atan2(y: f64, x: f64) -> f64 {
+        y.atan2(x)
+    }
+
+    pub fn powf(x: f64, y: f64) -> f64 {
+        x.powf(y)
+    }
+
+    pub fn exp(x: f64) -> f64 {
+        x.exp()
+    }
+
+    pub fn log10(x: f64) -> f64 {
+        x.log10()
+    }
+
+    pub fn log2(x: f64) -> f64 {
+        x.log2()
+    }
+
+    pub fn abs(x: f64) -> f64 {
+        x.abs()
+    }
+
+    pub fn floor(x: f64) -> f64 {
+        x.floor()
+    }
+
+    pub fn round(x: f64) -> f64 {
+        x.round()
+    }
+}
+
+#[cfg(not(feature = "dsp"))]
+mod imp {
+    pub use libm::{atan2, cos, exp, floor, log10, log2, pow as powf, round, sin, sqrt};
+
+    pub fn abs(x: f64) -> f64 {
+        libm::fabs(x)
+    }
+}
+
+pub use imp::*;
@@ -0,0 +1,18 @@
+//! The DSP building blocks that need neither std nor an allocator, so that
+//! they can run on embedded targets as well. They process one sample at a
+//! time; the `Signal` wrappers for the desktop live in the other modules.
+//!
+//! Enable with `--no-default-features --features dsp-core`.
+
+pub mod biquad;
+pub mod delay;
+pub mod drift;
+pub mod envelope;
+pub mod fm;
+pub mod karplus;
+pub(crate) mod math;
+pub mod multiosc;
+pub mod noise;
+pub mod phasor;
+pub mod polyblep;
+pub mod smooth;
@@ -0,0 +1,170 @@
+use super::math;
+use super::phasor::Phasor;
+use super::polyblep::{poly_blep, saw};
+use super::smooth::{SmoothedParam, SMOOTHING};
+use crate::units::{Hz, Ms};
+
+// the length of the crossfade on switching the waveform
+const CROSSFADE: Ms = Ms(5.0);
+
+/// The waveforms of `MultiOsc`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    #[default]
+    Saw,
+    /// A pulse whose width is set by `MultiOsc::set_pulse_width()`
+    Pulse,
+}
+
+/// An oscillator whose waveform can be switched while playing. All the
+/// waveforms share one `Phasor`, so the phase continues across a switch, and
+/// the old and the new waveforms are crossfaded over 5 ms to avoid a click.
+/// Only the active waveform (or the two while crossfading) is computed.
+///
+/// The saw and the pulse are anti-aliased by polyBLEP; the triangle and the
+/// sine are naive, which is fine as their harmonics fall off quickly.
+pub struct MultiOsc {
+    phasor: Phasor,
+    waveform: Waveform,
+    pulse_width: SmoothedParam,
+    // the waveform fading out, and the frames left of the crossfade
+    fading: Option<(Waveform, usize)>,
+    crossfade_frames: usize,
+}
+
+impl MultiOsc {
+    pub fn new(fs: f64, waveform: Waveform) -> Self {
+        Self {
+            phasor: Phasor::new(),
+            waveform,
+            pulse_width: SmoothedParam::new(fs, SMOOTHING, 0.5),
+            fading: None,
+            crossfade_frames: CROSSFADE.to_frames(fs).0.max(1),
+        }
+    }
+
+    pub fn set_freq(&mut self, fs: f64, freq: Hz) {
+        self.phasor.set_freq(fs, freq);
+    }
+
+    /// Switches to the waveform, crossfading from the current output. A
+    /// switch during a crossfade restarts it from the waveform that was
+    /// active.
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        if waveform != self.waveform {
+            self.fading = Some((self.waveform, self.crossfade_frames));
+            self.waveform = waveform;
+        }
+    }
+
+    pub fn waveform(&self) -> Waveform {
+        self.waveform
+    }
+
+    /// The ratio of the high part of the pulse, within (0.0, 1.0); 0.5 is a
+    /// square. The width glides to the new one over 20 ms.
+    pub fn set_pulse_width(&mut self, width: f64) {
+        self.pulse_width.set_target(width.clamp(0.01, 0.99));
+    }
+
+    /// The current phase within [0.0, 1.0).
+    pub fn phase(&self) -> f64 {
+        self.phasor.phase()
+    }
+
+    /// Returns the current sample and advances the phase.
+    pub fn next_sample(&mut self) -> f64 {
+        let width = self.pulse_width.next_value();
+        let mut out = self.render(self.waveform, width);
+
+        if let Some((old, remaining)) = self.fading {
+            let gain = remaining as f64 / (self.crossfade_frames + 1) as f64;
+            out = gain * self.render(old, width) + (1.0 - gain) * out;
+            self.fading = (remaining > 1).then_some((old, remaining - 1));
+        }
+
+        self.phasor.advance();
+        out
+    }
+
+    fn render(&self, waveform: Waveform, width: f64) -> f64 {
+        let phase = self.phasor.phase();
+        let delta = self.phasor.increment();
+        match waveform {
+            Waveform::Sine => math::sin(2.0 * core::f64::consts::PI * phase),
+            Waveform::Triangle => 1.0 - 4.0 * math::abs(phase - 0.5),
+            Waveform::Saw => saw(phase, delta),
+            Waveform::Pulse => {
+                let naive = if phase < width { 1.0 } else { -1.0 };
+                let fall = phase - width;
+                let fall = if fall < 0.0 { fall + 1.0 } else { fall };
+                naive + poly_blep(phase, delta) - poly_blep(fall, delta)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    // the largest jump between the adjacent samples
+    fn max_jump(osc: &mut MultiOsc, len: usize, switch: Option<(usize, Waveform)>) -> f64 {
+        let mut prev = osc.next_sample();
+        let mut max = 0.0_f64;
+        for i in 1..len {
+            if let Some((at, waveform)) = switch {
+                if i == at {
+                    osc.set_waveform(waveform);
+                }
+            }
+            let y = osc.next_sample();
+            max = max.max((y - prev).abs());
+            prev = y;
+        }
+        max
+    }
+
+    fn osc(waveform: Waveform) -> MultiOsc {
+        let mut osc = MultiOsc::new(FS, waveform);
+        osc.set_freq(FS, Hz(261.0));
+        osc
+    }
+
+    #[test]
+    fn switching_does_not_click() {
+        let natural = max_jump(&mut osc(Waveform::Saw), 4800, None).max(max_jump(
+            &mut osc(Waveform::Pulse),
+            4800,
+            None,
+        ));
+        // at arbitrary frames, not at the wraps
+        for at in [1234, 2000, 3001] {
+            let jump = max_jump(&mut osc(Waveform::Saw), 4800, Some((at, Waveform::Pulse)));
+            assert!(jump <= natural + 1e-9, "{jump} > {natural} at {at}");
+        }
+    }
+
+    #[test]
+    fn switching_keeps_the_phase() {
+        let mut switched = osc(Waveform::Saw);
+        let mut phasor = Phasor::new();
+        phasor.set_freq(FS, Hz(261.0));
+        for i in 0..4800 {
+            match i {
+                1234 => switched.set_waveform(Waveform::Pulse),
+                2000 => switched.set_waveform(Waveform::Sine),
+                2100 => switched.set_waveform(Waveform::Triangle),
+                _ => {}
+            }
+            assert_eq!(switched.phase(), phasor.phase(), "frame {i}");
+            switched.next_sample();
+            phasor.advance();
+        }
+        assert_eq!(switched.waveform(), Waveform::Triangle);
+    }
+}
@@ -0,0 +1,23 @@
+/// A white noise from the xorshift64* generator. This is not as good as the
+/// one of dasp, but enough for exciting a string.
+pub struct Noise {
+    state: u64,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        // the state must not be 0
+        Self { state: seed.max(1) }
+    }
+
+    /// Returns a sample within [-1.0, 1.0).
+    pub fn next_sample(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let x = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d);
+
+        // use the upper 53 bits as the mantissa
+        (x >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+    }
+}
@@ -0,0 +1,131 @@
+use super::math;
+use crate::units::Hz;
+
+/// A phase accumulator within [0.0, 1.0), which also reports where exactly
+/// the phase wrapped between two samples (what the BLEP corrections need).
+///
+/// The phase is computed as `base + count * inc` rather than by adding `inc`
+/// on every sample, so the rounding errors don't accumulate; e.g. 441 Hz at
+/// 44.1 kHz wraps exactly every 100 samples.
+#[derive(Clone, Debug, Default)]
+pub struct Phasor {
+    phase: f64,
+    inc: f64,
+    // the phase when `inc` was last changed, and the samples since then
+    base: f64,
+    count: u64,
+}
+
+impl Phasor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_freq(&mut self, fs: f64, freq: Hz) {
+        self.set_increment(freq.normalized(fs));
+    }
+
+    /// Sets the increment per sample (the frequency in cycles per sample),
+    /// which must be within [0.0, 1.0).
+    pub fn set_increment(&mut self, inc: f64) {
+        if inc != self.inc {
+            self.base = self.phase;
+            self.count = 0;
+            self.inc = inc;
+        }
+    }
+
+    pub fn phase(&self) -> f64 {
+        self.phase
+    }
+
+    pub fn increment(&self) -> f64 {
+        self.inc
+    }
+
+    /// Advances by one sample. If the phase wrapped, returns how long ago it
+    /// did in samples, within [0.0, 1.0); e.g. 0.25 means it crossed 1.0 a
+    /// quarter of a sample before the current sample.
+    pub fn advance(&mut self) -> Option<f64> {
+        self.count += 1;
+        let total = self.base + self.count as f64 * self.inc;
+        let phase = total - math::floor(total);
+
+        let wrapped = phase < self.phase;
+        self.phase = phase;
+        if wrapped {
+            Some(self.since_wrap())
+        } else {
+            None
+        }
+    }
+
+    /// Jumps to `phase` given from outside (e.g. by `dasp::signal::Phase`),
+    /// inferring the increment from the previous phase. Returns the same as
+    /// `advance()`.
+    pub fn follow(&mut self, phase: f64) -> Option<f64> {
+        // if the phase didn't increase, it should be because the phase got
+        // wrapped at 1.0
+        let wrapped = phase <= self.phase;
+        self.inc = if wrapped {
+            1.0 + phase - self.phase
+        } else {
+            phase - self.phase
+        };
+        self.phase = phase;
+        self.base = phase;
+        self.count = 0;
+
+        if wrapped {
+            Some(self.since_wrap())
+        } else {
+            None
+        }
+    }
+
+    fn since_wrap(&self) -> f64 {
+        if self.inc > 0.0 {
+            (self.phase / self.inc).min(1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_exactly_every_period() {
+        let mut phasor = Phasor::new();
+        phasor.set_freq(44100.0, Hz(441.0));
+        for n in 1..=100_000_u32 {
+            let wrap = phasor.advance();
+            if n.is_multiple_of(100) {
+                assert!(wrap.is_some_and(|w| w < 1e-9), "sample {n}: {wrap:?}");
+            } else {
+                assert!(wrap.is_none(), "sample {n}: {wrap:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn reports_fractional_wraps() {
+        // a period of 44.1 samples, so the k-th wrap is at 44.1 k
+        let mut phasor = Phasor::new();
+        phasor.set_freq(44100.0, Hz(1000.0));
+        let mut k = 0;
+        for n in 1..=10_000 {
+            if let Some(since) = phasor.advance() {
+                k += 1;
+                let expected = n as f64 - 44.1 * k as f64;
+                assert!(
+                    (since - expected).abs() < 1e-9,
+                    "sample {n}: {since} != {expected}"
+                );
+            }
+        }
+        assert_eq!(k, 226);
+    }
+}
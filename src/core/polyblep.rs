@@ -0,0 +1,59 @@
+use super::phasor::Phasor;
+use crate::units::Hz;
+
+/// A sawtooth with the polyBLEP anti-aliasing, driven by its own `Phasor`.
+/// Either set the frequency with `set_freq()` and call `next_sample()`, or
+/// give the phase from outside on each frame with `process()`, so the
+/// frequency can be modulated freely.
+///
+/// This implementation is derived from https://github.com/electro-smith/DaisySP/blob/master/Source/Synthesis/oscillator.cpp
+#[derive(Default)]
+pub struct PolyBlepSaw {
+    phasor: Phasor,
+}
+
+impl PolyBlepSaw {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_freq(&mut self, fs: f64, freq: Hz) {
+        self.phasor.set_freq(fs, freq);
+    }
+
+    /// Returns the current sample and advances the phase.
+    pub fn next_sample(&mut self) -> f64 {
+        let out = self.render();
+        self.phasor.advance();
+        out
+    }
+
+    /// `phase` is within [0.0, 1.0).
+    pub fn process(&mut self, phase: f64) -> f64 {
+        self.phasor.follow(phase);
+        self.render()
+    }
+
+    fn render(&self) -> f64 {
+        saw(self.phasor.phase(), self.phasor.increment())
+    }
+}
+
+/// The falling sawtooth at `phase` with the polyBLEP correction, where
+/// `delta` is the increment of the phase per sample.
+pub(crate) fn saw(phase: f64, delta: f64) -> f64 {
+    phase * -2.0 + 1.0 + poly_blep(phase, delta)
+}
+
+/// The polyBLEP residual of a step of +2 at the phase of 0.
+pub(crate) fn poly_blep(phase: f64, delta: f64) -> f64 {
+    if phase < delta {
+        let t = phase / delta;
+        -t * t + 2.0 * t - 1.0
+    } else if phase > 1.0 - delta {
+        let t = (phase - 1.0) / delta;
+        t * t + 2.0 * t + 1.0
+    } else {
+        0.0
+    }
+}
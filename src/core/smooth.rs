@@ -0,0 +1,123 @@
+use super::math;
+use crate::units::Ms;
+
+/// The smoothing time of the parameters that can be set while playing (e.g.
+/// by the `set_*()` methods of the oscillators and the panners).
+pub const SMOOTHING: Ms = Ms(20.0);
+
+// the part of a change left at the end of the smoothing time, i.e. -60 dB
+const RESIDUAL: f64 = 0.001;
+
+/// A parameter that glides toward its target by a one-pole low-pass, so that
+/// changing it while playing doesn't make zipper noise. Call `next_value()`
+/// once per frame.
+///
+/// The smoothing time is the time to come within 0.1% (-60 dB) of a change,
+/// after which the value snaps to the target, so it reaches the target
+/// exactly within the time.
+#[derive(Clone, Copy, Debug)]
+pub struct SmoothedParam {
+    value: f64,
+    target: f64,
+    coef: f64,
+    // the distance to the target below which the value snaps to it
+    snap: f64,
+}
+
+impl SmoothedParam {
+    /// Starts at `value` (without gliding).
+    pub fn new(fs: f64, time: Ms, value: f64) -> Self {
+        let mut param = Self {
+            value,
+            target: value,
+            coef: 0.0,
+            snap: 0.0,
+        };
+        param.set_time(fs, time);
+        param
+    }
+
+    /// Sets the smoothing time. 0 makes the changes instant.
+    pub fn set_time(&mut self, fs: f64, time: Ms) {
+        let frames = time.0 * fs / 1000.0;
+        // coef^frames = RESIDUAL
+        self.coef = if frames > 0.0 {
+            math::powf(RESIDUAL, 1.0 / frames)
+        } else {
+            0.0
+        };
+    }
+
+    /// Glides from the current value to `target`. Setting the same target
+    /// again does nothing, so this can be called on every frame.
+    pub fn set_target(&mut self, target: f64) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        // with a margin for the rounding on the last frame of the time
+        self.snap = math::abs(target - self.value) * RESIDUAL * (1.0 + 1e-6);
+    }
+
+    /// Jumps to `value` without gliding, e.g. on the start of a note.
+    pub fn reset(&mut self, value: f64) {
+        self.value = value;
+        self.target = value;
+    }
+
+    pub fn target(&self) -> f64 {
+        self.target
+    }
+
+    /// The current value without proceeding.
+    pub fn value(&self) -> f64 {
+        self.value
+    }
+
+    pub fn is_gliding(&self) -> bool {
+        self.value != self.target
+    }
+
+    /// Proceeds a frame and returns the value.
+    pub fn next_value(&mut self) -> f64 {
+        if self.value != self.target {
+            let value = self.target + self.coef * (self.value - self.target);
+            // also snap when the rounding stops the value short of the target
+            self.value = if math::abs(value - self.target) <= self.snap || value == self.value {
+                self.target
+            } else {
+                value
+            };
+        }
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glides_and_reaches_the_target_within_the_time() {
+        let fs = 48000.0;
+        let frames = (SMOOTHING.0 * fs / 1000.0) as usize;
+        let mut param = SmoothedParam::new(fs, SMOOTHING, 0.0);
+        param.set_target(1.0);
+
+        // a small step on the first frame, rather than the whole jump
+        let first = param.next_value();
+        assert!(first > 0.0 && first < 0.01, "{first}");
+        let mut prev = first;
+        for _ in 1..frames {
+            let value = param.next_value();
+            assert!(value >= prev && value - prev < 0.01);
+            prev = value;
+        }
+        assert_eq!(param.value(), 1.0);
+        assert!(!param.is_gliding());
+
+        let mut instant = SmoothedParam::new(fs, Ms(0.0), 0.0);
+        instant.set_target(1.0);
+        assert_eq!(instant.next_value(), 1.0);
+    }
+}
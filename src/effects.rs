@@ -0,0 +1,2976 @@
+use crate::analysis::PitchTracker;
+use crate::core::biquad::Biquad;
+use crate::core::envelope::EnvelopeFollower;
+use crate::core::noise::Noise;
+use crate::core::smooth::SmoothedParam;
+use crate::fft::{hann, Complex, Fft};
+use crate::filter::butterworth_band_pass;
+use crate::latency::Latency;
+use crate::oscillator::LfoShape;
+use crate::params::{AtomicF64, FrameClock};
+use crate::quality::{self, Quality};
+use crate::resample::Oversample;
+use crate::stereo::MonoEffect;
+use crate::tail::HasTail;
+use crate::units::{Db, Frames, Hz, LfoRate, Ms};
+use dasp::Signal;
+use std::f64::consts::FRAC_1_SQRT_2;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A delay line backed by a circular buffer.
+pub struct DelayLine {
+    buffer: Vec<f64>,
+    pos: usize, // the position the next sample is written to
+}
+
+impl DelayLine {
+    pub fn new(max_delay: usize) -> Self {
+        Self {
+            buffer: vec![0.0; max_delay + 1],
+            pos: 0,
+        }
+    }
+
+    /// Returns the sample pushed `delay` steps before the one that will be
+    /// pushed next (i.e. `tap(1)` is the most recently pushed sample).
+    pub fn tap(&self, delay: usize) -> f64 {
+        let len = self.buffer.len();
+        self.buffer[(self.pos + len - delay) % len]
+    }
+
+    /// Same as `tap()`, but accepts a fractional delay by linear interpolation.
+    pub fn tap_fractional(&self, delay: f64) -> f64 {
+        let i = delay.floor() as usize;
+        let frac = delay - i as f64;
+        (1.0 - frac) * self.tap(i) + frac * self.tap(i + 1)
+    }
+
+    pub fn push(&mut self, x: f64) {
+        self.buffer[self.pos] = x;
+        self.pos = (self.pos + 1) % self.buffer.len();
+    }
+
+    /// Pushes `x` and returns the sample delayed by `delay` steps.
+    pub fn process(&mut self, x: f64, delay: usize) -> f64 {
+        if delay == 0 {
+            self.push(x);
+            return x;
+        }
+
+        let out = self.tap(delay);
+        self.push(x);
+        out
+    }
+}
+
+/// A Schroeder all-pass filter, i.e. (z^-M - g) / (1 - g z^-M)
+struct Allpass {
+    line: DelayLine,
+    delay: usize,
+    g: f64,
+}
+
+impl Allpass {
+    fn new(delay: usize, max_delay: usize, g: f64) -> Self {
+        Self {
+            line: DelayLine::new(max_delay),
+            delay,
+            g,
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let delayed = self.line.tap(self.delay);
+        self.feed(x, delayed)
+    }
+
+    fn process_modulated(&mut self, x: f64, delay: f64) -> f64 {
+        let delayed = self.line.tap_fractional(delay);
+        self.feed(x, delayed)
+    }
+
+    fn feed(&mut self, x: f64, delayed: f64) -> f64 {
+        let w = x + self.g * delayed;
+        self.line.push(w);
+        delayed - self.g * w
+    }
+}
+
+/// The stage placed before the diffuse tail of a reverb. This consists of
+///
+/// - the discrete early reflections, i.e. the first echoes from the walls of
+///   a room, modeled as the taps on a delay line (the times are relative to
+///   the direct sound)
+/// - the pre-delay, i.e. the delay before the input reaches the diffuse tail
+pub struct EarlyReflections {
+    fs: f64, // sampling rate
+    line: DelayLine,
+    taps: Vec<(usize, f64)>,
+    pre_delay_length: usize,
+}
+
+impl EarlyReflections {
+    pub fn new(fs: f64, pre_delay: Ms) -> Self {
+        let pre_delay_length = pre_delay.to_frames(fs).0;
+        Self {
+            fs,
+            line: DelayLine::new(pre_delay_length),
+            taps: Vec::new(),
+            pre_delay_length,
+        }
+    }
+
+    /// Adds the reflections; each tap is (time, gain).
+    pub fn with_taps(mut self, taps: &[(Ms, f64)]) -> Self {
+        self.taps.extend(
+            taps.iter()
+                .map(|&(time, gain)| (time.to_frames(self.fs).0.max(1), gain)),
+        );
+
+        let max_delay = self
+            .taps
+            .iter()
+            .map(|&(pos, _)| pos)
+            .chain(std::iter::once(self.pre_delay_length))
+            .max()
+            .unwrap_or(0);
+        self.line = DelayLine::new(max_delay);
+
+        self
+    }
+
+    /// Returns the sum of the reflections and the input to the diffuse tail.
+    pub fn process(&mut self, x: f64) -> (f64, f64) {
+        let reflections = self
+            .taps
+            .iter()
+            .map(|&(pos, gain)| gain * self.line.tap(pos))
+            .sum();
+        let tail_input = if self.pre_delay_length == 0 {
+            x
+        } else {
+            self.line.tap(self.pre_delay_length)
+        };
+        self.line.push(x);
+
+        (reflections, tail_input)
+    }
+}
+
+// The delay lengths in the paper are for the sampling rate of 29761 Hz.
+const DATTORRO_FS: f64 = 29761.0;
+
+const INPUT_DIFFUSERS: [(usize, f64); 4] = [(142, 0.75), (107, 0.75), (379, 0.625), (277, 0.625)];
+const BANDWIDTH: f64 = 0.9995;
+const DECAY_DIFFUSION_1: f64 = 0.7;
+const EXCURSION: f64 = 16.0;
+const MOD_HZ: f64 = 1.0;
+const OUTPUT_GAIN: f64 = 0.6;
+// the time to go in and out of the freeze
+const FREEZE_FADE: Ms = Ms(50.0);
+
+// (the modulated all-pass, the first delay, the second all-pass, the second delay)
+const LEFT_TANK: [usize; 4] = [672, 4453, 1800, 3720];
+const RIGHT_TANK: [usize; 4] = [908, 4217, 2656, 3163];
+
+// The output taps; (whether the node is in the left half, index of the tank node,
+// tap position, sign).
+// The tank nodes are 0: the first delay, 1: the second all-pass, 2: the second delay.
+#[rustfmt::skip]
+const LEFT_OUTPUT_TAPS: [(bool, usize, usize, f64); 7] = [
+    (false, 0, 266, 1.0), (false, 0, 2974, 1.0), (false, 1, 1913, -1.0), (false, 2, 1996, 1.0),
+    (true, 0, 1990, -1.0), (true, 1, 187, -1.0), (true, 2, 1066, -1.0),
+];
+#[rustfmt::skip]
+const RIGHT_OUTPUT_TAPS: [(bool, usize, usize, f64); 7] = [
+    (true, 0, 353, 1.0), (true, 0, 3627, 1.0), (true, 1, 1228, -1.0), (true, 2, 2673, 1.0),
+    (false, 0, 2111, -1.0), (false, 1, 335, -1.0), (false, 2, 121, -1.0),
+];
+
+struct TankHalf {
+    modulated: Allpass,
+    delay1: DelayLine,
+    delay1_length: usize,
+    damping_state: f64,
+    diffuser: Allpass,
+    delay2: DelayLine,
+    delay2_length: usize,
+}
+
+impl TankHalf {
+    fn new(
+        lengths: [usize; 4],
+        scale: impl Fn(usize) -> usize,
+        excursion: f64,
+        decay_diffusion_2: f64,
+    ) -> Self {
+        let modulated_length = scale(lengths[0]);
+        Self {
+            modulated: Allpass::new(
+                modulated_length,
+                modulated_length + excursion.ceil() as usize + 1,
+                -DECAY_DIFFUSION_1,
+            ),
+            delay1: DelayLine::new(scale(lengths[1])),
+            delay1_length: scale(lengths[1]),
+            damping_state: 0.0,
+            diffuser: Allpass::new(scale(lengths[2]), scale(lengths[2]), decay_diffusion_2),
+            delay2: DelayLine::new(scale(lengths[3])),
+            delay2_length: scale(lengths[3]),
+        }
+    }
+
+    fn output(&self) -> f64 {
+        self.delay2.tap(self.delay2_length)
+    }
+
+    fn process(&mut self, x: f64, modulated_delay: f64, decay: f64, damping: f64) {
+        let x = self.modulated.process_modulated(x, modulated_delay);
+        let x = self.delay1.process(x, self.delay1_length);
+        self.damping_state = (1.0 - damping) * x + damping * self.damping_state;
+        let x = self.diffuser.process(self.damping_state * decay);
+        self.delay2.push(x);
+    }
+
+    fn node(&self, index: usize, pos: usize) -> f64 {
+        match index {
+            0 => self.delay1.tap(pos),
+            1 => self.diffuser.line.tap(pos),
+            _ => self.delay2.tap(pos),
+        }
+    }
+}
+
+/// A plate reverb following the topology described in Jon Dattorro, "Effect
+/// Design Part 1: Reverberator and Other Filters" (1997).
+///
+/// - `decay`: the gain of the tank's feedback loop (0.0 - 1.0)
+/// - `damping`: the amount of the high frequency damping in the tank (0.0 - 1.0)
+/// - `pre_delay`: the delay before the input reaches the tank
+///
+/// The early reflections can be added by `with_early_reflections()`.
+///
+/// With `with_freeze()`, the tail can be frozen: the input to the tank is
+/// muted, and the tank loses nothing (the decay of 1.0 without the damping),
+/// so the current tail sustains indefinitely as a pad (without the
+/// modulation), while the dry signal still passes.
+pub struct PlateReverb<S: Signal<Frame = f64>> {
+    signal: S,
+    fs: f64, // sampling rate
+    decay: f64,
+    damping: f64,
+    mix: f64,
+    early: EarlyReflections,
+    bandwidth_state: f64,
+    input_diffusers: Vec<Allpass>,
+    left: TankHalf,
+    right: TankHalf,
+    lfo_phase: f64,
+    excursion: f64,
+    left_taps: [(bool, usize, usize, f64); 7],
+    right_taps: [(bool, usize, usize, f64); 7],
+    quality: Quality,
+    // the flag, and how much it's frozen (ramped between 0.0 and 1.0)
+    freeze: Option<Arc<AtomicBool>>,
+    frozen: SmoothedParam,
+}
+
+impl<S: Signal<Frame = f64>> PlateReverb<S> {
+    pub fn new(signal: S, fs: f64, decay: f64, damping: f64, pre_delay: Ms) -> Self {
+        let scale = |n: usize| (n as f64 * fs / DATTORRO_FS).round() as usize;
+
+        let input_diffusers = INPUT_DIFFUSERS
+            .iter()
+            .map(|&(n, g)| Allpass::new(scale(n), scale(n), g))
+            .collect();
+
+        let scale_taps = |taps: [(bool, usize, usize, f64); 7]| {
+            taps.map(|(is_left, index, pos, sign)| (is_left, index, scale(pos).max(1), sign))
+        };
+
+        let decay = decay.clamp(0.0, 1.0);
+        let decay_diffusion_2 = (decay + 0.15).clamp(0.25, 0.5);
+        let excursion = EXCURSION * fs / DATTORRO_FS;
+
+        Self {
+            signal,
+            fs,
+            decay,
+            damping: damping.clamp(0.0, 1.0),
+            mix: 0.5,
+            early: EarlyReflections::new(fs, pre_delay),
+            bandwidth_state: 0.0,
+            input_diffusers,
+            left: TankHalf::new(LEFT_TANK, scale, excursion, decay_diffusion_2),
+            right: TankHalf::new(RIGHT_TANK, scale, excursion, decay_diffusion_2),
+            lfo_phase: 0.0,
+            excursion,
+            left_taps: scale_taps(LEFT_OUTPUT_TAPS),
+            right_taps: scale_taps(RIGHT_OUTPUT_TAPS),
+            quality: quality::global(),
+            freeze: None,
+            frozen: SmoothedParam::new(fs, FREEZE_FADE, 0.0),
+        }
+    }
+
+    /// The low quality uses only the first 2 of the 4 input diffusers and 4
+    /// of the 7 output taps of each channel (scaled to keep the level), and
+    /// doesn't modulate the tank. The normal and the high quality are the
+    /// same, the full algorithm.
+    pub fn set_quality(&mut self, quality: Quality) {
+        self.quality = quality;
+    }
+
+    /// Sets the ratio of the reverberated signal in the output (default: 0.5).
+    pub fn with_mix(mut self, mix: f64) -> Self {
+        self.mix = mix.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Adds the early reflections; each tap is (time, gain).
+    pub fn with_early_reflections(mut self, taps: &[(Ms, f64)]) -> Self {
+        self.early = self.early.with_taps(taps);
+        self
+    }
+
+    /// Freezes the tail while the flag is set (e.g. toggled from a UI). The
+    /// change is ramped over 50 ms, so it doesn't click.
+    pub fn with_freeze(mut self, freeze: Arc<AtomicBool>) -> Self {
+        self.freeze = Some(freeze);
+        self
+    }
+
+    fn tap_output(&self, taps: &[(bool, usize, usize, f64)]) -> f64 {
+        // the taps are mostly uncorrelated, so the level is proportional to
+        // the square root of the number of them
+        let (taps, gain) = if self.quality == Quality::Low {
+            (&taps[..4], OUTPUT_GAIN * (7.0_f64 / 4.0).sqrt())
+        } else {
+            (taps, OUTPUT_GAIN)
+        };
+        taps.iter()
+            .map(|&(is_left, index, pos, sign)| {
+                let half = if is_left { &self.left } else { &self.right };
+                sign * half.node(index, pos)
+            })
+            .sum::<f64>()
+            * gain
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for PlateReverb<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let orig = self.signal.next();
+
+        let (reflections, x) = self.early.process(orig);
+
+        if let Some(freeze) = &self.freeze {
+            let target = if freeze.load(Ordering::Relaxed) {
+                1.0
+            } else {
+                0.0
+            };
+            self.frozen.set_target(target);
+        }
+        let frozen = self.frozen.next_value();
+        let x = (1.0 - frozen) * x;
+        let decay = self.decay + (1.0 - self.decay) * frozen;
+        let damping = (1.0 - frozen) * self.damping;
+
+        self.bandwidth_state = BANDWIDTH * x + (1.0 - BANDWIDTH) * self.bandwidth_state;
+        let diffusers = if self.quality == Quality::Low { 2 } else { 4 };
+        let diffused = self.input_diffusers[..diffusers]
+            .iter_mut()
+            .fold(self.bandwidth_state, |x, ap| ap.process(x));
+
+        // figure-eight: the output of one half is fed to the other half
+        let left_feedback = self.left.output();
+        let right_feedback = self.right.output();
+
+        let lfo = 2.0 * std::f64::consts::PI * self.lfo_phase;
+        self.lfo_phase = (self.lfo_phase + MOD_HZ / self.fs).fract();
+
+        // the interpolation of the modulated delays loses a bit of the highs
+        // on every pass, which would make a frozen tail fade out, so the
+        // modulation stops while frozen
+        let excursion = if self.quality == Quality::Low {
+            0.0
+        } else {
+            (1.0 - frozen) * self.excursion
+        };
+        let left_delay = self.left.modulated.delay as f64 + excursion * lfo.sin();
+        let right_delay = self.right.modulated.delay as f64 + excursion * lfo.cos();
+
+        self.left.process(
+            diffused + decay * right_feedback,
+            left_delay,
+            decay,
+            damping,
+        );
+        self.right.process(
+            diffused + decay * left_feedback,
+            right_delay,
+            decay,
+            damping,
+        );
+
+        let tail = (self.tap_output(&self.left_taps) + self.tap_output(&self.right_taps)) / 2.0;
+        let wet = (1.0 - frozen) * reflections + tail;
+
+        (1.0 - self.mix) * orig + self.mix * wet
+    }
+}
+
+impl<S: Signal<Frame = f64>> HasTail for PlateReverb<S> {
+    fn has_tail(&self) -> bool {
+        self.mix > 0.0
+    }
+}
+
+// The coefficients of the all-pass pair whose outputs differ in phase by 90
+// degrees over almost the whole band, designed by Olli Niemitalo.
+// c.f. https://yehar.com/blog/?p=368
+const HILBERT_COEFS_1: [f64; 4] = [
+    0.479400865589,
+    0.876218493539,
+    0.976597589508,
+    0.997499255936,
+];
+const HILBERT_COEFS_2: [f64; 4] = [
+    0.161758498368,
+    0.733028932341,
+    0.945349700329,
+    0.990599156684,
+];
+
+/// A chain of second-order all-pass sections, y(t) = a^2 (x(t) + y(t-2)) - x(t-2)
+struct AllpassChain {
+    coefs: [f64; 4],
+    x: [[f64; 2]; 4], // [x(t-1), x(t-2)] of each section
+    y: [[f64; 2]; 4], // [y(t-1), y(t-2)] of each section
+}
+
+impl AllpassChain {
+    fn new(coefs: [f64; 4]) -> Self {
+        Self {
+            coefs: coefs.map(|a| a * a),
+            x: [[0.0; 2]; 4],
+            y: [[0.0; 2]; 4],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let mut input = x;
+        for i in 0..4 {
+            let out = self.coefs[i] * (input + self.y[i][1]) - self.x[i][1];
+            self.x[i] = [input, self.x[i][0]];
+            self.y[i] = [out, self.y[i][0]];
+            input = out;
+        }
+        input
+    }
+}
+
+/// A Hilbert transformer that produces the analytic signal, i.e. a pair of
+/// signals (real, imaginary) where the imaginary part is the real part shifted
+/// by 90 degrees.
+pub struct Hilbert {
+    real: AllpassChain,
+    real_delayed: f64,
+    imag: AllpassChain,
+}
+
+impl Hilbert {
+    pub fn new() -> Self {
+        Self {
+            real: AllpassChain::new(HILBERT_COEFS_1),
+            real_delayed: 0.0,
+            imag: AllpassChain::new(HILBERT_COEFS_2),
+        }
+    }
+
+    pub fn process(&mut self, x: f64) -> (f64, f64) {
+        // the first path is delayed by one sample
+        let real = self.real_delayed;
+        self.real_delayed = self.real.process(x);
+
+        // the second path leads the first by 90 degrees, so negate it
+        (real, -self.imag.process(x))
+    }
+}
+
+impl Default for Hilbert {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shifts all the frequencies of the signal by `shift` (which can be
+/// negative), using single-sideband modulation. Unlike ring modulation, this
+/// produces only one sideband, so the harmonic relationship is broken and the
+/// result sounds inharmonic and metallic.
+pub struct FrequencyShifter<S: Signal<Frame = f64>> {
+    signal: S,
+    fs: f64, // sampling rate
+    shift_hz: f64,
+    hilbert: Hilbert,
+    phase: f64,
+}
+
+impl<S: Signal<Frame = f64>> FrequencyShifter<S> {
+    pub fn new(signal: S, fs: f64, shift: Hz) -> Self {
+        Self {
+            signal,
+            fs,
+            shift_hz: shift.0,
+            hilbert: Hilbert::new(),
+            phase: 0.0,
+        }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for FrequencyShifter<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let (real, imag) = self.hilbert.process(self.signal.next());
+
+        let theta = 2.0 * std::f64::consts::PI * self.phase;
+        self.phase = (self.phase + self.shift_hz / self.fs).rem_euclid(1.0);
+
+        // the real part of (real + i imag) * exp(i theta)
+        real * theta.cos() - imag * theta.sin()
+    }
+}
+
+/// A spectral gate for basic noise reduction. The spectrum of the first
+/// `learn` frames of the signal is taken as the noise profile (so the signal
+/// should start with noise only), and after that, the frequency bins that are
+/// not louder than the profile by the threshold are attenuated by `reduction`.
+///
+/// The STFT uses the Hann window with the hop size of a quarter of the FFT
+/// size, so the output is delayed by the FFT size.
+pub struct SpectralGate<S: Signal<Frame = f64>> {
+    signal: S,
+    fft: Fft,
+    window: Vec<f64>,
+    hop: usize,
+    input: Vec<f64>,
+    output: Vec<f64>,
+    spectrum: Vec<Complex>,
+    count: usize,
+    // the average magnitude of each bin during the learning
+    noise_profile: Vec<f64>,
+    learn_hops: usize,
+    learned_hops: usize,
+    threshold: f64,
+    reduction: f64,
+}
+
+impl<S: Signal<Frame = f64>> SpectralGate<S> {
+    /// `fft_size` must be a power of 2 and at least 4. `reduction` is the
+    /// attenuation of the gated bins (e.g. `Db(-30.0)`).
+    pub fn new(signal: S, fft_size: usize, learn: Frames, reduction: Db) -> Self {
+        assert!(fft_size >= 4, "the FFT size must be at least 4");
+
+        let hop = fft_size / 4;
+        Self {
+            signal,
+            fft: Fft::new(fft_size),
+            window: hann(fft_size),
+            hop,
+            input: vec![0.0; fft_size],
+            output: vec![0.0; fft_size],
+            spectrum: vec![Complex::default(); fft_size],
+            count: 0,
+            noise_profile: vec![0.0; fft_size],
+            learn_hops: learn.0.div_ceil(hop),
+            learned_hops: 0,
+            threshold: Db(6.0).to_gain(),
+            reduction: reduction.to_gain(),
+        }
+    }
+
+    /// How much louder than the noise profile a bin must be to pass. The
+    /// default is 6 dB.
+    pub fn with_threshold(mut self, threshold: Db) -> Self {
+        self.threshold = threshold.to_gain();
+        self
+    }
+
+    fn process_frame(&mut self) {
+        for ((c, &x), &w) in self.spectrum.iter_mut().zip(&self.input).zip(&self.window) {
+            *c = Complex::new(x * w, 0.0);
+        }
+        self.fft.forward(&mut self.spectrum);
+
+        if self.learned_hops < self.learn_hops {
+            self.learned_hops += 1;
+            let k = 1.0 / self.learn_hops as f64;
+            for (n, c) in self.noise_profile.iter_mut().zip(&self.spectrum) {
+                *n += c.norm() * k;
+            }
+        } else {
+            // since the input is real, the gains of bin k and bin n - k are the
+            // same and the result stays real
+            for (c, &n) in self.spectrum.iter_mut().zip(&self.noise_profile) {
+                if c.norm() <= n * self.threshold {
+                    *c = c.scale(self.reduction);
+                }
+            }
+        }
+
+        self.fft.inverse(&mut self.spectrum);
+
+        // the overlapping windows (analysis and synthesis) sum to 1.5
+        self.output.copy_within(self.hop.., 0);
+        let len = self.output.len();
+        self.output[len - self.hop..].fill(0.0);
+        for ((y, c), &w) in self.output.iter_mut().zip(&self.spectrum).zip(&self.window) {
+            *y += c.re * w / 1.5;
+        }
+
+        self.input.copy_within(self.hop.., 0);
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for SpectralGate<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let len = self.input.len();
+        self.input[len - self.hop + self.count] = self.signal.next();
+        let out = self.output[self.count];
+
+        self.count += 1;
+        if self.count == self.hop {
+            self.count = 0;
+            self.process_frame();
+        }
+
+        out
+    }
+}
+
+impl<S: Signal<Frame = f64>> Latency for SpectralGate<S> {
+    fn latency_frames(&self) -> usize {
+        self.input.len()
+    }
+}
+
+/// Changes the duration of the signal by `ratio` (e.g. 2.0 makes it twice as
+/// long) without changing the pitch, using a phase vocoder. The frames of the
+/// STFT are taken every `hop / ratio` frames and overlap-added every `hop`
+/// frames, and the phase of each bin is advanced by its estimated true
+/// frequency so that the partials stay continuous.
+///
+/// This pulls the inner signal `1 / ratio` times as fast as it's pulled. The
+/// output is delayed by `fft_size * 3 / 4` frames (of the output), and
+/// transients get smeared, as is usual for a phase vocoder.
+pub struct TimeStretch<S: Signal<Frame = f64>> {
+    signal: S,
+    fft: Fft,
+    window: Vec<f64>,
+    hop: usize,
+    ratio: f64,
+    // the input from the absolute position `input_offset`
+    input: Vec<f64>,
+    input_offset: usize,
+    // the absolute position of the next analysis frame
+    analysis_pos: f64,
+    prev_analysis_start: Option<usize>,
+    // the magnitude and phase of each bin (up to the Nyquist frequency)
+    mag: Vec<f64>,
+    phase: Vec<f64>,
+    prev_phase: Vec<f64>,
+    synth_phase: Vec<f64>,
+    peaks: Vec<usize>,
+    spectrum: Vec<Complex>,
+    output: Vec<f64>,
+    count: usize,
+}
+
+impl<S: Signal<Frame = f64>> TimeStretch<S> {
+    /// `fft_size` must be a power of 2 and at least 4. `ratio` must be
+    /// positive.
+    pub fn new(signal: S, ratio: f64, fft_size: usize) -> Self {
+        assert!(fft_size >= 4, "the FFT size must be at least 4");
+        assert!(ratio > 0.0, "the ratio must be positive");
+
+        let hop = fft_size / 4;
+        let bins = fft_size / 2 + 1;
+        Self {
+            signal,
+            fft: Fft::new(fft_size),
+            window: hann(fft_size),
+            hop,
+            ratio,
+            // start with the frames that end at the beginning of the signal, so
+            // that the output doesn't fade in
+            input: vec![0.0; fft_size - hop],
+            input_offset: 0,
+            analysis_pos: 0.0,
+            prev_analysis_start: None,
+            mag: vec![0.0; bins],
+            phase: vec![0.0; bins],
+            prev_phase: vec![0.0; bins],
+            synth_phase: vec![0.0; bins],
+            peaks: Vec::with_capacity(bins),
+            spectrum: vec![Complex::default(); fft_size],
+            output: vec![0.0; fft_size],
+            count: 0,
+        }
+    }
+
+    fn process_frame(&mut self) {
+        let n = self.fft.len();
+        let start = self.analysis_pos.round() as usize;
+        self.analysis_pos += self.hop as f64 / self.ratio;
+
+        // drop the input before the frame, and pull the input until the end
+        // of the frame
+        let consumed = start - self.input_offset;
+        self.input.drain(..consumed.min(self.input.len()));
+        self.input_offset = start;
+        while self.input.len() < n {
+            self.input.push(self.signal.next());
+        }
+
+        for ((c, &x), &w) in self.spectrum.iter_mut().zip(&self.input).zip(&self.window) {
+            *c = Complex::new(x * w, 0.0);
+        }
+        self.fft.forward(&mut self.spectrum);
+
+        let pi = std::f64::consts::PI;
+        let bins = n / 2 + 1;
+        for k in 0..bins {
+            self.mag[k] = self.spectrum[k].norm();
+            self.phase[k] = self.spectrum[k].arg();
+        }
+
+        // the local maxima of the magnitude, which are taken as the partials
+        self.peaks.clear();
+        for k in 0..bins {
+            let left = k == 0 || self.mag[k] > self.mag[k - 1];
+            let right = k == bins - 1 || self.mag[k] >= self.mag[k + 1];
+            if left && right {
+                self.peaks.push(k);
+            }
+        }
+
+        let analysis_hop = self.prev_analysis_start.map(|prev| start - prev);
+        for &k in &self.peaks {
+            let omega = 2.0 * pi * k as f64 / n as f64; // the bin frequency
+
+            self.synth_phase[k] = match analysis_hop {
+                Some(h) if h > 0 => {
+                    // the deviation from the phase advance expected for the bin
+                    // frequency tells the true frequency of the partial
+                    let expected = self.prev_phase[k] + omega * h as f64;
+                    let deviation = (self.phase[k] - expected + pi).rem_euclid(2.0 * pi) - pi;
+                    let freq = omega + deviation / h as f64;
+                    self.synth_phase[k] + freq * self.hop as f64
+                }
+                Some(_) => self.synth_phase[k] + omega * self.hop as f64,
+                None => self.phase[k],
+            };
+        }
+
+        // Lock the phases of the other bins to the nearest peak, keeping the
+        // phase differences in the analysis frame (the identity phase locking
+        // by Laroche and Dolson). Otherwise, the bins of the same partial get
+        // the different errors of the phase, which cancel each other out.
+        let mut i = 0;
+        for k in 0..bins {
+            while i + 1 < self.peaks.len()
+                && self.peaks[i + 1].abs_diff(k) < self.peaks[i].abs_diff(k)
+            {
+                i += 1;
+            }
+            let p = self.peaks[i];
+            if k != p {
+                self.synth_phase[k] = self.synth_phase[p] + self.phase[k] - self.phase[p];
+            }
+        }
+
+        for k in 0..bins {
+            self.spectrum[k] = Complex::from_angle(self.synth_phase[k]).scale(self.mag[k]);
+        }
+        self.prev_phase.copy_from_slice(&self.phase);
+
+        // keep the conjugate symmetry so that the output is real
+        for k in 1..n / 2 {
+            self.spectrum[n - k] = self.spectrum[k].conj();
+        }
+        self.prev_analysis_start = Some(start);
+
+        self.fft.inverse(&mut self.spectrum);
+
+        // the overlapping windows (analysis and synthesis) sum to 1.5
+        self.output.copy_within(self.hop.., 0);
+        self.output[n - self.hop..].fill(0.0);
+        for ((y, c), &w) in self.output.iter_mut().zip(&self.spectrum).zip(&self.window) {
+            *y += c.re * w / 1.5;
+        }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for TimeStretch<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        if self.count == 0 {
+            self.process_frame();
+        }
+
+        let out = self.output[self.count];
+        self.count = (self.count + 1) % self.hop;
+
+        out
+    }
+}
+
+impl<S: Signal<Frame = f64>> Latency for TimeStretch<S> {
+    fn latency_frames(&self) -> usize {
+        self.fft.len() - self.hop
+    }
+}
+
+/// Stretches `input` by `ratio` with `TimeStretch`, compensating the latency.
+/// The length of the result is `input.len() * ratio` (rounded).
+pub fn time_stretch(input: &[f64], ratio: f64, fft_size: usize) -> Vec<f64> {
+    let len = (input.len() as f64 * ratio).round() as usize;
+    let signal = dasp::signal::from_iter(input.iter().copied());
+    let stretch = TimeStretch::new(signal, ratio, fft_size);
+    let latency = stretch.latency_frames();
+    stretch.take(latency + len).skip(latency).collect()
+}
+
+/// Stretches `input` by `ratio` with `Wsola` of the default parameters. See
+/// `Wsola::stretch()`.
+pub fn stretch(input: &[f64], fs: f64, ratio: f64) -> Vec<f64> {
+    Wsola::new(fs).stretch(input, ratio)
+}
+
+/// Changes the duration of a buffer without changing the pitch by WSOLA
+/// (waveform similarity overlap-add, by Verhelst and Roelands). The segments
+/// of the input are overlap-added every `hop` of the output and taken every
+/// `hop / ratio` of the input, but each one is shifted within the `search`
+/// range to the position most similar to the natural continuation of the
+/// previous segment, so that the waveforms line up on the overlaps.
+///
+/// Unlike `TimeStretch` (a phase vocoder), this copies the waveform as is, so
+/// the transients (e.g. the attacks of plucks) stay sharp, though a
+/// transient can be repeated or skipped when the segments are longer than the
+/// gap between the transients. Shorter windows smear less, but the window
+/// and the search range should cover a period of the lowest pitch.
+#[derive(Clone, Copy, Debug)]
+pub struct Wsola {
+    window: usize,
+    hop: usize,
+    search: usize,
+}
+
+impl Wsola {
+    /// The window is 20 ms, the hop is 10 ms, and the search range is
+    /// ±8 ms.
+    pub fn new(fs: f64) -> Self {
+        Self {
+            window: Ms(20.0).to_frames(fs).0.max(2),
+            hop: Ms(10.0).to_frames(fs).0.max(1),
+            search: Ms(8.0).to_frames(fs).0,
+        }
+    }
+
+    /// Sets the length of the segments, which must be at least twice the hop.
+    pub fn with_window(mut self, window: Frames) -> Self {
+        self.window = window.0;
+        self
+    }
+
+    /// Sets the interval of the segments on the output.
+    pub fn with_hop(mut self, hop: Frames) -> Self {
+        self.hop = hop.0;
+        self
+    }
+
+    /// Sets how far a segment can be shifted in either direction.
+    pub fn with_search(mut self, search: Frames) -> Self {
+        self.search = search.0;
+        self
+    }
+
+    /// Stretches `input` by `ratio` (e.g. 2.0 makes it twice as long), which
+    /// must be positive. The length of the result is `input.len() * ratio`
+    /// (rounded). With the ratio of 1.0, the result is the input.
+    pub fn stretch(&self, input: &[f64], ratio: f64) -> Vec<f64> {
+        assert!(ratio > 0.0, "the ratio must be positive");
+        assert!(
+            self.hop > 0 && self.window >= 2 * self.hop,
+            "the window must be at least twice the hop"
+        );
+        let (n, hop, search) = (self.window, self.hop, self.search);
+        let len = (input.len() as f64 * ratio).round() as usize;
+        let window = hann(n);
+
+        // the input padded with zeros, so that the segments can start before
+        // the beginning and end after the end
+        let pad = n + search;
+        let mut padded = vec![0.0; pad];
+        padded.extend_from_slice(input);
+        // (the last segment is nominally at most `(n / 2 + hop) / ratio`
+        // after the end, and the target of the search is a hop later)
+        let tail = ((n / 2 + hop) as f64 / ratio).ceil() as usize + hop + 1;
+        padded.resize(padded.len() + pad + tail, 0.0);
+        // the segment starting at `pos` of the input (which can be negative)
+        let segment = |pos: isize| {
+            let start = (pos + pad as isize) as usize;
+            &padded[start..start + n]
+        };
+
+        // the segments are centered on the multiples of the hop, so that the
+        // first one fades out the output from the beginning, not in
+        let half = n as isize / 2;
+        let mut output = vec![0.0; len + n];
+        let mut weight = vec![0.0; len + n];
+        let mut prev: Option<isize> = None;
+        let mut k = 0;
+        while k * hop < len + n / 2 {
+            let nominal = (k as f64 * hop as f64 / ratio).round() as isize - half;
+            let pos = match prev {
+                Some(prev) => {
+                    let target = segment(prev + hop as isize);
+                    best_shift(target, |d| segment(nominal + d), search)
+                        .map_or(nominal, |d| nominal + d)
+                }
+                None => nominal,
+            };
+            prev = Some(pos);
+
+            // the output position of the segment, whose part before the
+            // beginning is dropped
+            let out_start = (k * hop) as isize - half;
+            for (i, (&x, &w)) in segment(pos).iter().zip(&window).enumerate() {
+                let j = out_start + i as isize;
+                if j >= 0 {
+                    output[j as usize] += x * w;
+                    weight[j as usize] += w;
+                }
+            }
+            k += 1;
+        }
+
+        output.truncate(len);
+        for (y, &w) in output.iter_mut().zip(&weight) {
+            if w > 1e-9 {
+                *y /= w;
+            }
+        }
+        output
+    }
+}
+
+/// The shift in `-search..=search` where the candidate segment correlates
+/// best with `target`, preferring the smaller shifts on ties, or `None` if
+/// all the candidates are silent.
+fn best_shift<'a, F>(target: &[f64], candidate: F, search: usize) -> Option<isize>
+where
+    F: Fn(isize) -> &'a [f64],
+{
+    let mut best: Option<(isize, f64)> = None;
+    let search = search as isize;
+    let shifts = std::iter::once(0).chain((1..=search).flat_map(|d| [d, -d]));
+    for d in shifts {
+        let c = candidate(d);
+        let energy: f64 = c.iter().map(|x| x * x).sum();
+        if energy <= 0.0 {
+            continue;
+        }
+        // normalized by the candidate only, as the target is the same
+        let score = target.iter().zip(c).map(|(a, b)| a * b).sum::<f64>() / energy.sqrt();
+        if best.is_none_or(|(_, s)| score > s) {
+            best = Some((d, score));
+        }
+    }
+    best.map(|(d, _)| d)
+}
+
+/// Plays a buffer at the position set by another thread (0.0 is the start and
+/// 1.0 is the end), by overlap-adding Hann-windowed grains: every half of a
+/// grain, a new grain starts reading the buffer at the current position at the
+/// original speed. So moving the position at any rate "scrubs" through the
+/// buffer without changing the pitch, and holding it still sustains a drone of
+/// the sound at the position.
+///
+/// As in `Wsola`, each grain is shifted within the search range to where it
+/// continues the previous grain best, so that the waveforms line up on the
+/// overlaps instead of beating (which a drone of the grains at exactly the
+/// same position would, unless the hop happens to be a multiple of the
+/// period).
+pub struct Scrubber {
+    samples: Vec<f64>,
+    position: Arc<AtomicF64>,
+    window: Vec<f64>,
+    search: usize,
+    // the positions in the buffer of the grains playing, and their ages
+    grains: Vec<(usize, usize)>,
+    // the position of the latest grain
+    prev: Option<usize>,
+    cur_frame: usize,
+}
+
+impl Scrubber {
+    /// The grains are 50 ms long, and the search range is ±12 ms.
+    pub fn new(samples: Vec<f64>, fs: f64, position: Arc<AtomicF64>) -> Self {
+        Self {
+            samples,
+            position,
+            window: vec![],
+            search: Ms(12.0).to_frames(fs).0,
+            grains: Vec::with_capacity(2),
+            prev: None,
+            cur_frame: 0,
+        }
+        .with_grain(Ms(50.0).to_frames(fs))
+    }
+
+    /// Sets the length of the grains (rounded down to even). Longer grains
+    /// sound smoother but smear the position more.
+    pub fn with_grain(mut self, grain: Frames) -> Self {
+        self.window = hann((grain.0 / 2).max(1) * 2);
+        self
+    }
+
+    /// Sets how far a grain can be shifted in either direction, which should
+    /// cover a period of the lowest pitch. 0 disables the search.
+    pub fn with_search(mut self, search: Frames) -> Self {
+        self.search = search.0;
+        self
+    }
+
+    // the start of a new grain in the buffer
+    fn grain_start(&self) -> usize {
+        let n = self.window.len();
+        let last = self.samples.len().saturating_sub(n) as isize;
+        let position = self.position.get().clamp(0.0, 1.0);
+        let nominal = (position * last as f64).round() as isize;
+
+        let Some(prev) = self.prev else {
+            return nominal as usize;
+        };
+        // the segment of `n` frames from `pos`, clamped to the buffer
+        let segment = |pos: isize| {
+            let start = pos.clamp(0, last) as usize;
+            &self.samples[start..(start + n).min(self.samples.len())]
+        };
+        let target = segment((prev + n / 2) as isize);
+        let shift = best_shift(target, |d| segment(nominal + d), self.search).unwrap_or(0);
+        (nominal + shift).clamp(0, last) as usize
+    }
+}
+
+impl Signal for Scrubber {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        // the Hann windows overlapped by half sum up to 1
+        let n = self.window.len();
+        if self.cur_frame.is_multiple_of(n / 2) {
+            let start = self.grain_start();
+            self.grains.push((start, 0));
+            self.prev = Some(start);
+        }
+        self.cur_frame += 1;
+
+        let mut y = 0.0;
+        for (start, age) in &mut self.grains {
+            let x = self.samples.get(*start + *age).copied().unwrap_or(0.0);
+            y += self.window[*age] * x;
+            *age += 1;
+        }
+        self.grains.retain(|&(_, age)| age < n);
+        y
+    }
+}
+
+// the corner and the amount of the pre-emphasis before the saturation
+const TAPE_EMPHASIS_FREQ: Hz = Hz(3000.0);
+const TAPE_EMPHASIS_GAIN: Db = Db(6.0);
+
+/// The amplitude (in frames) of a sinusoidal delay modulation at `rate` that
+/// deviates the pitch by `cents` at most. The pitch ratio is `1 - d'(t)`, and
+/// the derivative of `a sin(2 pi f t)` is at most `2 pi f a`.
+fn delay_amplitude_for_cents(fs: f64, rate: Hz, cents: f64) -> f64 {
+    if rate.0 <= 0.0 {
+        return 0.0;
+    }
+    (2.0_f64.powf(cents.abs() / 1200.0) - 1.0) / (2.0 * std::f64::consts::PI * rate.normalized(fs))
+}
+
+/// An emulation of a tape machine, which is off by default and consists of:
+///
+/// - wow: slow pitch drift by a sinusoidal modulation of the delay
+/// - flutter: faster, irregular pitch wobble by band-pass filtered noise
+/// - saturation: `tanh` between a pre-emphasis and a de-emphasis filter, so
+///   the highs saturate earlier, as on tape
+/// - hiss: white noise at a constant level
+///
+/// The signal is delayed by the depth of the wow and flutter (0 when both
+/// are off), and nulls against the dry signal when everything is off.
+pub struct Tape<S: Signal<Frame = f64>> {
+    signal: S,
+    fs: f64, // sampling rate
+    line: DelayLine,
+    base_delay: f64,
+    wow_rate: f64, // in cycles per sample
+    wow_amplitude: f64,
+    wow_phase: f64,
+    flutter_filter: Biquad,
+    flutter_amplitude: f64,
+    flutter_noise: Noise,
+    drive: f64,
+    pre_emphasis: Biquad,
+    de_emphasis: Biquad,
+    hiss_gain: f64,
+    hiss_noise: Noise,
+}
+
+impl<S: Signal<Frame = f64>> Tape<S> {
+    pub fn new(signal: S, fs: f64) -> Self {
+        Self {
+            signal,
+            fs,
+            line: DelayLine::new(1),
+            base_delay: 0.0,
+            wow_rate: 0.0,
+            wow_amplitude: 0.0,
+            wow_phase: 0.0,
+            flutter_filter: Biquad::band_pass(fs, Hz(6.0), 2.0),
+            flutter_amplitude: 0.0,
+            flutter_noise: Noise::new(1),
+            drive: 0.0,
+            pre_emphasis: Biquad::high_shelf(
+                fs,
+                TAPE_EMPHASIS_FREQ,
+                FRAC_1_SQRT_2,
+                TAPE_EMPHASIS_GAIN,
+            ),
+            de_emphasis: Biquad::high_shelf(
+                fs,
+                TAPE_EMPHASIS_FREQ,
+                FRAC_1_SQRT_2,
+                Db(-TAPE_EMPHASIS_GAIN.0),
+            ),
+            hiss_gain: 0.0,
+            hiss_noise: Noise::new(2),
+        }
+    }
+
+    /// The wow at `rate` (typically around 0.5 Hz) that deviates the pitch by
+    /// `depth` cents at most.
+    pub fn with_wow(mut self, rate: Hz, depth: f64) -> Self {
+        self.wow_rate = rate.normalized(self.fs);
+        self.wow_amplitude = delay_amplitude_for_cents(self.fs, rate, depth);
+        self.resize_delay();
+        self
+    }
+
+    /// The flutter around `rate` (typically around 6 Hz). `depth` is roughly
+    /// the RMS deviation of the pitch in cents; since this is noise, the
+    /// peaks go a few times beyond.
+    pub fn with_flutter(mut self, rate: Hz, depth: f64) -> Self {
+        let q = 2.0;
+        self.flutter_filter = Biquad::band_pass(self.fs, rate, q);
+
+        // Scale the filtered noise so that its RMS is the same as the sine of
+        // the amplitude 1. The noise within [-1, 1) has the variance of 1/3,
+        // and the noise-equivalent bandwidth of the band-pass is pi / 2 times
+        // its bandwidth (rate / q).
+        let bandwidth = std::f64::consts::FRAC_PI_2 * rate.0 / q;
+        let variance = 1.0 / 3.0 * bandwidth / (self.fs / 2.0);
+        let scale = (0.5 / variance).sqrt();
+
+        self.flutter_amplitude = delay_amplitude_for_cents(self.fs, rate, depth) * scale;
+        self.resize_delay();
+        self
+    }
+
+    /// `drive` is the gain into the `tanh`; larger saturates more. 0 disables
+    /// the saturation.
+    pub fn with_saturation(mut self, drive: f64) -> Self {
+        self.drive = drive.max(0.0);
+        self
+    }
+
+    /// The level of the hiss (RMS in dBFS), e.g. `Db(-60.0)`.
+    pub fn with_hiss(mut self, level: Db) -> Self {
+        // the RMS of the noise within [-1, 1) is 1 / sqrt(3)
+        self.hiss_gain = level.to_gain() * 3.0_f64.sqrt();
+        self
+    }
+
+    fn resize_delay(&mut self) {
+        // The flutter is noise, so leave room for 3 times its amplitude (the
+        // scale is for the RMS of a sine, which is amplitude / sqrt(2)).
+        self.base_delay = self.wow_amplitude + 3.0 * FRAC_1_SQRT_2 * self.flutter_amplitude;
+        self.line = DelayLine::new((self.base_delay * 2.0).ceil() as usize + 2);
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for Tape<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let mut x = self.signal.next();
+
+        if self.drive > 0.0 {
+            let emphasized = self.pre_emphasis.process(x);
+            let saturated = (self.drive * emphasized).tanh() / self.drive;
+            x = self.de_emphasis.process(saturated);
+        }
+
+        let wow = self.wow_amplitude * (2.0 * std::f64::consts::PI * self.wow_phase).sin();
+        self.wow_phase = (self.wow_phase + self.wow_rate).rem_euclid(1.0);
+
+        let flutter = if self.flutter_amplitude > 0.0 {
+            let noise = self.flutter_noise.next_sample();
+            self.flutter_amplitude * self.flutter_filter.process(noise)
+        } else {
+            0.0
+        };
+
+        // tap(1) is the sample just pushed, i.e. no delay
+        self.line.push(x);
+        let max_delay = self.base_delay * 2.0;
+        let delay = (self.base_delay + wow + flutter).clamp(0.0, max_delay);
+        let out = self.line.tap_fractional(1.0 + delay);
+
+        out + self.hiss_gain * self.hiss_noise.next_sample()
+    }
+}
+
+/// Sweeps a mono signal across the stereo field with an LFO, producing
+/// stereo frames. The gains follow the equal-power pan law, and optionally,
+/// the channel on the far side is delayed a bit more as the source moves away
+/// from the center, which makes the position clearer by the Haas effect.
+///
+/// The rate is free or synced to the tempo, e.g.
+/// `LfoRate::Division(NoteDivision::Straight(1))` for a cycle per bar of 4/4.
+/// With `with_clock()`, the LFO follows the frames counted by the clock, so
+/// that it stays on the beat from the start of the song.
+pub struct AutoPanner<S: Signal<Frame = f64>> {
+    signal: S,
+    fs: f64, // sampling rate
+    shape: LfoShape,
+    depth: f64,
+    lfo: LfoPhase,
+    haas_delay: f64,
+    left: DelayLine,
+    right: DelayLine,
+}
+
+impl<S: Signal<Frame = f64>> AutoPanner<S> {
+    /// `depth` is 0.0 (stays at the center) to 1.0 (sweeps from hard left to
+    /// hard right), and the pan sweeps once per cycle of `rate` at the tempo
+    /// of `bpm`, starting from the center.
+    pub fn new(signal: S, fs: f64, rate: LfoRate, bpm: f64, shape: LfoShape, depth: f64) -> Self {
+        Self {
+            signal,
+            fs,
+            shape,
+            depth: depth.clamp(0.0, 1.0),
+            lfo: LfoPhase::new(rate.cycle(fs, bpm)),
+            haas_delay: 0.0,
+            left: DelayLine::new(1),
+            right: DelayLine::new(1),
+        }
+    }
+
+    /// Follows the clock (advanced by the audio thread, e.g. by `Clocked`),
+    /// so that the pan is at the center on every cycle from its frame 0.
+    pub fn with_clock(mut self, clock: Arc<FrameClock>) -> Self {
+        self.lfo.clock = Some(clock);
+        self
+    }
+
+    /// Delays the far channel by up to `max_delay` (a few milliseconds)
+    /// when panned hard.
+    pub fn with_haas(mut self, max_delay: Ms) -> Self {
+        self.haas_delay = max_delay.0.max(0.0) * self.fs / 1000.0;
+        let len = self.haas_delay.ceil() as usize + 2;
+        self.left = DelayLine::new(len);
+        self.right = DelayLine::new(len);
+        self
+    }
+
+    /// The current pan position, from -1.0 (left) to 1.0 (right).
+    pub fn pan(&self) -> f64 {
+        self.depth * self.shape.value(self.lfo.phase())
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for AutoPanner<S> {
+    type Frame = [f64; 2];
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        let pan = self.pan();
+        self.lfo.advance();
+
+        // the equal-power pan law: the angle from 0 (left) to pi / 2 (right)
+        let theta = (pan + 1.0) * std::f64::consts::FRAC_PI_4;
+        let (gain_l, gain_r) = (theta.cos(), theta.sin());
+
+        // tap(1) is the sample just pushed, i.e. no delay
+        self.left.push(x);
+        self.right.push(x);
+        let delay_l = self.haas_delay * pan.max(0.0);
+        let delay_r = self.haas_delay * (-pan).max(0.0);
+
+        [
+            gain_l * self.left.tap_fractional(1.0 + delay_l),
+            gain_r * self.right.tap_fractional(1.0 + delay_r),
+        ]
+    }
+}
+
+// the number of taps of `MultiTapDelay`
+const MAX_TAPS: usize = 8;
+// the times of the envelope follower of the ducking of `MultiTapDelay`
+const DUCKING_ATTACK: Ms = Ms(10.0);
+const DUCKING_RELEASE: Ms = Ms(100.0);
+
+/// A tap of `MultiTapDelay`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tap {
+    time: Frames,
+    gain: f64,
+    pan: f64,
+    cutoff: Option<Hz>,
+}
+
+impl Tap {
+    /// A tap `time` (at least 1 frame) after the input, at the center. Use
+    /// `Ms::from_note()` for the note values.
+    pub fn new(time: Frames, gain: f64) -> Self {
+        Self {
+            time,
+            gain,
+            pan: 0.0,
+            cutoff: None,
+        }
+    }
+
+    /// Pans the tap from -1.0 (left) to 1.0 (right) by the equal-power law.
+    pub fn with_pan(mut self, pan: f64) -> Self {
+        self.pan = pan.clamp(-1.0, 1.0);
+        self
+    }
+
+    /// Filters the tap by a one-pole low-pass, e.g. to make the repeats
+    /// darker as on a tape echo.
+    pub fn with_low_pass(mut self, cutoff: Hz) -> Self {
+        self.cutoff = Some(cutoff);
+        self
+    }
+}
+
+/// A delay with up to 8 taps, each with its own time, gain, pan, and
+/// low-pass, producing stereo frames. One of the taps can be fed back to the
+/// input of the delay (after its low-pass, before its gain and pan), so that
+/// its pattern repeats.
+///
+/// With `with_ducking()`, the taps are turned down while the dry input is
+/// loud, so that the echoes (e.g. of a vocal) bloom only in the gaps between
+/// the phrases instead of blurring them. The feedback isn't ducked, so the
+/// repeats keep building up underneath and come out once the input stops.
+pub struct MultiTapDelay<S: Signal<Frame = f64>> {
+    signal: S,
+    fs: f64, // sampling rate
+    taps: Vec<Tap>,
+    // the coefficients and the states of the low-passes of the taps
+    coefs: Vec<f64>,
+    states: Vec<f64>,
+    feedback: Option<(usize, f64)>,
+    dry: f64,
+    line: DelayLine,
+    // the follower of the dry level, the threshold, and the maximum reduction
+    ducking: Option<(EnvelopeFollower, Db, Db)>,
+}
+
+impl<S: Signal<Frame = f64>> MultiTapDelay<S> {
+    /// The dry signal is kept at the center with the gain of 1.0.
+    pub fn new(signal: S, fs: f64) -> Self {
+        Self {
+            signal,
+            fs,
+            taps: Vec::with_capacity(MAX_TAPS),
+            coefs: Vec::with_capacity(MAX_TAPS),
+            states: Vec::with_capacity(MAX_TAPS),
+            feedback: None,
+            dry: 1.0,
+            line: DelayLine::new(1),
+            ducking: None,
+        }
+    }
+
+    /// Adds a tap. Panics on the 9th tap or on the time of 0.
+    pub fn with_tap(mut self, tap: Tap) -> Self {
+        assert!(self.taps.len() < MAX_TAPS, "at most {MAX_TAPS} taps");
+        assert!(tap.time.0 > 0, "the time of a tap must be at least 1 frame");
+
+        // 1 - e^(-2 pi fc / fs) is the coefficient for the cutoff
+        let coef = tap.cutoff.map_or(1.0, |fc| {
+            1.0 - (-2.0 * std::f64::consts::PI * fc.normalized(self.fs)).exp()
+        });
+        self.taps.push(tap);
+        self.coefs.push(coef);
+        self.states.push(0.0);
+
+        let max_time = self.taps.iter().map(|t| t.time.0).max().unwrap_or(1);
+        self.line = DelayLine::new(max_time);
+        self
+    }
+
+    /// Feeds the tap of the index (in the order added) back to the input by
+    /// `gain`, which should be less than 1.0.
+    pub fn with_feedback(mut self, tap: usize, gain: f64) -> Self {
+        assert!(tap < self.taps.len(), "no tap of the index {tap}");
+        self.feedback = Some((tap, gain));
+        self
+    }
+
+    /// Sets the gain of the dry signal (default: 1.0).
+    pub fn with_dry(mut self, dry: f64) -> Self {
+        self.dry = dry;
+        self
+    }
+
+    /// Ducks the taps by the level of the dry input (its envelope, with the
+    /// attack of 10 ms and the release of 100 ms): above `threshold`, they
+    /// are turned down by as much as the level exceeds it, up to `range`
+    /// (e.g. `Db(-20.0)` and `Db(20.0)`).
+    pub fn with_ducking(mut self, threshold: Db, range: Db) -> Self {
+        let follower = EnvelopeFollower::new(self.fs, DUCKING_ATTACK, DUCKING_RELEASE);
+        self.ducking = Some((follower, threshold, Db(range.0.abs())));
+        self
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for MultiTapDelay<S> {
+    type Frame = [f64; 2];
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        let mut out = [self.dry * FRAC_1_SQRT_2 * x; 2];
+
+        let duck = match &mut self.ducking {
+            Some((follower, threshold, range)) => {
+                let level = Db::from_gain(follower.process(x.abs()));
+                Db(-(level.0 - threshold.0).clamp(0.0, range.0)).to_gain()
+            }
+            None => 1.0,
+        };
+
+        let mut fed_back = 0.0;
+        for (i, tap) in self.taps.iter().enumerate() {
+            // read before pushing, so that tap(time) is `time` frames ago
+            let y = self.line.tap(tap.time.0);
+            let state = &mut self.states[i];
+            *state += self.coefs[i] * (y - *state);
+
+            if let Some((_, gain)) = self.feedback.filter(|&(j, _)| j == i) {
+                fed_back = gain * *state;
+            }
+
+            // the equal-power law, by sin for both so that the hard pans are
+            // exactly silent on the other side
+            let quarter = std::f64::consts::FRAC_PI_4;
+            let gain_l = ((1.0 - tap.pan) * quarter).sin();
+            let gain_r = ((1.0 + tap.pan) * quarter).sin();
+            out[0] += duck * tap.gain * gain_l * *state;
+            out[1] += duck * tap.gain * gain_r * *state;
+        }
+        self.line.push(x + fed_back);
+
+        out
+    }
+}
+
+impl<S: Signal<Frame = f64>> HasTail for MultiTapDelay<S> {
+    fn has_tail(&self) -> bool {
+        !self.taps.is_empty()
+    }
+}
+
+// the crossover frequency of the harmonic tremolo, as on the Fender amps
+const HARMONIC_TREMOLO_CROSSOVER: Hz = Hz(800.0);
+
+/// The harmonic tremolo of the Fender amps of the early '60s: the signal is
+/// split into the lows and the highs, which are modulated by the LFO in the
+/// opposite phases and summed, so the tone sweeps between dark and bright
+/// rather than the level going up and down.
+///
+/// The bands are split by a 4th-order Linkwitz-Riley crossover, whose bands
+/// sum to a flat (all-pass) response, so the depth of 0 sounds like the
+/// crossover alone. As with `AutoPanner`, the rate can be synced to the
+/// tempo, and the LFO to a clock.
+pub struct HarmonicTremolo<S: Signal<Frame = f64>> {
+    signal: S,
+    shape: LfoShape,
+    depth: f64,
+    lfo: LfoPhase,
+    low: [Biquad; 2],
+    high: [Biquad; 2],
+}
+
+impl<S: Signal<Frame = f64>> HarmonicTremolo<S> {
+    /// `depth` is 0.0 (no modulation) to 1.0 (each band is silenced at the
+    /// trough), and the LFO cycles once per cycle of `rate` at the tempo of
+    /// `bpm`. The crossover is at 800 Hz.
+    pub fn new(signal: S, fs: f64, rate: LfoRate, bpm: f64, shape: LfoShape, depth: f64) -> Self {
+        let (low, high) = linkwitz_riley(fs, HARMONIC_TREMOLO_CROSSOVER);
+        Self {
+            signal,
+            shape,
+            depth: depth.clamp(0.0, 1.0),
+            lfo: LfoPhase::new(rate.cycle(fs, bpm)),
+            low,
+            high,
+        }
+    }
+
+    /// Follows the clock, as `AutoPanner::with_clock()`.
+    pub fn with_clock(mut self, clock: Arc<FrameClock>) -> Self {
+        self.lfo.clock = Some(clock);
+        self
+    }
+
+    /// Sets the crossover frequency.
+    pub fn with_crossover(mut self, fs: f64, crossover: Hz) -> Self {
+        (self.low, self.high) = linkwitz_riley(fs, crossover);
+        self
+    }
+
+    /// The current gains of the low and the high bands. One is at the
+    /// minimum (`1 - depth`) when the other is at the maximum (1.0).
+    pub fn gains(&self) -> (f64, f64) {
+        let v = self.shape.value(self.lfo.phase());
+        let low = 1.0 - self.depth * (1.0 + v) / 2.0;
+        let high = 1.0 - self.depth * (1.0 - v) / 2.0;
+        (low, high)
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for HarmonicTremolo<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        let (gain_low, gain_high) = self.gains();
+        self.lfo.advance();
+
+        let low = self.low.iter_mut().fold(x, |y, f| f.process(y));
+        let high = self.high.iter_mut().fold(x, |y, f| f.process(y));
+        gain_low * low + gain_high * high
+    }
+}
+
+// the phase of the LFO of `AutoPanner` and `HarmonicTremolo` in cycles,
+// which runs by itself, or follows the frames counted by a clock
+struct LfoPhase {
+    cycle: usize,
+    phase: f64,
+    clock: Option<Arc<FrameClock>>,
+}
+
+impl LfoPhase {
+    fn new(cycle: Frames) -> Self {
+        Self {
+            cycle: cycle.0.max(1),
+            phase: 0.0,
+            clock: None,
+        }
+    }
+
+    fn phase(&self) -> f64 {
+        match &self.clock {
+            Some(clock) => (clock.now() % self.cycle as u64) as f64 / self.cycle as f64,
+            None => self.phase,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.phase = (self.phase + 1.0 / self.cycle as f64).rem_euclid(1.0);
+    }
+}
+
+/// The low-pass and the high-pass of a 4th-order Linkwitz-Riley crossover,
+/// i.e. two Butterworth sections each.
+fn linkwitz_riley(fs: f64, fc: Hz) -> ([Biquad; 2], [Biquad; 2]) {
+    let low = || Biquad::low_pass(fs, fc, FRAC_1_SQRT_2);
+    let high = || Biquad::high_pass(fs, fc, FRAC_1_SQRT_2);
+    ([low(), low()], [high(), high()])
+}
+
+/// Shapes the attack and the sustain of notes independently, without
+/// touching the synthesis: e.g. boosting the attack makes a pluck snappier,
+/// and cutting the sustain makes it shorter.
+///
+/// The level (the smoothed power) is followed by two pairs of envelope
+/// followers. A fast-attack one rising ahead of a slow-attack one marks an
+/// attack, and a slow-release one staying above a fast-release one marks a
+/// sustain (the decaying tail). The gain is interpolated by how clearly each
+/// is detected, so steady tones are not affected.
+pub struct TransientShaper<S: Signal<Frame = f64>> {
+    signal: S,
+    fs: f64, // sampling rate
+    attack_gain: Db,
+    sustain_gain: Db,
+    power: EnvelopeFollower,
+    // fast attack, slow attack, fast release, slow release
+    detectors: [EnvelopeFollower; 4],
+}
+
+impl<S: Signal<Frame = f64>> TransientShaper<S> {
+    /// `attack_gain` and `sustain_gain` are applied to the attacks and to the
+    /// tails respectively; 0 dB for both leaves the signal as it is.
+    pub fn new(signal: S, fs: f64, attack_gain: Db, sustain_gain: Db) -> Self {
+        Self {
+            signal,
+            fs,
+            attack_gain,
+            sustain_gain,
+            power: EnvelopeFollower::new(fs, Ms(1.0), Ms(1.0)),
+            detectors: Self::detectors(fs, Ms(20.0)),
+        }
+    }
+
+    /// The time scale of the detection; an attack is something that rises
+    /// faster than this, and a sustain is something that lasts longer than
+    /// this. The default is 20 ms.
+    pub fn with_detector(mut self, time: Ms) -> Self {
+        self.detectors = Self::detectors(self.fs, time);
+        self
+    }
+
+    fn detectors(fs: f64, time: Ms) -> [EnvelopeFollower; 4] {
+        let fast = Ms(time.0 / 20.0);
+        let slower = Ms(time.0 * 4.0);
+        [
+            EnvelopeFollower::new(fs, fast, slower),
+            EnvelopeFollower::new(fs, time, slower),
+            EnvelopeFollower::new(fs, fast, time),
+            EnvelopeFollower::new(fs, fast, slower),
+        ]
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for TransientShaper<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+
+        // smooth the power a bit so that the followers don't follow each cycle
+        let p = self.power.process(x * x);
+
+        let [fast_attack, slow_attack, fast_release, slow_release] =
+            self.detectors.each_mut().map(|d| d.process(p));
+
+        // how clearly the attack and the sustain are detected, within [0, 1]
+        let ratio = |a: f64, b: f64| {
+            if b > 0.0 {
+                (1.0 - a / b).clamp(0.0, 1.0)
+            } else {
+                0.0
+            }
+        };
+        let attack = ratio(slow_attack, fast_attack);
+        let sustain = ratio(fast_release, slow_release);
+
+        let gain = Db(self.attack_gain.0 * attack + self.sustain_gain.0 * sustain).to_gain();
+        x * gain
+    }
+}
+
+// the default times of the level detector of `Compressor`
+const COMPRESSOR_ATTACK: Ms = Ms(10.0);
+const COMPRESSOR_RELEASE: Ms = Ms(100.0);
+
+/// A feed-forward compressor: above `threshold`, the level is reduced so that
+/// it exceeds the threshold by only `1 / ratio` as much. The level is the
+/// envelope of the absolute value, followed with the attack and the release
+/// times (10 ms and 100 ms by default).
+///
+/// With `with_sidechain()`, the level is detected on another signal (the key)
+/// instead of the input, e.g. to duck a pad by a kick (pumping). The key can
+/// be high-passed by `with_key_high_pass()` so that the bass doesn't dominate
+/// the detection.
+pub struct Compressor<S: Signal<Frame = f64>> {
+    signal: S,
+    fs: f64, // sampling rate
+    computer: GainComputer,
+    key: Option<Box<dyn Signal<Frame = f64> + Send>>,
+    key_filter: Option<Biquad>,
+    // the current gain reduction, and the meter of it for another thread
+    gain_reduction: Db,
+    meter: Option<Arc<AtomicF64>>,
+}
+
+impl<S: Signal<Frame = f64>> Compressor<S> {
+    /// `ratio` is clamped to 1.0 (no compression) or more.
+    pub fn new(signal: S, fs: f64, threshold: Db, ratio: f64) -> Self {
+        Self {
+            signal,
+            fs,
+            computer: GainComputer::new(fs, threshold, ratio),
+            key: None,
+            key_filter: None,
+            gain_reduction: Db(0.0),
+            meter: None,
+        }
+    }
+
+    /// Sets the attack and the release times of the level detector.
+    pub fn with_times(mut self, attack: Ms, release: Ms) -> Self {
+        self.computer = self.computer.with_times(attack, release);
+        self
+    }
+
+    /// Detects the level on `key` instead of the input. The key is pulled
+    /// one frame per frame of the input.
+    pub fn with_sidechain<K: Signal<Frame = f64> + Send + 'static>(mut self, key: K) -> Self {
+        self.key = Some(Box::new(key));
+        self
+    }
+
+    /// High-passes the key (the sidechain, or the input without it) at
+    /// `cutoff` before the level detection.
+    pub fn with_key_high_pass(mut self, cutoff: Hz) -> Self {
+        self.key_filter = Some(Biquad::high_pass(self.fs, cutoff, FRAC_1_SQRT_2));
+        self
+    }
+
+    /// Stores the gain reduction (in dB, 0.0 or more) of each frame in
+    /// `meter`, e.g. for a GR meter on a UI.
+    pub fn with_meter(mut self, meter: Arc<AtomicF64>) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// The gain reduction of the last frame (0 dB or more).
+    pub fn gain_reduction(&self) -> Db {
+        self.gain_reduction
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for Compressor<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        let key = match &mut self.key {
+            Some(key) => key.next(),
+            None => x,
+        };
+        let key = match &mut self.key_filter {
+            Some(filter) => filter.process(key),
+            None => key,
+        };
+
+        self.gain_reduction = self.computer.process(key);
+        if let Some(meter) = &self.meter {
+            meter.set(self.gain_reduction.0);
+        }
+
+        x * Db(-self.gain_reduction.0).to_gain()
+    }
+}
+
+/// The level detector and the gain computer of `Compressor`, for a
+/// processor that picks the key itself, e.g. the louder of two channels to
+/// compress them by the same gain.
+pub struct GainComputer {
+    fs: f64, // sampling rate
+    threshold: Db,
+    ratio: f64,
+    detector: EnvelopeFollower,
+}
+
+impl GainComputer {
+    /// `ratio` is clamped to 1.0 (no compression) or more.
+    pub fn new(fs: f64, threshold: Db, ratio: f64) -> Self {
+        Self {
+            fs,
+            threshold,
+            ratio: ratio.max(1.0),
+            detector: EnvelopeFollower::new(fs, COMPRESSOR_ATTACK, COMPRESSOR_RELEASE),
+        }
+    }
+
+    pub fn with_times(mut self, attack: Ms, release: Ms) -> Self {
+        self.detector = EnvelopeFollower::new(self.fs, attack, release);
+        self
+    }
+
+    /// Follows the level of a sample of the key, and returns the gain
+    /// reduction (0 dB or more).
+    pub fn process(&mut self, key: f64) -> Db {
+        let level = Db::from_gain(self.detector.process(key.abs()));
+        let over = (level.0 - self.threshold.0).max(0.0);
+        Db(over * (1.0 - 1.0 / self.ratio))
+    }
+}
+
+/// A peak limiter without a lookahead: the gain drops at once on a peak over
+/// `ceiling`, so that the output never exceeds it, and recovers with the
+/// release time. This distorts the transients a bit, so it's for catching
+/// the peaks left by a compressor rather than for loudness.
+pub struct Limiter {
+    ceiling: f64,
+    // the envelope of the peaks, which is never below the input
+    peak: EnvelopeFollower,
+}
+
+impl Limiter {
+    pub fn new(fs: f64, ceiling: Db, release: Ms) -> Self {
+        Self {
+            ceiling: ceiling.to_gain(),
+            peak: EnvelopeFollower::new(fs, Ms(0.0), release),
+        }
+    }
+
+    /// Returns the gain for a frame whose (absolute) peak is `peak`, e.g.
+    /// the louder of two channels to limit them by the same gain.
+    pub fn gain(&mut self, peak: f64) -> f64 {
+        let peak = self.peak.process(peak.abs());
+        if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        }
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        x * self.gain(x)
+    }
+}
+
+// the sub is unmuted above the first, and muted below the second confidence
+const OCTAVER_CONFIDENCE_ON: f64 = 0.9;
+const OCTAVER_CONFIDENCE_OFF: f64 = 0.8;
+
+/// The waveform of the sub-octave of `Octaver`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubShape {
+    Sine,
+    /// like the flip-flop of analog octavers; this is not band-limited
+    Square,
+}
+
+/// Adds a tone one octave below the input, following the pitch detected by
+/// YIN and the level of the input. When the pitch is unclear (noise, chords,
+/// silence), the sub is muted rather than following a wrong pitch. The
+/// threshold of the confidence has a hysteresis so that the sub doesn't
+/// chatter, and muting and unmuting are ramped to avoid clicks.
+///
+/// The pitch is detected on a window of 2048 samples every 1024 samples (for
+/// the normal quality), so the sub follows the changes of the pitch with a
+/// delay of up to about 40 ms at 48 kHz, and the lowest pitch is about 47 Hz.
+pub struct Octaver<S: Signal<Frame = f64>> {
+    signal: S,
+    fs: f64, // sampling rate
+    shape: SubShape,
+    level: f64,
+    tracker: PitchTracker,
+    envelope: EnvelopeFollower,
+    sub_hz: f64,
+    phase: f64,
+    open: bool,
+    gain: f64,
+    ramp: f64,
+}
+
+impl<S: Signal<Frame = f64>> Octaver<S> {
+    /// `level` is the level of the sub relative to the input.
+    pub fn new(signal: S, fs: f64, shape: SubShape, level: f64) -> Self {
+        Self {
+            signal,
+            fs,
+            shape,
+            level,
+            tracker: PitchTracker::new(fs, 2048, octaver_hop(quality::global())),
+            envelope: EnvelopeFollower::new(fs, Ms(20.0), Ms(20.0)),
+            sub_hz: 0.0,
+            phase: 0.0,
+            open: false,
+            gain: 0.0,
+            ramp: 1.0 / Ms(10.0).to_frames(fs).0.max(1) as f64,
+        }
+    }
+
+    /// How often the pitch is detected: every 2048 samples for the low
+    /// quality (so the sub follows the pitch twice as slowly), 1024 for the
+    /// normal quality, and 512 for the high quality. The detection is the
+    /// most expensive part.
+    pub fn set_quality(&mut self, quality: Quality) {
+        self.tracker = PitchTracker::new(self.fs, 2048, octaver_hop(quality));
+    }
+}
+
+fn octaver_hop(quality: Quality) -> usize {
+    match quality {
+        Quality::Low => 2048,
+        Quality::Normal => 1024,
+        Quality::High => 512,
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for Octaver<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+
+        let confidence = match self.tracker.process(x) {
+            Some(pitch) => {
+                if self.open || pitch.confidence > OCTAVER_CONFIDENCE_ON {
+                    self.sub_hz = pitch.frequency.0 / 2.0;
+                }
+                pitch.confidence
+            }
+            None => 0.0,
+        };
+        if self.open && confidence < OCTAVER_CONFIDENCE_OFF {
+            self.open = false;
+        } else if !self.open && confidence > OCTAVER_CONFIDENCE_ON {
+            self.open = true;
+        }
+
+        let target = if self.open { 1.0 } else { 0.0 };
+        self.gain += (target - self.gain).clamp(-self.ramp, self.ramp);
+
+        // the peak of a sine is pi / 2 times its average absolute value
+        let level = std::f64::consts::FRAC_PI_2 * self.envelope.process(x.abs());
+
+        let sine = (2.0 * std::f64::consts::PI * self.phase).sin();
+        let sub = match self.shape {
+            SubShape::Sine => sine,
+            SubShape::Square => sine.signum(),
+        };
+        self.phase = (self.phase + self.sub_hz / self.fs).rem_euclid(1.0);
+
+        x + self.level * self.gain * level * sub
+    }
+}
+
+// the range of the bands of `Vocoder`
+const VOCODER_LOWEST: Hz = Hz(100.0);
+const VOCODER_HIGHEST: Hz = Hz(8000.0);
+
+// the order of the band-passes of `Vocoder`
+const VOCODER_ORDER: usize = 6;
+
+struct VocoderBand {
+    modulator: Vec<Biquad>,
+    carrier: Vec<Biquad>,
+    envelope: EnvelopeFollower,
+    level: f64,
+}
+
+/// A channel vocoder, which imposes the spectral envelope of the modulator
+/// (typically a voice) on the carrier (typically a saw chord or noise).
+///
+/// Both are split into the same bands, log-spaced between 100 Hz and 8 kHz,
+/// by Butterworth band-passes of the 12th order. The level of each band of
+/// the modulator is followed by an envelope follower, and is applied to the
+/// same band of the carrier.
+pub struct Vocoder<C: Signal<Frame = f64>, M: Signal<Frame = f64>> {
+    carrier: C,
+    modulator: M,
+    bands: Vec<VocoderBand>,
+}
+
+impl<C: Signal<Frame = f64>, M: Signal<Frame = f64>> Vocoder<C, M> {
+    /// `bands` is the number of the bands; 12 to 16 is typical.
+    pub fn new(carrier: C, modulator: M, fs: f64, bands: usize) -> Self {
+        assert!(bands > 0, "at least 1 band is needed");
+
+        let ratio = (VOCODER_HIGHEST.0 / VOCODER_LOWEST.0).powf(1.0 / bands as f64);
+        let bands = (0..bands)
+            .map(|k| {
+                let low = Hz(VOCODER_LOWEST.0 * ratio.powi(k as i32));
+                let high = Hz(low.0 * ratio);
+                let band_pass = || butterworth_band_pass(fs, low, high, VOCODER_ORDER);
+                VocoderBand {
+                    modulator: band_pass(),
+                    carrier: band_pass(),
+                    envelope: EnvelopeFollower::new(fs, Ms(5.0), Ms(20.0)),
+                    level: 0.0,
+                }
+            })
+            .collect();
+
+        Self {
+            carrier,
+            modulator,
+            bands,
+        }
+    }
+
+    /// The current levels of the bands of the modulator, from the lowest
+    /// band; these are the gains applied to the bands of the carrier.
+    pub fn levels(&self) -> Vec<f64> {
+        self.bands.iter().map(|band| band.level).collect()
+    }
+}
+
+impl<C: Signal<Frame = f64>, M: Signal<Frame = f64>> Signal for Vocoder<C, M> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let c = self.carrier.next();
+        let m = self.modulator.next();
+
+        self.bands
+            .iter_mut()
+            .map(|band| {
+                let m = band.modulator.iter_mut().fold(m, |x, f| f.process(x));
+                let c = band.carrier.iter_mut().fold(c, |x, f| f.process(x));
+                // the peak of a sine is pi / 2 times its average absolute value
+                band.level = std::f64::consts::FRAC_PI_2 * band.envelope.process(m.abs());
+                band.level * c
+            })
+            .sum()
+    }
+}
+
+// the offset into the tanh of `Exciter`, which makes the curve asymmetric so
+// that the even harmonics are generated as well as the odd ones
+const EXCITER_BIAS: f64 = 0.3;
+
+// the taps per phase of the oversampling of `Exciter` (for the normal quality)
+const EXCITER_TAPS_PER_PHASE: usize = 16;
+
+/// The waveshaper of `Exciter`. The gain for small inputs is 1, and the DC
+/// offset is removed for the zero input.
+struct ExciterShaper {
+    drive: f64,
+}
+
+impl MonoEffect for ExciterShaper {
+    fn process(&mut self, x: f64) -> f64 {
+        let bias = EXCITER_BIAS.tanh();
+        ((self.drive * x + EXCITER_BIAS).tanh() - bias) / (self.drive * (1.0 - bias * bias))
+    }
+}
+
+/// Brightens a dull sound by generating new harmonics from its highs. The
+/// signal is high-passed above `corner`, saturated by an asymmetric `tanh`
+/// (which makes both the even and the odd harmonics), high-passed again to
+/// remove the DC and the low intermodulation products, and then mixed with
+/// the dry signal.
+///
+/// The saturation is oversampled (4 times for the normal quality) so that the
+/// harmonics above the Nyquist frequency don't alias. The dry signal is
+/// delayed to stay aligned with it.
+pub struct Exciter<S: Signal<Frame = f64>> {
+    signal: S,
+    drive: f64,
+    mix: f64,
+    pre: Biquad,
+    shaper: Oversample<ExciterShaper>,
+    post: Biquad,
+    dry: DelayLine,
+}
+
+impl<S: Signal<Frame = f64>> Exciter<S> {
+    /// `drive` is the gain into the saturation (e.g. 1 to 10; larger makes
+    /// more harmonics), and `mix` is the level of the processed highs added
+    /// to the dry signal; 0 leaves the signal as it is.
+    pub fn new(signal: S, fs: f64, corner: Hz, drive: f64, mix: f64) -> Self {
+        assert!(drive > 0.0, "drive must be positive");
+
+        let mut exciter = Self {
+            signal,
+            drive,
+            mix,
+            pre: Biquad::high_pass(fs, corner, FRAC_1_SQRT_2),
+            shaper: Oversample::new(ExciterShaper { drive }, 4, EXCITER_TAPS_PER_PHASE),
+            post: Biquad::high_pass(fs, corner, FRAC_1_SQRT_2),
+            // enough for the high quality
+            dry: DelayLine::new(EXCITER_TAPS_PER_PHASE * 2),
+        };
+        exciter.set_quality(quality::global());
+        exciter
+    }
+
+    /// The oversampling: 2 times with the half-length filters for the low
+    /// quality, 4 times for the normal quality, and 8 times with the
+    /// double-length filters for the high quality. This changes the latency.
+    pub fn set_quality(&mut self, quality: Quality) {
+        let factor = match quality {
+            Quality::Low => 2,
+            Quality::Normal => 4,
+            Quality::High => 8,
+        };
+        self.shaper = Oversample::new(
+            ExciterShaper { drive: self.drive },
+            factor,
+            EXCITER_TAPS_PER_PHASE,
+        );
+        self.shaper.set_quality(quality);
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for Exciter<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        let dry = self.dry.process(x, self.shaper.latency_frames());
+
+        let highs = self.pre.process(x);
+        let excited = self.post.process(self.shaper.process(highs));
+
+        dry + self.mix * excited
+    }
+}
+
+impl<S: Signal<Frame = f64>> Latency for Exciter<S> {
+    fn latency_frames(&self) -> usize {
+        self.shaper.latency_frames()
+    }
+}
+
+/// The resonances of the body of an instrument as a bank of parallel
+/// band-pass biquads added to the dry signal, a cheap alternative to
+/// convolving with the impulse response of the body. Each mode boosts around
+/// its frequency by about `20 * log10(1 + gain)` dB.
+pub struct BodyResonator {
+    modes: Vec<(Biquad, f64)>,
+}
+
+impl BodyResonator {
+    /// `modes` are the frequency, the Q, and the gain (relative to the dry
+    /// signal) of the modes.
+    pub fn new(fs: f64, modes: &[(Hz, f64, f64)]) -> Self {
+        Self {
+            modes: modes
+                .iter()
+                .map(|&(freq, q, gain)| (Biquad::band_pass(fs, freq, q), gain))
+                .collect(),
+        }
+    }
+
+    /// A rough model of the body of an acoustic guitar: the air mode around
+    /// 100 Hz, the main top plate mode around 200 Hz, and a few weaker ones
+    /// above.
+    pub fn guitar(fs: f64) -> Self {
+        Self::new(
+            fs,
+            &[
+                (Hz(98.0), 15.0, 2.0),
+                (Hz(204.0), 20.0, 3.0),
+                (Hz(290.0), 25.0, 1.5),
+                (Hz(390.0), 25.0, 1.5),
+                (Hz(550.0), 20.0, 1.0),
+                (Hz(820.0), 20.0, 0.8),
+                (Hz(1200.0), 15.0, 0.6),
+            ],
+        )
+    }
+}
+
+impl MonoEffect for BodyResonator {
+    fn process(&mut self, x: f64) -> f64 {
+        x + self
+            .modes
+            .iter_mut()
+            .map(|(band_pass, gain)| *gain * band_pass.process(x))
+            .sum::<f64>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dasp::signal;
+
+    const FS: f64 = 48000.0;
+
+    fn sine(freq: f64, amp: f64) -> impl Signal<Frame = f64> + Send {
+        signal::rate(FS).const_hz(freq).sine().scale_amp(amp)
+    }
+
+    /// The amplitude of the component of `freq` by a DFT of one bin with the
+    /// Hann window; `samples` should be a whole number of seconds long.
+    fn amplitude(samples: &[f64], freq: f64) -> f64 {
+        let window = hann(samples.len());
+        let sum =
+            samples
+                .iter()
+                .zip(window)
+                .enumerate()
+                .fold(Complex::default(), |acc, (n, (&x, w))| {
+                    let theta = -2.0 * std::f64::consts::PI * freq * n as f64 / FS;
+                    acc + Complex::from_angle(theta).scale(x * w)
+                });
+        // the Hann window halves the amplitude
+        4.0 * sum.norm() / samples.len() as f64
+    }
+
+    #[test]
+    fn sidechain_reduces_gain_of_silent_input() {
+        let meter = Arc::new(AtomicF64::new(0.0));
+        let mut comp = Compressor::new(signal::equilibrium(), FS, Db(-20.0), 4.0)
+            .with_sidechain(sine(1000.0, 1.0))
+            .with_meter(meter.clone());
+        for _ in 0..FS as usize / 10 {
+            assert_eq!(comp.next(), 0.0);
+        }
+        // -3 dB of the peak of the sine over -20 dB by the ratio of 4
+        assert!(meter.get() > 10.0, "{}", meter.get());
+    }
+
+    #[test]
+    fn loud_sidechain_ducks_a_quiet_input() {
+        // -29 dB, well below the threshold by itself
+        let quiet = || sine(440.0, 0.05);
+        let rms = |x: &[f64]| (x.iter().map(|x| x * x).sum::<f64>() / x.len() as f64).sqrt();
+        let input: Vec<f64> = quiet().take(2 * FS as usize).collect();
+
+        let comp = Compressor::new(quiet(), FS, Db(-20.0), 4.0);
+        let out: Vec<f64> = comp.take(input.len()).collect();
+        assert_eq!(out, input);
+
+        // the key sounds for the first half second
+        let key_frames = FS as usize / 2;
+        let key: Vec<f64> = sine(1000.0, 1.0).take(key_frames).collect();
+        let key = signal::from_iter(key.into_iter().chain(std::iter::repeat(0.0)));
+        let comp = Compressor::new(quiet(), FS, Db(-20.0), 4.0).with_sidechain(key);
+        let out: Vec<f64> = comp.take(input.len()).collect();
+        let ducked =
+            rms(&out[key_frames / 2..key_frames]) / rms(&input[key_frames / 2..key_frames]);
+        assert!(ducked < 0.5, "{ducked}");
+        // and recovers after the release
+        let recovered = rms(&out[3 * key_frames..]) / rms(&input[3 * key_frames..]);
+        assert!((recovered - 1.0).abs() < 0.01, "{recovered}");
+    }
+
+    #[test]
+    fn limiter_holds_the_ceiling_and_recovers() {
+        let ceiling = Db(-6.0).to_gain();
+        let mut limiter = Limiter::new(FS, Db(-6.0), Ms(50.0));
+        let mut loud = sine(440.0, 1.0);
+        let peak = (0..FS as usize / 10)
+            .map(|_| limiter.process(loud.next()).abs())
+            .fold(0.0, f64::max);
+        assert!(peak <= ceiling + 1e-12 && peak > 0.99 * ceiling, "{peak}");
+
+        // below the ceiling again after the release
+        let mut quiet = sine(440.0, 0.1);
+        for _ in 0..FS as usize {
+            limiter.process(quiet.next());
+        }
+        let x = quiet.next();
+        assert_eq!(limiter.process(x), x);
+    }
+
+    #[test]
+    fn key_high_pass_ignores_bass() {
+        let gain_reduction = |freq: f64| {
+            let mut comp = Compressor::new(signal::equilibrium(), FS, Db(-20.0), 4.0)
+                .with_sidechain(sine(freq, 1.0))
+                .with_key_high_pass(Hz(200.0));
+            for _ in 0..FS as usize / 2 {
+                comp.next();
+            }
+            comp.gain_reduction().0
+        };
+        let (bass, treble) = (gain_reduction(50.0), gain_reduction(1000.0));
+        assert!(
+            bass < treble / 2.0,
+            "{bass} dB at 50 Hz, {treble} dB at 1 kHz"
+        );
+    }
+
+    #[test]
+    fn frequency_shifter_shifts_a_single_sideband() {
+        for (shift, wanted, image) in [(100.0, 540.0, 340.0), (-100.0, 340.0, 540.0)] {
+            let mut shifter = FrequencyShifter::new(sine(440.0, 1.0), FS, Hz(shift));
+            // skip the transient of the Hilbert transform
+            let samples: Vec<f64> = (0..2 * FS as usize)
+                .map(|_| shifter.next())
+                .skip(FS as usize)
+                .collect();
+            let (wanted, image) = (amplitude(&samples, wanted), amplitude(&samples, image));
+            assert!((wanted - 1.0).abs() < 0.05, "{shift} Hz: {wanted}");
+            assert!(image < 0.01, "{shift} Hz: the image of {image}");
+        }
+    }
+
+    #[test]
+    fn spectral_gate_reduces_noise_floor() {
+        let (learn, len) = (FS as usize / 2, 2 * FS as usize);
+        let mut noise = Noise::new(1);
+        let mut tone = sine(1000.0, 0.5);
+        let input = signal::from_iter((0..len).map(move |n| {
+            let x = 0.05 * noise.next_sample();
+            // noise only while learning
+            if n < learn {
+                x
+            } else {
+                x + tone.next()
+            }
+        }));
+        let mut gate = SpectralGate::new(input, 2048, Frames(learn), Db(-30.0));
+        let output: Vec<f64> = (0..len).map(|_| gate.next()).collect();
+
+        // the last second, well after the learning (and the latency)
+        let last = &output[len - FS as usize..];
+        let tone_amp = amplitude(last, 1000.0);
+        let power = last.iter().map(|x| x * x).sum::<f64>() / last.len() as f64;
+        let noise_power = power - tone_amp * tone_amp / 2.0;
+        // the power of the uniform noise within [-0.05, 0.05); the bins around
+        // the tone pass, so expect 6 dB less rather than the full reduction
+        let input_noise_power = 0.05 * 0.05 / 3.0;
+
+        assert!((tone_amp - 0.5).abs() < 0.025, "the tone: {tone_amp}");
+        assert!(
+            noise_power < input_noise_power / 4.0,
+            "the noise: {noise_power} (from {input_noise_power})"
+        );
+    }
+
+    #[test]
+    fn time_stretch_doubles_duration_keeping_pitch() {
+        let len = FS as usize / 2;
+        let input: Vec<f64> = sine(440.0, 0.5)
+            .take(len)
+            .chain(std::iter::repeat_n(0.0, len))
+            .collect();
+        let output = time_stretch(&input, 2.0, 2048);
+        assert_eq!(output.len(), 2 * input.len());
+
+        let rms = |samples: &[f64]| {
+            (samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64).sqrt()
+        };
+        // the tone lasts for a second instead of a half, and ends there
+        let at = |seconds: f64| (seconds * FS) as usize;
+        assert!(rms(&output[at(0.8)..at(0.95)]) > 0.3);
+        assert!(rms(&output[at(1.1)..at(1.5)]) < 0.01);
+
+        let pitch = crate::analysis::detect_pitch(&output[at(0.4)..at(0.4) + 4096], FS).unwrap();
+        assert!((pitch.frequency.0 - 440.0).abs() < 2.0, "{:?}", pitch);
+    }
+
+    #[test]
+    fn tape_wow_oscillates_the_pitch_within_the_depth() {
+        let (rate, depth) = (2.0, 20.0);
+        let len = 2 * FS as usize;
+        let mut tape = Tape::new(sine(1000.0, 1.0), FS).with_wow(Hz(rate), depth);
+        let y: Vec<f64> = (0..len).map(|_| tape.next()).collect();
+
+        // the upward zero crossings, interpolated linearly
+        let crossings: Vec<f64> = (1..len)
+            .filter(|&i| y[i - 1] < 0.0 && y[i] >= 0.0)
+            .map(|i| i as f64 - 1.0 + y[i - 1] / (y[i - 1] - y[i]))
+            .collect();
+        // the pitch in cents over consecutive windows of 10 periods
+        let cents: Vec<f64> = crossings
+            .windows(11)
+            .step_by(10)
+            .map(|w| 1200.0 * (FS * 10.0 / (w[10] - w[0]) / 1000.0).log2())
+            .collect();
+
+        let max = cents.iter().copied().fold(f64::MIN, f64::max);
+        let min = cents.iter().copied().fold(f64::MAX, f64::min);
+        assert!(max < depth + 1.0 && max > depth - 3.0, "{max}");
+        assert!(min > -depth - 1.0 && min < -depth + 3.0, "{min}");
+
+        // swings between sharp and flat twice per cycle of the wow
+        let mut swings = 0;
+        let mut sharp = None;
+        for &c in &cents {
+            let now = if c > depth / 2.0 {
+                Some(true)
+            } else if c < -depth / 2.0 {
+                Some(false)
+            } else {
+                sharp
+            };
+            if sharp.is_some() && now != sharp {
+                swings += 1;
+            }
+            sharp = now;
+        }
+        let expected = (2.0 * rate * len as f64 / FS) as i32;
+        assert!((swings - expected).abs() <= 1, "{swings} swings");
+    }
+
+    #[test]
+    fn tape_of_zero_depth_nulls() {
+        let mut dry = sine(440.0, 0.8);
+        let mut tape = Tape::new(sine(440.0, 0.8), FS)
+            .with_wow(Hz(0.5), 0.0)
+            .with_flutter(Hz(6.0), 0.0)
+            .with_saturation(0.0)
+            .with_hiss(Db(f64::NEG_INFINITY));
+        for i in 0..FS as usize {
+            let (x, y) = (dry.next(), tape.next());
+            assert!((x - y).abs() < 1e-12, "frame {i}: {x} != {y}");
+        }
+    }
+
+    #[test]
+    fn auto_panner_returns_to_its_start_after_a_synced_cycle() {
+        use crate::units::{LfoRate, NoteDivision};
+
+        // a bar at 120 BPM
+        let rate = LfoRate::Division(NoteDivision::Straight(1));
+        let cycle = rate.cycle(FS, 120.0);
+        assert_eq!(cycle, Frames(96000));
+        for shape in [LfoShape::Sine, LfoShape::Triangle, LfoShape::Square] {
+            let mut panner = AutoPanner::new(sine(440.0, 0.5), FS, rate, 120.0, shape, 0.8);
+            let start = panner.pan();
+            let mut moved = false;
+            for _ in 0..cycle.0 {
+                panner.next();
+                moved |= (panner.pan() - start).abs() > 0.5;
+            }
+            assert!(moved, "{shape:?}");
+            assert!((panner.pan() - start).abs() < 1e-9, "{shape:?}");
+        }
+    }
+
+    #[test]
+    fn auto_panner_follows_the_clock() {
+        use crate::params::Clocked;
+        use crate::units::NoteDivision;
+
+        // a quarter note at 120 BPM, from the frame 6000 of the clock
+        let rate = LfoRate::Division(NoteDivision::Straight(4));
+        let clock = Arc::new(FrameClock::new());
+        for _ in 0..6000 {
+            clock.advance();
+        }
+        let panner = AutoPanner::new(sine(440.0, 0.5), FS, rate, 120.0, LfoShape::Sine, 1.0)
+            .with_clock(clock.clone());
+        // a quarter of the cycle in, i.e. at the right
+        assert!((panner.pan() - 1.0).abs() < 1e-9);
+        let mut clocked = Clocked::new(panner, clock.clone());
+        for _ in 0..18000 {
+            clocked.next();
+        }
+        // back to the center on the beat
+        assert_eq!(clock.now(), 24000);
+        let panner = AutoPanner::new(sine(440.0, 0.5), FS, rate, 120.0, LfoShape::Sine, 1.0)
+            .with_clock(clock);
+        assert!(panner.pan().abs() < 1e-9);
+    }
+
+    #[test]
+    fn auto_panner_of_zero_depth_stays_centered() {
+        let rate = LfoRate::Hz(48.0);
+        let mut panner = AutoPanner::new(sine(440.0, 0.5), FS, rate, 120.0, LfoShape::Sine, 0.0)
+            .with_haas(Ms(5.0));
+        for _ in 0..5000 {
+            assert_eq!(panner.pan(), 0.0);
+            let [l, r] = panner.next();
+            assert!((l - r).abs() < 1e-12, "{l} != {r}");
+        }
+    }
+
+    // a 5 ms burst followed by a steady tone of a quarter of its level
+    fn burst_and_tone() -> Vec<f64> {
+        let burst = (5.0 * FS / 1000.0) as usize;
+        let mut tone = sine(440.0, 1.0);
+        (0..FS as usize / 2)
+            .map(|i| tone.next() * if i < burst { 1.0 } else { 0.25 })
+            .collect()
+    }
+
+    // the peak of the burst relative to the peak of the end of the tone
+    fn burst_to_tone_db(x: &[f64]) -> f64 {
+        let peak = |x: &[f64]| x.iter().fold(0.0_f64, |m, x| m.max(x.abs()));
+        let burst = (5.0 * FS / 1000.0) as usize;
+        Db::from_gain(peak(&x[..burst]) / peak(&x[x.len() - 4800..])).0
+    }
+
+    #[test]
+    fn transient_shaper_boosts_the_attack() {
+        let dry = burst_and_tone();
+        for gain in [6.0, -6.0] {
+            let mut shaper =
+                TransientShaper::new(signal::from_iter(dry.clone()), FS, Db(gain), Db(0.0));
+            let wet: Vec<f64> = (0..dry.len()).map(|_| shaper.next()).collect();
+            let boost = burst_to_tone_db(&wet) - burst_to_tone_db(&dry);
+            assert!((boost - gain).abs() < 1.5, "{gain} dB: {boost} dB");
+        }
+    }
+
+    #[test]
+    fn transient_shaper_of_zero_gains_nulls() {
+        let dry = burst_and_tone();
+        let mut shaper = TransientShaper::new(signal::from_iter(dry.clone()), FS, Db(0.0), Db(0.0));
+        for (i, x) in dry.iter().enumerate() {
+            assert_eq!(shaper.next(), *x, "frame {i}");
+        }
+    }
+
+    // the sub the octaver adds to `input`
+    fn octaver_sub(input: Vec<f64>) -> Vec<f64> {
+        let mut octaver = Octaver::new(signal::from_iter(input.clone()), FS, SubShape::Sine, 1.0);
+        input.iter().map(|x| octaver.next() - x).collect()
+    }
+
+    #[test]
+    fn octaver_adds_an_octave_below() {
+        let len = FS as usize;
+        let mut tone = sine(220.0, 0.5);
+        let sub = octaver_sub((0..len).map(|_| tone.next()).collect());
+
+        // the frequency by the upward zero crossings of the second half
+        let crossings: Vec<usize> = (len / 2..len)
+            .filter(|&i| sub[i - 1] < 0.0 && sub[i] >= 0.0)
+            .collect();
+        let (first, last) = (crossings[0], crossings[crossings.len() - 1]);
+        let freq = (crossings.len() - 1) as f64 * FS / (last - first) as f64;
+        let cents = 1200.0 * (freq / 110.0).log2();
+        assert!(cents.abs() < 10.0, "{freq} Hz");
+    }
+
+    #[test]
+    fn octaver_mutes_the_sub_of_noise() {
+        let len = FS as usize;
+        let sub = octaver_sub(signal::noise(1).take(len).collect());
+        let rms = (sub.iter().map(|s| s * s).sum::<f64>() / len as f64).sqrt();
+        assert!(Db::from_gain(rms).0 < -60.0, "{rms}");
+    }
+
+    #[test]
+    fn vocoder_lights_up_only_the_band_of_the_modulator() {
+        let (bands, k) = (12, 5);
+        let len = FS as usize / 2;
+
+        // a burst of noise within the middle half (in octaves) of the band k
+        let ratio = (VOCODER_HIGHEST.0 / VOCODER_LOWEST.0).powf(1.0 / bands as f64);
+        let low = VOCODER_LOWEST.0 * ratio.powf(k as f64 + 0.25);
+        let high = VOCODER_LOWEST.0 * ratio.powf(k as f64 + 0.75);
+        let mut filter = butterworth_band_pass(FS, Hz(low), Hz(high), VOCODER_ORDER);
+        let burst: Vec<f64> = signal::noise(1)
+            .take(len)
+            .enumerate()
+            .map(|(i, x)| if i < len / 2 { x } else { 0.0 })
+            .map(|x| filter.iter_mut().fold(x, |x, f| f.process(x)))
+            .collect();
+
+        let mut vocoder = Vocoder::new(signal::noise(2), signal::from_iter(burst), FS, bands);
+        let mut peaks = vec![0.0_f64; bands];
+        for _ in 0..len {
+            vocoder.next();
+            for (peak, level) in peaks.iter_mut().zip(vocoder.levels()) {
+                *peak = peak.max(level);
+            }
+        }
+
+        for (i, &peak) in peaks.iter().enumerate().filter(|&(i, _)| i != k) {
+            let db = Db::from_gain(peak / peaks[k]).0;
+            assert!(db < -20.0, "band {i}: {db} dB");
+        }
+    }
+
+    #[test]
+    fn exciter_adds_harmonics() {
+        let len = FS as usize;
+        let dry: Vec<f64> = sine(2000.0, 0.5).take(len).collect();
+        let mut exciter = Exciter::new(sine(2000.0, 0.5), FS, Hz(1000.0), 4.0, 0.5);
+        let wet: Vec<f64> = (0..len).map(|_| exciter.next()).collect();
+        for harmonic in [4000.0, 6000.0] {
+            let (dry, wet) = (amplitude(&dry, harmonic), amplitude(&wet, harmonic));
+            assert!(
+                wet > 0.01 && wet > 1000.0 * dry,
+                "{harmonic} Hz: {dry} -> {wet}"
+            );
+        }
+    }
+
+    #[test]
+    fn exciter_of_zero_mix_nulls() {
+        let dry: Vec<f64> = signal::noise(1).take(4800).collect();
+        let mut exciter = Exciter::new(signal::from_iter(dry.clone()), FS, Hz(3000.0), 4.0, 0.0);
+        let latency = exciter.latency_frames();
+        let wet: Vec<f64> = (0..dry.len()).map(|_| exciter.next()).collect();
+        assert!(wet[..latency].iter().all(|&y| y == 0.0));
+        assert_eq!(wet[latency..], dry[..dry.len() - latency]);
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(8))]
+
+        #[test]
+        fn low_quality_patch_is_bounded_and_finite(
+            freq in 40.0..2000.0_f64,
+            drive in 0.5..10.0_f64,
+            decay in 0.0..1.0_f64,
+            seed in 0..1000_u64,
+        ) {
+            // the reference patch of the benchmark, with a bit of noise
+            let noise = signal::noise(seed).scale_amp(0.1);
+            let input = sine(freq, 0.5).add_amp(noise);
+            let mut exciter = Exciter::new(input, FS, Hz(3000.0), drive, 0.5);
+            exciter.set_quality(Quality::Low);
+            let mut octaver = Octaver::new(exciter, FS, SubShape::Square, 1.0);
+            octaver.set_quality(Quality::Low);
+            let mut reverb = PlateReverb::new(octaver, FS, decay, 0.3, Ms(10.0));
+            reverb.set_quality(Quality::Low);
+            for _ in 0..FS as usize / 5 {
+                let y = reverb.next();
+                proptest::prop_assert!(y.is_finite() && y.abs() < 4.0, "{}", y);
+            }
+        }
+    }
+
+    #[test]
+    fn early_reflections_precede_the_pre_delayed_tail() {
+        let taps = [(Ms(7.0), 0.5), (Ms(13.0), 0.3)];
+        let reverb = PlateReverb::new(impulse(), FS, 0.5, 0.3, Ms(20.0))
+            .with_mix(1.0)
+            .with_early_reflections(&taps);
+        let out: Vec<f64> = reverb.take(FS as usize / 10).collect();
+
+        let pre_delay = Ms(20.0).to_frames(FS).0;
+        for (i, &y) in out[..pre_delay].iter().enumerate() {
+            let expected = match taps.iter().find(|(time, _)| time.to_frames(FS).0 == i) {
+                Some(&(_, gain)) => gain,
+                None => 0.0,
+            };
+            assert_eq!(y, expected, "frame {i}");
+        }
+        // and then the tail
+        assert!(out[pre_delay..].iter().any(|&y| y != 0.0));
+    }
+
+    #[test]
+    fn plate_reverb_tail_is_dense_and_smooth() {
+        let reverb = PlateReverb::new(impulse(), FS, 0.8, 0.3, Ms(10.0)).with_mix(1.0);
+        let out: Vec<f64> = reverb.take(3 * FS as usize / 2).collect();
+
+        // the levels of the 10 ms windows of the decay, from 50 ms on
+        let window = Ms(10.0).to_frames(FS).0;
+        let windows: Vec<&[f64]> = out[5 * window..].chunks_exact(window).collect();
+        let levels: Vec<f64> = windows
+            .iter()
+            .map(|w| {
+                let rms = (w.iter().map(|x| x * x).sum::<f64>() / w.len() as f64).sqrt();
+                Db::from_gain(rms).0
+            })
+            .collect();
+
+        // fit a line (in dB) to the decay by the least squares
+        let n = levels.len() as f64;
+        let mean_x = (n - 1.0) / 2.0;
+        let mean_y = levels.iter().sum::<f64>() / n;
+        let (mut sxy, mut sxx) = (0.0, 0.0);
+        for (i, y) in levels.iter().enumerate() {
+            sxy += (i as f64 - mean_x) * (y - mean_y);
+            sxx += (i as f64 - mean_x).powi(2);
+        }
+        let slope = sxy / sxx;
+        assert!(slope < 0.0, "{slope}");
+        let ripple = levels
+            .iter()
+            .enumerate()
+            .map(|(i, y)| (y - (mean_y + slope * (i as f64 - mean_x))).abs())
+            .fold(0.0, f64::max);
+        assert!(ripple < 3.0, "{ripple} dB");
+
+        // dense: no window is dominated by a few isolated spikes (a single
+        // spike in a window has the crest factor of about 22) or has gaps
+        for (i, w) in windows.iter().enumerate() {
+            let rms = (w.iter().map(|x| x * x).sum::<f64>() / w.len() as f64).sqrt();
+            let peak = w.iter().fold(0.0, |a: f64, x| a.max(x.abs()));
+            assert!(
+                peak < 10.0 * rms,
+                "window {i}: the crest factor of {}",
+                peak / rms
+            );
+            let audible = w.iter().filter(|x| x.abs() > rms / 100.0).count();
+            // which fill the tail as noise would, once built up by 100 ms
+            if i >= 5 {
+                assert!(
+                    audible > w.len() * 95 / 100,
+                    "window {i}: {audible} of {}",
+                    w.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn frozen_reverb_sustains_its_tail() {
+        // a burst of noise of half a second, and then silence
+        let burst = FS as usize / 2;
+        let reverb = |freeze: bool| {
+            let input = signal::noise(1)
+                .scale_amp(0.5)
+                .take(burst)
+                .chain(std::iter::repeat(0.0));
+            let flag = Arc::new(AtomicBool::new(false));
+            let mut reverb = PlateReverb::new(signal::from_iter(input), FS, 0.7, 0.3, Ms(10.0))
+                .with_mix(1.0)
+                .with_freeze(flag.clone());
+            (0..4 * burst)
+                .map(|i| {
+                    // frozen just after the burst
+                    flag.store(freeze && i >= burst, Ordering::Relaxed);
+                    reverb.next()
+                })
+                .collect::<Vec<f64>>()
+        };
+        let rms = |x: &[f64]| (x.iter().map(|x| x * x).sum::<f64>() / x.len() as f64).sqrt();
+        // in quarter seconds, from after the ramp of the freeze
+        let windows =
+            |out: &[f64]| -> Vec<f64> { out[3 * burst / 2..].chunks(burst / 2).map(rms).collect() };
+
+        let frozen = windows(&reverb(true));
+        assert!(frozen[0] > 0.01, "{frozen:?}");
+        for level in &frozen {
+            let change = Db::from_gain(level / frozen[0]).0;
+            assert!(change.abs() < 1.0, "{change} dB in {frozen:?}");
+        }
+        // while the tail decays without it
+        let decaying = windows(&reverb(false));
+        assert!(
+            decaying[decaying.len() - 1] < decaying[0] / 4.0,
+            "{decaying:?}"
+        );
+    }
+
+    #[test]
+    fn body_resonator_boosts_its_modes_in_a_pluck() {
+        use crate::core::karplus::KarplusStrong;
+
+        let mut string = KarplusStrong::<1024>::new(FS, Hz(330.0), 0.5, Ms(1500.0), 1).unwrap();
+        string.pluck();
+        let dry: Vec<f64> = (0..FS as usize).map(|_| string.process()).collect();
+        let mut body = BodyResonator::guitar(FS);
+        let wet: Vec<f64> = dry.iter().map(|&x| body.process(x)).collect();
+
+        let boost = |freq| Db::from_gain(amplitude(&wet, freq) / amplitude(&dry, freq)).0;
+        for freq in [98.0, 204.0, 290.0] {
+            assert!(boost(freq) > 6.0, "{freq} Hz: {} dB", boost(freq));
+        }
+        // far from the modes
+        assert!(boost(8000.0).abs() < 1.0, "{} dB", boost(8000.0));
+    }
+
+    // the frequency by counting the periods between the first and the last
+    // rising zero crossings, interpolated between the samples
+    fn zero_crossing_freq(samples: &[f64]) -> f64 {
+        let crossings: Vec<f64> = samples
+            .windows(2)
+            .enumerate()
+            .filter(|(_, w)| w[0] < 0.0 && w[1] >= 0.0)
+            .map(|(i, w)| i as f64 + w[0] / (w[0] - w[1]))
+            .collect();
+        let periods = (crossings.len() - 1) as f64;
+        periods * FS / (crossings[crossings.len() - 1] - crossings[0])
+    }
+
+    #[test]
+    fn wsola_of_ratio_one_is_the_input() {
+        let input: Vec<f64> = sine(440.0, 0.5).take(FS as usize / 2).collect();
+        let output = stretch(&input, FS, 1.0);
+        assert_eq!(output.len(), input.len());
+        let diff = input
+            .iter()
+            .zip(&output)
+            .map(|(x, y)| (x - y).abs())
+            .fold(0.0, f64::max);
+        assert!(diff < 1e-9, "{diff}");
+    }
+
+    #[test]
+    fn wsola_doubles_the_duration_keeping_the_pitch() {
+        let hop = Ms(10.0).to_frames(FS).0;
+        let len = FS as usize / 2;
+        let input: Vec<f64> = sine(440.0, 0.5)
+            .take(len)
+            .chain(std::iter::repeat_n(0.0, len))
+            .collect();
+        let output = stretch(&input, FS, 2.0);
+        assert_eq!(output.len(), 2 * input.len());
+
+        // the tone ends at a second instead of a half
+        let end = output.iter().rposition(|y| y.abs() > 0.01).unwrap();
+        assert!(end.abs_diff(FS as usize) <= hop, "ends at {end}");
+
+        for ratio in [0.75, 1.5, 2.0] {
+            let output = stretch(&input[..len], FS, ratio);
+            let middle = &output[output.len() / 4..output.len() * 3 / 4];
+            let cents = 1200.0 * (zero_crossing_freq(middle) / 440.0).log2();
+            assert!(cents.abs() < 1.0, "{cents} cents at {ratio}");
+        }
+    }
+
+    #[test]
+    fn harmonic_tremolo_modulates_the_bands_in_opposite_phases() {
+        // half a second per cycle; the low band is at the trough a quarter in
+        let rate = LfoRate::Hz(2.0);
+        let cycle = rate.cycle(FS, 120.0);
+        let tremolo =
+            |freq| HarmonicTremolo::new(sine(freq, 1.0), FS, rate, 120.0, LfoShape::Sine, 1.0);
+        let rms_around = |freq: f64, center: usize| {
+            let tremolo = tremolo(freq);
+            let out: Vec<f64> = tremolo.take(cycle.0).collect();
+            let window = &out[center - 480..center + 480];
+            (window.iter().map(|x| x * x).sum::<f64>() / window.len() as f64).sqrt()
+        };
+        let (low_trough, high_trough) = (cycle.0 / 4, cycle.0 * 3 / 4);
+
+        let mut tremolo = tremolo(100.0);
+        for _ in 0..low_trough {
+            tremolo.next();
+        }
+        assert!(tremolo.gains().0.abs() < 1e-9);
+        assert!((tremolo.gains().1 - 1.0).abs() < 1e-9);
+
+        // the band-limited inputs in each band
+        let (low, high) = (100.0, 8000.0);
+        assert!(rms_around(low, low_trough) < 0.01);
+        assert!(rms_around(high, low_trough) > 0.65);
+        assert!(rms_around(low, high_trough) > 0.65);
+        assert!(rms_around(high, high_trough) < 0.01);
+    }
+
+    #[test]
+    fn harmonic_tremolo_of_zero_depth_is_the_crossover() {
+        let input = || signal::noise(7);
+        let tremolo =
+            HarmonicTremolo::new(input(), FS, LfoRate::Hz(48.0), 120.0, LfoShape::Sine, 0.0);
+        let (mut low, mut high) = linkwitz_riley(FS, HARMONIC_TREMOLO_CROSSOVER);
+        let mut input = input();
+        for (i, y) in tremolo.take(10000).enumerate() {
+            let x = input.next();
+            let expected = low.iter_mut().fold(x, |y, f| f.process(y))
+                + high.iter_mut().fold(x, |y, f| f.process(y));
+            assert_eq!(y, expected, "frame {i}");
+        }
+    }
+
+    fn impulse() -> impl Signal<Frame = f64> {
+        signal::from_iter(std::iter::once(1.0).chain(std::iter::repeat(0.0)))
+    }
+
+    #[test]
+    fn multi_tap_delay_places_the_taps() {
+        let delay = MultiTapDelay::new(impulse(), FS)
+            .with_dry(0.0)
+            .with_tap(Tap::new(Frames(100), 0.5))
+            .with_tap(Tap::new(Frames(250), 0.8).with_pan(-1.0))
+            .with_tap(Tap::new(Frames(400), 0.3).with_pan(1.0));
+        let out: Vec<[f64; 2]> = delay.take(1000).collect();
+
+        let center = 0.5 * FRAC_1_SQRT_2;
+        for (i, [l, r]) in out.into_iter().enumerate() {
+            let expected = match i {
+                100 => [center, center],
+                // only on the side of the full pan
+                250 => [0.8, 0.0],
+                400 => [0.0, 0.3],
+                _ => [0.0, 0.0],
+            };
+            assert!((l - expected[0]).abs() < 1e-12, "frame {i}: {l}");
+            assert!((r - expected[1]).abs() < 1e-12, "frame {i}: {r}");
+        }
+    }
+
+    #[test]
+    fn multi_tap_delay_repeats_the_fed_back_tap() {
+        let delay = MultiTapDelay::new(impulse(), FS)
+            .with_dry(0.0)
+            .with_tap(Tap::new(Frames(100), 1.0).with_pan(-1.0))
+            .with_feedback(0, 0.5);
+        let left: Vec<f64> = delay.take(1000).map(|[l, _]| l).collect();
+        for (i, l) in left.into_iter().enumerate() {
+            // halved on each repeat
+            let expected = if i > 0 && i.is_multiple_of(100) {
+                0.5_f64.powi(i as i32 / 100 - 1)
+            } else {
+                0.0
+            };
+            assert!((l - expected).abs() < 1e-12, "frame {i}: {l}");
+        }
+    }
+
+    #[test]
+    fn ducking_delay_blooms_only_in_the_gaps() {
+        // a loud phrase of a second, and then silence
+        let phrase = FS as usize;
+        let delay = |ducking: bool| {
+            let input: Vec<f64> = sine(440.0, 0.5).take(phrase).collect();
+            let input = signal::from_iter(input.into_iter().chain(std::iter::repeat(0.0)));
+            let delay = MultiTapDelay::new(input, FS)
+                .with_dry(0.0)
+                .with_tap(Tap::new(Ms(150.0).to_frames(FS), 0.5))
+                .with_feedback(0, 0.5);
+            let delay = if ducking {
+                delay.with_ducking(Db(-30.0), Db(20.0))
+            } else {
+                delay
+            };
+            delay.take(2 * phrase).map(|[l, _]| l).collect::<Vec<f64>>()
+        };
+        let (plain, ducked) = (delay(false), delay(true));
+        let rms = |x: &[f64]| (x.iter().map(|x| x * x).sum::<f64>() / x.len() as f64).sqrt();
+
+        // suppressed under the phrase, by the whole range
+        let under = phrase / 2..phrase;
+        let ratio = rms(&ducked[under.clone()]) / rms(&plain[under]);
+        assert!(ratio < Db(-19.0).to_gain(), "{ratio}");
+
+        // and the same after it, as the feedback kept building up
+        let gap = 3 * phrase / 2..2 * phrase;
+        assert!(rms(&ducked[gap.clone()]) > 0.01);
+        assert_eq!(ducked[gap.clone()], plain[gap]);
+    }
+
+    #[test]
+    fn scrubber_holding_the_position_sustains_a_drone() {
+        // 220 Hz in the first half second, and 880 Hz in the next
+        let n = FS as usize;
+        let samples: Vec<f64> = (0..n)
+            .map(|i| {
+                let freq = if i < n / 2 { 220.0 } else { 880.0 };
+                0.5 * (2.0 * std::f64::consts::PI * freq * i as f64 / FS).sin()
+            })
+            .collect();
+        let position = Arc::new(AtomicF64::new(0.25));
+        // covering a period of 220 Hz
+        let mut scrubber =
+            Scrubber::new(samples, FS, position.clone()).with_search(Ms(5.0).to_frames(FS));
+
+        // twice as long as the whole buffer from the same point
+        let out: Vec<f64> = (0..2 * n).map(|_| scrubber.next()).collect();
+        let settled = &out[n / 10..];
+        assert!((zero_crossing_freq(settled) - 220.0).abs() < 1.0);
+        let rms = |x: &[f64]| (x.iter().map(|x| x * x).sum::<f64>() / x.len() as f64).sqrt();
+        for chunk in settled.chunks(n / 10) {
+            let rms = rms(chunk);
+            assert!(
+                (rms - 0.5 * std::f64::consts::FRAC_1_SQRT_2).abs() < 0.03,
+                "{rms}"
+            );
+        }
+
+        position.set(0.75);
+        let out: Vec<f64> = (0..n / 2).map(|_| scrubber.next()).collect();
+        assert!((zero_crossing_freq(&out[n / 10..]) - 880.0).abs() < 2.0);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::gate::{Edge, EdgeDetector, Gate, SeqGate};
+use crate::humanize::{Step, MAX_RATCHET};
+use crate::units::Frames;
+use dasp::Signal;
+
+pub use crate::core::envelope::Env;
+
+impl Env {
+    /// An envelope of a single note that ends after `total_frames`.
+    pub fn finite(
+        total_frames: Frames,
+        attack_frames: Frames,
+        release_frames: Frames,
+    ) -> impl Iterator<Item = f64> {
+        let mut env = Self::new(total_frames, attack_frames, release_frames);
+        std::iter::from_fn(move || env.next_level())
+    }
+
+    /// An envelope that plays a note of `step_length` on each step of `seq`
+    /// whose value is `true`, and keeps silent after the end of the sequence.
+    /// This is `triggered()` by a `SeqGate` which closes `release_frames`
+    /// before the end of each step, so that the note ends with the step.
+    pub fn gated(
+        seq: Vec<bool>,
+        step_length: Frames,
+        attack_frames: Frames,
+        release_frames: Frames,
+    ) -> impl Signal<Frame = f64> {
+        // closes at least a frame before the end, so the next note restarts
+        let gate_length = step_length.0.saturating_sub(release_frames.0.max(1));
+        let gate = SeqGate::new(seq, step_length, Frames(gate_length));
+        Self::triggered(gate, attack_frames, release_frames)
+    }
+
+    /// An envelope driven by a gate: the rising edge starts the attack, which
+    /// rises linearly to 1.0 in `attack_frames` and sustains while the gate
+    /// is open, and the falling edge starts the release, which falls linearly
+    /// to 0.0 in `release_frames`. Both start from the current level, so that
+    /// a note restarted (or released) in the middle doesn't click.
+    pub fn triggered<G: Gate>(
+        gate: G,
+        attack_frames: Frames,
+        release_frames: Frames,
+    ) -> impl Signal<Frame = f64> {
+        Triggered {
+            gate,
+            edges: EdgeDetector::new(),
+            attack_frames: attack_frames.0,
+            release_frames: release_frames.0,
+            rising: false,
+            from: 0.0,
+            elapsed: 0,
+            level: 0.0,
+        }
+    }
+
+    /// An envelope that plays a note of `note_length` from the start of each
+    /// step (which must be in order) scaled by its velocity (with the accent),
+    /// and keeps silent before the first step and after the last note. A note
+    /// still sounding is cut when the next step starts. With the straight
+    /// steps of `Humanize::steps()`, this is the same as `gated()` with all
+    /// `true`.
+    ///
+    /// A step with the ratchet of `n` plays `n` notes of `note_length / n`
+    /// one after another instead, each with its own attack and release.
+    pub fn scheduled(
+        steps: Vec<Step>,
+        note_length: Frames,
+        attack_frames: Frames,
+        release_frames: Frames,
+    ) -> impl Signal<Frame = f64> {
+        let mut steps = steps.into_iter();
+        let next_step = steps.next();
+        Scheduled {
+            env: Self::new(note_length, attack_frames, release_frames),
+            note_length: note_length.0,
+            steps,
+            next_step,
+            velocity: None,
+            retriggers: Vec::with_capacity(MAX_RATCHET - 1),
+            cur_frame: 0,
+        }
+    }
+}
+
+struct Triggered<G: Gate> {
+    gate: G,
+    edges: EdgeDetector,
+    attack_frames: usize,
+    release_frames: usize,
+    // whether in the attack (or the sustain), or in the release
+    rising: bool,
+    // the level at the last edge, and the frames since then
+    from: f64,
+    elapsed: usize,
+    level: f64,
+}
+
+impl<G: Gate> Signal for Triggered<G> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        if let Some(edge) = self.edges.process(self.gate.next_gate()) {
+            self.rising = edge == Edge::Rising;
+            self.from = self.level;
+            self.elapsed = 0;
+        }
+        self.elapsed = self.elapsed.saturating_add(1);
+
+        // computed from the edge rather than accumulated, so that a note
+        // from 0.0 has the same levels as `Env::next_level()`
+        self.level = if self.rising {
+            if self.elapsed >= self.attack_frames {
+                1.0
+            } else {
+                self.from + (1.0 - self.from) * self.elapsed as f64 / self.attack_frames as f64
+            }
+        } else if self.elapsed >= self.release_frames {
+            0.0
+        } else {
+            let left = self.release_frames - self.elapsed;
+            self.from * left as f64 / self.release_frames as f64
+        };
+        self.level
+    }
+}
+
+struct Scheduled {
+    env: Env,
+    note_length: usize,
+    steps: std::vec::IntoIter<Step>,
+    next_step: Option<Step>,
+    // the velocity of the note sounding, if any
+    velocity: Option<f64>,
+    // the frames of the notes left of the ratchet, in the reverse order
+    retriggers: Vec<usize>,
+    cur_frame: usize,
+}
+
+impl Signal for Scheduled {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        if let Some(step) = self.next_step.filter(|s| s.start.0 <= self.cur_frame) {
+            // the notes of the ratchet divide the note evenly
+            let ratchet = step.ratchet.max(1);
+            let length = self.note_length / ratchet;
+            self.env.set_note_length(Frames(length));
+            self.env.retrigger();
+            let cur_frame = self.cur_frame;
+            self.retriggers.clear();
+            self.retriggers
+                .extend((1..ratchet).rev().map(|i| cur_frame + i * length));
+            self.velocity = Some(step.gain());
+            self.next_step = self.steps.next();
+        } else if self.retriggers.last() == Some(&self.cur_frame) {
+            self.retriggers.pop();
+            self.env.retrigger();
+        }
+        self.cur_frame += 1;
+
+        match (self.velocity, self.env.next_level()) {
+            (Some(velocity), Some(level)) => velocity * level,
+            _ => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finite_and_gated_overlap_identically() {
+        let (note, attack, release) = (Frames(1000), Frames(100), Frames(300));
+        let finite: Vec<f64> = Env::finite(note, attack, release).collect();
+        assert_eq!(finite.len(), note.0);
+
+        let mut gated = Env::gated(vec![true, false], note, attack, release);
+        let gated: Vec<f64> = (0..2 * note.0).map(|_| gated.next()).collect();
+        assert_eq!(finite[..], gated[..note.0]);
+        // the rest
+        assert!(gated[note.0..].iter().all(|&x| x == 0.0));
+    }
+
+    // the frames where the level starts rising from 0
+    fn attacks(levels: &[f64]) -> Vec<usize> {
+        (0..levels.len())
+            .filter(|&i| levels[i] > 0.0 && (i == 0 || levels[i - 1] == 0.0))
+            .collect()
+    }
+
+    #[test]
+    fn ratchet_retriggers_evenly_within_the_step() {
+        let (note, attack, release) = (Frames(1200), Frames(10), Frames(50));
+        let steps = vec![
+            Step::new(Frames(0), 1.0).with_ratchet(3),
+            Step::new(Frames(2000), 1.0),
+        ];
+        let mut env = Env::scheduled(steps, note, attack, release);
+        let levels: Vec<f64> = (0..4000).map(|_| env.next()).collect();
+        assert_eq!(attacks(&levels), [0, 400, 800, 2000]);
+        // each note of the ratchet ends before the next
+        assert_eq!(levels[399], 0.0);
+        assert!(levels[400..800].contains(&1.0));
+    }
+
+    #[test]
+    fn accent_boosts_the_peak() {
+        let (note, attack, release) = (Frames(1000), Frames(10), Frames(50));
+        let steps = vec![
+            Step::new(Frames(0), 0.5),
+            Step::new(Frames(1000), 0.5).with_accent(true),
+        ];
+        let mut env = Env::scheduled(steps, note, attack, release);
+        let levels: Vec<f64> = (0..2000).map(|_| env.next()).collect();
+        let peak = |x: &[f64]| x.iter().fold(0.0, |a: f64, &b| a.max(b));
+        assert_eq!(peak(&levels[..1000]), 0.5);
+        assert_eq!(peak(&levels[1000..]), 0.5 * crate::humanize::ACCENT);
+    }
+
+    #[test]
+    fn edges_of_a_gate_start_the_attack_and_the_release() {
+        use crate::params::AtomicF64;
+        use std::sync::Arc;
+
+        // a gate from the events of another thread
+        let gate = Arc::new(AtomicF64::new(0.0));
+        let mut env = Env::triggered(gate.clone(), Frames(3), Frames(4));
+        let mut render = |open: bool, n: usize| -> Vec<f64> {
+            gate.set(if open { 1.0 } else { 0.0 });
+            (0..n).map(|_| env.next()).collect()
+        };
+        assert_eq!(render(false, 2), [0.0, 0.0]);
+        assert_eq!(render(true, 5), [1.0 / 3.0, 2.0 / 3.0, 1.0, 1.0, 1.0]);
+        assert_eq!(render(false, 5), [0.75, 0.5, 0.25, 0.0, 0.0]);
+
+        // released in the middle of the attack, from the level reached
+        let mut env = Env::triggered(
+            SeqGate::new(vec![true], Frames(8), Frames(2)),
+            Frames(4),
+            Frames(2),
+        );
+        let levels: Vec<f64> = (0..5).map(|_| env.next()).collect();
+        assert_eq!(levels, [0.25, 0.5, 0.25, 0.0, 0.0]);
+    }
+}
@@ -0,0 +1,218 @@
+//! Fades and crossfades of buffers for offline use, and looping a signal with
+//! a crossfaded seam.
+
+use crate::units::Frames;
+use dasp::Signal;
+use std::f64::consts::FRAC_PI_2;
+
+// the level where the exponential curve starts, i.e. -60 dB
+const EXP_FLOOR: f64 = 0.001;
+
+/// The shape of a fade.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FadeCurve {
+    /// The gain changes linearly. A crossfade keeps the amplitude of
+    /// correlated content (e.g. the same waveform on both sides) constant.
+    Linear,
+    /// `sin` and `cos` of a quarter period. A crossfade keeps the power of
+    /// uncorrelated content (e.g. different parts of a recording) constant.
+    #[default]
+    EqualPower,
+    /// The gain changes linearly in dB from -60 dB (and then reaches 0), which
+    /// sounds even to the ear.
+    Exponential,
+}
+
+impl FadeCurve {
+    /// The gain of a fade-in at `t` (0.0 to 1.0). The gain of a fade-out is
+    /// that at `1 - t`.
+    pub fn gain(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Self::Linear => t,
+            Self::EqualPower => (FRAC_PI_2 * t).sin(),
+            Self::Exponential => (EXP_FLOOR.powf(1.0 - t) - EXP_FLOOR) / (1.0 - EXP_FLOOR),
+        }
+    }
+}
+
+/// Fades in the first `length` frames of `samples` from silence.
+pub fn fade_in(samples: &mut [f64], length: Frames, curve: FadeCurve) {
+    let n = length.0.min(samples.len());
+    for (i, x) in samples[..n].iter_mut().enumerate() {
+        *x *= curve.gain(i as f64 / n as f64);
+    }
+}
+
+/// Fades out the last `length` frames of `samples` to silence.
+pub fn fade_out(samples: &mut [f64], length: Frames, curve: FadeCurve) {
+    let n = length.0.min(samples.len());
+    let start = samples.len() - n;
+    for (i, x) in samples[start..].iter_mut().enumerate() {
+        *x *= curve.gain(1.0 - (i + 1) as f64 / n as f64);
+    }
+}
+
+/// Joins `a` and `b`, overlapping the last `length` frames of `a` with the
+/// first ones of `b` while fading out `a` and fading in `b`. The length of the
+/// result is `a.len() + b.len() - length`, where `length` is limited to the
+/// shorter of them.
+pub fn crossfade(a: &[f64], b: &[f64], length: Frames, curve: FadeCurve) -> Vec<f64> {
+    let n = length.0.min(a.len()).min(b.len());
+    let start = a.len() - n;
+
+    let mut out = Vec::with_capacity(a.len() + b.len() - n);
+    out.extend_from_slice(&a[..start]);
+    for (i, (&x, &y)) in a[start..].iter().zip(&b[..n]).enumerate() {
+        let t = (i as f64 + 0.5) / n as f64;
+        out.push(x * curve.gain(1.0 - t) + y * curve.gain(t));
+    }
+    out.extend_from_slice(&b[n..]);
+    out
+}
+
+/// The positive-going zero crossing nearest to `pos` within `window` frames
+/// either way, i.e. the index `i` where `samples[i - 1] < 0.0 <= samples[i]`,
+/// preferring the earlier one on ties. A loop between two of them doesn't
+/// jump at the seam.
+pub fn nearest_zero_crossing(samples: &[f64], pos: usize, window: usize) -> Option<usize> {
+    let is_crossing =
+        |i: usize| i > 0 && i < samples.len() && samples[i - 1] < 0.0 && samples[i] >= 0.0;
+    (0..=window).find_map(|d| {
+        [pos.checked_sub(d), pos.checked_add(d)]
+            .into_iter()
+            .flatten()
+            .find(|&i| is_crossing(i))
+    })
+}
+
+/// Repeats the first `length` frames of a signal forever. The end of each
+/// cycle is crossfaded into the start of it, so the seam doesn't click even if
+/// the signal doesn't end where it starts.
+///
+/// The signal is rendered on `new()`. The first cycle plays from the start as
+/// it is, and the following cycles start `crossfade` frames later, right after
+/// the part the end has faded into, so a cycle is `length - crossfade` frames
+/// long, except for the first one.
+pub struct Loop {
+    intro: Vec<f64>,
+    cycle: Vec<f64>,
+    cur_frame: usize,
+    in_intro: bool,
+}
+
+impl Loop {
+    /// `crossfade` is limited to the half of `length` (which must not be 0),
+    /// and the curve is `FadeCurve::EqualPower`.
+    pub fn new<S: Signal<Frame = f64>>(signal: S, length: Frames, crossfade: Frames) -> Self {
+        Self::with_curve(signal, length, crossfade, FadeCurve::EqualPower)
+    }
+
+    /// `new()` with the curve of the crossfade.
+    pub fn with_curve<S: Signal<Frame = f64>>(
+        signal: S,
+        length: Frames,
+        crossfade: Frames,
+        curve: FadeCurve,
+    ) -> Self {
+        assert!(length.0 > 0, "the length of the loop must be positive");
+        let samples: Vec<f64> = signal.take(length.0).collect();
+        let n = crossfade.0.min(samples.len() / 2);
+
+        // the end fades into the start, after which the cycle continues
+        let (start, rest) = samples.split_at(n);
+        let mut cycle = rest.to_vec();
+        let len = cycle.len();
+        for (i, (y, &x)) in cycle[len - n..].iter_mut().zip(start).enumerate() {
+            let t = (i as f64 + 0.5) / n as f64;
+            *y = *y * curve.gain(1.0 - t) + x * curve.gain(t);
+        }
+
+        Self {
+            intro: start.to_vec(),
+            cycle,
+            cur_frame: 0,
+            in_intro: n > 0,
+        }
+    }
+}
+
+impl Signal for Loop {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        if self.in_intro {
+            let x = self.intro[self.cur_frame];
+            self.cur_frame += 1;
+            if self.cur_frame == self.intro.len() {
+                self.in_intro = false;
+                self.cur_frame = 0;
+            }
+            return x;
+        }
+
+        let x = self.cycle[self.cur_frame];
+        self.cur_frame = (self.cur_frame + 1) % self.cycle.len();
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dasp::signal;
+
+    const FS: f64 = 48000.0;
+
+    fn rms(samples: &[f64]) -> f64 {
+        (samples.iter().map(|x| x * x).sum::<f64>() / samples.len() as f64).sqrt()
+    }
+
+    #[test]
+    fn loop_seam_does_not_jump() {
+        // a ramp from -0.8 up to +0.8 over 0.1 s, which jumps by 1.6 at the
+        // seam without the crossfade
+        let length = 4800;
+        let mut n = 0;
+        let ramp = signal::gen_mut(move || {
+            n += 1;
+            -0.8 + 1.6 * (n - 1) as f64 / (length - 1) as f64
+        });
+        let crossfade = Frames(480); // 10 ms
+        let mut looped = Loop::new(ramp, Frames(length), crossfade);
+        let out: Vec<f64> = (0..4 * length).map(|_| looped.next()).collect();
+
+        // the steepest slope of the equal-power crossfade from +0.8 to -0.8,
+        // i.e. of 0.8 * (cos - sin), plus that of the ramp
+        let slope = 0.8 * 2f64.sqrt() * FRAC_PI_2 / crossfade.0 as f64 + 1.6 / length as f64;
+        let jump = out
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f64::max);
+        assert!(jump <= slope * 1.01, "{jump} > {slope}");
+        // a cycle is shortened by the crossfade
+        let cycle = length - crossfade.0;
+        assert_eq!(
+            out[length..length + cycle],
+            out[length + cycle..length + 2 * cycle]
+        );
+    }
+
+    #[test]
+    fn crossfade_keeps_the_power() {
+        // 44 periods in 0.1 s, so the end of it continues into the start
+        let a: Vec<f64> = signal::rate(FS)
+            .const_hz(440.0)
+            .sine()
+            .take(FS as usize / 10)
+            .collect();
+        let joined = crossfade(&a, &a, Frames(2400), FadeCurve::Linear);
+        assert_eq!(joined.len(), 2 * a.len() - 2400);
+        // in the chunks of 11 periods, over the crossfade and around it
+        let expected = rms(&a);
+        for chunk in joined.chunks(1200) {
+            let ratio = rms(chunk) / expected;
+            assert!((ratio - 1.0).abs() < 0.01, "{ratio}");
+        }
+    }
+}
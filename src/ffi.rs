@@ -0,0 +1,456 @@
+//! A C API of a monophonic synth voice (a saw through a low-pass) for calling
+//! from other languages, e.g. Python's `ctypes` in a notebook. It plays like a
+//! classic mono lead: the last note pressed sounds, and a note pressed while
+//! another is held glides to its pitch without retriggering (legato).
+//! Releasing it returns to the previous note if that's still held (see the
+//! mono mode of `PolySynth`). Build the shared library without the audio
+//! devices (cpal) by:
+//!
+//! ```text
+//! cargo rustc --release --lib --crate-type cdylib --no-default-features --features ffi
+//! ```
+//!
+//! With `--features ffi,song`, an engine can also play a song file along
+//! with the voice (see `spp_load_song()`).
+//!
+//! The engines are referred to by handles (0 is never a valid one), so a
+//! stale or wrong handle is an error instead of a crash. All the functions
+//! return `SPP_OK` or a negative error code, and never unwind a panic into
+//! the caller. See `ffi/sound_programming_practice.h` for the header and
+//! `ffi/render.py` for an example.
+
+use crate::core::biquad::Biquad;
+use crate::core::multiosc::Waveform;
+use crate::core::smooth::SmoothedParam;
+#[cfg(feature = "song")]
+use crate::params::ParamSet;
+use crate::poly::{PolySynth, VoiceEvent};
+#[cfg(feature = "song")]
+use crate::song::{Song, SongPlayer};
+use crate::units::{Hz, Ms};
+use std::ffi::{c_char, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard};
+
+pub const SPP_OK: i32 = 0;
+/// A pointer argument is null.
+pub const SPP_ERR_NULL: i32 = -1;
+/// The handle doesn't refer to a live engine.
+pub const SPP_ERR_HANDLE: i32 = -2;
+/// A value is out of its range, a name is unknown, a string is not UTF-8, or
+/// a song is invalid.
+pub const SPP_ERR_INVALID: i32 = -3;
+/// A bug: the function panicked.
+pub const SPP_ERR_PANIC: i32 = -4;
+
+// the engines; a handle is the index plus 1
+static ENGINES: Mutex<Vec<Option<Engine>>> = Mutex::new(Vec::new());
+
+// the time for the cutoff to glide to the new value
+const SMOOTHING: Ms = Ms(20.0);
+
+struct Engine {
+    fs: f64, // sampling rate
+    // a voice of a saw in the mono mode, and its stereo output
+    synth: PolySynth,
+    synth_buf: Vec<[f64; 2]>,
+    lpf: Biquad,
+    cutoff: SmoothedParam,
+    q: f64,
+    volume: f64,
+    attack: Ms,
+    release: Ms,
+    // the song playing along, with its parameters, and its stereo output
+    #[cfg(feature = "song")]
+    song: Option<(SongPlayer, ParamSet)>,
+    #[cfg(feature = "song")]
+    song_buf: Vec<[f64; 2]>,
+}
+
+impl Engine {
+    fn new(fs: f64) -> Self {
+        let cutoff = 2000.0;
+        let q = core::f64::consts::FRAC_1_SQRT_2;
+        let (attack, release) = (Ms(5.0), Ms(200.0));
+        Self {
+            fs,
+            synth: PolySynth::new(fs, Waveform::Saw, 1)
+                .with_envelope(attack, release)
+                .with_mono(Ms(50.0)),
+            synth_buf: vec![],
+            lpf: Biquad::low_pass(fs, Hz(cutoff), q),
+            cutoff: SmoothedParam::new(fs, SMOOTHING, cutoff),
+            q,
+            volume: 0.5,
+            attack,
+            release,
+            #[cfg(feature = "song")]
+            song: None,
+            #[cfg(feature = "song")]
+            song_buf: vec![],
+        }
+    }
+
+    fn note_on(&mut self, key: u8, velocity: u8) {
+        let velocity = f64::from(velocity) / 127.0;
+        self.synth.handle(VoiceEvent::NoteOn { key, velocity });
+    }
+
+    fn note_off(&mut self, key: u8) {
+        self.synth.handle(VoiceEvent::NoteOff { key });
+    }
+
+    fn set_param(&mut self, name: &str, value: f64) -> i32 {
+        if !value.is_finite() {
+            return SPP_ERR_INVALID;
+        }
+        match name {
+            "cutoff" if value > 0.0 => self.cutoff.set_target(value.min(self.fs * 0.45)),
+            "q" if value > 0.0 => {
+                self.q = value;
+                let cutoff = Hz(self.cutoff.value());
+                self.lpf
+                    .set_coefficients(&Biquad::low_pass(self.fs, cutoff, self.q));
+            }
+            "volume" if value >= 0.0 => self.volume = value,
+            "attack" if value >= 0.0 => {
+                self.attack = Ms(value);
+                self.synth.set_envelope(self.attack, self.release);
+            }
+            "release" if value >= 0.0 => {
+                self.release = Ms(value);
+                self.synth.set_envelope(self.attack, self.release);
+            }
+            "glide" if value >= 0.0 => self.synth.set_glide(Ms(value)),
+            _ => {
+                // a parameter of the song
+                #[cfg(feature = "song")]
+                if let Some((_, params)) = &self.song {
+                    if params.set(name, value) {
+                        return SPP_OK;
+                    }
+                }
+                return SPP_ERR_INVALID;
+            }
+        }
+        SPP_OK
+    }
+
+    /// Starts playing the song from its start, replacing the one playing.
+    #[cfg(feature = "song")]
+    fn load_song(&mut self, toml: &str) -> i32 {
+        let Ok(song) = Song::from_toml(toml) else {
+            return SPP_ERR_INVALID;
+        };
+        let params = song.params();
+        match SongPlayer::new(&song, self.fs, &params) {
+            Ok(player) => {
+                self.song = Some((player, params));
+                SPP_OK
+            }
+            Err(_) => SPP_ERR_INVALID,
+        }
+    }
+
+    fn render(&mut self, out: &mut [f32]) {
+        let mut voice = std::mem::take(&mut self.synth_buf);
+        voice.resize(out.len(), [0.0; 2]);
+        self.synth.render_block(&mut voice, &[]);
+        for (x, [l, r]) in out.iter_mut().zip(&voice) {
+            // the voice is centered, at `FRAC_1_SQRT_2` on both sides
+            *x = self.filter(core::f64::consts::FRAC_1_SQRT_2 * (l + r)) as f32;
+        }
+        self.synth_buf = voice;
+        // mixed down to mono
+        #[cfg(feature = "song")]
+        if let Some((player, _)) = &mut self.song {
+            self.song_buf.resize(out.len(), [0.0; 2]);
+            player.render_block(&mut self.song_buf);
+            for (x, [l, r]) in out.iter_mut().zip(&self.song_buf) {
+                *x += (0.5 * (l + r)) as f32;
+            }
+        }
+    }
+
+    fn filter(&mut self, x: f64) -> f64 {
+        if self.cutoff.is_gliding() {
+            let cutoff = Hz(self.cutoff.next_value());
+            self.lpf
+                .set_coefficients(&Biquad::low_pass(self.fs, cutoff, self.q));
+        }
+        self.volume * self.lpf.process(x)
+    }
+}
+
+fn engines() -> MutexGuard<'static, Vec<Option<Engine>>> {
+    // a panic while locked leaves the engines as they were, so keep going
+    ENGINES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Runs `f` on the engine of the handle, catching a panic.
+fn with_engine(handle: u32, f: impl FnOnce(&mut Engine) -> i32) -> i32 {
+    catch_unwind(AssertUnwindSafe(|| {
+        let mut engines = engines();
+        let engine = (handle as usize)
+            .checked_sub(1)
+            .and_then(|i| engines.get_mut(i))
+            .and_then(Option::as_mut);
+        match engine {
+            Some(engine) => f(engine),
+            None => SPP_ERR_HANDLE,
+        }
+    }))
+    .unwrap_or(SPP_ERR_PANIC)
+}
+
+/// Creates an engine at the sampling rate, and returns its handle, or 0 if
+/// the rate is not positive.
+#[no_mangle]
+pub extern "C" fn spp_engine_new(sample_rate: f64) -> u32 {
+    if !(sample_rate.is_finite() && sample_rate > 0.0) {
+        return 0;
+    }
+    catch_unwind(|| {
+        let mut engines = engines();
+        let engine = Some(Engine::new(sample_rate));
+        // reuse a freed slot
+        let index = match engines.iter().position(Option::is_none) {
+            Some(i) => {
+                engines[i] = engine;
+                i
+            }
+            None => {
+                engines.push(engine);
+                engines.len() - 1
+            }
+        };
+        u32::try_from(index + 1).unwrap_or(0)
+    })
+    .unwrap_or(0)
+}
+
+/// Frees the engine. The handle is invalid afterwards (until it's reused by
+/// `spp_engine_new()`).
+#[no_mangle]
+pub extern "C" fn spp_engine_free(handle: u32) -> i32 {
+    catch_unwind(|| {
+        let mut engines = engines();
+        match (handle as usize)
+            .checked_sub(1)
+            .and_then(|i| engines.get_mut(i))
+        {
+            Some(slot @ Some(_)) => {
+                *slot = None;
+                SPP_OK
+            }
+            _ => SPP_ERR_HANDLE,
+        }
+    })
+    .unwrap_or(SPP_ERR_PANIC)
+}
+
+/// Starts a note of the MIDI key (0 to 127) and velocity (1 to 127). If
+/// another note is held, the pitch glides to the new key without
+/// retriggering.
+#[no_mangle]
+pub extern "C" fn spp_note_on(handle: u32, key: u8, velocity: u8) -> i32 {
+    with_engine(handle, |engine| {
+        if key > 127 || !(1..=127).contains(&velocity) {
+            return SPP_ERR_INVALID;
+        }
+        engine.note_on(key, velocity);
+        SPP_OK
+    })
+}
+
+/// Releases the key. If it's the one sounding, the pitch glides back to the
+/// previous key held, or the note ends if none is held.
+#[no_mangle]
+pub extern "C" fn spp_note_off(handle: u32, key: u8) -> i32 {
+    with_engine(handle, |engine| {
+        if key > 127 {
+            return SPP_ERR_INVALID;
+        }
+        engine.note_off(key);
+        SPP_OK
+    })
+}
+
+/// Sets a parameter by the name: `cutoff` (Hz), `q`, `volume` (gain),
+/// `attack`, `release`, and `glide` (ms), or one of the song loaded.
+///
+/// # Safety
+///
+/// `name` must be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn spp_set_param(handle: u32, name: *const c_char, value: f64) -> i32 {
+    if name.is_null() {
+        return SPP_ERR_NULL;
+    }
+    // SAFETY: the caller guarantees a NUL-terminated string
+    let Ok(name) = unsafe { CStr::from_ptr(name) }.to_str() else {
+        return SPP_ERR_INVALID;
+    };
+    with_engine(handle, |engine| engine.set_param(name, value))
+}
+
+/// Renders `frames` samples (mono) into `out`.
+///
+/// # Safety
+///
+/// `out` must be null or point to at least `frames` writable floats.
+#[no_mangle]
+pub unsafe extern "C" fn spp_render(handle: u32, out: *mut f32, frames: usize) -> i32 {
+    if out.is_null() {
+        return SPP_ERR_NULL;
+    }
+    // SAFETY: the caller guarantees the length
+    let out = unsafe { std::slice::from_raw_parts_mut(out, frames) };
+    with_engine(handle, |engine| {
+        engine.render(out);
+        SPP_OK
+    })
+}
+
+/// Loads a song (the TOML of a song file) to play from its start along with
+/// the voice, replacing the one loaded. After it ends, the engine renders
+/// only the voice. The parameters of the tracks (e.g. `bass.cutoff`) can be
+/// set by `spp_set_param()`. Only with the `song` feature.
+///
+/// # Safety
+///
+/// `toml` must be null or a NUL-terminated string.
+#[cfg(feature = "song")]
+#[no_mangle]
+pub unsafe extern "C" fn spp_load_song(handle: u32, toml: *const c_char) -> i32 {
+    if toml.is_null() {
+        return SPP_ERR_NULL;
+    }
+    // SAFETY: the caller guarantees a NUL-terminated string
+    let Ok(toml) = unsafe { CStr::from_ptr(toml) }.to_str() else {
+        return SPP_ERR_INVALID;
+    };
+    with_engine(handle, |engine| engine.load_song(toml))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    const FS: f64 = 48000.0;
+
+    // the tests share the engines, so a handle freed by one isn't reused by
+    // another while it checks the handle
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    fn serial() -> MutexGuard<'static, ()> {
+        SERIAL.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn render(handle: u32, frames: usize) -> Vec<f32> {
+        let mut out = vec![0.0; frames];
+        assert_eq!(
+            unsafe { spp_render(handle, out.as_mut_ptr(), frames) },
+            SPP_OK
+        );
+        out
+    }
+
+    fn set_param(handle: u32, name: &str, value: f64) -> i32 {
+        let name = CString::new(name).unwrap();
+        unsafe { spp_set_param(handle, name.as_ptr(), value) }
+    }
+
+    #[test]
+    fn plays_a_note_and_releases_it() {
+        let _serial = serial();
+        let handle = spp_engine_new(FS);
+        assert_ne!(handle, 0);
+        assert!(render(handle, 480).iter().all(|&x| x == 0.0));
+        assert_eq!(spp_note_on(handle, 60, 100), SPP_OK);
+        assert!(render(handle, 4800).iter().any(|&x| x.abs() > 0.01));
+        assert_eq!(spp_note_off(handle, 60), SPP_OK);
+        // well after the release of 200 ms
+        render(handle, 48000);
+        assert!(render(handle, 480).iter().all(|&x| x.abs() < 1e-4));
+        assert_eq!(spp_engine_free(handle), SPP_OK);
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        let _serial = serial();
+        assert_eq!(spp_engine_new(0.0), 0);
+        assert_eq!(spp_engine_new(f64::NAN), 0);
+
+        let handle = spp_engine_new(FS);
+        assert_eq!(spp_note_on(handle, 128, 100), SPP_ERR_INVALID);
+        assert_eq!(spp_note_on(handle, 60, 0), SPP_ERR_INVALID);
+        assert_eq!(spp_note_off(handle, 128), SPP_ERR_INVALID);
+        assert_eq!(set_param(handle, "cutoff", 800.0), SPP_OK);
+        assert_eq!(set_param(handle, "cutoff", -1.0), SPP_ERR_INVALID);
+        assert_eq!(set_param(handle, "volume", f64::INFINITY), SPP_ERR_INVALID);
+        assert_eq!(set_param(handle, "resonance", 1.0), SPP_ERR_INVALID);
+        // not UTF-8
+        let name = c"\xffcutoff";
+        assert_eq!(
+            unsafe { spp_set_param(handle, name.as_ptr(), 800.0) },
+            SPP_ERR_INVALID
+        );
+        assert_eq!(
+            unsafe { spp_set_param(handle, std::ptr::null(), 800.0) },
+            SPP_ERR_NULL
+        );
+        assert_eq!(
+            unsafe { spp_render(handle, std::ptr::null_mut(), 64) },
+            SPP_ERR_NULL
+        );
+        assert_eq!(spp_engine_free(handle), SPP_OK);
+    }
+
+    #[test]
+    fn rejects_invalid_handles() {
+        let _serial = serial();
+        let freed = spp_engine_new(FS);
+        assert_eq!(spp_engine_free(freed), SPP_OK);
+        for handle in [0, freed, u32::MAX] {
+            assert_eq!(spp_engine_free(handle), SPP_ERR_HANDLE);
+            assert_eq!(spp_note_on(handle, 60, 100), SPP_ERR_HANDLE);
+            assert_eq!(spp_note_off(handle, 60), SPP_ERR_HANDLE);
+            assert_eq!(set_param(handle, "cutoff", 800.0), SPP_ERR_HANDLE);
+            let mut out = [0.0; 64];
+            assert_eq!(
+                unsafe { spp_render(handle, out.as_mut_ptr(), out.len()) },
+                SPP_ERR_HANDLE
+            );
+        }
+        // and the slot is reused
+        let handle = spp_engine_new(FS);
+        assert_eq!(handle, freed);
+        assert_eq!(spp_engine_free(handle), SPP_OK);
+    }
+
+    #[cfg(feature = "song")]
+    #[test]
+    fn plays_a_song_along_with_the_voice() {
+        let _serial = serial();
+        let handle = spp_engine_new(FS);
+        let song = c"bars = 1\n[[tracks]]\nname = \"bass\"\npattern = \"C2 . - .\"\n";
+        assert_eq!(unsafe { spp_load_song(handle, song.as_ptr()) }, SPP_OK);
+        // without any note of the voice
+        assert!(render(handle, 4800).iter().any(|&x| x.abs() > 0.01));
+        assert_eq!(set_param(handle, "bass.cutoff", 500.0), SPP_OK);
+        assert_eq!(set_param(handle, "lead.cutoff", 500.0), SPP_ERR_INVALID);
+
+        assert_eq!(
+            unsafe { spp_load_song(handle, c"bars = 0".as_ptr()) },
+            SPP_ERR_INVALID
+        );
+        assert_eq!(
+            unsafe { spp_load_song(handle, std::ptr::null()) },
+            SPP_ERR_NULL
+        );
+        assert_eq!(unsafe { spp_load_song(0, song.as_ptr()) }, SPP_ERR_HANDLE);
+        assert_eq!(spp_engine_free(handle), SPP_OK);
+    }
+}
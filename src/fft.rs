@@ -0,0 +1,161 @@
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Self { re, im }
+    }
+
+    /// exp(i * theta)
+    pub fn from_angle(theta: f64) -> Self {
+        Self::new(theta.cos(), theta.sin())
+    }
+
+    pub fn conj(self) -> Self {
+        Self::new(self.re, -self.im)
+    }
+
+    pub fn norm(self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn scale(self, k: f64) -> Self {
+        Self::new(self.re * k, self.im * k)
+    }
+}
+
+impl Add for Complex {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl AddAssign for Complex {
+    fn add_assign(&mut self, rhs: Self) {
+        self.re += rhs.re;
+        self.im += rhs.im;
+    }
+}
+
+impl Sub for Complex {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Self::new(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        Self::new(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+/// The periodic Hann window of length `n`. With the hop size of `n / 4`, the
+/// squares of the overlapping windows sum to 1.5.
+pub fn hann(n: usize) -> Vec<f64> {
+    (0..n)
+        .map(|i| 0.5 - 0.5 * (2.0 * std::f64::consts::PI * i as f64 / n as f64).cos())
+        .collect()
+}
+
+/// A radix-2 FFT of a fixed size (which must be a power of 2).
+pub struct Fft {
+    n: usize,
+    twiddles: Vec<Complex>,
+}
+
+impl Fft {
+    pub fn new(n: usize) -> Self {
+        assert!(n.is_power_of_two(), "the FFT size must be a power of 2");
+
+        let twiddles = (0..n / 2)
+            .map(|k| Complex::from_angle(-2.0 * std::f64::consts::PI * k as f64 / n as f64))
+            .collect();
+
+        Self { n, twiddles }
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    pub fn forward(&self, data: &mut [Complex]) {
+        self.transform(data, false);
+    }
+
+    /// The inverse FFT, including the scaling by 1 / n.
+    pub fn inverse(&self, data: &mut [Complex]) {
+        self.transform(data, true);
+        let k = 1.0 / self.n as f64;
+        for x in data.iter_mut() {
+            *x = x.scale(k);
+        }
+    }
+
+    /// The FFT of a real signal; `input` is zero-padded to the FFT size.
+    pub fn forward_real(&self, input: &[f64]) -> Vec<Complex> {
+        let mut data = vec![Complex::default(); self.n];
+        for (d, &x) in data.iter_mut().zip(input) {
+            d.re = x;
+        }
+        self.forward(&mut data);
+        data
+    }
+
+    fn transform(&self, data: &mut [Complex], inverse: bool) {
+        let n = self.n;
+        assert_eq!(data.len(), n);
+
+        // bit-reversal permutation
+        let mut j = 0;
+        for i in 1..n {
+            let mut bit = n >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        // butterflies
+        let mut len = 2;
+        while len <= n {
+            let stride = n / len;
+            for start in (0..n).step_by(len) {
+                for k in 0..len / 2 {
+                    let w = self.twiddles[k * stride];
+                    let w = if inverse { w.conj() } else { w };
+                    let a = data[start + k];
+                    let b = data[start + k + len / 2] * w;
+                    data[start + k] = a + b;
+                    data[start + k + len / 2] = a - b;
+                }
+            }
+            len <<= 1;
+        }
+    }
+}
@@ -0,0 +1,420 @@
+use crate::core::biquad::Biquad;
+use crate::fft::Complex;
+use crate::latency::Latency;
+use crate::units::Hz;
+use dasp::Signal;
+use std::f64::consts::PI;
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+fn blackman(n: usize, len: usize) -> f64 {
+    if len == 1 {
+        return 1.0;
+    }
+    let t = 2.0 * std::f64::consts::PI * n as f64 / (len - 1) as f64;
+    0.42 - 0.5 * t.cos() + 0.08 * (2.0 * t).cos()
+}
+
+/// The coefficients of a low-pass FIR filter designed by the windowed-sinc
+/// method (with the Blackman window). `taps` should be odd so that the delay
+/// is an integer number of frames.
+///
+/// Note that this takes the raw `f64`s (`fc` is in the same unit as `fs`) so
+/// that it can be used with normalized frequencies.
+pub fn windowed_sinc_low_pass(fs: f64, fc: f64, taps: usize) -> Vec<f64> {
+    let center = (taps - 1) as f64 / 2.0;
+    let fc = fc / fs; // normalized cutoff frequency
+
+    let h: Vec<f64> = (0..taps)
+        .map(|n| 2.0 * fc * sinc(2.0 * fc * (n as f64 - center)) * blackman(n, taps))
+        .collect();
+
+    // normalize the gain at DC to 1
+    let sum: f64 = h.iter().sum();
+    h.iter().map(|x| x / sum).collect()
+}
+
+/// The coefficients of a high-pass FIR filter, made by the spectral inversion
+/// of the low-pass one.
+pub fn windowed_sinc_high_pass(fs: f64, fc: f64, taps: usize) -> Vec<f64> {
+    let mut h = windowed_sinc_low_pass(fs, fc, taps);
+    for x in h.iter_mut() {
+        *x = -*x;
+    }
+    h[(taps - 1) / 2] += 1.0;
+    h
+}
+
+/// The coefficients of a band-pass FIR filter, made by the difference between
+/// two low-pass ones.
+pub fn windowed_sinc_band_pass(fs: f64, low: f64, high: f64, taps: usize) -> Vec<f64> {
+    let h_low = windowed_sinc_low_pass(fs, low, taps);
+    let h_high = windowed_sinc_low_pass(fs, high, taps);
+    h_high.iter().zip(&h_low).map(|(h, l)| h - l).collect()
+}
+
+/// A FIR filter. Since the coefficients are symmetric, the phase is linear
+/// (i.e. every frequency is delayed by the same `(taps - 1) / 2` frames)
+/// unlike the biquads, at the cost of the latency.
+pub struct FirFilter<S: Signal<Frame = f64>> {
+    signal: S,
+    coefs: Vec<f64>,
+    // The history of the input. Each sample is written twice, at `pos` and
+    // `pos + len`, so that the last `len` samples are always contiguous.
+    history: Vec<f64>,
+    pos: usize,
+}
+
+impl<S: Signal<Frame = f64>> FirFilter<S> {
+    /// Panics if `coefs` is empty.
+    pub fn new(signal: S, coefs: Vec<f64>) -> Self {
+        assert!(
+            !coefs.is_empty(),
+            "a FIR filter needs at least one coefficient"
+        );
+        let len = coefs.len();
+        Self {
+            signal,
+            coefs,
+            history: vec![0.0; len * 2],
+            pos: 0,
+        }
+    }
+
+    /// `taps` is rounded up to odd.
+    pub fn low_pass(signal: S, fs: f64, fc: Hz, taps: usize) -> Self {
+        Self::new(signal, windowed_sinc_low_pass(fs, fc.0, taps | 1))
+    }
+
+    /// `taps` is rounded up to odd.
+    pub fn high_pass(signal: S, fs: f64, fc: Hz, taps: usize) -> Self {
+        Self::new(signal, windowed_sinc_high_pass(fs, fc.0, taps | 1))
+    }
+
+    /// `taps` is rounded up to odd.
+    pub fn band_pass(signal: S, fs: f64, low: Hz, high: Hz, taps: usize) -> Self {
+        Self::new(signal, windowed_sinc_band_pass(fs, low.0, high.0, taps | 1))
+    }
+
+    /// Raw-unit convenience form of `low_pass()`, taking `fc` as a bare
+    /// `f64` in hertz. Prefer `low_pass()`.
+    pub fn low_pass_raw(signal: S, fs: f64, fc: f64, taps: usize) -> Self {
+        Self::low_pass(signal, fs, Hz(fc), taps)
+    }
+
+    /// Raw-unit convenience form of `high_pass()`. Prefer `high_pass()`.
+    pub fn high_pass_raw(signal: S, fs: f64, fc: f64, taps: usize) -> Self {
+        Self::high_pass(signal, fs, Hz(fc), taps)
+    }
+
+    /// Raw-unit convenience form of `band_pass()`. Prefer `band_pass()`.
+    pub fn band_pass_raw(signal: S, fs: f64, low: f64, high: f64, taps: usize) -> Self {
+        Self::band_pass(signal, fs, Hz(low), Hz(high), taps)
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for FirFilter<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let len = self.coefs.len();
+        let orig = self.signal.next();
+
+        self.history[self.pos] = orig;
+        self.history[self.pos + len] = orig;
+        self.pos = (self.pos + 1) % len;
+
+        // history[pos..pos + len] is from the oldest to the newest
+        self.history[self.pos..self.pos + len]
+            .iter()
+            .zip(self.coefs.iter().rev())
+            .map(|(x, h)| x * h)
+            .sum()
+    }
+}
+
+impl<S: Signal<Frame = f64>> Latency for FirFilter<S> {
+    fn latency_frames(&self) -> usize {
+        (self.coefs.len() - 1) / 2
+    }
+}
+
+/// The factor `(s + w)` of an analog transfer function, transformed by the
+/// bilinear transform (without the `(1 + z^-1)` of the denominator) as the
+/// coefficients of `1` and `z^-1`. `w` is prewarped so that the corner
+/// frequency stays at the same place.
+fn bilinear_factor(w: f64, fs: f64) -> [f64; 2] {
+    let w = 2.0 * fs * (w / (2.0 * fs)).tan();
+    [2.0 * fs + w, -(2.0 * fs - w)]
+}
+
+/// The product of two first-order polynomials of z^-1.
+fn mul_factors(p: [f64; 2], q: [f64; 2]) -> [f64; 3] {
+    [p[0] * q[0], p[0] * q[1] + p[1] * q[0], p[1] * q[1]]
+}
+
+/// The magnitude of the response of a cascade of biquads (`b`, `a`) at `f`.
+fn cascade_magnitude(sections: &[([f64; 3], [f64; 3])], fs: f64, f: f64) -> f64 {
+    let omega = 2.0 * PI * f / fs;
+    let magnitude = |c: &[f64; 3]| {
+        let re = c[0] + c[1] * omega.cos() + c[2] * (2.0 * omega).cos();
+        let im = -c[1] * omega.sin() - c[2] * (2.0 * omega).sin();
+        re.hypot(im)
+    };
+    sections
+        .iter()
+        .map(|(b, a)| magnitude(b) / magnitude(a))
+        .product()
+}
+
+fn biquad(b: [f64; 3], a: [f64; 3]) -> Biquad {
+    Biquad::new(b[0], b[1], b[2], a[0], a[1], a[2])
+}
+
+/// A Butterworth band-pass between `low` and `high` (the -3 dB points), as a
+/// cascade of `order` biquads; the slopes are `6 * order` dB/oct on each
+/// side. Unlike `Biquad::band_pass()`, the passband is flat and the skirts
+/// are steep, which suits splitting a signal into adjacent bands.
+///
+/// The edges are prewarped, so they stay at the same place after the
+/// bilinear transform. The gain at the center (the geometric mean of the
+/// edges) is normalized to 0 dB.
+pub fn butterworth_band_pass(fs: f64, low: Hz, high: Hz, order: usize) -> Vec<Biquad> {
+    assert!(order > 0, "the order must be at least 1");
+
+    let prewarp = |f: f64| 2.0 * fs * (PI * f / fs).tan();
+    let (wl, wh) = (prewarp(low.0), prewarp(high.0));
+    let w0 = (wl * wh).sqrt();
+    let bw = wh - wl;
+
+    // The band-pass transform s -> (s^2 + w0^2) / (bw s) maps each pole p of
+    // the low-pass prototype to the two roots of s^2 - p bw s + w0^2.
+    let mut denominators = vec![];
+    for m in 0..order.div_ceil(2) {
+        let theta = PI * (2 * m + order + 1) as f64 / (2 * order) as f64;
+        let p = Complex::from_angle(theta);
+        if p.im.abs() < 1e-12 {
+            // a real pole gives a conjugate pair (or two real poles) at once
+            denominators.push([1.0, -p.re * bw, w0 * w0]);
+        } else {
+            // a pair of complex poles gives two pairs; take one from each
+            let pb = p.scale(bw);
+            let d = pb * pb - Complex::new(4.0 * w0 * w0, 0.0);
+            let sqrt_d = Complex::from_angle(d.arg() / 2.0).scale(d.norm().sqrt());
+            for q in [pb + sqrt_d, pb - sqrt_d] {
+                let q = q.scale(0.5);
+                denominators.push([1.0, -2.0 * q.re, q.norm() * q.norm()]);
+            }
+        }
+    }
+
+    // each section is s / (s^2 + a1 s + a0), transformed by the bilinear
+    // transform s = k (1 - z^-1) / (1 + z^-1)
+    let k = 2.0 * fs;
+    let mut sections: Vec<([f64; 3], [f64; 3])> = denominators
+        .iter()
+        .map(|&[_, a1, a0]| {
+            (
+                [k, 0.0, -k],
+                [k * k + a1 * k + a0, 2.0 * (a0 - k * k), k * k - a1 * k + a0],
+            )
+        })
+        .collect();
+
+    // the analog center w0 is mapped back to this frequency
+    let center = fs / PI * (w0 / (2.0 * fs)).atan();
+    let gain = cascade_magnitude(&sections, fs, center);
+    for b in sections[0].0.iter_mut() {
+        *b /= gain;
+    }
+
+    sections.into_iter().map(|(b, a)| biquad(b, a)).collect()
+}
+
+/// The A-weighting of IEC 61672-1, which approximates the sensitivity of the
+/// ear at low levels. The gain is 0 dB at 1 kHz, and falls to about -19 dB at
+/// 100 Hz and -50 dB at 20 Hz.
+///
+/// The analog transfer function is converted by the bilinear transform with
+/// prewarping, so the response above 10 kHz deviates from the standard a bit
+/// at 44.1 kHz and 48 kHz.
+pub struct AWeighting<S: Signal<Frame = f64>> {
+    signal: S,
+    stages: [Biquad; 3],
+}
+
+impl<S: Signal<Frame = f64>> AWeighting<S> {
+    pub fn new(signal: S, fs: f64) -> Self {
+        // the poles of the analog transfer function
+        //
+        //                         k s^4
+        //   H(s) = ---------------------------------------
+        //          (s + w1)^2 (s + w2) (s + w3) (s + w4)^2
+        let w1 = 2.0 * PI * 20.598997;
+        let w2 = 2.0 * PI * 107.65265;
+        let w3 = 2.0 * PI * 737.86223;
+        let w4 = 2.0 * PI * 12194.217;
+
+        let (p1, p2, p3, p4) = (
+            bilinear_factor(w1, fs),
+            bilinear_factor(w2, fs),
+            bilinear_factor(w3, fs),
+            bilinear_factor(w4, fs),
+        );
+        let k = (2.0 * fs) * (2.0 * fs);
+        let mut sections = [
+            // s^2 / (s + w1)^2
+            ([k, -2.0 * k, k], mul_factors(p1, p1)),
+            // s^2 / ((s + w2) (s + w3))
+            ([k, -2.0 * k, k], mul_factors(p2, p3)),
+            // 1 / (s + w4)^2
+            ([1.0, 2.0, 1.0], mul_factors(p4, p4)),
+        ];
+
+        // normalize the gain at 1 kHz to 0 dB
+        let gain = cascade_magnitude(&sections, fs, 1000.0);
+        for b in sections[0].0.iter_mut() {
+            *b /= gain;
+        }
+
+        Self {
+            signal,
+            stages: sections.map(|(b, a)| biquad(b, a)),
+        }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for AWeighting<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        self.stages.iter_mut().fold(x, |x, stage| stage.process(x))
+    }
+}
+
+/// The K-weighting of ITU-R BS.1770 for measuring the loudness (LUFS): a
+/// high shelf of +4 dB modeling the head, followed by a high-pass around
+/// 38 Hz. The gain is about +0.7 dB at 1 kHz, which the loudness formula
+/// compensates by its -0.691 dB.
+///
+/// The coefficients are computed for `fs` as in libebur128, and match the
+/// ones in the standard at 48 kHz.
+pub struct KWeighting<S: Signal<Frame = f64>> {
+    signal: S,
+    stages: [Biquad; 2],
+}
+
+impl<S: Signal<Frame = f64>> KWeighting<S> {
+    pub fn new(signal: S, fs: f64) -> Self {
+        // the high shelf
+        let f0 = 1681.974450955533;
+        let g = 3.999843853973347;
+        let q = 0.7071752369554196;
+
+        let k = (PI * f0 / fs).tan();
+        let vh = 10.0_f64.powf(g / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let shelf = biquad(
+            [
+                vh + vb * k / q + k * k,
+                2.0 * (k * k - vh),
+                vh - vb * k / q + k * k,
+            ],
+            [
+                1.0 + k / q + k * k,
+                2.0 * (k * k - 1.0),
+                1.0 - k / q + k * k,
+            ],
+        );
+
+        // the high-pass
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+
+        // unlike the shelf, only the denominator is normalized
+        let k = (PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = biquad(
+            [a0, -2.0 * a0, a0],
+            [a0, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k],
+        );
+
+        Self {
+            signal,
+            stages: [shelf, high_pass],
+        }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for KWeighting<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        self.stages.iter_mut().fold(x, |x, stage| stage.process(x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dasp::signal;
+
+    #[test]
+    fn fir_group_delay_is_constant() {
+        let fs = 48000.0;
+        let taps = 101;
+        let impulse = signal::from_iter(std::iter::once(1.0).chain(std::iter::repeat(0.0)));
+        let mut fir = FirFilter::band_pass(impulse, fs, Hz(500.0), Hz(5000.0), taps);
+        let delay = fir.latency_frames();
+        assert_eq!(delay, 50);
+        let h: Vec<f64> = (0..taps).map(|_| fir.next()).collect();
+
+        // H(w) e^(jw delay) is real at any frequency if the group delay is
+        // `delay` everywhere
+        for freq in (1..48).map(|i| i as f64 * 500.0) {
+            let w = 2.0 * PI * freq / fs;
+            let h = h
+                .iter()
+                .enumerate()
+                .fold(Complex::default(), |acc, (n, &h)| {
+                    acc + Complex::from_angle(w * (delay as f64 - n as f64)).scale(h)
+                });
+            assert!(h.im.abs() < 1e-9 * h.norm().max(1e-6), "{freq} Hz: {h:?}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one coefficient")]
+    fn fir_rejects_empty_coefficients() {
+        FirFilter::new(signal::equilibrium::<f64>(), vec![]);
+    }
+
+    // the gain of the A-weighting for a sine at `freq`, from the RMS after
+    // the transient
+    fn a_weighting_db(fs: f64, freq: f64) -> f64 {
+        let len = fs as usize;
+        let mut filter = AWeighting::new(signal::rate(fs).const_hz(freq).sine(), fs);
+        let y: Vec<f64> = (0..len).map(|_| filter.next()).collect();
+        let rms = (y[len / 2..].iter().map(|y| y * y).sum::<f64>() / (len / 2) as f64).sqrt();
+        20.0 * (rms * 2.0_f64.sqrt()).log10()
+    }
+
+    #[test]
+    fn a_weighting_is_flat_at_1khz_and_rolls_off_low() {
+        let fs = 48000.0;
+        let a = |freq| a_weighting_db(fs, freq);
+        assert!(a(1000.0).abs() < 0.05, "{}", a(1000.0));
+        // -19.1 dB and -30.2 dB in IEC 61672-1
+        assert!((a(100.0) + 19.1).abs() < 0.3, "{}", a(100.0));
+        assert!((a(50.0) + 30.2).abs() < 0.3, "{}", a(50.0));
+    }
+}
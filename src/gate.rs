@@ -0,0 +1,218 @@
+//! Gates: on/off signals that say when a note is held, decoupled from what
+//! they drive. A gate can come from a sequence of steps (`SeqGate`), from a
+//! comparator on a signal such as an LFO (`Comparator`), or from the events
+//! of another thread through a parameter (`Arc<AtomicF64>`, e.g. of a
+//! `ParamSet` set by the OSC or the stdin). Any of them drives an envelope
+//! (`Env::triggered()`) or a one-shot sample (`OneShot`) in the same way: the
+//! rising edge starts the note, and the falling edge releases it.
+
+use crate::params::AtomicF64;
+use crate::units::Frames;
+use dasp::Signal;
+use std::sync::Arc;
+
+/// A source of a gate, which is open while a note is held.
+pub trait Gate {
+    /// Returns whether the gate is open on the next frame.
+    fn next_gate(&mut self) -> bool;
+}
+
+/// A parameter as a gate, open while it's 0.5 or more (e.g. set to 1.0 on
+/// the note on and 0.0 on the note off).
+impl Gate for Arc<AtomicF64> {
+    fn next_gate(&mut self) -> bool {
+        self.get() >= 0.5
+    }
+}
+
+/// A change of a gate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    /// The gate opened.
+    Rising,
+    /// The gate closed.
+    Falling,
+}
+
+/// Finds the edges of a gate, frame by frame. The gate is closed before the
+/// first frame.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EdgeDetector {
+    open: bool,
+}
+
+impl EdgeDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process(&mut self, open: bool) -> Option<Edge> {
+        let edge = match (self.open, open) {
+            (false, true) => Some(Edge::Rising),
+            (true, false) => Some(Edge::Falling),
+            _ => None,
+        };
+        self.open = open;
+        edge
+    }
+}
+
+/// A gate from a sequence: open for `gate_length` from the start of each
+/// step of `step_length` whose value is `true`, and closed after the end of
+/// the sequence. A gate as long as the step (or longer) ties the step to the
+/// next one, so the note isn't restarted (legato).
+pub struct SeqGate {
+    seq: std::vec::IntoIter<bool>,
+    step_length: usize,
+    gate_length: usize,
+    note_on: bool,
+    cur_frame: usize,
+}
+
+impl SeqGate {
+    pub fn new(seq: Vec<bool>, step_length: Frames, gate_length: Frames) -> Self {
+        let mut seq = seq.into_iter();
+        let note_on = seq.next().unwrap_or(false);
+        Self {
+            seq,
+            step_length: step_length.0.max(1),
+            gate_length: gate_length.0,
+            note_on,
+            cur_frame: 0,
+        }
+    }
+}
+
+impl Gate for SeqGate {
+    fn next_gate(&mut self) -> bool {
+        // proceed to the next step
+        if self.cur_frame == self.step_length {
+            self.note_on = self.seq.next().unwrap_or(false);
+            self.cur_frame = 0;
+        }
+        self.cur_frame += 1;
+        self.note_on && self.cur_frame <= self.gate_length
+    }
+}
+
+/// A gate open while a signal (e.g. an LFO) is above the threshold. With the
+/// hysteresis, it opens above `threshold + hysteresis / 2` and closes below
+/// `threshold - hysteresis / 2`, so that a noisy signal around the threshold
+/// doesn't chatter.
+pub struct Comparator<S: Signal<Frame = f64>> {
+    signal: S,
+    threshold: f64,
+    hysteresis: f64,
+    open: bool,
+}
+
+impl<S: Signal<Frame = f64>> Comparator<S> {
+    pub fn new(signal: S, threshold: f64) -> Self {
+        Self {
+            signal,
+            threshold,
+            hysteresis: 0.0,
+            open: false,
+        }
+    }
+
+    pub fn with_hysteresis(mut self, hysteresis: f64) -> Self {
+        self.hysteresis = hysteresis.max(0.0);
+        self
+    }
+}
+
+impl<S: Signal<Frame = f64>> Gate for Comparator<S> {
+    fn next_gate(&mut self) -> bool {
+        let x = self.signal.next();
+        let half = self.hysteresis / 2.0;
+        if self.open {
+            self.open = x >= self.threshold - half;
+        } else {
+            self.open = x > self.threshold + half;
+        }
+        self.open
+    }
+}
+
+/// Plays a sample from its start on each rising edge of a gate, to its end
+/// regardless of the gate (like a drum). A rising edge while playing
+/// restarts it.
+pub struct OneShot<G: Gate> {
+    gate: G,
+    edges: EdgeDetector,
+    samples: Arc<[f64]>,
+    // the position in the sample; the length if not playing
+    pos: usize,
+}
+
+impl<G: Gate> OneShot<G> {
+    pub fn new(gate: G, samples: Arc<[f64]>) -> Self {
+        let pos = samples.len();
+        Self {
+            gate,
+            edges: EdgeDetector::new(),
+            samples,
+            pos,
+        }
+    }
+}
+
+impl<G: Gate> Signal for OneShot<G> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        if self.edges.process(self.gate.next_gate()) == Some(Edge::Rising) {
+            self.pos = 0;
+        }
+        let Some(&x) = self.samples.get(self.pos) else {
+            return 0.0;
+        };
+        self.pos += 1;
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gates(mut gate: impl Gate, n: usize) -> Vec<bool> {
+        (0..n).map(|_| gate.next_gate()).collect()
+    }
+
+    #[test]
+    fn seq_gate_opens_on_the_steps() {
+        let gate = SeqGate::new(vec![true, false, true], Frames(4), Frames(2));
+        let expected = [1, 1, 0, 0, 0, 0, 0, 0, 1, 1, 0, 0, 0, 0];
+        assert_eq!(gates(gate, 14), expected.map(|x| x == 1));
+
+        // a gate as long as the step ties the steps
+        let gate = SeqGate::new(vec![true, true], Frames(4), Frames(4));
+        let mut edges = EdgeDetector::new();
+        let edges: Vec<_> = gates(gate, 10)
+            .into_iter()
+            .filter_map(|g| edges.process(g))
+            .collect();
+        assert_eq!(edges, [Edge::Rising, Edge::Falling]);
+    }
+
+    #[test]
+    fn comparator_does_not_chatter_around_the_threshold() {
+        let input = [0.0, 0.05, -0.05, 0.15, 0.05, -0.05, 0.05, -0.15, 0.05];
+        let gate = Comparator::new(dasp::signal::from_iter(input), 0.0).with_hysteresis(0.2);
+        let expected = [0, 0, 0, 1, 1, 1, 1, 0, 0];
+        assert_eq!(gates(gate, input.len()), expected.map(|x| x == 1));
+    }
+
+    #[test]
+    fn one_shot_plays_to_the_end_on_each_rising_edge() {
+        let gate = SeqGate::new(vec![true, true, false], Frames(4), Frames(1));
+        let mut one_shot = OneShot::new(gate, Arc::from([1.0, 2.0, 3.0]));
+        let out: Vec<f64> = (0..12).map(|_| one_shot.next()).collect();
+        assert_eq!(
+            out,
+            [1.0, 2.0, 3.0, 0.0, 1.0, 2.0, 3.0, 0.0, 0.0, 0.0, 0.0, 0.0]
+        );
+    }
+}
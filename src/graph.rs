@@ -0,0 +1,345 @@
+//! A tiny builder of signal flow graphs for sketching filter topologies: the
+//! nodes are the input, gains, delays, and adders, connected freely (even in
+//! loops, as long as each loop contains a delay). The graph is compiled into
+//! a flat list of operations, which processes a sample without allocating.
+//!
+//! For example, a one-pole low-pass `y[n] = (1 - a) x[n] + a y[n-1]` is an
+//! adder fed by `gain(1 - a)` on the input and by `gain(a)` on `delay(1)` of
+//! the adder itself.
+
+/// A node of a graph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+#[derive(Clone, Copy, Debug)]
+enum Kind {
+    Input,
+    Gain(f64),
+    Delay(usize),
+    Add,
+}
+
+/// The error on compiling a graph.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    /// The node is on a loop that contains no delay, so its value can't be
+    /// computed.
+    DelayFreeLoop(NodeId),
+    /// The gain or the delay doesn't have exactly one input.
+    InvalidInputs(NodeId),
+    /// The delay is 0 frames.
+    ZeroDelay(NodeId),
+}
+
+impl std::fmt::Display for GraphError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DelayFreeLoop(n) => write!(f, "node {} is on a loop without delay", n.0),
+            Self::InvalidInputs(n) => write!(f, "node {} must have exactly one input", n.0),
+            Self::ZeroDelay(n) => write!(f, "node {} has a delay of 0 frames", n.0),
+        }
+    }
+}
+
+impl std::error::Error for GraphError {}
+
+/// Builds a graph. The values flow along the connections; an adder sums all
+/// of its inputs in the order of the connections.
+#[derive(Default)]
+pub struct GraphBuilder {
+    kinds: Vec<Kind>,
+    inputs: Vec<Vec<usize>>,
+}
+
+impl GraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn node(&mut self, kind: Kind) -> NodeId {
+        self.kinds.push(kind);
+        self.inputs.push(vec![]);
+        NodeId(self.kinds.len() - 1)
+    }
+
+    /// The input of the graph, i.e. the argument of `Graph::process()`.
+    pub fn input(&mut self) -> NodeId {
+        self.node(Kind::Input)
+    }
+
+    pub fn gain(&mut self, gain: f64) -> NodeId {
+        self.node(Kind::Gain(gain))
+    }
+
+    /// The delay of `frames` frames (`z^-frames`).
+    pub fn delay(&mut self, frames: usize) -> NodeId {
+        self.node(Kind::Delay(frames))
+    }
+
+    pub fn add(&mut self) -> NodeId {
+        self.node(Kind::Add)
+    }
+
+    pub fn connect(&mut self, from: NodeId, to: NodeId) {
+        self.inputs[to.0].push(from.0);
+    }
+
+    /// Validates and compiles the graph, whose output is the value of
+    /// `output`.
+    pub fn build(self, output: NodeId) -> Result<Graph, GraphError> {
+        let n = self.kinds.len();
+
+        let mut delays = vec![];
+        let mut delay_len = 0;
+        for (i, kind) in self.kinds.iter().enumerate() {
+            match kind {
+                Kind::Gain(_) | Kind::Delay(_) if self.inputs[i].len() != 1 => {
+                    return Err(GraphError::InvalidInputs(NodeId(i)));
+                }
+                Kind::Delay(0) => return Err(GraphError::ZeroDelay(NodeId(i))),
+                Kind::Delay(frames) => {
+                    delays.push(DelayOp {
+                        src: self.inputs[i][0],
+                        dst: i,
+                        offset: delay_len,
+                        len: *frames,
+                        pos: 0,
+                    });
+                    delay_len += frames;
+                }
+                _ => {}
+            }
+        }
+
+        // Sort the nodes topologically (Kahn's algorithm), where the outputs
+        // of the delays are known at the beginning of each frame and thus
+        // don't depend on anything. What remains unsorted is on a loop.
+        let depends = |i: usize| !matches!(self.kinds[i], Kind::Delay(_));
+        let mut pending: Vec<usize> = (0..n)
+            .map(|i| if depends(i) { self.inputs[i].len() } else { 0 })
+            .collect();
+        let mut dependents = vec![vec![]; n];
+        for (i, inputs) in self.inputs.iter().enumerate() {
+            if depends(i) {
+                for &src in inputs {
+                    dependents[src].push(i);
+                }
+            }
+        }
+
+        let mut queue: Vec<usize> = (0..n).filter(|&i| pending[i] == 0).collect();
+        let mut order = vec![];
+        while let Some(i) = queue.pop() {
+            order.push(i);
+            for &j in &dependents[i] {
+                pending[j] -= 1;
+                if pending[j] == 0 {
+                    queue.push(j);
+                }
+            }
+        }
+        if let Some(i) = (0..n).find(|&i| pending[i] > 0) {
+            return Err(GraphError::DelayFreeLoop(NodeId(i)));
+        }
+
+        // flatten into the operations
+        let mut sources = vec![];
+        let mut ops = vec![];
+        for i in order {
+            let op = match self.kinds[i] {
+                Kind::Input => Op::Input { dst: i },
+                Kind::Gain(gain) => Op::Gain {
+                    src: self.inputs[i][0],
+                    dst: i,
+                    gain,
+                },
+                // already set at the beginning of the frame
+                Kind::Delay(_) => continue,
+                Kind::Add => {
+                    let start = sources.len();
+                    sources.extend(&self.inputs[i]);
+                    Op::Add {
+                        srcs: start..sources.len(),
+                        dst: i,
+                    }
+                }
+            };
+            ops.push(op);
+        }
+
+        Ok(Graph {
+            ops,
+            sources,
+            delays,
+            delay_buffer: vec![0.0; delay_len],
+            values: vec![0.0; n],
+            output: output.0,
+        })
+    }
+}
+
+enum Op {
+    Input {
+        dst: usize,
+    },
+    Gain {
+        src: usize,
+        dst: usize,
+        gain: f64,
+    },
+    Add {
+        srcs: std::ops::Range<usize>,
+        dst: usize,
+    },
+}
+
+struct DelayOp {
+    src: usize,
+    dst: usize,
+    // the circular buffer of this delay within `delay_buffer`
+    offset: usize,
+    len: usize,
+    pos: usize,
+}
+
+/// A compiled graph. See `GraphBuilder`.
+pub struct Graph {
+    ops: Vec<Op>,
+    // the inputs of the adders, flattened
+    sources: Vec<usize>,
+    delays: Vec<DelayOp>,
+    delay_buffer: Vec<f64>,
+    // the values of the nodes in the current frame
+    values: Vec<f64>,
+    output: usize,
+}
+
+impl Graph {
+    pub fn process(&mut self, x: f64) -> f64 {
+        // the outputs of the delays are the values of `len` frames before
+        for d in &self.delays {
+            self.values[d.dst] = self.delay_buffer[d.offset + d.pos];
+        }
+
+        for op in &self.ops {
+            match op {
+                Op::Input { dst } => self.values[*dst] = x,
+                Op::Gain { src, dst, gain } => self.values[*dst] = gain * self.values[*src],
+                Op::Add { srcs, dst } => {
+                    self.values[*dst] = self.sources[srcs.clone()]
+                        .iter()
+                        .map(|&s| self.values[s])
+                        .sum()
+                }
+            }
+        }
+
+        for d in self.delays.iter_mut() {
+            self.delay_buffer[d.offset + d.pos] = self.values[d.src];
+            d.pos = (d.pos + 1) % d.len;
+        }
+
+        self.values[self.output]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::biquad::Biquad;
+    use crate::core::karplus::KarplusStrong;
+    use crate::core::noise::Noise;
+    use crate::units::{Hz, Ms};
+
+    const FS: f64 = 48000.0;
+
+    #[test]
+    fn biquad_graph_matches_the_biquad() {
+        let mut biquad = Biquad::low_pass(FS, Hz(800.0), 2.0);
+        let [b0, b1, b2, a1, a2] = biquad.coefficients();
+
+        // the direct form I, summed in the same order as `Biquad::process()`
+        let mut g = GraphBuilder::new();
+        let x = g.input();
+        let y = g.add();
+        let x1 = g.delay(1);
+        let x2 = g.delay(1);
+        let y1 = g.delay(1);
+        let y2 = g.delay(1);
+        g.connect(x, x1);
+        g.connect(x1, x2);
+        g.connect(y, y1);
+        g.connect(y1, y2);
+        for (src, gain) in [(x, b0), (x1, b1), (x2, b2), (y1, -a1), (y2, -a2)] {
+            let term = g.gain(gain);
+            g.connect(src, term);
+            g.connect(term, y);
+        }
+        let mut graph = g.build(y).unwrap();
+
+        let mut noise = Noise::new(1);
+        for i in 0..10000 {
+            let x = noise.next_sample();
+            assert_eq!(graph.process(x), biquad.process(x), "frame {i}");
+        }
+    }
+
+    #[test]
+    fn karplus_strong_graph_matches_the_string() {
+        let mut string = KarplusStrong::<1024>::new(FS, Hz(220.0), 0.5, Ms(2000.0), 7).unwrap();
+        let (g_ap, c, d) = string.coefficients();
+        let len = string.delay_line_length();
+
+        let mut g = GraphBuilder::new();
+        let excitation = g.input();
+        let out = g.add();
+        let delayed = g.delay(len);
+        g.connect(out, delayed);
+        let last_delayed = g.delay(1);
+        g.connect(delayed, last_delayed);
+
+        // the fractional all-pass
+        let all_passed = g.add();
+        let last_all_passed = g.delay(1);
+        g.connect(all_passed, last_all_passed);
+        for (src, gain) in [(last_all_passed, -g_ap), (delayed, g_ap)] {
+            let term = g.gain(gain);
+            g.connect(src, term);
+            g.connect(term, all_passed);
+        }
+        g.connect(last_delayed, all_passed);
+
+        // the loss filter and the loop gain
+        let loss = g.add();
+        for (src, gain) in [(all_passed, 1.0 - d), (last_all_passed, d)] {
+            let term = g.gain(gain);
+            g.connect(src, term);
+            g.connect(term, loss);
+        }
+        let feedback = g.gain(c);
+        g.connect(loss, feedback);
+        g.connect(excitation, out);
+        g.connect(feedback, out);
+        let mut graph = g.build(out).unwrap();
+
+        // the same burst of noise as `pluck()`
+        let mut noise = Noise::new(7);
+        string.pluck();
+        for i in 0..20000 {
+            let x = if i < len { noise.next_sample() } else { 0.0 };
+            assert_eq!(graph.process(x), string.process(), "frame {i}");
+        }
+    }
+
+    #[test]
+    fn delay_free_loop_is_an_error() {
+        let mut g = GraphBuilder::new();
+        let x = g.input();
+        let sum = g.add();
+        let feedback = g.gain(0.5);
+        g.connect(x, sum);
+        g.connect(sum, feedback);
+        g.connect(feedback, sum);
+        assert!(matches!(g.build(sum), Err(GraphError::DelayFreeLoop(_))));
+    }
+}
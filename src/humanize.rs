@@ -0,0 +1,191 @@
+//! Humanizing the steps of a sequence: small random offsets of the timing
+//! and the velocity, so that it doesn't sound mechanical. The random numbers
+//! come from a seeded generator, so the renders are reproducible.
+
+use crate::core::noise::Noise;
+use crate::units::Frames;
+
+/// The boost of the velocity of an accented step.
+pub const ACCENT: f64 = 1.5;
+
+/// The maximum number of the notes a step can be subdivided into.
+pub const MAX_RATCHET: usize = 4;
+
+/// A step placed on the timeline, with the velocity as the gain (1.0 is the
+/// nominal one). See `Env::scheduled()` for playing them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    pub start: Frames,
+    pub velocity: f64,
+    /// Plays louder by `ACCENT` (and, e.g., opens the filter).
+    pub accent: bool,
+    /// The number of the notes the step is subdivided into (1 to 4).
+    pub ratchet: usize,
+}
+
+impl Step {
+    /// A step without the accent or the ratchet.
+    pub fn new(start: Frames, velocity: f64) -> Self {
+        Self {
+            start,
+            velocity,
+            accent: false,
+            ratchet: 1,
+        }
+    }
+
+    pub fn with_accent(mut self, accent: bool) -> Self {
+        self.accent = accent;
+        self
+    }
+
+    /// Subdivides the step into `ratchet` notes, which is clamped to 1 to 4.
+    pub fn with_ratchet(mut self, ratchet: usize) -> Self {
+        self.ratchet = ratchet.clamp(1, MAX_RATCHET);
+        self
+    }
+
+    /// The velocity with the accent.
+    pub fn gain(&self) -> f64 {
+        if self.accent {
+            self.velocity * ACCENT
+        } else {
+            self.velocity
+        }
+    }
+}
+
+/// The ranges of the random offsets. Both are 0 by default, which gives the
+/// straight steps.
+#[derive(Clone, Copy, Debug)]
+pub struct Humanize {
+    seed: u64,
+    timing: Frames,
+    velocity: f64,
+    lock_first: bool,
+}
+
+impl Humanize {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            timing: Frames(0),
+            velocity: 0.0,
+            lock_first: false,
+        }
+    }
+
+    /// Offsets the start of each step by up to ± `timing`.
+    pub fn with_timing(mut self, timing: Frames) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Offsets the velocity of each step by up to ± `velocity`, e.g. 0.1 for
+    /// ±10%.
+    pub fn with_velocity(mut self, velocity: f64) -> Self {
+        self.velocity = velocity.abs();
+        self
+    }
+
+    /// Keeps the first step of the pattern on the grid (only the timing; the
+    /// velocity still varies), so that the downbeat stays tight.
+    pub fn with_lock_first(mut self, lock_first: bool) -> Self {
+        self.lock_first = lock_first;
+        self
+    }
+
+    /// Places `count` steps of `step_length` from the frame 0. The offsets
+    /// are clamped to less than the half of a step, so a step never moves
+    /// before the previous one or past the next one, nor before the frame 0.
+    pub fn steps(&self, count: usize, step_length: Frames) -> Vec<Step> {
+        let mut noise = Noise::new(self.seed);
+        let max_offset = self.timing.0.min((step_length.0 / 2).saturating_sub(1)) as f64;
+
+        (0..count)
+            .map(|i| {
+                // draw both always, so that the sequence of the random numbers
+                // doesn't depend on the settings
+                let timing = noise.next_sample();
+                let velocity = noise.next_sample();
+
+                let nominal = i * step_length.0;
+                let offset = if i == 0 && self.lock_first {
+                    0
+                } else {
+                    (timing * max_offset).round() as isize
+                };
+                Step::new(
+                    Frames(nominal.saturating_add_signed(offset)),
+                    (1.0 + velocity * self.velocity).max(0.0),
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Env;
+    use dasp::Signal;
+
+    const STEP: Frames = Frames(6000);
+
+    #[test]
+    fn same_seed_same_steps() {
+        let humanize = Humanize::new(42)
+            .with_timing(Frames(576))
+            .with_velocity(0.1);
+        assert_eq!(humanize.steps(64, STEP), humanize.steps(64, STEP));
+        assert_ne!(
+            humanize.steps(64, STEP),
+            Humanize::new(43)
+                .with_timing(Frames(576))
+                .with_velocity(0.1)
+                .steps(64, STEP)
+        );
+    }
+
+    #[test]
+    fn offsets_are_clamped() {
+        let steps = Humanize::new(1)
+            .with_timing(Frames(576))
+            .with_velocity(0.1)
+            .with_lock_first(true)
+            .steps(1000, STEP);
+        assert_eq!(steps[0].start, Frames(0));
+        for (i, step) in steps.iter().enumerate() {
+            assert!(step.start.0.abs_diff(i * STEP.0) <= 576, "step {i}");
+            assert!((0.9..=1.1).contains(&step.velocity), "step {i}");
+        }
+
+        // a range longer than the steps stays within the half of a step
+        let steps = Humanize::new(1)
+            .with_timing(Frames(10000))
+            .steps(1000, Frames(100));
+        for (i, step) in steps.iter().enumerate() {
+            assert!(step.start.0.abs_diff(i * 100) < 50, "step {i}");
+        }
+        assert!(steps.windows(2).all(|w| w[0].start < w[1].start));
+    }
+
+    #[test]
+    fn zero_ranges_are_straight() {
+        let steps = Humanize::new(7).steps(8, STEP);
+        for (i, step) in steps.iter().enumerate() {
+            assert_eq!(*step, Step::new(Frames(i * STEP.0), 1.0));
+        }
+
+        let (attack, release) = (Frames(100), Frames(1000));
+        let mut scheduled = Env::scheduled(steps, STEP, attack, release);
+        let mut gated = Env::gated(vec![true; 8], STEP, attack, release);
+        for i in 0..9 * STEP.0 {
+            assert_eq!(
+                scheduled.next().to_bits(),
+                gated.next().to_bits(),
+                "frame {i}"
+            );
+        }
+    }
+}
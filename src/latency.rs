@@ -0,0 +1,143 @@
+use crate::effects::DelayLine;
+use crate::stereo::MonoEffect;
+
+/// Reports the delay an effect introduces to its input, so that the other
+/// paths can be delayed by the same amount to stay aligned (e.g. with
+/// `dasp::Signal::delay()`). A `MonoEffect` reports its `latency()`, so
+/// implement this only for the effects on a `Signal`.
+pub trait Latency {
+    fn latency_frames(&self) -> usize;
+}
+
+impl<E: MonoEffect + ?Sized> Latency for E {
+    fn latency_frames(&self) -> usize {
+        self.latency()
+    }
+}
+
+/// Mixes parallel branches of effects fed the same input, e.g. the dry
+/// signal and a convolution reverb. Each branch is delayed so that it lines
+/// up with the slowest one by `MonoEffect::latency()`; otherwise, mixing a
+/// branch with latency and one without comb-filters. The mix is delayed by
+/// the largest latency, which it reports as its own.
+///
+/// The latencies are read when the branches are added, so they should not
+/// change afterwards (e.g. by changing the quality of `Oversample`).
+pub struct ParallelMix {
+    branches: Vec<Branch>,
+}
+
+struct Branch {
+    effect: Box<dyn MonoEffect + Send>,
+    gain: f64,
+    compensation: DelayLine,
+    delay: usize,
+}
+
+impl ParallelMix {
+    pub fn new() -> Self {
+        Self { branches: vec![] }
+    }
+
+    /// Adds a branch mixed at `gain`; e.g. `|x| x` for the dry signal.
+    pub fn with_branch<E: MonoEffect + Send + 'static>(mut self, effect: E, gain: f64) -> Self {
+        self.branches.push(Branch {
+            effect: Box::new(effect),
+            gain,
+            compensation: DelayLine::new(0),
+            delay: 0,
+        });
+
+        let latency = self.latency();
+        for branch in &mut self.branches {
+            let delay = latency - branch.effect.latency();
+            if delay != branch.delay {
+                branch.compensation = DelayLine::new(delay);
+                branch.delay = delay;
+            }
+        }
+        self
+    }
+}
+
+impl Default for ParallelMix {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MonoEffect for ParallelMix {
+    fn process(&mut self, x: f64) -> f64 {
+        self.branches
+            .iter_mut()
+            .map(|b| {
+                let y = b.effect.process(x);
+                b.gain * b.compensation.process(y, b.delay)
+            })
+            .sum()
+    }
+
+    fn latency(&self) -> usize {
+        self.branches
+            .iter()
+            .map(|b| b.effect.latency())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convolution::Convolver;
+    use dasp::Signal;
+
+    #[test]
+    fn compensated_dry_path_stays_aligned() {
+        // an impulse response of a unit impulse only delays by the block size
+        let mut mix = ParallelMix::new()
+            .with_branch(Convolver::new(&[1.0], 64), 1.0)
+            .with_branch(|x| x, -1.0);
+        assert_eq!(mix.latency(), 64);
+        assert_eq!(mix.latency_frames(), 64);
+
+        // aligned, the branches cancel out each other
+        for x in dasp::signal::noise(1).take(1000) {
+            assert!(mix.process(x).abs() < 1e-12);
+        }
+    }
+
+    // a pure delay that reports it
+    struct Late(DelayLine);
+
+    impl MonoEffect for Late {
+        fn process(&mut self, x: f64) -> f64 {
+            self.0.process(x, 64)
+        }
+
+        fn latency(&self) -> usize {
+            64
+        }
+    }
+
+    #[test]
+    fn half_and_half_is_the_delayed_signal() {
+        let mut mix = ParallelMix::new()
+            .with_branch(|x| x, 0.5)
+            .with_branch(Late(DelayLine::new(64)), 0.5);
+        assert_eq!(mix.latency(), 64);
+
+        let mut reference = DelayLine::new(64);
+        for x in dasp::signal::noise(2).take(1000) {
+            let expected = reference.process(x, 64);
+            assert!((mix.process(x) - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn no_branches_is_silent() {
+        let mut mix = ParallelMix::new();
+        assert_eq!(mix.latency(), 0);
+        assert_eq!(mix.process(1.0), 0.0);
+    }
+}
@@ -0,0 +1,74 @@
+#![cfg_attr(not(any(feature = "dsp", feature = "ffi", feature = "wasm")), no_std)]
+
+#[cfg(feature = "dsp")]
+pub mod analysis;
+#[cfg(feature = "dsp")]
+pub mod convolution;
+#[cfg(feature = "dsp-core")]
+pub mod core;
+#[cfg(feature = "dsp")]
+pub mod effects;
+#[cfg(feature = "dsp")]
+pub mod envelope;
+#[cfg(feature = "dsp")]
+pub mod fade;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "dsp")]
+pub mod fft;
+#[cfg(feature = "dsp")]
+pub mod filter;
+#[cfg(feature = "dsp")]
+pub mod gate;
+#[cfg(feature = "dsp")]
+pub mod graph;
+#[cfg(feature = "dsp")]
+pub mod humanize;
+#[cfg(feature = "dsp")]
+pub mod latency;
+#[cfg(feature = "dsp")]
+pub mod looper;
+#[cfg(feature = "midi")]
+pub mod midi;
+#[cfg(feature = "osc")]
+pub mod osc;
+#[cfg(feature = "dsp")]
+pub mod oscillator;
+#[cfg(feature = "dsp")]
+pub mod params;
+#[cfg(any(feature = "dsp", feature = "ffi"))]
+pub mod poly;
+#[cfg(feature = "dsp")]
+pub mod quality;
+#[cfg(feature = "dsp")]
+pub mod resample;
+#[cfg(feature = "std")]
+pub mod runner;
+#[cfg(feature = "dsp")]
+pub mod sampler;
+#[cfg(feature = "dsp")]
+pub mod sanitize;
+#[cfg(feature = "dsp")]
+pub mod schedule;
+#[cfg(feature = "dsp")]
+pub mod sfz;
+#[cfg(feature = "dsp")]
+pub mod slicer;
+#[cfg(feature = "song")]
+pub mod song;
+#[cfg(feature = "dsp")]
+pub mod spatial;
+#[cfg(feature = "dsp")]
+pub mod stereo;
+#[cfg(feature = "dsp")]
+pub mod tail;
+#[cfg(feature = "dsp")]
+pub mod transport;
+#[cfg(feature = "tui")]
+pub mod tui;
+#[cfg(feature = "dsp-core")]
+pub mod units;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "dsp")]
+pub mod wav;
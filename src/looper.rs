@@ -0,0 +1,416 @@
+//! A looper, like a looper pedal: it records a phrase of the live input, then
+//! plays it back in a loop, and overdubs more layers on it while playing.
+
+use crate::fade::FadeCurve;
+use crate::units::{Frames, Ms};
+
+// the default length of the crossfade at the seam
+const CROSSFADE: Ms = Ms(10.0);
+
+/// What a `Looper` is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoopState {
+    /// Nothing is recorded yet.
+    Empty,
+    /// Recording a new loop; its length is fixed when this ends. With
+    /// `with_bar()`, this continues until the bar line after the command to
+    /// end it, if it came early.
+    Recording,
+    Playing,
+    /// Playing and adding the input to the loop.
+    Overdubbing,
+    /// Stopped at the start of the loop.
+    Stopped,
+}
+
+/// Records the input into a buffer and loops it. Drive it by the commands
+/// (`record()`, `play()`, `overdub()`, `stop()`, and `undo()`) between the
+/// calls to `process()`, which take effect on the next frame. All the memory
+/// is allocated up front, so the commands are safe on the audio thread.
+///
+/// The output is only the loop; mix the input in for monitoring.
+///
+/// With `with_bar()`, the length of the loop snaps to the nearest whole
+/// number of bars, so that it stays in time with the tempo even if the end
+/// of the recording is slightly off. Ending late discards the extra frames
+/// (and the playback starts where it would be, in time), and ending early
+/// keeps recording until the bar line.
+///
+/// The seam of the loop (from its end back to its start) is crossfaded, so
+/// that it doesn't click even if the end doesn't meet the start. The end of
+/// the loop fades into what was played just before the recording started,
+/// which is what leads into the start; so the loop keeps its length.
+pub struct Looper {
+    state: LoopState,
+    buffer: Vec<f64>,
+    // the loop before the last overdub
+    undo: Vec<f64>,
+    can_undo: bool,
+    capacity: usize,
+    bar: Option<usize>,
+    // the length at which the recording ends, and the state after it
+    record_to: usize,
+    after_recording: LoopState,
+    // the last frames of the input (a circular buffer as long as the
+    // crossfade), and what they were when the recording started
+    history: Vec<f64>,
+    history_pos: usize,
+    preroll: Vec<f64>,
+    // the position in the loop of the next frame
+    pos: usize,
+}
+
+impl Looper {
+    /// `max_length` is the longest loop that can be recorded.
+    pub fn new(fs: f64, max_length: Ms) -> Self {
+        let capacity = max_length.to_frames(fs).0.max(1);
+        Self {
+            state: LoopState::Empty,
+            buffer: Vec::with_capacity(capacity),
+            undo: Vec::with_capacity(capacity),
+            can_undo: false,
+            capacity,
+            bar: None,
+            record_to: capacity,
+            after_recording: LoopState::Playing,
+            history: vec![],
+            history_pos: 0,
+            preroll: vec![],
+            pos: 0,
+        }
+        .with_crossfade(CROSSFADE.to_frames(fs))
+    }
+
+    /// Sets the length of the crossfade at the seam (10 ms by default). 0
+    /// disables it.
+    pub fn with_crossfade(mut self, crossfade: Frames) -> Self {
+        self.history = vec![0.0; crossfade.0];
+        self.history_pos = 0;
+        self.preroll = Vec::with_capacity(crossfade.0);
+        self
+    }
+
+    /// Quantizes the length of the loop to whole bars of this length, e.g.
+    /// `Ms::from_note(bpm, 1.0).to_frames(fs)` for the tempo of `bpm`. The
+    /// maximum length should hold a bar at least; otherwise, this is
+    /// ignored.
+    pub fn with_bar(mut self, bar: Frames) -> Self {
+        self.bar = Some(bar.0).filter(|&bar| bar > 0);
+        self
+    }
+
+    pub fn state(&self) -> LoopState {
+        self.state
+    }
+
+    /// The length of the loop (while recording, so far).
+    pub fn len(&self) -> Frames {
+        Frames(self.buffer.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// The loop as recorded and overdubbed.
+    pub fn buffer(&self) -> &[f64] {
+        &self.buffer
+    }
+
+    /// Starts recording a new loop, discarding the current one.
+    pub fn record(&mut self) {
+        self.buffer.clear();
+        self.can_undo = false;
+        self.pos = 0;
+        self.record_to = self.capacity;
+        self.after_recording = LoopState::Playing;
+        self.state = LoopState::Recording;
+
+        // the oldest frame of the history is at the position
+        let (newer, older) = self.history.split_at(self.history_pos);
+        self.preroll.clear();
+        self.preroll.extend_from_slice(older);
+        self.preroll.extend_from_slice(newer);
+    }
+
+    /// Ends the recording, and plays the loop from its start on the next
+    /// frame, so that it's in sync with the phrase played. While
+    /// overdubbing, ends the overdub and keeps playing.
+    pub fn play(&mut self) {
+        match self.state {
+            LoopState::Recording => self.end_recording(LoopState::Playing),
+            LoopState::Overdubbing | LoopState::Stopped => self.state = LoopState::Playing,
+            LoopState::Empty | LoopState::Playing => {}
+        }
+    }
+
+    /// Starts adding the input to the loop as a new layer (ending the
+    /// recording first, if recording).
+    pub fn overdub(&mut self) {
+        match self.state {
+            LoopState::Recording => self.end_recording(LoopState::Overdubbing),
+            LoopState::Playing | LoopState::Stopped => self.enter(LoopState::Overdubbing),
+            LoopState::Empty | LoopState::Overdubbing => {}
+        }
+    }
+
+    /// Stops the playback (ending the recording or the overdub first), and
+    /// rewinds to the start of the loop. A recording stopped before the bar
+    /// line is padded with silence up to it.
+    pub fn stop(&mut self) {
+        match self.state {
+            LoopState::Recording => self.end_recording(LoopState::Stopped),
+            LoopState::Empty => {}
+            _ => self.state = LoopState::Stopped,
+        }
+        self.pos = 0;
+    }
+
+    /// Removes the last overdub layer (ending the overdub first, if
+    /// overdubbing). Undoing again brings the layer back. Returns false if
+    /// there's nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        if !self.can_undo {
+            return false;
+        }
+        if self.state == LoopState::Overdubbing {
+            self.state = LoopState::Playing;
+        }
+        std::mem::swap(&mut self.buffer, &mut self.undo);
+        true
+    }
+
+    /// Processes a frame of the input, and returns the frame of the loop.
+    pub fn process(&mut self, input: f64) -> f64 {
+        if !self.history.is_empty() {
+            self.history[self.history_pos] = input;
+            self.history_pos = (self.history_pos + 1) % self.history.len();
+        }
+
+        match self.state {
+            LoopState::Recording => {
+                self.buffer.push(input);
+                // reached the bar line, or the buffer is full
+                if self.buffer.len() == self.record_to {
+                    self.end_recording(self.after_recording);
+                }
+                0.0
+            }
+            LoopState::Playing => {
+                let y = self.buffer[self.pos];
+                self.pos = (self.pos + 1) % self.buffer.len();
+                y
+            }
+            LoopState::Overdubbing => {
+                let y = self.buffer[self.pos];
+                self.buffer[self.pos] = y + input;
+                self.pos = (self.pos + 1) % self.buffer.len();
+                y
+            }
+            LoopState::Empty | LoopState::Stopped => 0.0,
+        }
+    }
+
+    /// Ends the recording and then enters `next`, or keeps recording until
+    /// the quantized length if it's not reached yet.
+    fn end_recording(&mut self, next: LoopState) {
+        let len = self.buffer.len();
+        let length = self.quantize(len);
+        if len < length && next != LoopState::Stopped {
+            self.record_to = length;
+            self.after_recording = next;
+            return;
+        }
+
+        // the frames recorded beyond the length are where the loop is now
+        let overshoot = len.saturating_sub(length);
+        self.buffer.resize(length, 0.0);
+        self.crossfade_seam();
+        self.pos = if next == LoopState::Stopped || length == 0 {
+            0
+        } else {
+            overshoot % length
+        };
+        self.state = LoopState::Playing;
+        self.enter(next);
+    }
+
+    /// Fades the end of the loop into the preroll, which leads into the
+    /// start.
+    fn crossfade_seam(&mut self) {
+        let n = self.preroll.len().min(self.buffer.len());
+        let start = self.buffer.len() - n;
+        let preroll = &self.preroll[self.preroll.len() - n..];
+        for (i, (y, &x)) in self.buffer[start..].iter_mut().zip(preroll).enumerate() {
+            let t = (i as f64 + 0.5) / n as f64;
+            *y = *y * FadeCurve::EqualPower.gain(1.0 - t) + x * FadeCurve::EqualPower.gain(t);
+        }
+    }
+
+    // the nearest whole number of bars (at least 1) that fits the buffer
+    fn quantize(&self, len: usize) -> usize {
+        let Some(bar) = self.bar else {
+            return len;
+        };
+        let max_bars = self.capacity / bar;
+        if len == 0 || max_bars == 0 {
+            return len;
+        }
+        let bars = (len as f64 / bar as f64).round() as usize;
+        bars.clamp(1, max_bars) * bar
+    }
+
+    /// Enters `state` from a recorded loop (or stays empty).
+    fn enter(&mut self, state: LoopState) {
+        if self.is_empty() {
+            self.state = LoopState::Empty;
+            return;
+        }
+        if state == LoopState::Overdubbing {
+            self.undo.clear();
+            self.undo.extend_from_slice(&self.buffer);
+            self.can_undo = true;
+        }
+        self.state = state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    fn phrase(len: usize, freq: f64) -> Vec<f64> {
+        (0..len)
+            .map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / FS).sin())
+            .collect()
+    }
+
+    #[test]
+    fn plays_back_the_recording() {
+        let mut looper = Looper::new(FS, Ms(1000.0)).with_crossfade(Frames(0));
+        let input = phrase(4800, 440.0);
+        looper.record();
+        for &x in &input {
+            assert_eq!(looper.process(x), 0.0);
+        }
+        looper.play();
+        assert_eq!(looper.state(), LoopState::Playing);
+        assert_eq!(looper.buffer(), &input[..]);
+
+        // twice around the loop
+        let out: Vec<f64> = (0..2 * input.len()).map(|_| looper.process(0.0)).collect();
+        assert_eq!(out[..input.len()], input[..]);
+        assert_eq!(out[input.len()..], input[..]);
+    }
+
+    #[test]
+    fn undo_removes_the_last_overdub() {
+        let mut looper = Looper::new(FS, Ms(1000.0)).with_crossfade(Frames(0));
+        let first = phrase(4800, 440.0);
+        let second = phrase(4800, 660.0);
+        looper.record();
+        for &x in &first {
+            looper.process(x);
+        }
+        looper.overdub();
+        for &x in &second {
+            looper.process(x);
+        }
+        looper.play();
+        let layered: Vec<f64> = first.iter().zip(&second).map(|(a, b)| a + b).collect();
+        assert_eq!(looper.buffer(), &layered[..]);
+
+        assert!(looper.undo());
+        assert_eq!(looper.buffer(), &first[..]);
+        let out: Vec<f64> = (0..first.len()).map(|_| looper.process(0.0)).collect();
+        assert_eq!(out, first);
+        // and redo
+        assert!(looper.undo());
+        assert_eq!(looper.buffer(), &layered[..]);
+    }
+
+    #[test]
+    fn nothing_to_undo() {
+        let mut looper = Looper::new(FS, Ms(1000.0));
+        assert!(!looper.undo());
+        looper.record();
+        looper.play();
+        assert_eq!(looper.state(), LoopState::Empty);
+        assert!(!looper.undo());
+    }
+
+    #[test]
+    fn length_snaps_to_whole_bars() {
+        let bar = 4800;
+        let looper = || {
+            let mut looper = Looper::new(FS, Ms(1000.0)).with_bar(Frames(bar));
+            looper.record();
+            looper
+        };
+
+        // ended slightly early: recording continues to the bar line
+        let mut early = looper();
+        for _ in 0..2 * bar - 100 {
+            early.process(0.5);
+        }
+        early.play();
+        assert_eq!(early.state(), LoopState::Recording);
+        for _ in 0..100 {
+            early.process(0.5);
+        }
+        assert_eq!(early.state(), LoopState::Playing);
+        assert_eq!(early.len(), Frames(2 * bar));
+
+        // ended slightly late: the extra frames are dropped, and the playback
+        // is where it would be
+        let mut late = looper();
+        for i in 0..2 * bar + 100 {
+            late.process(i as f64);
+        }
+        late.play();
+        assert_eq!(late.state(), LoopState::Playing);
+        assert_eq!(late.len(), Frames(2 * bar));
+        assert_eq!(late.process(0.0), 100.0);
+    }
+
+    #[test]
+    fn seam_does_not_click() {
+        let mut looper = Looper::new(FS, Ms(1000.0));
+        // not a whole number of periods, so the end doesn't meet the start
+        let input = phrase(6000, 440.0);
+        let (before, recorded) = input.split_at(1000);
+        for &x in before {
+            looper.process(x);
+        }
+        looper.record();
+        for &x in &recorded[..4937] {
+            looper.process(x);
+        }
+        looper.play();
+
+        let out: Vec<f64> = (0..3 * 4937).map(|_| looper.process(0.0)).collect();
+        // the steepest slope of the sine
+        let slope = 2.0 * std::f64::consts::PI * 440.0 / FS;
+        let jump = out
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f64::max);
+        assert!(jump < 1.1 * slope, "{jump}");
+
+        // without the crossfade, it clicks
+        let mut looper = Looper::new(FS, Ms(1000.0)).with_crossfade(Frames(0));
+        looper.record();
+        for &x in &recorded[..4937] {
+            looper.process(x);
+        }
+        looper.play();
+        let out: Vec<f64> = (0..2 * 4937).map(|_| looper.process(0.0)).collect();
+        let jump = out
+            .windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f64::max);
+        assert!(jump > 2.0 * slope, "{jump}");
+    }
+}
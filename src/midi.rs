@@ -0,0 +1,656 @@
+//! Reading the notes of Standard MIDI Files (SMF), following the MIDI clock
+//! of external devices, and sending notes to them. Enable with `--features
+//! midi`.
+
+use crate::poly::{sort_events, TimedEvent, VoiceEvent};
+use crate::units::Frames;
+use midly::{MetaMessage, MidiMessage, Smf, Timing, TrackEventKind};
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+
+// the tempo until the first tempo event, i.e. 120 BPM
+const DEFAULT_TEMPO: f64 = 500_000.0; // microseconds per beat
+
+/// A note of a MIDI file, placed on the timeline in frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Note {
+    pub start: Frames,
+    pub length: Frames,
+    pub channel: u8,
+    pub key: u8,
+    pub velocity: u8,
+}
+
+/// Reads the notes of the MIDI file. See `parse()`.
+pub fn read<P: AsRef<Path>>(path: P, fs: f64) -> Result<Vec<Note>, anyhow::Error> {
+    parse(&std::fs::read(path)?, fs)
+}
+
+/// Parses an SMF and returns the notes of all the tracks, sorted by the
+/// start. The ticks are converted to frames following the tempo events (in
+/// any track, as the format 1 files put them in the first one), and the
+/// tempo is 120 BPM until the first of them. The tracks are played
+/// simultaneously even in the format 2 files.
+///
+/// A note-on with the velocity of 0 is a note-off. A note-off ends the
+/// earliest note of the same key and channel still sounding, and the notes
+/// left sounding end at the end of the track.
+pub fn parse(bytes: &[u8], fs: f64) -> Result<Vec<Note>, anyhow::Error> {
+    let smf = Smf::parse(bytes)?;
+    let clock = TickClock::new(&smf, fs);
+
+    let mut notes = vec![];
+    for track in &smf.tracks {
+        let mut tick = 0;
+        // the start ticks and the velocities of the sounding notes
+        let mut sounding: HashMap<(u8, u8), VecDeque<(u64, u8)>> = HashMap::new();
+
+        for event in track {
+            tick += u64::from(event.delta.as_int());
+            let TrackEventKind::Midi { channel, message } = event.kind else {
+                continue;
+            };
+            let channel = channel.as_int();
+            let (key, velocity) = match message {
+                MidiMessage::NoteOn { key, vel } => (key.as_int(), vel.as_int()),
+                MidiMessage::NoteOff { key, .. } => (key.as_int(), 0),
+                _ => continue,
+            };
+
+            if velocity > 0 {
+                let queue = sounding.entry((channel, key)).or_default();
+                queue.push_back((tick, velocity));
+            } else if let Some((start, velocity)) = sounding
+                .get_mut(&(channel, key))
+                .and_then(|queue| queue.pop_front())
+            {
+                notes.push(clock.note(start, tick, channel, key, velocity));
+            }
+        }
+
+        for ((channel, key), queue) in sounding {
+            for (start, velocity) in queue {
+                notes.push(clock.note(start, tick, channel, key, velocity));
+            }
+        }
+    }
+
+    notes.sort_by_key(|note| (note.start, note.channel, note.key));
+    Ok(notes)
+}
+
+/// Converts the ticks to the frames.
+struct TickClock {
+    // the start tick, the frame of it, and the frames per tick of each
+    // section of a tempo
+    sections: Vec<(u64, f64, f64)>,
+}
+
+impl TickClock {
+    fn new(smf: &Smf, fs: f64) -> Self {
+        let ticks_per_beat = match smf.header.timing {
+            Timing::Metrical(ticks_per_beat) => f64::from(ticks_per_beat.as_int()),
+            Timing::Timecode(fps, subframes) => {
+                // ticks are in the real time, so the tempo doesn't matter
+                let frames_per_tick = fs / (f64::from(fps.as_f32()) * f64::from(subframes));
+                return Self {
+                    sections: vec![(0, 0.0, frames_per_tick)],
+                };
+            }
+        };
+        let frames_per_tick = |tempo: f64| tempo / 1e6 * fs / ticks_per_beat;
+
+        let mut tempos = vec![];
+        for track in &smf.tracks {
+            let mut tick = 0;
+            for event in track {
+                tick += u64::from(event.delta.as_int());
+                if let TrackEventKind::Meta(MetaMessage::Tempo(tempo)) = event.kind {
+                    tempos.push((tick, f64::from(tempo.as_int())));
+                }
+            }
+        }
+        // stable, so the later one in the same tick wins
+        tempos.sort_by_key(|&(tick, _)| tick);
+
+        let mut sections = vec![(0, 0.0, frames_per_tick(DEFAULT_TEMPO))];
+        for (tick, tempo) in tempos {
+            let &(start, frame, rate) = sections.last().unwrap();
+            let frame = frame + (tick - start) as f64 * rate;
+            if tick == start {
+                sections.pop();
+            }
+            sections.push((tick, frame, frames_per_tick(tempo)));
+        }
+        Self { sections }
+    }
+
+    fn frame(&self, tick: u64) -> f64 {
+        let i = self
+            .sections
+            .partition_point(|&(start, _, _)| start <= tick);
+        let (start, frame, rate) = self.sections[i - 1];
+        frame + (tick - start) as f64 * rate
+    }
+
+    fn note(&self, start: u64, end: u64, channel: u8, key: u8, velocity: u8) -> Note {
+        let start = self.frame(start).round() as usize;
+        let end = self.frame(end).round() as usize;
+        Note {
+            start: Frames(start),
+            length: Frames(end - start),
+            channel,
+            key,
+            velocity,
+        }
+    }
+}
+
+/// Collects the events of the notes (e.g. of `read()`) that start or end in
+/// the range of frames `from..to` into `events`, with the offsets from
+/// `from`, to render the block by `PolySynth::render_block()`. The events
+/// are sorted by `sort_events()`.
+pub fn note_events(notes: &[Note], from: Frames, to: Frames, events: &mut Vec<TimedEvent>) {
+    let range = from.0..to.0;
+    events.clear();
+    for note in notes {
+        let end = note.start.0 + note.length.0;
+        if range.contains(&end) {
+            events.push(TimedEvent {
+                offset: end - from.0,
+                event: VoiceEvent::NoteOff { key: note.key },
+            });
+        }
+        if range.contains(&note.start.0) {
+            events.push(TimedEvent {
+                offset: note.start.0 - from.0,
+                event: VoiceEvent::NoteOn {
+                    key: note.key,
+                    velocity: f64::from(note.velocity) / 127.0,
+                },
+            });
+        }
+    }
+    sort_events(events);
+}
+
+/// The event of a Note On or a Note Off message (on any channel), if it is
+/// one. A Note On with the velocity of 0 is a Note Off.
+pub fn voice_event(message: &[u8]) -> Option<VoiceEvent> {
+    match *message {
+        [status, key, velocity, ..] if status & 0xF0 == 0x90 && velocity > 0 => {
+            Some(VoiceEvent::NoteOn {
+                key: key & 0x7F,
+                velocity: f64::from(velocity & 0x7F) / 127.0,
+            })
+        }
+        [status, key, ..] if status & 0xF0 == 0x80 || status & 0xF0 == 0x90 => {
+            Some(VoiceEvent::NoteOff { key: key & 0x7F })
+        }
+        _ => None,
+    }
+}
+
+/// The offset in a block of `len` frames starting at `block_start` of a
+/// message that arrived at `time` (both in seconds on the same clock, as in
+/// `ClockFollower::handle()`). The messages received during a block are
+/// usually rendered in the next one, a block later, with these offsets to
+/// keep their intervals. Too late or too early ones are clamped into the
+/// block.
+pub fn block_offset(time: f64, block_start: f64, fs: f64, len: usize) -> usize {
+    let offset = ((time - block_start) * fs).round().max(0.0) as usize;
+    offset.min(len.saturating_sub(1))
+}
+
+// the MIDI clock pulses per quarter note
+const CLOCKS_PER_BEAT: u32 = 24;
+
+// how much a new interval of the clock moves the estimate of the tempo
+const TEMPO_SMOOTHING: f64 = 0.1;
+
+/// What `ClockFollower::handle()` found in a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClockEvent {
+    /// The step of the index (counted from the start) begins.
+    Step(u64),
+    Start,
+    Stop,
+    Continue,
+}
+
+/// Follows the MIDI clock from an external device (a DAW or a drum
+/// machine), so that a sequencer can advance its steps on the clock and
+/// know the tempo. Feed all the received messages to `handle()` with the
+/// times they arrived.
+///
+/// The realtime messages of clock (0xF8), start (0xFA), continue (0xFB),
+/// and stop (0xFC) are understood, as well as the song position pointer
+/// (0xF2). The clock is counted only while running, i.e. after start or
+/// continue, but the tempo is estimated from every clock, as devices keep
+/// sending it while stopped.
+pub struct ClockFollower {
+    clocks_per_step: u32,
+    running: bool,
+    // the clocks counted since the start
+    position: u64,
+    last_clock: Option<f64>,
+    // the smoothed interval of the clocks in seconds
+    interval: Option<f64>,
+}
+
+impl ClockFollower {
+    /// `clocks_per_step` is the length of a step in MIDI clocks, e.g. 6 for
+    /// the sixteenth notes.
+    pub fn new(clocks_per_step: u32) -> Self {
+        assert!(clocks_per_step > 0, "clocks_per_step must be positive");
+        Self {
+            clocks_per_step,
+            running: false,
+            position: 0,
+            last_clock: None,
+            interval: None,
+        }
+    }
+
+    /// Handles a MIDI message that arrived at `time` in seconds (of any
+    /// origin, but monotonic), and returns what happened, if any.
+    pub fn handle(&mut self, message: &[u8], time: f64) -> Option<ClockEvent> {
+        match *message {
+            [0xF8, ..] => {
+                if let Some(last) = self.last_clock {
+                    let interval = time - last;
+                    self.interval = Some(match self.interval {
+                        Some(cur) => cur + TEMPO_SMOOTHING * (interval - cur),
+                        None => interval,
+                    });
+                }
+                self.last_clock = Some(time);
+
+                if !self.running {
+                    return None;
+                }
+                let position = self.position;
+                self.position += 1;
+                position
+                    .is_multiple_of(u64::from(self.clocks_per_step))
+                    .then(|| ClockEvent::Step(position / u64::from(self.clocks_per_step)))
+            }
+            [0xFA, ..] => {
+                self.running = true;
+                self.position = 0;
+                Some(ClockEvent::Start)
+            }
+            [0xFB, ..] => {
+                self.running = true;
+                Some(ClockEvent::Continue)
+            }
+            [0xFC, ..] => {
+                self.running = false;
+                Some(ClockEvent::Stop)
+            }
+            // the position in sixteenth notes, 14 bits in LSB and MSB
+            [0xF2, lsb, msb, ..] => {
+                let sixteenths = u64::from(lsb & 0x7F) | (u64::from(msb & 0x7F) << 7);
+                self.position = sixteenths * u64::from(CLOCKS_PER_BEAT / 4);
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// The tempo in BPM estimated from the intervals of the clocks, or
+    /// `None` until two clocks arrive.
+    pub fn bpm(&self) -> Option<f64> {
+        self.interval
+            .filter(|&interval| interval > 0.0)
+            .map(|interval| 60.0 / (interval * f64::from(CLOCKS_PER_BEAT)))
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// The index of the current step (the last one begun, or the one to
+    /// begin first after start).
+    pub fn step(&self) -> u64 {
+        self.position.saturating_sub(1) / u64::from(self.clocks_per_step)
+    }
+}
+
+/// Where `MidiOut` sends the messages, i.e. a MIDI output port of `connect()`,
+/// or a `Vec` collecting them (e.g. for checking them without any device).
+pub trait MidiSink {
+    fn send(&mut self, message: &[u8]) -> Result<(), anyhow::Error>;
+}
+
+impl MidiSink for midir::MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<(), anyhow::Error> {
+        Ok(midir::MidiOutputConnection::send(self, message)?)
+    }
+}
+
+impl MidiSink for Vec<Vec<u8>> {
+    fn send(&mut self, message: &[u8]) -> Result<(), anyhow::Error> {
+        self.push(message.to_vec());
+        Ok(())
+    }
+}
+
+// the name of this program on the MIDI system
+const CLIENT_NAME: &str = "sound-programming-practice";
+
+/// The names of the MIDI output ports.
+pub fn output_ports() -> Result<Vec<String>, anyhow::Error> {
+    let output = midir::MidiOutput::new(CLIENT_NAME)?;
+    let names = output
+        .ports()
+        .iter()
+        .map(|port| output.port_name(port))
+        .collect::<Result<_, _>>()?;
+    Ok(names)
+}
+
+/// Connects to the first MIDI output port whose name contains `name`
+/// (case-insensitive).
+pub fn connect(name: &str) -> Result<MidiOut<midir::MidiOutputConnection>, anyhow::Error> {
+    let output = midir::MidiOutput::new(CLIENT_NAME)?;
+    let ports = output.ports();
+    let mut names = vec![];
+    for port in &ports {
+        let port_name = output.port_name(port)?;
+        if port_name.to_lowercase().contains(&name.to_lowercase()) {
+            let connection = output
+                .connect(port, CLIENT_NAME)
+                .map_err(|e| anyhow::anyhow!("failed to connect to {port_name}: {e}"))?;
+            return Ok(MidiOut::new(connection));
+        }
+        names.push(port_name);
+    }
+    Err(anyhow::anyhow!(
+        "no MIDI output port matches {name} (available: {})",
+        names.join(", ")
+    ))
+}
+
+/// Sends the notes as Note On and Note Off messages, keeping them paired: a
+/// key is never turned on twice without a Note Off in between, no Note Off is
+/// sent for a key not sounding, and the keys still sounding are turned off
+/// on drop so that nothing hangs on the external synth.
+pub struct MidiOut<P: MidiSink> {
+    port: P,
+    // the channels and the keys sounding
+    sounding: Vec<(u8, u8)>,
+}
+
+impl<P: MidiSink> MidiOut<P> {
+    pub fn new(port: P) -> Self {
+        Self {
+            port,
+            sounding: vec![],
+        }
+    }
+
+    pub fn port(&self) -> &P {
+        &self.port
+    }
+
+    /// Turns on the key on the channel (0-15). If it's already sounding, it's
+    /// turned off first to retrigger. The velocity of 0 turns it off.
+    pub fn note_on(&mut self, channel: u8, key: u8, velocity: u8) -> Result<(), anyhow::Error> {
+        let (channel, key, velocity) = (channel & 0x0F, key & 0x7F, velocity & 0x7F);
+        if velocity == 0 {
+            return self.note_off(channel, key);
+        }
+        if self.is_sounding(channel, key) {
+            self.note_off(channel, key)?;
+        }
+        self.port.send(&[0x90 | channel, key, velocity])?;
+        self.sounding.push((channel, key));
+        Ok(())
+    }
+
+    /// Turns off the key on the channel, if it's sounding.
+    pub fn note_off(&mut self, channel: u8, key: u8) -> Result<(), anyhow::Error> {
+        let (channel, key) = (channel & 0x0F, key & 0x7F);
+        let Some(i) = self.sounding.iter().position(|&n| n == (channel, key)) else {
+            return Ok(());
+        };
+        self.sounding.swap_remove(i);
+        self.port.send(&[0x80 | channel, key, 0])
+    }
+
+    /// Follows the gate of a step: turns on the key when the gate opens, and
+    /// off when it closes. Call this on each step with the gate of the step.
+    pub fn gate(
+        &mut self,
+        channel: u8,
+        key: u8,
+        velocity: u8,
+        gate: bool,
+    ) -> Result<(), anyhow::Error> {
+        match (gate, self.is_sounding(channel & 0x0F, key & 0x7F)) {
+            (true, false) => self.note_on(channel, key, velocity),
+            (false, true) => self.note_off(channel, key),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends the notes that start or end in the range of frames `from..to`,
+    /// e.g. the notes of `read()` for each buffer of the audio, in the order
+    /// of the frames. At the same frame, the Note Offs are sent before the
+    /// Note Ons, so that a note ending where the same key starts again is
+    /// retriggered.
+    pub fn send_notes(
+        &mut self,
+        notes: &[Note],
+        from: Frames,
+        to: Frames,
+    ) -> Result<(), anyhow::Error> {
+        let range = from.0..to.0;
+        // the frames, whether they are Note Ons, and the notes
+        let mut events = vec![];
+        for note in notes {
+            let end = note.start.0 + note.length.0;
+            if range.contains(&end) {
+                events.push((end, false, note));
+            }
+            if range.contains(&note.start.0) {
+                events.push((note.start.0, true, note));
+            }
+        }
+        events.sort_by_key(|&(frame, on, _)| (frame, on));
+
+        for (_, on, note) in events {
+            if on {
+                self.note_on(note.channel, note.key, note.velocity)?;
+            } else {
+                self.note_off(note.channel, note.key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Turns off all the keys sounding.
+    pub fn all_notes_off(&mut self) -> Result<(), anyhow::Error> {
+        while let Some(&(channel, key)) = self.sounding.last() {
+            self.note_off(channel, key)?;
+        }
+        Ok(())
+    }
+
+    fn is_sounding(&self, channel: u8, key: u8) -> bool {
+        self.sounding.contains(&(channel, key))
+    }
+}
+
+impl<P: MidiSink> Drop for MidiOut<P> {
+    fn drop(&mut self) {
+        // nothing can be done about the errors here
+        let _ = self.all_notes_off();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(start: usize, length: usize, key: u8) -> Note {
+        Note {
+            start: Frames(start),
+            length: Frames(length),
+            channel: 0,
+            key,
+            velocity: 127,
+        }
+    }
+
+    #[test]
+    fn note_events_are_placed_in_blocks() {
+        // the second note starts where the first ends
+        let notes = [note(100, 412, 60), note(512, 30, 60), note(700, 900, 64)];
+        let mut events = vec![];
+        note_events(&notes, Frames(0), Frames(512), &mut events);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].offset, 100);
+
+        note_events(&notes, Frames(512), Frames(1024), &mut events);
+        let offsets: Vec<(usize, bool)> = events
+            .iter()
+            .map(|e| (e.offset, matches!(e.event, VoiceEvent::NoteOn { .. })))
+            .collect();
+        assert_eq!(offsets, [(0, false), (0, true), (30, false), (188, true)]);
+    }
+
+    #[test]
+    fn message_timestamps_to_offsets() {
+        assert_eq!(
+            voice_event(&[0x91, 60, 100]),
+            Some(VoiceEvent::NoteOn {
+                key: 60,
+                velocity: 100.0 / 127.0
+            })
+        );
+        assert_eq!(
+            voice_event(&[0x90, 60, 0]),
+            Some(VoiceEvent::NoteOff { key: 60 })
+        );
+        assert_eq!(voice_event(&[0xF8]), None);
+
+        // 48 kHz, blocks of 512 frames
+        assert_eq!(block_offset(1.005, 1.0, 48000.0, 512), 240);
+        assert_eq!(block_offset(0.9, 1.0, 48000.0, 512), 0);
+        assert_eq!(block_offset(1.5, 1.0, 48000.0, 512), 511);
+    }
+
+    #[test]
+    fn parses_notes_following_the_tempo() {
+        #[rustfmt::skip]
+        let track = [
+            0x00, 0x90, 60, 100, // on at tick 0
+            0x60, 0x80, 60, 0, // off at tick 96 (a beat)
+            0x00, 0xFF, 0x51, 0x03, 0x03, 0xD0, 0x90, // 250000 us/beat (240 BPM)
+            0x00, 0x90, 64, 80,
+            0x60, 0x90, 64, 0, // on with the velocity of 0
+            0x00, 0xFF, 0x2F, 0x00,
+        ];
+        let mut smf = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk\0\0\0".to_vec();
+        smf.push(track.len() as u8);
+        smf.extend(track);
+
+        // 120 BPM is 24000 frames per beat, and 240 BPM is 12000
+        let notes = parse(&smf, 48000.0).unwrap();
+        assert_eq!(
+            notes,
+            [
+                Note {
+                    start: Frames(0),
+                    length: Frames(24000),
+                    channel: 0,
+                    key: 60,
+                    velocity: 100,
+                },
+                Note {
+                    start: Frames(24000),
+                    length: Frames(12000),
+                    channel: 0,
+                    key: 64,
+                    velocity: 80,
+                },
+            ]
+        );
+        assert!(parse(b"not a midi file", 48000.0).is_err());
+    }
+
+    #[test]
+    fn clock_follower_advances_at_the_tempo() {
+        // 120 BPM is 48 clocks per second; steps of sixteenth notes
+        let mut follower = ClockFollower::new(6);
+        let interval = 60.0 / (120.0 * 24.0);
+        let mut steps = vec![];
+        let mut time = 0.0;
+        let mut clocks = |follower: &mut ClockFollower, n: usize, steps: &mut Vec<u64>| {
+            for _ in 0..n {
+                if let Some(ClockEvent::Step(step)) = follower.handle(&[0xF8], time) {
+                    steps.push(step);
+                }
+                time += interval;
+            }
+        };
+
+        // no steps before start, but the tempo is followed
+        clocks(&mut follower, 24, &mut steps);
+        assert!(steps.is_empty());
+        assert!((follower.bpm().unwrap() - 120.0).abs() < 1e-6);
+
+        assert_eq!(follower.handle(&[0xFA], 0.0), Some(ClockEvent::Start));
+        // 2 seconds at 120 BPM are 4 beats, i.e. 16 sixteenths
+        clocks(&mut follower, 96, &mut steps);
+        assert_eq!(steps, (0..16).collect::<Vec<_>>());
+        assert_eq!(follower.step(), 15);
+
+        assert_eq!(follower.handle(&[0xFC], 0.0), Some(ClockEvent::Stop));
+        clocks(&mut follower, 24, &mut steps);
+        assert_eq!(steps.len(), 16);
+        assert_eq!(follower.handle(&[0xFB], 0.0), Some(ClockEvent::Continue));
+        clocks(&mut follower, 6, &mut steps);
+        assert_eq!(steps.last(), Some(&16));
+    }
+
+    #[test]
+    fn gates_and_notes_are_sent_in_pairs() {
+        let mut out = MidiOut::new(vec![]);
+        for gate in [true, true, false, true, false, false] {
+            out.gate(1, 60, 100, gate).unwrap();
+        }
+        assert_eq!(
+            out.port(),
+            &[
+                vec![0x91, 60, 100],
+                vec![0x81, 60, 0],
+                vec![0x91, 60, 100],
+                vec![0x81, 60, 0],
+            ]
+        );
+
+        // a note shorter than the buffer is turned off in the same buffer,
+        // and a note starting where the same key ends is retriggered
+        let mut out = MidiOut::new(vec![]);
+        let notes = [note(0, 100, 60), note(100, 1000, 60), note(1100, 1000, 62)];
+        for i in 0..3 {
+            out.send_notes(&notes, Frames(i * 512), Frames((i + 1) * 512))
+                .unwrap();
+        }
+        assert_eq!(
+            out.port(),
+            &[
+                vec![0x90, 60, 127],
+                vec![0x80, 60, 0],
+                vec![0x90, 60, 127],
+                vec![0x80, 60, 0],
+                vec![0x90, 62, 127],
+            ]
+        );
+        out.all_notes_off().unwrap();
+        assert_eq!(out.port().last(), Some(&vec![0x80, 62, 0]));
+    }
+}
@@ -0,0 +1,307 @@
+//! A minimal Open Sound Control (OSC 1.0) server over UDP, for controlling
+//! the parameters from TouchOSC and the like. Enable with `--features osc`.
+//!
+//! Only the messages (and the bundles of them) with the arguments of `i`,
+//! `f`, `d`, and `s` are understood; the time tags of the bundles are ignored
+//! and the messages are applied as soon as they arrive.
+
+use crate::params::ParamSet;
+use anyhow::{anyhow, bail};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// An argument of an OSC message.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Double(f64),
+    String(String),
+}
+
+impl OscArg {
+    /// The value as a number, if it is.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Int(v) => Some(*v as f64),
+            Self::Float(v) => Some(*v as f64),
+            Self::Double(v) => Some(*v),
+            Self::String(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+/// Parses a packet, which is either a message or a bundle, into the
+/// messages in it.
+pub fn parse_packet(packet: &[u8]) -> Result<Vec<OscMessage>, anyhow::Error> {
+    let mut messages = vec![];
+    parse_into(packet, &mut messages)?;
+    Ok(messages)
+}
+
+fn parse_into(packet: &[u8], messages: &mut Vec<OscMessage>) -> Result<(), anyhow::Error> {
+    let mut reader = Reader {
+        buf: packet,
+        pos: 0,
+    };
+
+    if packet.starts_with(b"#bundle\0") {
+        reader.take(16)?; // skip the time tag
+        while reader.pos < packet.len() {
+            let size = reader.i32()?;
+            let size =
+                usize::try_from(size).map_err(|_| anyhow!("negative OSC bundle element size"))?;
+            let element = reader.take(size)?;
+            parse_into(element, messages)?;
+        }
+        return Ok(());
+    }
+
+    let address = reader.string()?;
+    if !address.starts_with('/') {
+        bail!("invalid OSC address: {address}");
+    }
+
+    // the type tags may be omitted by the very old implementations
+    let mut args = vec![];
+    if reader.pos < packet.len() {
+        let tags = reader.string()?;
+        let tags = tags
+            .strip_prefix(',')
+            .ok_or_else(|| anyhow!("invalid OSC type tags: {tags}"))?;
+        for tag in tags.chars() {
+            let arg = match tag {
+                'i' => OscArg::Int(reader.i32()?),
+                'f' => OscArg::Float(f32::from_bits(reader.i32()? as u32)),
+                'd' => OscArg::Double(f64::from_bits(u64::from_be_bytes(
+                    reader.take(8)?.try_into().unwrap(),
+                ))),
+                's' => OscArg::String(reader.string()?),
+                _ => bail!("unsupported OSC type tag: {tag}"),
+            };
+            args.push(arg);
+        }
+    }
+
+    messages.push(OscMessage { address, args });
+    Ok(())
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], anyhow::Error> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .ok_or_else(|| anyhow!("truncated OSC packet"))?;
+        let bytes = self
+            .buf
+            .get(self.pos..end)
+            .ok_or_else(|| anyhow!("truncated OSC packet"))?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn i32(&mut self) -> Result<i32, anyhow::Error> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// A string terminated by NUL and padded to a multiple of 4 bytes.
+    fn string(&mut self) -> Result<String, anyhow::Error> {
+        let rest = &self.buf[self.pos..];
+        let len = rest
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| anyhow!("unterminated OSC string"))?;
+        let s = std::str::from_utf8(&rest[..len])?.to_string();
+        self.take((len + 4) & !3)?;
+        Ok(s)
+    }
+}
+
+/// Listens for OSC messages on a UDP port in the background, and sets the
+/// parameters: e.g. with the prefix `/synth`, `/synth/cutoff 800.0` sets the
+/// parameter `cutoff` to 800.0. The messages to unknown parameters or
+/// without a numeric argument are ignored. The server stops when dropped.
+pub struct OscServer {
+    local_addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl OscServer {
+    pub fn spawn<A: ToSocketAddrs>(
+        addr: A,
+        prefix: &str,
+        params: Arc<ParamSet>,
+    ) -> Result<Self, anyhow::Error> {
+        let socket = UdpSocket::bind(addr)?;
+        let local_addr = socket.local_addr()?;
+        // wake up regularly to check whether to stop
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let prefix = format!("{}/", prefix.trim_end_matches('/'));
+
+        let thread = {
+            let stop = stop.clone();
+            std::thread::spawn(move || {
+                // the maximum size of a UDP packet
+                let mut buf = vec![0; 65536];
+                while !stop.load(Ordering::Relaxed) {
+                    let Ok((len, _)) = socket.recv_from(&mut buf) else {
+                        continue;
+                    };
+                    let messages = match parse_packet(&buf[..len]) {
+                        Ok(messages) => messages,
+                        Err(e) => {
+                            eprintln!("warning: {e}");
+                            continue;
+                        }
+                    };
+                    for msg in messages {
+                        let name = msg.address.strip_prefix(&prefix);
+                        let value = msg.args.first().and_then(OscArg::as_f64);
+                        if let (Some(name), Some(value)) = (name, value) {
+                            params.set(name, value);
+                        }
+                    }
+                }
+            })
+        };
+
+        Ok(Self {
+            local_addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address listening on, e.g. to find the port when bound to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl Drop for OscServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // an OSC string: NUL-terminated and padded to a multiple of 4 bytes
+    fn string(s: &str) -> Vec<u8> {
+        let mut bytes = s.as_bytes().to_vec();
+        bytes.resize((s.len() + 4) & !3, 0);
+        bytes
+    }
+
+    fn message(address: &str, value: f32) -> Vec<u8> {
+        [string(address), string(",f"), value.to_be_bytes().to_vec()].concat()
+    }
+
+    fn bundle(elements: &[(i32, &[u8])]) -> Vec<u8> {
+        let mut packet = [string("#bundle"), vec![0; 8]].concat();
+        for (size, element) in elements {
+            packet.extend(size.to_be_bytes());
+            packet.extend(*element);
+        }
+        packet
+    }
+
+    #[test]
+    fn parses_messages_and_bundles() {
+        let packet = [
+            string("/synth/mix"),
+            string(",ifds"),
+            7_i32.to_be_bytes().to_vec(),
+            0.5_f32.to_be_bytes().to_vec(),
+            0.25_f64.to_be_bytes().to_vec(),
+            string("saw"),
+        ]
+        .concat();
+        let messages = parse_packet(&packet).unwrap();
+        assert_eq!(
+            messages,
+            [OscMessage {
+                address: "/synth/mix".to_string(),
+                args: vec![
+                    OscArg::Int(7),
+                    OscArg::Float(0.5),
+                    OscArg::Double(0.25),
+                    OscArg::String("saw".to_string()),
+                ],
+            }]
+        );
+
+        let (a, b) = (message("/a", 1.0), message("/b", 2.0));
+        let packet = bundle(&[(a.len() as i32, &a), (b.len() as i32, &b)]);
+        let addresses: Vec<String> = parse_packet(&packet)
+            .unwrap()
+            .into_iter()
+            .map(|m| m.address)
+            .collect();
+        assert_eq!(addresses, ["/a", "/b"]);
+    }
+
+    #[test]
+    fn rejects_malformed_packets() {
+        let msg = message("/a", 1.0);
+        let malformed = [
+            // the sizes of the bundle elements: negative (which used to
+            // overflow the position), beyond the packet, and the largest
+            bundle(&[(-4, &msg)]),
+            bundle(&[(msg.len() as i32 + 4, &msg)]),
+            bundle(&[(i32::MAX, &msg)]),
+            // the time tag cut off
+            string("#bundle"),
+            // the argument cut off
+            msg[..msg.len() - 2].to_vec(),
+            b"/abc".to_vec(),
+            message("no-slash", 1.0),
+            [string("/a"), string("ix")].concat(),
+        ];
+        for packet in malformed {
+            assert!(parse_packet(&packet).is_err(), "{packet:?}");
+        }
+    }
+
+    #[test]
+    fn server_sets_the_parameter() {
+        let mut params = ParamSet::new();
+        let cutoff = params.add("cutoff", 2000.0);
+        let server = OscServer::spawn("127.0.0.1:0", "/synth", Arc::new(params)).unwrap();
+
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .send_to(&message("/synth/cutoff", 800.0), server.local_addr())
+            .unwrap();
+        for _ in 0..200 {
+            if cutoff.get() == 800.0 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(cutoff.get(), 800.0);
+    }
+}
@@ -0,0 +1,100 @@
+use dasp::Signal;
+
+/// A sine oscillator that owns its phase explicitly. The frequency is given
+/// by a `Signal` and only changes the increment of the phase, so the phase
+/// never jumps (and never clicks) however abruptly the frequency changes.
+pub struct PhaseAccumOsc<S: Signal<Frame = f64>> {
+    hz: S,
+    fs: f64, // sampling rate
+    phase: f64,
+}
+
+impl<S: Signal<Frame = f64>> PhaseAccumOsc<S> {
+    pub fn new(hz: S, fs: f64) -> Self {
+        Self { hz, fs, phase: 0.0 }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for PhaseAccumOsc<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let out = (2.0 * std::f64::consts::PI * self.phase).sin();
+
+        // the phase is kept within [0.0, 1.0) to avoid losing the precision
+        self.phase = (self.phase + self.hz.next() / self.fs).rem_euclid(1.0);
+
+        out
+    }
+}
+
+/// The shape of an LFO.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Square,
+}
+
+impl LfoShape {
+    /// The value within [-1.0, 1.0] at `phase` (in cycles, within [0.0, 1.0)).
+    /// All the shapes start from 0.0 (or 1.0 for the square) and rise first.
+    pub fn value(self, phase: f64) -> f64 {
+        match self {
+            LfoShape::Sine => (2.0 * std::f64::consts::PI * phase).sin(),
+            LfoShape::Triangle => {
+                // 0 -> 1 -> 0 -> -1 -> 0
+                let t = (phase + 0.25).rem_euclid(1.0);
+                1.0 - 4.0 * (t - 0.5).abs()
+            }
+            LfoShape::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    // a melody of steps of `step_length` frames, as the `Track` of the
+    // examples
+    fn melody(notes: &[f64], step_length: usize) -> impl Signal<Frame = f64> {
+        let hz: Vec<f64> = notes
+            .iter()
+            .flat_map(|&hz| std::iter::repeat_n(hz, step_length))
+            .collect();
+        dasp::signal::from_iter(hz)
+    }
+
+    #[test]
+    fn large_jumps_keep_the_phase() {
+        // 110 Hz to 3520 Hz and back, in the middle of the cycles
+        let (notes, step_length) = ([110.0, 3520.0, 110.0, 3520.0, 110.0], 1000);
+        let osc = PhaseAccumOsc::new(melody(&notes, step_length), FS);
+        let out: Vec<f64> = osc.take(notes.len() * step_length).collect();
+
+        // no step is larger than the one of the highest note
+        let max_step = 2.0 * std::f64::consts::PI * 3520.0 / FS + 1e-9;
+        for (i, w) in out.windows(2).enumerate() {
+            assert!((w[1] - w[0]).abs() <= max_step, "frame {}: {w:?}", i + 1);
+        }
+
+        // the phase carries over at the switches, rather than restarting
+        let mut phase = 0.0;
+        for (i, &x) in out.iter().enumerate() {
+            let expected = (2.0 * std::f64::consts::PI * phase).sin();
+            assert!((x - expected).abs() < 1e-9, "frame {i}: {x} != {expected}");
+            phase += notes[i / step_length] / FS;
+        }
+        // e.g. 2.29 cycles of 110 Hz into the first switch
+        assert!(out[step_length].abs() > 0.9, "{}", out[step_length]);
+    }
+}
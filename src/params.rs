@@ -0,0 +1,610 @@
+//! Parameters shared between the audio thread and the others (a UI, the
+//! stdin, the network), without locking.
+
+use crate::core::smooth::SmoothedParam;
+use crate::units::Ms;
+use dasp::Signal;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// An `f64` that can be shared between threads, stored as its bits.
+#[derive(Debug, Default)]
+pub struct AtomicF64(AtomicU64);
+
+impl AtomicF64 {
+    pub fn new(value: f64) -> Self {
+        Self(AtomicU64::new(value.to_bits()))
+    }
+
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// A set of named parameters. The set itself is fixed once built; only the
+/// values change. Clone the `Arc`s of the parameters the audio thread needs
+/// before starting the stream, so it never looks up the names.
+///
+/// The changes by `set()` can be recorded as an `Automation` by
+/// `start_recording()`.
+#[derive(Debug, Default)]
+pub struct ParamSet {
+    params: HashMap<String, Arc<AtomicF64>>,
+    recording: Mutex<Option<(Arc<FrameClock>, Automation)>>,
+}
+
+impl ParamSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a parameter with the initial value, and returns it.
+    pub fn add(&mut self, name: &str, value: f64) -> Arc<AtomicF64> {
+        let param = Arc::new(AtomicF64::new(value));
+        self.params.insert(name.to_string(), param.clone());
+        param
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<AtomicF64>> {
+        self.params.get(name)
+    }
+
+    /// The parameter to read on the audio thread, whose changes glide over
+    /// `time` rather than jump (see `Smoothed`). `None` if there's no such
+    /// parameter.
+    pub fn smoothed(&self, name: &str, fs: f64, time: Ms) -> Option<Smoothed> {
+        self.get(name)
+            .map(|param| Smoothed::new(param.clone(), fs, time))
+    }
+
+    /// Sets the value of the parameter, and returns false if there's no such
+    /// parameter.
+    pub fn set(&self, name: &str, value: f64) -> bool {
+        let Some(param) = self.params.get(name) else {
+            return false;
+        };
+        // set while holding the lock so that the recorded order is the order
+        // the values are set
+        let mut recording = self.recording.lock().unwrap();
+        param.set(value);
+        if let Some((clock, automation)) = recording.as_mut() {
+            automation.events.push(AutomationEvent {
+                frame: clock.now(),
+                name: name.to_string(),
+                value,
+            });
+        }
+        true
+    }
+
+    /// Captures the current values of all the parameters.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            values: self
+                .params
+                .iter()
+                .map(|(name, param)| (name.clone(), param.get()))
+                .collect(),
+        }
+    }
+
+    /// Sets the values of the snapshot back (by `set()`, so they are
+    /// recorded while recording). The parameters not in the snapshot are
+    /// left as they are.
+    pub fn restore(&self, snapshot: &Snapshot) {
+        for (name, &value) in &snapshot.values {
+            self.set(name, value);
+        }
+    }
+
+    /// Starts recording the changes by `set()`, timestamped by the frames the
+    /// audio thread has rendered according to `clock`. A recording in
+    /// progress is discarded.
+    pub fn start_recording(&self, clock: Arc<FrameClock>) {
+        *self.recording.lock().unwrap() = Some((clock, Automation::default()));
+    }
+
+    /// Stops recording, and returns what was recorded (empty if not
+    /// recording).
+    pub fn stop_recording(&self) -> Automation {
+        self.recording
+            .lock()
+            .unwrap()
+            .take()
+            .map(|(_, automation)| automation)
+            .unwrap_or_default()
+    }
+}
+
+/// The values of the parameters of a `ParamSet` at a time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    values: HashMap<String, f64>,
+}
+
+impl Snapshot {
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.values.get(name).copied()
+    }
+}
+
+/// A stack of snapshots, to undo the changes or to compare two settings.
+#[derive(Debug, Default)]
+pub struct History {
+    snapshots: Vec<Snapshot>,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Pushes the current values.
+    pub fn capture(&mut self, params: &ParamSet) {
+        self.snapshots.push(params.snapshot());
+    }
+
+    /// Restores the last captured values and pops them. Returns false if
+    /// there's nothing to undo.
+    pub fn undo(&mut self, params: &ParamSet) -> bool {
+        match self.snapshots.pop() {
+            Some(snapshot) => {
+                params.restore(&snapshot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Swaps the current values and the last captured ones, so that calling
+    /// this repeatedly toggles between the two settings (A/B). Returns false
+    /// if nothing is captured.
+    pub fn swap(&mut self, params: &ParamSet) -> bool {
+        match self.snapshots.last_mut() {
+            Some(snapshot) => {
+                let current = params.snapshot();
+                params.restore(snapshot);
+                *snapshot = current;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The number of the captured snapshots.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+}
+
+/// A parameter shared with another thread, followed by a `SmoothedParam` on
+/// the audio thread so that setting it doesn't make zipper noise. Call
+/// `next_value()` once per frame.
+pub struct Smoothed {
+    param: Arc<AtomicF64>,
+    value: SmoothedParam,
+}
+
+impl Smoothed {
+    /// Starts at the current value of the parameter.
+    pub fn new(param: Arc<AtomicF64>, fs: f64, time: Ms) -> Self {
+        let value = SmoothedParam::new(fs, time, param.get());
+        Self { param, value }
+    }
+
+    /// Whether the value changes on the next frame, e.g. to recompute the
+    /// coefficients of a filter only then.
+    pub fn is_gliding(&self) -> bool {
+        self.value.is_gliding() || self.param.get() != self.value.target()
+    }
+
+    /// The current value without proceeding.
+    pub fn value(&self) -> f64 {
+        self.value.value()
+    }
+
+    /// Follows the latest value of the parameter, proceeds a frame, and
+    /// returns the value.
+    pub fn next_value(&mut self) -> f64 {
+        self.value.set_target(self.param.get());
+        self.value.next_value()
+    }
+}
+
+/// The number of the frames the audio thread has rendered, to timestamp the
+/// changes of the parameters. The audio thread should `advance()` it after
+/// each frame (or wrap the signal with `Clocked`).
+#[derive(Debug, Default)]
+pub struct FrameClock(AtomicU64);
+
+impl FrameClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of the frames rendered so far, i.e. the index of the frame
+    /// that will see a value set now.
+    pub fn now(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn advance(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Advances a `FrameClock` on each frame of the signal.
+pub struct Clocked<S: Signal> {
+    signal: S,
+    clock: Arc<FrameClock>,
+}
+
+impl<S: Signal> Clocked<S> {
+    pub fn new(signal: S, clock: Arc<FrameClock>) -> Self {
+        Self { signal, clock }
+    }
+}
+
+impl<S: Signal> Signal for Clocked<S> {
+    type Frame = S::Frame;
+
+    fn next(&mut self) -> Self::Frame {
+        let frame = self.signal.next();
+        self.clock.advance();
+        frame
+    }
+}
+
+/// A change of a parameter at a frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutomationEvent {
+    pub frame: u64,
+    pub name: String,
+    pub value: f64,
+}
+
+// the time of the fade of `Mute`
+const MUTE_FADE: Ms = Ms(50.0);
+
+// the length of the blocks `PeakMeter` measures, i.e. about 30 per second
+const METER_BLOCK: Ms = Ms(33.0);
+
+/// Fades a signal out to silence while the flag is set by another thread
+/// (e.g. on pausing), and back in when it's cleared. The fades take 50 ms, so
+/// muting never clicks. The signal keeps running while muted.
+pub struct Mute<S: Signal<Frame = f64>> {
+    signal: S,
+    muted: Arc<AtomicBool>,
+    gain: SmoothedParam,
+}
+
+impl<S: Signal<Frame = f64>> Mute<S> {
+    /// Starts muted (without the fade) if the flag is already set.
+    pub fn new(signal: S, fs: f64, muted: Arc<AtomicBool>) -> Self {
+        let gain = if muted.load(Ordering::Relaxed) {
+            0.0
+        } else {
+            1.0
+        };
+        Self {
+            signal,
+            muted,
+            gain: SmoothedParam::new(fs, MUTE_FADE, gain),
+        }
+    }
+
+    /// True when muted and the fade-out has finished, e.g. to end the stream
+    /// after it.
+    pub fn is_silent(&self) -> bool {
+        self.gain.value() == 0.0
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for Mute<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let muted = self.muted.load(Ordering::Relaxed);
+        self.gain.set_target(if muted { 0.0 } else { 1.0 });
+        self.gain.next_value() * self.signal.next()
+    }
+}
+
+/// Measures the peak of a signal for a meter on another thread: the peak
+/// (absolute) of each block of 33 ms is stored at the end of the block.
+pub struct PeakMeter<S: Signal<Frame = f64>> {
+    signal: S,
+    level: Arc<AtomicF64>,
+    block_length: usize,
+    cur_frame: usize,
+    peak: f64,
+}
+
+impl<S: Signal<Frame = f64>> PeakMeter<S> {
+    pub fn new(signal: S, fs: f64, level: Arc<AtomicF64>) -> Self {
+        Self {
+            signal,
+            level,
+            block_length: METER_BLOCK.to_frames(fs).0.max(1),
+            cur_frame: 0,
+            peak: 0.0,
+        }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for PeakMeter<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        self.peak = self.peak.max(x.abs());
+        self.cur_frame += 1;
+        if self.cur_frame == self.block_length {
+            self.level.set(self.peak);
+            self.peak = 0.0;
+            self.cur_frame = 0;
+        }
+        x
+    }
+}
+
+/// Passes a control signal (e.g. an envelope or an LFO) through, storing
+/// each value for another thread or for `runner::ControlDump` to read.
+pub struct Probe<S: Signal<Frame = f64>> {
+    signal: S,
+    value: Arc<AtomicF64>,
+}
+
+impl<S: Signal<Frame = f64>> Probe<S> {
+    pub fn new(signal: S, value: Arc<AtomicF64>) -> Self {
+        Self { signal, value }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for Probe<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        self.value.set(x);
+        x
+    }
+}
+
+/// The changes of parameters over time, recorded by
+/// `ParamSet::start_recording()` (or written by hand). The file format is a
+/// line of `<frame> <name> <value>` per change, in the order of the frames.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Automation {
+    events: Vec<AutomationEvent>,
+}
+
+impl Automation {
+    /// The events are sorted by the frame (stably, so the changes of the
+    /// same frame keep their order).
+    pub fn new(mut events: Vec<AutomationEvent>) -> Self {
+        events.sort_by_key(|e| e.frame);
+        Self { events }
+    }
+
+    pub fn events(&self) -> &[AutomationEvent] {
+        &self.events
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), anyhow::Error> {
+        let mut writer = BufWriter::new(std::fs::File::create(path)?);
+        for e in &self.events {
+            writeln!(writer, "{} {} {}", e.frame, e.name, e.value)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let reader = BufReader::new(std::fs::File::open(path)?);
+        let mut events = vec![];
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = || anyhow::anyhow!("invalid automation at line {}: {line}", i + 1);
+            let mut words = line.split_whitespace();
+            let (Some(frame), Some(name), Some(value), None) =
+                (words.next(), words.next(), words.next(), words.next())
+            else {
+                return Err(invalid());
+            };
+            events.push(AutomationEvent {
+                frame: frame.parse().map_err(|_| invalid())?,
+                name: name.to_string(),
+                value: value.parse().map_err(|_| invalid())?,
+            });
+        }
+        Ok(Self::new(events))
+    }
+}
+
+/// Replays an `Automation` on a signal: before each frame of the signal, the
+/// changes of the frame are set to the parameters, so the signal sees the
+/// same values at the same frames as when they were recorded (given it
+/// starts from the same state). The frames are counted from the start of
+/// this signal.
+pub struct Replay<S: Signal> {
+    signal: S,
+    params: Arc<ParamSet>,
+    events: std::vec::IntoIter<AutomationEvent>,
+    next_event: Option<AutomationEvent>,
+    cur_frame: u64,
+}
+
+impl<S: Signal> Replay<S> {
+    pub fn new(signal: S, params: Arc<ParamSet>, automation: Automation) -> Self {
+        let mut events = automation.events.into_iter();
+        let next_event = events.next();
+        Self {
+            signal,
+            params,
+            events,
+            next_event,
+            cur_frame: 0,
+        }
+    }
+}
+
+impl<S: Signal> Signal for Replay<S> {
+    type Frame = S::Frame;
+
+    fn next(&mut self) -> Self::Frame {
+        while let Some(e) = self.next_event.take_if(|e| e.frame <= self.cur_frame) {
+            // set the parameter directly so that replaying doesn't record
+            if let Some(param) = self.params.get(&e.name) {
+                param.set(e.value);
+            }
+            self.next_event = self.events.next();
+        }
+        self.cur_frame += 1;
+        self.signal.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> Arc<ParamSet> {
+        let mut params = ParamSet::new();
+        params.add("freq", 220.0);
+        params.add("cutoff", 2000.0);
+        Arc::new(params)
+    }
+
+    // the values of the parameters on each frame
+    fn probe(params: Arc<ParamSet>) -> impl Signal<Frame = [f64; 2]> {
+        dasp::signal::gen(move || {
+            let value = |name| params.get(name).unwrap().get();
+            [value("freq"), value("cutoff")]
+        })
+    }
+
+    #[test]
+    fn replay_reproduces_the_recorded_changes() {
+        let live = params();
+        let clock = Arc::new(FrameClock::new());
+        let mut signal = Clocked::new(probe(live.clone()), clock.clone());
+
+        live.start_recording(clock);
+        let mut recorded = vec![];
+        for frame in 0..1000 {
+            match frame {
+                100 => assert!(live.set("freq", 440.0)),
+                300 => assert!(live.set("cutoff", 800.0)),
+                _ => {}
+            }
+            recorded.push(signal.next());
+        }
+        let automation = live.stop_recording();
+        assert_eq!(automation.events().len(), 2);
+
+        let path = std::env::temp_dir().join(format!("automation-{}.txt", std::process::id()));
+        automation.save(&path).unwrap();
+        let automation = Automation::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let replayed = params();
+        let mut signal = Replay::new(probe(replayed.clone()), replayed, automation);
+        let replayed: Vec<[f64; 2]> = (0..1000).map(|_| signal.next()).collect();
+        assert_eq!(replayed, recorded);
+        assert_eq!(recorded[99], [220.0, 2000.0]);
+        assert_eq!(recorded[100], [440.0, 2000.0]);
+        assert_eq!(recorded[300], [440.0, 800.0]);
+    }
+
+    #[test]
+    fn undo_restores_every_captured_value() {
+        let params = params();
+        let mut history = History::new();
+        assert!(!history.undo(&params));
+
+        history.capture(&params);
+        let captured = params.snapshot();
+        params.set("freq", 880.0);
+        params.set("cutoff", 500.0);
+        history.capture(&params);
+        params.set("freq", 110.0);
+
+        // A/B between the last capture and now
+        assert!(history.swap(&params));
+        assert_eq!(params.get("freq").unwrap().get(), 880.0);
+        assert!(history.swap(&params));
+        assert_eq!(params.get("freq").unwrap().get(), 110.0);
+
+        assert!(history.undo(&params));
+        assert!(history.undo(&params));
+        assert!(history.is_empty());
+        assert_eq!(params.snapshot(), captured);
+        assert_eq!(captured.get("freq"), Some(220.0));
+        assert_eq!(captured.get("cutoff"), Some(2000.0));
+    }
+
+    #[test]
+    fn smoothed_parameter_glides_to_the_new_value() {
+        let params = params();
+        let fs = 48000.0;
+        let mut cutoff = params.smoothed("cutoff", fs, Ms(10.0)).unwrap();
+        assert!(params.smoothed("resonance", fs, Ms(10.0)).is_none());
+        assert_eq!(cutoff.next_value(), 2000.0);
+        assert!(!cutoff.is_gliding());
+
+        params.set("cutoff", 1000.0);
+        assert!(cutoff.is_gliding());
+        let first = cutoff.next_value();
+        assert!(first < 2000.0 && first > 1980.0, "{first}");
+        for _ in 1..480 {
+            cutoff.next_value();
+        }
+        assert_eq!(cutoff.value(), 1000.0);
+        assert!(!cutoff.is_gliding());
+    }
+
+    #[test]
+    fn mute_fades_out_and_back_in() {
+        let fs = 48000.0;
+        let fade = MUTE_FADE.to_frames(fs).0;
+        let muted = Arc::new(AtomicBool::new(false));
+        let dc = dasp::signal::from_iter(std::iter::repeat(1.0));
+        let mut mute = Mute::new(dc, fs, muted.clone());
+        assert_eq!(mute.next(), 1.0);
+
+        muted.store(true, Ordering::Relaxed);
+        let out: Vec<f64> = (0..fade).map(|_| mute.next()).collect();
+        // fading without a click, and silent within 50 ms
+        assert!(out[0] > 0.99);
+        assert!(out.windows(2).all(|w| w[1] <= w[0] && w[0] - w[1] < 0.01));
+        assert_eq!(out[fade - 1], 0.0);
+        assert!(mute.is_silent());
+
+        muted.store(false, Ordering::Relaxed);
+        let out: Vec<f64> = (0..fade).map(|_| mute.next()).collect();
+        assert!(out[0] < 0.01);
+        assert!(out.windows(2).all(|w| w[1] >= w[0] && w[1] - w[0] < 0.01));
+        assert_eq!(out[fade - 1], 1.0);
+        assert!(!mute.is_silent());
+
+        // starting muted
+        let dc = dasp::signal::from_iter(std::iter::repeat(1.0));
+        let mut mute = Mute::new(dc, fs, Arc::new(AtomicBool::new(true)));
+        assert_eq!(mute.next(), 0.0);
+    }
+}
@@ -0,0 +1,456 @@
+//! A pool of synth voices processed in blocks. The events of a block carry
+//! their frames within it, and the block is rendered in segments split at
+//! them, so the notes start and end exactly where they should (rather than
+//! at the start of the block) and the output doesn't depend on the size of
+//! the blocks.
+//!
+//! In the mono mode (see `PolySynth::with_mono()`), it plays like a classic
+//! mono lead instead: the last key pressed sounds, and a key pressed while
+//! another is held glides to its pitch without retriggering the envelope.
+
+use crate::core::multiosc::{MultiOsc, Waveform};
+use crate::core::noise::Noise;
+use crate::core::smooth::SmoothedParam;
+use crate::units::{Hz, Ms};
+
+/// A note event for `PolySynth`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoiceEvent {
+    /// The velocity is 0.0 to 1.0.
+    NoteOn {
+        key: u8,
+        velocity: f64,
+    },
+    NoteOff {
+        key: u8,
+    },
+}
+
+/// An event at `offset` frames from the start of a block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimedEvent {
+    pub offset: usize,
+    pub event: VoiceEvent,
+}
+
+/// Sorts the events by the offset, with the Note Offs before the Note Ons at
+/// the same offset, so that a note ending where the same key starts again is
+/// retriggered rather than cut.
+pub fn sort_events(events: &mut [TimedEvent]) {
+    events.sort_by_key(|e| (e.offset, matches!(e.event, VoiceEvent::NoteOn { .. })));
+}
+
+struct Voice {
+    osc: MultiOsc,
+    env: SmoothedParam,
+    key: u8,
+    velocity: f64,
+    // the pitch in MIDI keys (with the detune), which glides between the
+    // legato notes of the mono mode
+    pitch: SmoothedParam,
+    // the detune in cents, and the gains of the pan
+    cents: f64,
+    gains: [f64; 2],
+    // whether the key is still down
+    held: bool,
+    // the order of the notes, to steal the oldest voice
+    started: u64,
+}
+
+impl Voice {
+    fn is_active(&self) -> bool {
+        self.held || self.env.value() != 0.0 || self.env.target() != 0.0
+    }
+}
+
+/// A polyphonic synth of a fixed number of voices: oscillators with an
+/// envelope of exponential attack and release. A Note On takes a free voice
+/// (or as many as the unison), or the oldest one if all of them are sounding;
+/// a Note Off releases all the voices of the key.
+pub struct PolySynth {
+    fs: f64, // sampling rate
+    waveform: Waveform,
+    attack: Ms,
+    release: Ms,
+    voices: Vec<Voice>,
+    notes: u64,
+    // the number of the voices of a note, and their detune in cents
+    unison: usize,
+    unison_spread: f64,
+    // the ranges of the random detune (in cents) and pan of each note
+    detune: f64,
+    spread: f64,
+    random: Noise,
+    // the glide of the mono mode, and the keys held in the order pressed
+    glide: Option<Ms>,
+    held: Vec<u8>,
+}
+
+impl PolySynth {
+    /// The default envelope is 5 ms of attack and 100 ms of release.
+    pub fn new(fs: f64, waveform: Waveform, voices: usize) -> Self {
+        assert!(voices > 0, "a synth needs at least one voice");
+        let voices = (0..voices)
+            .map(|_| Voice {
+                osc: MultiOsc::new(fs, waveform),
+                env: SmoothedParam::new(fs, Ms(0.0), 0.0),
+                key: 0,
+                velocity: 0.0,
+                pitch: SmoothedParam::new(fs, Ms(0.0), 0.0),
+                cents: 0.0,
+                gains: pan_gains(0.0),
+                held: false,
+                started: 0,
+            })
+            .collect();
+        Self {
+            fs,
+            waveform,
+            attack: Ms(5.0),
+            release: Ms(100.0),
+            voices,
+            notes: 0,
+            unison: 1,
+            unison_spread: 0.0,
+            detune: 0.0,
+            spread: 0.0,
+            random: Noise::new(0),
+            glide: None,
+            held: vec![],
+        }
+    }
+
+    /// Plays one note at a time: the last key pressed sounds, and a key
+    /// pressed while another is held glides to its pitch over `glide`,
+    /// without retriggering the envelope (keeping the velocity of the first
+    /// note). Releasing it returns to the previous key if that's still held.
+    pub fn with_mono(mut self, glide: Ms) -> Self {
+        self.set_glide(glide);
+        self
+    }
+
+    /// Sets the glide of the mono mode, turning it on.
+    pub fn set_glide(&mut self, glide: Ms) {
+        self.glide = Some(glide);
+    }
+
+    /// Plays each note by `voices` voices detuned evenly within ± `spread`
+    /// cents and panned evenly from the left to the right, at the level of
+    /// a voice in total power. A note takes that many voices of the synth.
+    pub fn with_unison(mut self, voices: usize, spread: f64) -> Self {
+        assert!(voices > 0, "a note needs at least one voice");
+        self.unison = voices;
+        self.unison_spread = spread.abs();
+        self
+    }
+
+    /// Detunes each voice by a random amount within ± `cents`, and pans it at
+    /// random within ± `spread` (1.0 is a hard pan), so that the stacked notes
+    /// of a pad spread naturally. The random numbers come from `seed`, so the
+    /// output still depends only on the events. Both are 0 by default.
+    pub fn with_ensemble(mut self, cents: f64, spread: f64, seed: u64) -> Self {
+        self.detune = cents.abs();
+        self.spread = spread.clamp(0.0, 1.0);
+        self.random = Noise::new(seed);
+        self
+    }
+
+    /// The times for the envelope to reach the level of a note, and to fall
+    /// to silence after the Note Off.
+    pub fn with_envelope(mut self, attack: Ms, release: Ms) -> Self {
+        self.set_envelope(attack, release);
+        self
+    }
+
+    /// Sets the envelope of the notes from now on.
+    pub fn set_envelope(&mut self, attack: Ms, release: Ms) {
+        self.attack = attack;
+        self.release = release;
+    }
+
+    /// Sets the waveform of the notes from now on.
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    /// The number of the voices sounding, including the ones releasing.
+    pub fn active_voices(&self) -> usize {
+        self.voices.iter().filter(|v| v.is_active()).count()
+    }
+
+    /// Applies the event now, i.e. before the next frame rendered.
+    pub fn handle(&mut self, event: VoiceEvent) {
+        match (event, self.glide) {
+            (VoiceEvent::NoteOn { key, velocity }, None) => self.note_on(key, velocity),
+            (VoiceEvent::NoteOn { key, velocity }, Some(glide)) => {
+                self.legato_on(key, velocity, glide)
+            }
+            (VoiceEvent::NoteOff { key }, None) => self.release(key),
+            (VoiceEvent::NoteOff { key }, Some(glide)) => self.legato_off(key, glide),
+        }
+    }
+
+    fn release(&mut self, key: u8) {
+        for voice in self.voices.iter_mut().filter(|v| v.held && v.key == key) {
+            voice.held = false;
+            voice.env.set_time(self.fs, self.release);
+            voice.env.set_target(0.0);
+        }
+    }
+
+    // starts the note, or glides to it if another is held
+    fn legato_on(&mut self, key: u8, velocity: f64, glide: Ms) {
+        let legato = !self.held.is_empty();
+        self.held.retain(|&k| k != key);
+        self.held.push(key);
+
+        if legato {
+            self.glide_to(key, glide);
+        } else {
+            self.note_on(key, velocity);
+        }
+    }
+
+    // releasing the key sounding returns to the previous key held, or ends
+    // the note if none is; releasing another key only forgets it
+    fn legato_off(&mut self, key: u8, glide: Ms) {
+        let Some(i) = self.held.iter().position(|&k| k == key) else {
+            return;
+        };
+        let sounding = i == self.held.len() - 1;
+        self.held.remove(i);
+        if !sounding {
+            return;
+        }
+
+        match self.held.last() {
+            Some(&previous) => self.glide_to(previous, glide),
+            None => self.release(key),
+        }
+    }
+
+    fn glide_to(&mut self, key: u8, glide: Ms) {
+        for voice in self.voices.iter_mut().filter(|v| v.held) {
+            voice.key = key;
+            voice.pitch.set_time(self.fs, glide);
+            voice.pitch.set_target(f64::from(key) + voice.cents / 100.0);
+        }
+    }
+
+    fn note_on(&mut self, key: u8, velocity: f64) {
+        let n = self.unison;
+        let velocity = velocity / (n as f64).sqrt();
+        for i in 0..n {
+            // evenly from -1.0 to 1.0
+            let position = match n {
+                1 => 0.0,
+                _ => 2.0 * i as f64 / (n - 1) as f64 - 1.0,
+            };
+            self.start_voice(key, velocity, self.unison_spread * position, position);
+        }
+    }
+
+    fn start_voice(&mut self, key: u8, velocity: f64, cents: f64, pan: f64) {
+        let i = match self.voices.iter().position(|v| !v.is_active()) {
+            Some(i) => i,
+            None => (0..self.voices.len())
+                .min_by_key(|&i| self.voices[i].started)
+                .unwrap_or(0),
+        };
+        self.notes += 1;
+
+        let voice = &mut self.voices[i];
+        // from the start of the waveform, so that the output depends only on
+        // the events
+        voice.osc = MultiOsc::new(self.fs, self.waveform);
+        voice.cents = cents + self.detune * self.random.next_sample();
+        let pan = pan + self.spread * self.random.next_sample();
+        voice.gains = pan_gains(pan.clamp(-1.0, 1.0));
+        voice.pitch.reset(f64::from(key) + voice.cents / 100.0);
+        voice
+            .osc
+            .set_freq(self.fs, Hz::from_midi_note(voice.pitch.value()));
+        voice.env.reset(0.0);
+        voice.env.set_time(self.fs, self.attack);
+        voice.env.set_target(1.0);
+        voice.key = key;
+        voice.velocity = velocity;
+        voice.held = true;
+        voice.started = self.notes;
+    }
+
+    /// Renders a block into `out`, applying each event at its offset. The
+    /// events must be sorted by the offset (see `sort_events()`); the ones
+    /// at or beyond the end of the block are applied after it.
+    pub fn render_block(&mut self, out: &mut [[f64; 2]], events: &[TimedEvent]) {
+        debug_assert!(events.windows(2).all(|w| w[0].offset <= w[1].offset));
+        out.fill([0.0; 2]);
+
+        let mut pos = 0;
+        for e in events {
+            let offset = e.offset.min(out.len());
+            self.render(&mut out[pos..offset]);
+            pos = offset;
+            self.handle(e.event);
+        }
+        self.render(&mut out[pos..]);
+    }
+
+    fn render(&mut self, out: &mut [[f64; 2]]) {
+        for voice in self.voices.iter_mut().filter(|v| v.is_active()) {
+            let [gain_l, gain_r] = voice.gains;
+            for frame in out.iter_mut() {
+                if voice.pitch.is_gliding() {
+                    let freq = Hz::from_midi_note(voice.pitch.next_value());
+                    voice.osc.set_freq(self.fs, freq);
+                }
+                let y = voice.velocity * voice.env.next_value() * voice.osc.next_sample();
+                frame[0] += gain_l * y;
+                frame[1] += gain_r * y;
+            }
+        }
+    }
+}
+
+// the gains of the equal-power pan law for the pan from -1.0 (left) to 1.0
+// (right), which are exactly `FRAC_1_SQRT_2` at the center and 0.0 on the
+// other side of a hard pan
+fn pan_gains(pan: f64) -> [f64; 2] {
+    [((1.0 - pan) / 2.0).sqrt(), ((1.0 + pan) / 2.0).sqrt()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    fn on(offset: usize, key: u8) -> TimedEvent {
+        TimedEvent {
+            offset,
+            event: VoiceEvent::NoteOn { key, velocity: 0.5 },
+        }
+    }
+
+    fn off(offset: usize, key: u8) -> TimedEvent {
+        TimedEvent {
+            offset,
+            event: VoiceEvent::NoteOff { key },
+        }
+    }
+
+    // renders the events (with the offsets from the start) in the blocks
+    fn render(events: &[TimedEvent], len: usize, block: usize) -> Vec<[f64; 2]> {
+        let mut synth = PolySynth::new(FS, Waveform::Saw, 4);
+        let mut out = vec![[0.0; 2]; len];
+        for (i, chunk) in out.chunks_mut(block).enumerate() {
+            let start = i * block;
+            let events: Vec<TimedEvent> = events
+                .iter()
+                .filter(|e| (start..start + chunk.len()).contains(&e.offset))
+                .map(|e| TimedEvent {
+                    offset: e.offset - start,
+                    ..*e
+                })
+                .collect();
+            synth.render_block(chunk, &events);
+        }
+        out
+    }
+
+    #[test]
+    fn events_are_sample_accurate() {
+        let events = [on(100, 60), on(777, 64), off(3000, 60), off(5001, 64)];
+        let whole = render(&events, 20000, 20000);
+        // silent before the first note, and sounding from it
+        assert!(whole[..100].iter().all(|&f| f == [0.0; 2]));
+        assert!(whole[101] != [0.0; 2]);
+        for block in [1, 64, 100, 512] {
+            assert_eq!(render(&events, 20000, block), whole, "block size {block}");
+        }
+    }
+
+    #[test]
+    fn release_frees_the_voice() {
+        let mut synth = PolySynth::new(FS, Waveform::Sine, 2).with_envelope(Ms(1.0), Ms(10.0));
+        let mut out = vec![[0.0; 2]; 960];
+        synth.render_block(&mut out, &[on(0, 60), on(0, 67), off(100, 60)]);
+        assert_eq!(synth.active_voices(), 1);
+        // stealing the oldest voice
+        synth.render_block(&mut out, &[on(0, 72), on(0, 76)]);
+        assert_eq!(synth.active_voices(), 2);
+    }
+
+    #[test]
+    fn ensemble_spreads_the_notes_reproducibly() {
+        let render = |seed| {
+            let mut synth = PolySynth::new(FS, Waveform::Saw, 2).with_ensemble(10.0, 0.8, seed);
+            let mut out = vec![[0.0; 2]; 4800];
+            synth.render_block(&mut out, &[on(0, 60), on(0, 60)]);
+            let [a, b] = [&synth.voices[0], &synth.voices[1]];
+            assert!(a.cents != b.cents && a.cents.abs() <= 10.0 && b.cents.abs() <= 10.0);
+            assert!(a.gains != b.gains);
+            for gains in [a.gains, b.gains] {
+                assert!((gains[0].powi(2) + gains[1].powi(2) - 1.0).abs() < 1e-12);
+            }
+            out
+        };
+        assert_eq!(render(1), render(1));
+        assert_ne!(render(1), render(2));
+
+        // centered and in tune by default
+        let mut synth = PolySynth::new(FS, Waveform::Saw, 2);
+        let mut out = vec![[0.0; 2]; 4800];
+        synth.render_block(&mut out, &[on(0, 60), on(0, 64)]);
+        assert!(synth.voices.iter().all(|v| v.cents == 0.0));
+        assert!(out.iter().all(|&[l, r]| l == r));
+    }
+
+    #[test]
+    fn unison_plays_a_note_by_many_voices() {
+        let mut synth = PolySynth::new(FS, Waveform::Saw, 4).with_unison(3, 12.0);
+        let mut out = vec![[0.0; 2]; 4800];
+        synth.render_block(&mut out, &[on(0, 60)]);
+        assert_eq!(synth.active_voices(), 3);
+        let cents: Vec<f64> = synth.voices[..3].iter().map(|v| v.cents).collect();
+        assert_eq!(cents, [-12.0, 0.0, 12.0]);
+        let gains: Vec<[f64; 2]> = synth.voices[..3].iter().map(|v| v.gains).collect();
+        assert_eq!(gains, [[1.0, 0.0], pan_gains(0.0), [0.0, 1.0]]);
+
+        // and they are released together
+        synth.render_block(&mut out[..480], &[off(0, 60)]);
+        assert_eq!(synth.active_voices(), 3);
+        synth.render_block(&mut vec![[0.0; 2]; 48000], &[]);
+        assert_eq!(synth.active_voices(), 0);
+    }
+
+    #[test]
+    fn mono_glides_between_the_held_keys() {
+        let mut synth = PolySynth::new(FS, Waveform::Saw, 2).with_mono(Ms(50.0));
+        let mut out = vec![[0.0; 2]; 4800];
+        synth.render_block(&mut out, &[on(0, 60)]);
+        assert_eq!(synth.voices[0].env.value(), 1.0);
+
+        // gliding up on the same voice, with the envelope held
+        synth.render_block(&mut out[..480], &[on(0, 67)]);
+        assert_eq!(synth.active_voices(), 1);
+        let pitch = synth.voices[0].pitch.value();
+        assert!(pitch > 60.0 && pitch < 67.0, "{pitch}");
+        assert_eq!(synth.voices[0].env.value(), 1.0);
+        synth.render_block(&mut out, &[]);
+        assert_eq!(synth.voices[0].pitch.value(), 67.0);
+
+        // releasing the top key returns to the one held
+        synth.render_block(&mut out, &[off(0, 67)]);
+        assert_eq!(synth.voices[0].pitch.value(), 60.0);
+        assert_eq!(synth.voices[0].env.value(), 1.0);
+
+        // releasing a key not sounding only forgets it
+        synth.render_block(&mut out, &[on(0, 64), off(1, 60)]);
+        assert_eq!(synth.voices[0].pitch.value(), 64.0);
+        assert_eq!(synth.voices[0].env.target(), 1.0);
+        synth.handle(VoiceEvent::NoteOff { key: 64 });
+        assert_eq!(synth.voices[0].env.target(), 0.0);
+        assert_eq!(synth.active_voices(), 1);
+    }
+}
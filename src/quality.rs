@@ -0,0 +1,46 @@
+//! The global trade-off between the quality and the CPU load. The expensive
+//! components read the global quality when they are constructed, and each of
+//! them can be overridden by its `set_quality()`.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Quality {
+    /// For old machines; audibly worse, but much cheaper.
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl std::str::FromStr for Quality {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Self::Low),
+            "normal" => Ok(Self::Normal),
+            "high" => Ok(Self::High),
+            _ => Err(anyhow::anyhow!(
+                "invalid quality: {s} (low, normal, or high)"
+            )),
+        }
+    }
+}
+
+static GLOBAL: AtomicU8 = AtomicU8::new(Quality::Normal as u8);
+
+/// The quality the components use by default.
+pub fn global() -> Quality {
+    match GLOBAL.load(Ordering::Relaxed) {
+        0 => Quality::Low,
+        2 => Quality::High,
+        _ => Quality::Normal,
+    }
+}
+
+/// Sets the global quality. This affects only the components constructed
+/// after this.
+pub fn set_global(quality: Quality) {
+    GLOBAL.store(quality as u8, Ordering::Relaxed);
+}
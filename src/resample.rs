@@ -0,0 +1,244 @@
+use crate::filter::windowed_sinc_low_pass;
+use crate::quality::Quality;
+use crate::stereo::MonoEffect;
+
+// The cutoff frequency of the anti-imaging / anti-aliasing filter, relative to
+// the sampling rate of the lower rate.
+const CUTOFF: f64 = 0.45;
+
+/// The last `len` samples, kept contiguous by writing every sample twice.
+struct History {
+    buf: Vec<f64>,
+    pos: usize,
+}
+
+impl History {
+    fn new(len: usize) -> Self {
+        Self {
+            buf: vec![0.0; len * 2],
+            pos: 0,
+        }
+    }
+
+    fn push(&mut self, x: f64) {
+        let len = self.buf.len() / 2;
+        self.buf[self.pos] = x;
+        self.buf[self.pos + len] = x;
+        self.pos = (self.pos + 1) % len;
+    }
+
+    /// Returns the dot product of the history (from the newest to the oldest)
+    /// and the coefficients.
+    fn dot(&self, coefs: &[f64]) -> f64 {
+        let len = self.buf.len() / 2;
+        self.buf[self.pos..self.pos + len]
+            .iter()
+            .rev()
+            .zip(coefs)
+            .map(|(x, h)| x * h)
+            .sum()
+    }
+}
+
+/// The prototype low-pass filter for the conversion by `factor`. The length
+/// is `factor * taps_per_phase + 1`, so the delay of the filter is an integer
+/// number of frames both at the higher rate and at the lower rate (if
+/// `taps_per_phase` is even).
+fn prototype(factor: usize, taps_per_phase: usize) -> Vec<f64> {
+    windowed_sinc_low_pass(factor as f64, CUTOFF, factor * taps_per_phase + 1)
+}
+
+/// Upsamples by an integer factor with a polyphase FIR filter. Instead of
+/// filtering the zero-stuffed signal, each output sample is computed by one of
+/// the `factor` sub-filters, skipping the multiplications by zero.
+pub struct Upsampler {
+    factor: usize,
+    phases: Vec<Vec<f64>>,
+    history: History,
+}
+
+impl Upsampler {
+    pub fn new(factor: usize, taps_per_phase: usize) -> Self {
+        let h = prototype(factor, taps_per_phase);
+        let phases = (0..factor)
+            .map(|p| {
+                // multiply by the factor to compensate the energy lost by zero-stuffing
+                h.iter()
+                    .skip(p)
+                    .step_by(factor)
+                    .map(|x| x * factor as f64)
+                    .collect()
+            })
+            .collect();
+
+        Self {
+            factor,
+            phases,
+            history: History::new(taps_per_phase + 1),
+        }
+    }
+
+    /// Writes `factor` samples to `out`.
+    pub fn process(&mut self, x: f64, out: &mut [f64]) {
+        assert_eq!(out.len(), self.factor);
+
+        self.history.push(x);
+        for (y, phase) in out.iter_mut().zip(&self.phases) {
+            *y = self.history.dot(phase);
+        }
+    }
+}
+
+/// Downsamples by an integer factor with a FIR filter, computing only the
+/// output samples that are not discarded.
+pub struct Downsampler {
+    factor: usize,
+    coefs: Vec<f64>,
+    history: History,
+}
+
+impl Downsampler {
+    pub fn new(factor: usize, taps_per_phase: usize) -> Self {
+        let coefs = prototype(factor, taps_per_phase);
+        let history = History::new(coefs.len());
+        Self {
+            factor,
+            coefs,
+            history,
+        }
+    }
+
+    /// Consumes `factor` samples and returns one sample.
+    pub fn process(&mut self, input: &[f64]) -> f64 {
+        assert_eq!(input.len(), self.factor);
+
+        self.history.push(input[0]);
+        let out = self.history.dot(&self.coefs);
+        for &x in &input[1..] {
+            self.history.push(x);
+        }
+
+        out
+    }
+}
+
+pub fn upsample(input: &[f64], factor: usize, taps_per_phase: usize) -> Vec<f64> {
+    let mut upsampler = Upsampler::new(factor, taps_per_phase);
+    let mut out = vec![0.0; input.len() * factor];
+    for (&x, y) in input.iter().zip(out.chunks_mut(factor)) {
+        upsampler.process(x, y);
+    }
+    out
+}
+
+/// The length of the input should be a multiple of the factor; the remainder
+/// is discarded.
+pub fn downsample(input: &[f64], factor: usize, taps_per_phase: usize) -> Vec<f64> {
+    let mut downsampler = Downsampler::new(factor, taps_per_phase);
+    input
+        .chunks_exact(factor)
+        .map(|x| downsampler.process(x))
+        .collect()
+}
+
+/// Runs an effect at `factor` times the sampling rate, e.g. to reduce the
+/// aliasing of nonlinear processing.
+pub struct Oversample<E: MonoEffect> {
+    effect: E,
+    upsampler: Upsampler,
+    downsampler: Downsampler,
+    buf: Vec<f64>,
+    factor: usize,
+    // the taps per phase given to new(), and the ones actually used
+    nominal_taps_per_phase: usize,
+    taps_per_phase: usize,
+}
+
+impl<E: MonoEffect> Oversample<E> {
+    /// `effect` should be configured for the higher sampling rate. The
+    /// filters have `taps_per_phase` taps per phase as given, regardless of
+    /// the global quality; this is the length for the normal quality when
+    /// `set_quality()` is called.
+    pub fn new(effect: E, factor: usize, taps_per_phase: usize) -> Self {
+        Self {
+            effect,
+            upsampler: Upsampler::new(factor, taps_per_phase),
+            downsampler: Downsampler::new(factor, taps_per_phase),
+            buf: vec![0.0; factor],
+            factor,
+            nominal_taps_per_phase: taps_per_phase,
+            taps_per_phase,
+        }
+    }
+
+    /// The length of the filters: half of `taps_per_phase` for the low
+    /// quality, and double for the high quality. This changes the latency
+    /// and resets the filters.
+    pub fn set_quality(&mut self, quality: Quality) {
+        let taps_per_phase = match quality {
+            Quality::Low => (self.nominal_taps_per_phase / 2).max(2),
+            Quality::Normal => self.nominal_taps_per_phase,
+            Quality::High => self.nominal_taps_per_phase * 2,
+        };
+        if taps_per_phase != self.taps_per_phase {
+            self.upsampler = Upsampler::new(self.factor, taps_per_phase);
+            self.downsampler = Downsampler::new(self.factor, taps_per_phase);
+            self.taps_per_phase = taps_per_phase;
+        }
+    }
+}
+
+impl<E: MonoEffect> MonoEffect for Oversample<E> {
+    fn process(&mut self, x: f64) -> f64 {
+        self.upsampler.process(x, &mut self.buf);
+        for y in self.buf.iter_mut() {
+            *y = self.effect.process(*y);
+        }
+        self.downsampler.process(&self.buf)
+    }
+
+    fn latency(&self) -> usize {
+        // each filter delays factor * taps_per_phase / 2 frames at the higher rate
+        self.taps_per_phase
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upsample_then_downsample_reproduces_input() {
+        let taps_per_phase = 16;
+        // within the passband of the filters (below 0.45 fs)
+        let input: Vec<f64> = (0..2000)
+            .map(|n| {
+                let t = n as f64;
+                (0.05 * t).sin() + 0.5 * (0.3 * t + 1.0).sin() + 0.25 * (0.8 * t).cos()
+            })
+            .collect();
+
+        for factor in [2, 4] {
+            let up = upsample(&input, factor, taps_per_phase);
+            let output = downsample(&up, factor, taps_per_phase);
+            // each filter delays taps_per_phase / 2 frames at the lower rate;
+            // skip the start, where the filters see the input start abruptly
+            let delayed = output[taps_per_phase..].iter().zip(&input);
+            for (y, x) in delayed.skip(taps_per_phase * 2) {
+                assert!((y - x).abs() < 1e-3, "{factor}x: {y} != {x}");
+            }
+        }
+    }
+
+    #[test]
+    fn oversample_uses_the_given_taps_until_set_quality() {
+        let mut oversample = Oversample::new(|x: f64| x, 4, 16);
+        assert_eq!(oversample.latency(), 16);
+        oversample.set_quality(Quality::Low);
+        assert_eq!(oversample.latency(), 8);
+        oversample.set_quality(Quality::High);
+        assert_eq!(oversample.latency(), 32);
+        oversample.set_quality(Quality::Normal);
+        assert_eq!(oversample.latency(), 16);
+    }
+}
@@ -0,0 +1,1283 @@
+// This code is derived from these examples on RustAudio:
+//
+// - https://github.com/RustAudio/dasp/blob/master/examples/synth.rs
+// - https://github.com/RustAudio/cpal/blob/master/examples/record_wav.rs
+
+use crate::fade::{nearest_zero_crossing, FadeCurve};
+use crate::params::AtomicF64;
+use crate::sanitize::{take_non_finite, NonFinite, Sanitize};
+use crate::tail::{take_with_tail, HasTail};
+use crate::units::{Frames, Ms};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use dasp::{Frame, Sample, Signal};
+use std::f32::consts::FRAC_1_SQRT_2;
+use std::io::{BufWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+
+/// Plays the frames on the default output device until they end. `build`
+/// receives the config of the stream to construct the frames.
+///
+/// If the frames panic while playing, the stream outputs silence from then
+/// on, and the panic is returned as an error after the stream stops.
+///
+/// This is `Player::from_args()?.play(build)`, so the options on the command
+/// line (e.g. `--require-rate 48000`) apply.
+pub fn play<F, I>(build: F) -> Result<(), anyhow::Error>
+where
+    F: FnOnce(&cpal::StreamConfig) -> I,
+    I: Iterator<Item = f64> + Send + 'static,
+{
+    Player::from_args()?.play(build)
+}
+
+/// `play()` for stereo frames. See `Player::play_frames()` for how they are
+/// mapped to the channels of the device.
+pub fn play_stereo<F, I>(build: F) -> Result<(), anyhow::Error>
+where
+    F: FnOnce(&cpal::StreamConfig) -> I,
+    I: Iterator<Item = [f64; 2]> + Send + 'static,
+{
+    Player::from_args()?.play_stereo(build)
+}
+
+// the options of the command line, which take a value
+const REQUIRE_RATE: &str = "--require-rate";
+const HOST: &str = "--host";
+const QUALITY: &str = "--quality";
+const LAYOUT: &str = "--layout";
+const DUMP_CONTROLS: &str = "--dump-controls";
+const OPTIONS: [&str; 5] = [REQUIRE_RATE, HOST, QUALITY, LAYOUT, DUMP_CONTROLS];
+
+/// The speaker layout the examples render for (the ones that support it),
+/// selected by `--layout`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    #[default]
+    Stereo,
+    /// Front left, front right, rear left, and rear right
+    Quad,
+}
+
+impl Layout {
+    pub fn channels(self) -> usize {
+        match self {
+            Self::Stereo => 2,
+            Self::Quad => 4,
+        }
+    }
+}
+
+impl std::str::FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stereo" => Ok(Self::Stereo),
+            "quad" => Ok(Self::Quad),
+            _ => Err(anyhow::anyhow!("invalid layout: {s} (stereo or quad)")),
+        }
+    }
+}
+
+/// The options of the playback.
+pub struct Player {
+    clip_warning: bool,
+    required_rate: Option<u32>,
+    host: Option<String>,
+    layout: Layout,
+    dump_controls: Option<PathBuf>,
+    sanitize: bool,
+}
+
+impl Player {
+    pub fn new() -> Self {
+        Self {
+            clip_warning: true,
+            required_rate: None,
+            host: None,
+            layout: Layout::Stereo,
+            dump_controls: None,
+            sanitize: cfg!(debug_assertions),
+        }
+    }
+
+    /// Reads the options from the command line:
+    ///
+    /// - `--require-rate <Hz>`: see `with_required_rate()`
+    /// - `--host <name>`: see `with_host()`
+    /// - `--quality <low|normal|high>`: sets `quality::set_global()`
+    /// - `--layout <stereo|quad>`: see `with_layout()`
+    /// - `--dump-controls <path>`: see `with_dump_controls()`
+    ///
+    /// Use `positional_args()` for the other arguments of the example.
+    pub fn from_args() -> Result<Self, anyhow::Error> {
+        let mut player = Self::new();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            if !OPTIONS.contains(&arg.as_str()) {
+                continue;
+            }
+            let value = args
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("{arg} needs a value"))?;
+
+            if arg == REQUIRE_RATE {
+                let rate = value.parse().map_err(|_| {
+                    anyhow::anyhow!("invalid sample rate for {REQUIRE_RATE}: {value}")
+                })?;
+                player = player.with_required_rate(rate);
+            } else if arg == HOST {
+                player = player.with_host(&value);
+            } else if arg == QUALITY {
+                crate::quality::set_global(value.parse()?);
+            } else if arg == LAYOUT {
+                player = player.with_layout(value.parse()?);
+            } else if arg == DUMP_CONTROLS {
+                player = player.with_dump_controls(value);
+            }
+        }
+        Ok(player)
+    }
+
+    /// Plays on the host (the audio API) of the name, e.g. "jack" or "alsa"
+    /// (case-insensitive), and errors if it's not available or has no output
+    /// device (e.g. the JACK server is not running). The hosts other
+    /// than the default one need the corresponding feature of cpal, e.g.
+    /// `--features jack`, or `--features asio` on Windows (which needs the
+    /// ASIO SDK at build time; see the README of cpal).
+    ///
+    /// By default, JACK is used if the `jack` feature is enabled and the
+    /// server is running, and the default host of cpal otherwise.
+    pub fn with_host(mut self, name: &str) -> Self {
+        self.host = Some(name.to_string());
+        self
+    }
+
+    /// Plays at exactly this sample rate, e.g. when the signal chain assumes
+    /// 48 kHz. If the default config of the device has another rate, the
+    /// supported configs are searched by `find_config()`, and it is an error
+    /// if none supports the rate. By default, the device's default is used as
+    /// it is.
+    pub fn with_required_rate(mut self, rate: u32) -> Self {
+        self.required_rate = Some(rate);
+        self
+    }
+
+    /// Whether to replace the non-finite samples (NaN and infinity) of the
+    /// output with 0.0. The first one found in the output or by a `Sanitize`
+    /// of the chain is reported when the stream stops. The default is true
+    /// in the debug builds and false in the release builds.
+    pub fn with_sanitize(mut self, sanitize: bool) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    /// Whether to print a warning when the stream stops if any sample
+    /// exceeded ±1.0 (and got clipped). The default is true.
+    pub fn with_clip_warning(mut self, clip_warning: bool) -> Self {
+        self.clip_warning = clip_warning;
+        self
+    }
+
+    /// See `play()`.
+    pub fn play<F, I>(&self, build: F) -> Result<(), anyhow::Error>
+    where
+        F: FnOnce(&cpal::StreamConfig) -> I,
+        I: Iterator<Item = f64> + Send + 'static,
+    {
+        self.play_interleaved(build, 1)
+    }
+
+    /// See `play_stereo()`.
+    pub fn play_stereo<F, I>(&self, build: F) -> Result<(), anyhow::Error>
+    where
+        F: FnOnce(&cpal::StreamConfig) -> I,
+        I: Iterator<Item = [f64; 2]> + Send + 'static,
+    {
+        self.play_frames(build)
+    }
+
+    /// The layout the example should render for; this doesn't change how
+    /// the frames are played. The default is stereo.
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    /// The CSV file the example should write the control signals to by
+    /// `ControlDump`, rendering offline instead of playing. The examples
+    /// that support it check `dump_controls()` before playing; playing with
+    /// this set is an error, so that the option isn't silently ignored by
+    /// the others.
+    pub fn with_dump_controls<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.dump_controls = Some(path.into());
+        self
+    }
+
+    pub fn dump_controls(&self) -> Option<&Path> {
+        self.dump_controls.as_deref()
+    }
+
+    /// `play()` for frames of `N` channels. If the device has as many
+    /// channels or more, the frame goes to the first `N` channels; a stereo
+    /// frame repeats over the rest, and the rest are silent for more
+    /// channels. If the device has fewer channels, the frame is downmixed:
+    /// averaged for a mono device, and for a stereo device, the channels
+    /// beyond the first two are added at -3 dB to the first two
+    /// alternately, i.e. `L = FL + RL / sqrt(2)` and `R = FR + RR / sqrt(2)`
+    /// for quad.
+    pub fn play_frames<F, I, const N: usize>(&self, build: F) -> Result<(), anyhow::Error>
+    where
+        F: FnOnce(&cpal::StreamConfig) -> I,
+        I: Iterator<Item = [f64; N]> + Send + 'static,
+    {
+        self.play_interleaved(|config| build(config).flatten(), N)
+    }
+
+    // `build` returns the frames of `input_channels` channels interleaved
+    fn play_interleaved<F, I>(&self, build: F, input_channels: usize) -> Result<(), anyhow::Error>
+    where
+        F: FnOnce(&cpal::StreamConfig) -> I,
+        I: Iterator<Item = f64> + Send + 'static,
+    {
+        if self.dump_controls.is_some() {
+            return Err(anyhow::anyhow!(
+                "{DUMP_CONTROLS} is not supported by this example"
+            ));
+        }
+        let host = select_host(self.host.as_deref())?;
+        let device = host
+            .default_output_device()
+            .ok_or_else(|| anyhow::anyhow!("no output device is available"))?;
+        let default_config = device.default_output_config()?;
+
+        let config = match self.required_rate {
+            Some(rate) if default_config.sample_rate().0 != rate => {
+                let supported: Vec<_> = device.supported_output_configs()?.collect();
+                find_config(&supported, rate, default_config.channels()).ok_or_else(|| {
+                    anyhow::anyhow!("the output device doesn't support the sample rate {rate} Hz")
+                })?
+            }
+            _ => default_config,
+        };
+
+        println!("output:");
+        println!("  host: {}", host.id().name());
+        println!("  device: {}", device.name()?);
+        println!("  sample rate: {} Hz", config.sample_rate().0);
+        println!("  channels: {}", config.channels());
+        println!("  sample format: {:?}", config.sample_format());
+        match config.buffer_size() {
+            cpal::SupportedBufferSize::Range { min, max } => {
+                println!("  buffer size: {min} to {max} frames")
+            }
+            cpal::SupportedBufferSize::Unknown => println!("  buffer size: unknown"),
+        }
+
+        match config.sample_format() {
+            cpal::SampleFormat::F32 => {
+                self.run::<f32, F, I>(&device, &config.into(), build, input_channels)
+            }
+            cpal::SampleFormat::I16 => {
+                self.run::<i16, F, I>(&device, &config.into(), build, input_channels)
+            }
+            cpal::SampleFormat::U16 => {
+                self.run::<u16, F, I>(&device, &config.into(), build, input_channels)
+            }
+        }
+    }
+
+    fn run<T, F, I>(
+        &self,
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        build: F,
+        input_channels: usize,
+    ) -> Result<(), anyhow::Error>
+    where
+        T: cpal::Sample,
+        F: FnOnce(&cpal::StreamConfig) -> I,
+        I: Iterator<Item = f64> + Send + 'static,
+    {
+        let output = Sanitize::new(build(config), "output").with_enabled(self.sanitize);
+        let mut frames = ClipCounter::new(PanicGuard::new(output));
+        let clip_stats = frames.stats();
+        let panic_message = frames.frames.panic_message.clone();
+
+        let (complete_tx, complete_rx) = mpsc::sync_channel::<()>(1);
+
+        let mut input = vec![0.0; input_channels];
+        let mut mixed = vec![0.0; config.channels as usize];
+        let stream = device.build_output_stream(
+            config,
+            move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+                write_data(data, &mut input, &mut mixed, &complete_tx, &mut frames);
+            },
+            |err| eprintln!("{err}"),
+        )?;
+
+        stream.play()?;
+
+        complete_rx.recv().unwrap();
+        stream.pause()?;
+
+        if self.clip_warning && clip_stats.count() > 0 {
+            eprintln!(
+                "warning: {} samples exceeded ±1.0 and got clipped (peak: {:.3})",
+                clip_stats.count(),
+                clip_stats.peak()
+            );
+        }
+
+        if let Some(NonFinite { label, index }) = take_non_finite() {
+            eprintln!("warning: a non-finite sample appeared at {label} (sample {index}), replaced with 0.0");
+        }
+
+        let panic_message = panic_message.lock().unwrap().take();
+        match panic_message {
+            Some(msg) => Err(anyhow::anyhow!("the signal chain panicked: {msg}")),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The host of the name if given, or the default one (see
+/// `Player::with_host()`).
+fn select_host(name: Option<&str>) -> Result<cpal::Host, anyhow::Error> {
+    let available = cpal::available_hosts();
+
+    let Some(name) = name else {
+        #[cfg(all(
+            feature = "jack",
+            any(
+                target_os = "linux",
+                target_os = "dragonfly",
+                target_os = "freebsd",
+                target_os = "netbsd"
+            )
+        ))]
+        if let Ok(host) = cpal::host_from_id(cpal::HostId::Jack) {
+            // the host opens even if the server is not running, but then it
+            // has no device
+            if host.default_output_device().is_some() {
+                return Ok(host);
+            }
+        }
+
+        return Ok(cpal::default_host());
+    };
+
+    let id = find_host_id(&available, name).ok_or_else(|| {
+        let names: Vec<_> = available.iter().map(|id| id.name()).collect();
+        anyhow::anyhow!(
+            "the host {name} is not available (available: {}){}",
+            names.join(", "),
+            missing_host_hint(name)
+        )
+    })?;
+    let host = cpal::host_from_id(id)?;
+    if host.default_output_device().is_none() {
+        anyhow::bail!(
+            "the host {} has no output device{}",
+            id.name(),
+            missing_host_hint(name)
+        );
+    }
+    Ok(host)
+}
+
+/// What to do to make the host of the name available (or give it a device),
+/// for the error message.
+fn missing_host_hint(name: &str) -> &'static str {
+    if name.eq_ignore_ascii_case("jack") {
+        if cfg!(feature = "jack") {
+            "; is the JACK server running?"
+        } else {
+            "; build with `--features jack`"
+        }
+    } else if name.eq_ignore_ascii_case("asio") {
+        if !cfg!(target_os = "windows") {
+            "; ASIO is only available on Windows"
+        } else if cfg!(feature = "asio") {
+            "; is an ASIO driver installed?"
+        } else {
+            "; build with `--features asio` and the ASIO SDK (see the README of cpal)"
+        }
+    } else {
+        ""
+    }
+}
+
+/// Finds the host of the name (case-insensitive) among the available ones.
+pub fn find_host_id(available: &[cpal::HostId], name: &str) -> Option<cpal::HostId> {
+    available
+        .iter()
+        .copied()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+}
+
+/// The arguments on the command line except for the program name and the
+/// options of `Player::from_args()`.
+pub fn positional_args() -> Vec<String> {
+    let mut positional = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if OPTIONS.contains(&arg.as_str()) {
+            args.next();
+        } else {
+            positional.push(arg);
+        }
+    }
+    positional
+}
+
+/// A host (audio API) and its devices, as listed by `list_devices()`.
+pub struct HostInfo {
+    pub id: cpal::HostId,
+    /// The error if the host couldn't be opened or its devices couldn't be
+    /// listed (e.g. the JACK server is not running).
+    pub devices: Result<Vec<DeviceInfo>, anyhow::Error>,
+}
+
+/// A device and the stream configs it supports.
+pub struct DeviceInfo {
+    pub name: String,
+    pub is_default_input: bool,
+    pub is_default_output: bool,
+    pub input_configs: Vec<cpal::SupportedStreamConfigRange>,
+    pub output_configs: Vec<cpal::SupportedStreamConfigRange>,
+}
+
+/// Lists all the available hosts, their devices, and the supported configs,
+/// to find the names for `--host` and the rates for `--require-rate`. A
+/// device whose configs can't be queried (e.g. an output-only device for the
+/// input) just has no configs.
+pub fn list_devices() -> Vec<HostInfo> {
+    cpal::available_hosts()
+        .into_iter()
+        .map(|id| HostInfo {
+            id,
+            devices: list_host_devices(id),
+        })
+        .collect()
+}
+
+fn list_host_devices(id: cpal::HostId) -> Result<Vec<DeviceInfo>, anyhow::Error> {
+    let host = cpal::host_from_id(id)?;
+    let default_input = host.default_input_device().and_then(|d| d.name().ok());
+    let default_output = host.default_output_device().and_then(|d| d.name().ok());
+
+    let devices = host
+        .devices()?
+        .filter_map(|device| {
+            let name = device.name().ok()?;
+            Some(DeviceInfo {
+                is_default_input: default_input.as_ref() == Some(&name),
+                is_default_output: default_output.as_ref() == Some(&name),
+                input_configs: device
+                    .supported_input_configs()
+                    .map(|configs| configs.collect())
+                    .unwrap_or_default(),
+                output_configs: device
+                    .supported_output_configs()
+                    .map(|configs| configs.collect())
+                    .unwrap_or_default(),
+                name,
+            })
+        })
+        .collect();
+    Ok(devices)
+}
+
+/// Picks the config that plays at `rate` from the supported ones. Among the
+/// ones that support the rate, prefers (in this order) the one with
+/// `channels` channels, the one with the fewest channels, and the sample
+/// format of f32, i16, and u16. Returns `None` if none supports the rate.
+pub fn find_config(
+    supported: &[cpal::SupportedStreamConfigRange],
+    rate: u32,
+    channels: u16,
+) -> Option<cpal::SupportedStreamConfig> {
+    let format_rank = |format: cpal::SampleFormat| match format {
+        cpal::SampleFormat::F32 => 0,
+        cpal::SampleFormat::I16 => 1,
+        cpal::SampleFormat::U16 => 2,
+    };
+
+    supported
+        .iter()
+        .filter(|c| c.min_sample_rate().0 <= rate && rate <= c.max_sample_rate().0)
+        .min_by_key(|c| {
+            (
+                c.channels() != channels,
+                c.channels(),
+                format_rank(c.sample_format()),
+            )
+        })
+        .map(|c| c.clone().with_sample_rate(cpal::SampleRate(rate)))
+}
+
+// See `Player::play_frames()` for how the input channels are mapped to the
+// output.
+//
+// `input` and `mixed` are the buffers of a frame of the input and the output,
+// so as not to allocate on the audio thread.
+fn write_data<T>(
+    output: &mut [T],
+    input: &mut [f32],
+    mixed: &mut [f32],
+    complete_rx: &mpsc::SyncSender<()>,
+    frames: &mut dyn Iterator<Item = f64>,
+) where
+    T: cpal::Sample,
+{
+    let channels = mixed.len();
+    for frame in output.chunks_mut(channels) {
+        for sample in input.iter_mut() {
+            *sample = match frames.next() {
+                Some(sample) => sample.to_sample::<f32>(),
+                None => {
+                    complete_rx.try_send(()).ok();
+                    0.0
+                }
+            };
+        }
+
+        mixed.fill(0.0);
+        if channels == 1 {
+            mixed[0] = input.iter().sum::<f32>() / input.len() as f32;
+        } else if channels < input.len() {
+            for (i, &x) in input.iter().enumerate() {
+                let gain = if i < channels { 1.0 } else { FRAC_1_SQRT_2 };
+                mixed[i % channels] += gain * x;
+            }
+        } else if input.len() <= 2 {
+            for (i, y) in mixed.iter_mut().enumerate() {
+                *y = input[i % input.len()];
+            }
+        } else {
+            mixed[..input.len()].copy_from_slice(input);
+        }
+
+        for (sample, &y) in frame.iter_mut().zip(mixed.iter()) {
+            *sample = cpal::Sample::from::<f32>(&y);
+        }
+    }
+}
+
+/// Renders the first `frames` frames of the signal offline, i.e. as fast as
+/// possible without any output device.
+pub fn render<S: Signal>(signal: S, frames: Frames) -> Vec<S::Frame> {
+    signal.take(frames.0).collect()
+}
+
+// the bounces longer than this print the progress
+const BOUNCE_PROGRESS_MIN: Ms = Ms(10_000.0);
+
+/// Renders a signal to a buffer ahead of time (freezes it), so that a static
+/// but expensive part (e.g. a pad through a long reverb) costs nothing while
+/// playing. `nominal_frames` frames are taken, followed by the tail until
+/// silence as `take_with_tail()` does.
+///
+/// Call this in `build` of `play()`, which runs before the stream opens, so
+/// that the playback doesn't glitch at the start. The progress is printed to
+/// stderr if the signal is longer than 10 seconds.
+pub fn bounce<S>(signal: S, nominal_frames: Frames, fs: f64) -> SamplePlayer
+where
+    S: Signal<Frame = f64> + HasTail,
+{
+    let show_progress = nominal_frames.0 >= BOUNCE_PROGRESS_MIN.to_frames(fs).0;
+    let second = (fs as usize).max(1);
+
+    let mut samples = Vec::with_capacity(nominal_frames.0);
+    for x in take_with_tail(signal, nominal_frames, fs) {
+        samples.push(x);
+        if show_progress && samples.len().is_multiple_of(second) {
+            let percent = 100.0 * samples.len() as f64 / nominal_frames.0 as f64;
+            eprint!("\rbouncing: {:.0}%", percent.min(100.0));
+            if samples.len() > nominal_frames.0 {
+                eprint!(" (tail: {} s)", (samples.len() - nominal_frames.0) / second);
+            }
+        }
+    }
+    if show_progress {
+        // padded to overwrite the longest line of the progress
+        let done = format!("bouncing: done ({:.1} s)", samples.len() as f64 / fs);
+        eprintln!("\r{done:<32}");
+    }
+    SamplePlayer::new(samples)
+}
+
+// how far the loop points are moved to a zero crossing, and the length of
+// the crossfade at the seam if there's none
+const LOOP_SEARCH: Frames = Frames(256);
+
+/// How a `SamplePlayer` repeats its loop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LoopMode {
+    /// From the start to the end, over and over.
+    #[default]
+    Forward,
+    /// From the end back to the start, over and over.
+    Reverse,
+    /// Back and forth between the start and the end. The turns don't repeat
+    /// the frame at them, so the waveform is symmetric around them.
+    PingPong,
+}
+
+/// Plays a buffer once, and then silence (see `bounce()`), or repeats a loop
+/// of it forever after playing up to the loop.
+pub struct SamplePlayer {
+    samples: Vec<f64>,
+    cur_frame: usize,
+    // the start and the end (exclusive) of the loop, and the mode
+    looping: Option<(usize, usize, LoopMode)>,
+    backward: bool,
+}
+
+impl SamplePlayer {
+    pub fn new(samples: Vec<f64>) -> Self {
+        Self {
+            samples,
+            cur_frame: 0,
+            looping: None,
+            backward: false,
+        }
+    }
+
+    /// Loops between `start` and `end` (exclusive), moving each to the
+    /// nearest positive-going zero crossing within 256 frames so that the
+    /// seam doesn't click (see `loop_points()` for where they end up). If
+    /// there's none, the seam is crossfaded over 256 frames instead, which
+    /// changes the samples near the loop point: the end fades into the frames
+    /// before the start, or if there are too few of them, into the frames
+    /// from the start, and then the loop starts after them. A loop shorter
+    /// than 2 frames is ignored.
+    pub fn with_loop(mut self, start: Frames, end: Frames, mode: LoopMode) -> Self {
+        let end = end.0.min(self.samples.len());
+        if end < start.0 + 2 {
+            return self;
+        }
+        let search = LOOP_SEARCH.0;
+        let aligned = nearest_zero_crossing(&self.samples, start.0, search)
+            .zip(nearest_zero_crossing(&self.samples, end, search))
+            .filter(|&(s, e)| e >= s + 2);
+        let (start, end) = match aligned {
+            Some(points) => points,
+            // the turns of a ping-pong loop don't jump anyway
+            None if mode == LoopMode::PingPong => (start.0, end),
+            None => (self.crossfade_seam(start.0, end), end),
+        };
+        self.looping = Some((start, end, mode));
+        self
+    }
+
+    /// The start and the end (exclusive) of the loop, as adjusted.
+    pub fn loop_points(&self) -> Option<(Frames, Frames)> {
+        self.looping
+            .map(|(start, end, _)| (Frames(start), Frames(end)))
+    }
+
+    pub fn samples(&self) -> &[f64] {
+        &self.samples
+    }
+
+    // fades the frames before the end into the ones before the start, which
+    // is what leads into the start forward, and into the end backward (the
+    // first pass plays through the end region continuously, as it fades from
+    // the original frames), and returns the start of the loop
+    //
+    // Near the start of the buffer, there are too few frames before the start,
+    // so this fades into the frames from the start instead, and moves the
+    // start past them, which is what they lead into (as `fade::Loop` does).
+    fn crossfade_seam(&mut self, start: usize, end: usize) -> usize {
+        let curve = FadeCurve::EqualPower;
+        let len = end - start;
+        let (n, from, start) = if start >= LOOP_SEARCH.0.min(len) {
+            let n = LOOP_SEARCH.0.min(len);
+            (n, start - n, start)
+        } else {
+            // leaving 2 frames of the loop at least
+            let n = LOOP_SEARCH.0.min(len / 2).min(len - 2);
+            (n, start, start + n)
+        };
+        for i in 0..n {
+            let t = (i as f64 + 0.5) / n as f64;
+            let (y, x) = (self.samples[end - n + i], self.samples[from + i]);
+            self.samples[end - n + i] = y * curve.gain(1.0 - t) + x * curve.gain(t);
+        }
+        start
+    }
+}
+
+impl Signal for SamplePlayer {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.samples.get(self.cur_frame).copied().unwrap_or(0.0);
+        let Some((start, end, mode)) = self.looping else {
+            self.cur_frame = (self.cur_frame + 1).min(self.samples.len());
+            return x;
+        };
+
+        if !self.backward {
+            self.cur_frame += 1;
+            if self.cur_frame == end {
+                self.cur_frame = match mode {
+                    LoopMode::Forward => start,
+                    LoopMode::Reverse | LoopMode::PingPong => {
+                        self.backward = true;
+                        end - 2
+                    }
+                };
+            }
+        } else if self.cur_frame == start {
+            self.cur_frame = match mode {
+                LoopMode::Reverse => end - 1,
+                LoopMode::Forward | LoopMode::PingPong => {
+                    self.backward = false;
+                    start + 1
+                }
+            };
+        } else {
+            self.cur_frame -= 1;
+        }
+        x
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.looping.is_none() && self.cur_frame >= self.samples.len()
+    }
+}
+
+// the tail is already in the buffer
+impl HasTail for SamplePlayer {
+    fn has_tail(&self) -> bool {
+        false
+    }
+}
+
+/// Renders the first `frames` frames of the signal offline and writes them to
+/// a CSV file for inspecting in external tools (e.g. a spreadsheet): one frame
+/// per line, and the channels separated by commas. There is no header.
+pub fn dump_csv<S, P>(signal: S, frames: Frames, path: P) -> Result<(), anyhow::Error>
+where
+    S: Signal,
+    S::Frame: Frame<Sample = f64>,
+    P: AsRef<Path>,
+{
+    let mut w = BufWriter::new(std::fs::File::create(path)?);
+    for frame in render(signal, frames) {
+        for (i, x) in frame.channels().enumerate() {
+            if i > 0 {
+                write!(w, ",")?;
+            }
+            write!(w, "{x}")?;
+        }
+        writeln!(w)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+/// The interval of the rows of `ControlDump`.
+pub const CONTROL_BLOCK: Frames = Frames(64);
+
+/// Writes the values of the control signals (e.g. the level of an envelope,
+/// an LFO, or a cutoff, stored by `params::Probe` or by the patch itself) to
+/// a CSV file while the signal renders, for plotting them in external tools.
+/// A row is written before the first frame of every block of
+/// `CONTROL_BLOCK` frames: the time in seconds, and then the values in the
+/// order of `with_control()` as they are at that time, i.e. as the previous
+/// frame left them (so the first row has the initial values, e.g. 0.0 of an
+/// envelope before its attack). The first line is the header naming the
+/// columns.
+///
+/// The rows are written as the signal goes rather than buffered, so this
+/// is meant for the offline rendering, not for the audio thread. Call
+/// `finish()` at the end to flush the file and to see the errors.
+pub struct ControlDump<S: Signal> {
+    signal: S,
+    fs: f64,
+    controls: Vec<(String, Arc<AtomicF64>)>,
+    writer: BufWriter<std::fs::File>,
+    cur_frame: usize,
+    // the first error on writing, after which nothing is written
+    error: Option<std::io::Error>,
+}
+
+impl<S: Signal> ControlDump<S> {
+    /// Creates the file, which is empty until the first frame.
+    pub fn create<P: AsRef<Path>>(signal: S, fs: f64, path: P) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            signal,
+            fs,
+            controls: vec![],
+            writer: BufWriter::new(std::fs::File::create(path)?),
+            cur_frame: 0,
+            error: None,
+        })
+    }
+
+    /// Adds a column of the control.
+    pub fn with_control(mut self, name: &str, value: Arc<AtomicF64>) -> Self {
+        self.controls.push((name.to_string(), value));
+        self
+    }
+
+    /// Flushes the file, and returns the first error on writing, if any.
+    pub fn finish(mut self) -> Result<(), anyhow::Error> {
+        if let Some(e) = self.error.take() {
+            return Err(e.into());
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        write!(self.writer, "time")?;
+        for (name, _) in &self.controls {
+            write!(self.writer, ",{name}")?;
+        }
+        writeln!(self.writer)
+    }
+
+    fn write_row(&mut self) -> std::io::Result<()> {
+        write!(self.writer, "{}", self.cur_frame as f64 / self.fs)?;
+        for (_, value) in &self.controls {
+            write!(self.writer, ",{}", value.get())?;
+        }
+        writeln!(self.writer)
+    }
+}
+
+impl<S: Signal> Signal for ControlDump<S> {
+    type Frame = S::Frame;
+
+    fn next(&mut self) -> Self::Frame {
+        // before the controls are updated by rendering the frame
+        if self.error.is_none() && self.cur_frame.is_multiple_of(CONTROL_BLOCK.0) {
+            let header = if self.cur_frame == 0 {
+                self.write_header()
+            } else {
+                Ok(())
+            };
+            if let Err(e) = header.and_then(|_| self.write_row()) {
+                self.error = Some(e);
+            }
+        }
+        self.cur_frame += 1;
+        self.signal.next()
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.signal.is_exhausted()
+    }
+}
+
+/// Ends the frames when they panic, instead of letting the panic unwind into
+/// the audio callback (the behavior of which depends on the backend). Once
+/// panicked, this never calls the inner frames again.
+pub struct PanicGuard<I: Iterator<Item = f64>> {
+    frames: I,
+    dead: bool,
+    panic_message: Arc<Mutex<Option<String>>>,
+}
+
+impl<I: Iterator<Item = f64>> PanicGuard<I> {
+    pub fn new(frames: I) -> Self {
+        Self {
+            frames,
+            dead: false,
+            panic_message: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// The message of the panic, if any.
+    pub fn panic_message(&self) -> Option<String> {
+        self.panic_message.lock().unwrap().clone()
+    }
+}
+
+impl<I: Iterator<Item = f64>> Iterator for PanicGuard<I> {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.dead {
+            return None;
+        }
+
+        match panic::catch_unwind(AssertUnwindSafe(|| self.frames.next())) {
+            Ok(frame) => frame,
+            Err(e) => {
+                self.dead = true;
+
+                let msg = if let Some(s) = e.downcast_ref::<&str>() {
+                    s.to_string()
+                } else if let Some(s) = e.downcast_ref::<String>() {
+                    s.clone()
+                } else {
+                    "unknown panic".to_string()
+                };
+                *self.panic_message.lock().unwrap() = Some(msg);
+
+                None
+            }
+        }
+    }
+}
+
+/// The number and the peak of the samples that exceeded ±1.0, shared with
+/// `ClipCounter` on the audio thread.
+#[derive(Default)]
+pub struct ClipStats {
+    count: AtomicUsize,
+    // the bits of the f64
+    peak: AtomicU64,
+}
+
+impl ClipStats {
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// The largest absolute value among the clipped samples, or 0.0 if none.
+    pub fn peak(&self) -> f64 {
+        f64::from_bits(self.peak.load(Ordering::Relaxed))
+    }
+}
+
+/// Counts the samples that exceed ±1.0, which get clipped when converted to
+/// the output format. This only reports; the samples are passed as they are.
+pub struct ClipCounter<I: Iterator<Item = f64>> {
+    frames: I,
+    stats: Arc<ClipStats>,
+}
+
+impl<I: Iterator<Item = f64>> ClipCounter<I> {
+    pub fn new(frames: I) -> Self {
+        Self {
+            frames,
+            stats: Arc::new(ClipStats::default()),
+        }
+    }
+
+    pub fn stats(&self) -> Arc<ClipStats> {
+        self.stats.clone()
+    }
+}
+
+impl<I: Iterator<Item = f64>> Iterator for ClipCounter<I> {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let frame = self.frames.next()?;
+
+        let abs = frame.abs();
+        if abs > 1.0 {
+            self.stats.count.fetch_add(1, Ordering::Relaxed);
+            // only this iterator writes the peak, so no compare-exchange is needed
+            if abs > self.stats.peak() {
+                self.stats.peak.store(abs.to_bits(), Ordering::Relaxed);
+            }
+        }
+
+        Some(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panic_outputs_silence_and_reports() {
+        let mut n = 0;
+        let source = std::iter::from_fn(move || {
+            n += 1;
+            assert!(n <= 100, "bad parameter");
+            Some(0.5)
+        });
+        let mut frames = PanicGuard::new(source);
+        let panic_message = frames.panic_message.clone();
+
+        // the default panic hook stays, as it's global to the tests running
+        // in parallel; its message goes to the captured output
+        let (complete_tx, complete_rx) = mpsc::sync_channel::<()>(1);
+        let mut output = vec![1.0_f32; 400];
+        write_data(
+            &mut output,
+            &mut [0.0; 2],
+            &mut [0.0; 2],
+            &complete_tx,
+            &mut frames,
+        );
+
+        assert!(output[..100].iter().all(|&x| x == 0.5));
+        assert!(output[100..].iter().all(|&x| x == 0.0));
+        assert!(complete_rx.try_recv().is_ok());
+        assert_eq!(
+            panic_message.lock().unwrap().as_deref(),
+            Some("bad parameter")
+        );
+    }
+
+    #[test]
+    fn clip_counter_counts_only_beyond_full_scale() {
+        let clipping = ClipCounter::new([0.5, 1.25, -1.5, 1.0, -0.2].into_iter());
+        let stats = clipping.stats();
+        assert_eq!(clipping.collect::<Vec<_>>(), [0.5, 1.25, -1.5, 1.0, -0.2]);
+        assert_eq!(stats.count(), 2);
+        assert_eq!(stats.peak(), 1.5);
+
+        let within = ClipCounter::new([0.5, -1.0, 1.0, 0.99].into_iter());
+        let stats = within.stats();
+        within.for_each(drop);
+        assert_eq!(stats.count(), 0);
+        assert_eq!(stats.peak(), 0.0);
+    }
+
+    #[test]
+    fn dump_csv_writes_a_row_per_frame() {
+        let path = std::env::temp_dir().join(format!("dump-{}.csv", std::process::id()));
+        let stereo = dasp::signal::gen(|| [0.25, -0.5]);
+        dump_csv(stereo, Frames(1000), &path).unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(csv.lines().count(), 1000);
+        assert!(csv.lines().all(|row| row == "0.25,-0.5"));
+    }
+
+    #[test]
+    fn control_dump_writes_a_row_per_block() {
+        use crate::envelope::Env;
+        use crate::params::Probe;
+
+        let path = std::env::temp_dir().join(format!("controls-{}.csv", std::process::id()));
+        let level = Arc::new(AtomicF64::new(0.0));
+        let env = Env::gated(vec![true, false], Frames(1000), Frames(100), Frames(300));
+        let mut dump = ControlDump::create(Probe::new(env, level.clone()), 48000.0, &path)
+            .unwrap()
+            .with_control("env", level);
+        (&mut dump).take(2000).for_each(drop);
+        dump.finish().unwrap();
+        let csv = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut lines = csv.lines();
+        assert_eq!(lines.next(), Some("time,env"));
+        let rows: Vec<[f64; 2]> = lines
+            .map(|row| {
+                let (time, env) = row.split_once(',').unwrap();
+                [time.parse().unwrap(), env.parse().unwrap()]
+            })
+            .collect();
+        // the frames 0, 64, ..., 1984
+        assert_eq!(rows.len(), 2000usize.div_ceil(CONTROL_BLOCK.0));
+        assert!(rows.windows(2).all(|w| w[0][0] < w[1][0]));
+        assert_eq!(rows[0], [0.0, 0.0]);
+        assert!(rows.iter().any(|&[_, env]| env > 0.99));
+        assert_eq!(rows[rows.len() - 1][1], 0.0);
+    }
+
+    // a sine of 440 Hz (always positive with the offset), and its steepest
+    // slope
+    fn sine(len: usize, offset: f64) -> (Vec<f64>, f64) {
+        let w = 2.0 * std::f64::consts::PI * 440.0 / 48000.0;
+        let samples = (0..len).map(|i| offset + (w * i as f64).sin()).collect();
+        (samples, w)
+    }
+
+    fn max_jump(x: &[f64]) -> f64 {
+        x.windows(2)
+            .map(|w| (w[1] - w[0]).abs())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn loop_points_move_to_zero_crossings() {
+        let (samples, slope) = sine(48000, 0.0);
+        for mode in [LoopMode::Forward, LoopMode::Reverse] {
+            let mut player =
+                SamplePlayer::new(samples.clone()).with_loop(Frames(1234), Frames(20017), mode);
+            let (start, end) = player.loop_points().unwrap();
+            assert!(samples[start.0 - 1] < 0.0 && samples[start.0] >= 0.0);
+            assert!(samples[end.0 - 1] < 0.0 && samples[end.0] >= 0.0);
+            let out: Vec<f64> = (0..60000).map(|_| player.next()).collect();
+            assert!(
+                max_jump(&out) <= 1.01 * slope,
+                "{mode:?}: {}",
+                max_jump(&out)
+            );
+        }
+    }
+
+    #[test]
+    fn seam_without_zero_crossings_is_crossfaded() {
+        // the end is a quarter of a period off the start
+        let (samples, slope) = sine(48000, 1.5);
+        // and from the very start, where there are no frames before the loop,
+        // which then starts after the frames the end fades into
+        for (start, moved) in [(5000, 5000), (0, LOOP_SEARCH.0)] {
+            let mut player = SamplePlayer::new(samples.clone()).with_loop(
+                Frames(start),
+                Frames(20044),
+                LoopMode::Forward,
+            );
+            assert_eq!(player.loop_points(), Some((Frames(moved), Frames(20044))));
+            let out: Vec<f64> = (0..60000).map(|_| player.next()).collect();
+            // a little steeper where the crossfade starts, as the offset is
+            // faded in by the equal-power curve
+            assert!(
+                max_jump(&out) < 1.5 * slope,
+                "from {start}: {}",
+                max_jump(&out)
+            );
+        }
+    }
+
+    #[test]
+    fn ping_pong_is_symmetric_around_the_turns() {
+        let (samples, _) = sine(48000, 0.0);
+        let mut player =
+            SamplePlayer::new(samples).with_loop(Frames(1000), Frames(3000), LoopMode::PingPong);
+        let (start, end) = player.loop_points().unwrap();
+        let out: Vec<f64> = (0..10000).map(|_| player.next()).collect();
+        // the turn at the end, and the one at the start after it
+        let len = end.0 - start.0;
+        for turn in [end.0 - 1, end.0 - 1 + len - 1] {
+            for d in 1..500 {
+                assert_eq!(out[turn - d], out[turn + d], "around {turn}");
+            }
+        }
+    }
+
+    #[test]
+    fn find_config_prefers_rate_then_channels_then_format() {
+        use cpal::{SampleFormat, SampleRate, SupportedBufferSize, SupportedStreamConfigRange};
+        let range = |channels, min, max, format| {
+            SupportedStreamConfigRange::new(
+                channels,
+                SampleRate(min),
+                SampleRate(max),
+                SupportedBufferSize::Unknown,
+                format,
+            )
+        };
+        let supported = [
+            range(2, 96000, 96000, SampleFormat::F32),
+            range(6, 44100, 48000, SampleFormat::F32),
+            range(2, 44100, 48000, SampleFormat::I16),
+            range(1, 44100, 48000, SampleFormat::F32),
+            range(2, 44100, 48000, SampleFormat::F32),
+        ];
+        let pick = |rate, channels| {
+            find_config(&supported, rate, channels)
+                .map(|c| (c.sample_rate().0, c.channels(), c.sample_format()))
+        };
+
+        // the requested channels, in f32
+        assert_eq!(pick(48000, 2), Some((48000, 2, SampleFormat::F32)));
+        assert_eq!(pick(44100, 6), Some((44100, 6, SampleFormat::F32)));
+        // otherwise the fewest channels
+        assert_eq!(pick(48000, 4), Some((48000, 1, SampleFormat::F32)));
+        // only one supports the rate
+        assert_eq!(pick(96000, 1), Some((96000, 2, SampleFormat::F32)));
+        assert_eq!(pick(22050, 2), None);
+    }
+
+    #[test]
+    fn selects_the_requested_host_if_it_has_a_device() {
+        // depends on the hosts and the devices of the machine
+        for id in cpal::available_hosts() {
+            let has_device = cpal::host_from_id(id)
+                .map(|host| host.default_output_device().is_some())
+                .unwrap_or(false);
+            match select_host(Some(&id.name().to_uppercase())) {
+                Ok(host) => {
+                    assert!(has_device, "{}", id.name());
+                    assert_eq!(host.id(), id);
+                }
+                Err(e) => assert!(!has_device, "{}: {e}", id.name()),
+            }
+        }
+
+        let e = select_host(Some("no-such-host")).err().unwrap();
+        assert!(e.to_string().contains("is not available"), "{e}");
+    }
+
+    #[test]
+    #[cfg(all(feature = "asio", target_os = "windows"))]
+    fn selects_asio_if_a_driver_is_installed() {
+        let has_driver = cpal::host_from_id(cpal::HostId::Asio)
+            .is_ok_and(|host| host.default_output_device().is_some());
+        match select_host(Some("asio")) {
+            Ok(host) => assert_eq!(host.id(), cpal::HostId::Asio),
+            Err(e) => {
+                assert!(!has_driver, "{e}");
+                assert!(e.to_string().contains("ASIO driver"), "{e}");
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(not(target_os = "windows"))]
+    fn asio_is_only_on_windows() {
+        let e = select_host(Some("asio")).err().unwrap();
+        assert!(e.to_string().contains("only available on Windows"), "{e}");
+    }
+
+    #[test]
+    fn lists_at_least_the_default_host() {
+        let hosts = list_devices();
+        let default = cpal::default_host().id();
+        // the devices may be an error (e.g. no sound card), but not a panic
+        assert!(hosts.iter().any(|h| h.id == default));
+    }
+
+    #[test]
+    fn bounce_is_the_live_rendering_with_the_tail() {
+        let fs = 48000.0;
+        let reverb = || {
+            // a 50 ms burst of a sine
+            let burst = dasp::signal::rate(fs)
+                .const_hz(440.0)
+                .sine()
+                .take(2400)
+                .chain(std::iter::repeat(0.0));
+            crate::effects::PlateReverb::new(dasp::signal::from_iter(burst), fs, 0.5, 0.3, Ms(0.0))
+        };
+        let nominal = Frames(4800);
+        let live: Vec<f64> = take_with_tail(reverb(), nominal, fs).collect();
+        let mut frozen = bounce(reverb(), nominal, fs);
+        assert_eq!(frozen.samples(), live);
+
+        // until the silence of 50 ms at -80 dB
+        assert!(live.len() > nominal.0);
+        let window = Ms(50.0).to_frames(fs).0;
+        assert!(live[live.len() - window..].iter().all(|x| x.abs() < 1e-4));
+
+        let played: Vec<f64> = (0..live.len()).map(|_| frozen.next()).collect();
+        assert_eq!(played, live);
+        assert!(frozen.is_exhausted());
+        assert_eq!(frozen.next(), 0.0);
+
+        // no tail to wait for
+        let player = bounce(SamplePlayer::new(vec![0.5; 100]), Frames(50), fs);
+        assert_eq!(player.samples(), [0.5; 50]);
+    }
+}
@@ -0,0 +1,256 @@
+//! A sampler instrument: recordings (e.g. of a piano at a few keys and
+//! dynamics) mapped to the ranges of keys and velocities, played back at the
+//! pitch of the note by resampling. Several takes of the same note can be
+//! cycled through (round robin), so that repeated notes don't sound like a
+//! machine gun.
+
+use crate::tail::HasTail;
+use crate::units::Frames;
+use crate::wav::Wav;
+use dasp::Signal;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// A sample mapped to the ranges of keys and velocities (MIDI numbers). The
+/// default ranges are all of them.
+#[derive(Clone, Debug)]
+pub struct Zone {
+    samples: Arc<[f64]>,
+    fs: f64, // sampling rate of the sample
+    root: u8,
+    keys: RangeInclusive<u8>,
+    velocities: RangeInclusive<u8>,
+    loop_points: Option<(Frames, Frames)>,
+}
+
+impl Zone {
+    /// The sample of the WAV (mixed down to mono) sounding at the key of
+    /// `root`. The loop points of the WAV, if any, are used while the note is
+    /// held.
+    pub fn new(wav: &Wav, root: u8) -> Self {
+        Self::from_samples(wav.to_mono(), wav.fs, root).with_loop_points(wav.loop_points)
+    }
+
+    pub fn from_samples(samples: Vec<f64>, fs: f64, root: u8) -> Self {
+        Self {
+            samples: samples.into(),
+            fs,
+            root,
+            keys: 0..=127,
+            velocities: 0..=127,
+            loop_points: None,
+        }
+    }
+
+    pub fn with_keys(mut self, keys: RangeInclusive<u8>) -> Self {
+        self.keys = keys;
+        self
+    }
+
+    /// The layer of the dynamics, e.g. `0..=63` for the soft one and
+    /// `64..=127` for the loud one.
+    pub fn with_velocities(mut self, velocities: RangeInclusive<u8>) -> Self {
+        self.velocities = velocities;
+        self
+    }
+
+    /// The start and the end (exclusive) of the loop. An empty loop or one
+    /// outside of the sample is ignored.
+    pub fn with_loop_points(mut self, loop_points: Option<(Frames, Frames)>) -> Self {
+        self.loop_points =
+            loop_points.filter(|&(start, end)| start.0 < end.0 && end.0 <= self.samples.len());
+        self
+    }
+
+    pub fn contains(&self, key: u8, velocity: u8) -> bool {
+        self.keys.contains(&key) && self.velocities.contains(&velocity)
+    }
+}
+
+/// Zones of samples. A note plays one of the zones whose ranges contain its
+/// key and velocity. If there are several of them (e.g. the takes of the same
+/// note), they are used in turn, in the order added, keeping the turn for
+/// each key separately.
+#[derive(Clone, Debug, Default)]
+pub struct Sampler {
+    zones: Vec<Zone>,
+    // the number of the notes played so far for each key
+    round_robin: HashMap<u8, usize>,
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_zone(mut self, zone: Zone) -> Self {
+        self.zones.push(zone);
+        self
+    }
+
+    /// The index (in the order added) of the zone the next note of the key
+    /// and the velocity plays, if any.
+    pub fn select(&self, key: u8, velocity: u8) -> Option<usize> {
+        let mut zones = self
+            .zones
+            .iter()
+            .enumerate()
+            .filter(|(_, z)| z.contains(key, velocity))
+            .map(|(i, _)| i);
+        let count = zones.clone().count();
+        if count == 0 {
+            return None;
+        }
+        let turn = self.round_robin.get(&key).copied().unwrap_or(0);
+        zones.nth(turn % count)
+    }
+
+    /// Starts a note at the sampling rate of `fs`, or returns `None` if no
+    /// zone contains it. The gain is the velocity divided by 127, on top of
+    /// the level of the layer itself.
+    pub fn note_on(&mut self, key: u8, velocity: u8, fs: f64) -> Option<SamplerVoice> {
+        let zone = &self.zones[self.select(key, velocity)?];
+        *self.round_robin.entry(key).or_default() += 1;
+        let semitones = key as f64 - zone.root as f64;
+        Some(SamplerVoice {
+            samples: zone.samples.clone(),
+            loop_points: zone.loop_points,
+            pos: 0.0,
+            step: 2.0_f64.powf(semitones / 12.0) * zone.fs / fs,
+            gain: velocity as f64 / 127.0,
+            held: true,
+        })
+    }
+}
+
+/// A note of `Sampler`. The sample is read at the speed of the pitch with the
+/// linear interpolation. While held, the note repeats the loop of the zone (if
+/// any); after `note_off()`, it plays to the end of the sample.
+pub struct SamplerVoice {
+    samples: Arc<[f64]>,
+    loop_points: Option<(Frames, Frames)>,
+    pos: f64, // the position in the sample, in the frames of it
+    step: f64,
+    gain: f64,
+    held: bool,
+}
+
+impl SamplerVoice {
+    pub fn note_off(&mut self) {
+        self.held = false;
+    }
+
+    // the sample at `i`, where the end of the loop continues to its start
+    fn sample(&self, i: usize) -> f64 {
+        match self.loop_points {
+            Some((start, end)) if self.held && i >= end.0 => {
+                self.samples[start.0 + (i - end.0) % (end.0 - start.0)]
+            }
+            _ => self.samples.get(i).copied().unwrap_or(0.0),
+        }
+    }
+}
+
+impl Signal for SamplerVoice {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        if self.is_exhausted() {
+            return 0.0;
+        }
+
+        let i = self.pos.floor() as usize;
+        let frac = self.pos - i as f64;
+        let x = (1.0 - frac) * self.sample(i) + frac * self.sample(i + 1);
+
+        self.pos += self.step;
+        if let Some((start, end)) = self.loop_points.filter(|_| self.held) {
+            let (start, end) = (start.0 as f64, end.0 as f64);
+            if self.pos >= end {
+                self.pos = start + (self.pos - end) % (end - start);
+            }
+        }
+
+        self.gain * x
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.pos >= self.samples.len() as f64
+    }
+}
+
+// the sample is the tail
+impl HasTail for SamplerVoice {
+    fn has_tail(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    // a sample of the constant level, to tell the zones apart
+    fn zone(level: f64) -> Zone {
+        Zone::from_samples(vec![level; 1000], FS, 60)
+    }
+
+    #[test]
+    fn velocity_selects_the_layer() {
+        let mut sampler = Sampler::new()
+            .with_zone(zone(0.2).with_velocities(0..=63))
+            .with_zone(zone(0.9).with_velocities(64..=127));
+        assert_eq!(sampler.select(60, 30), Some(0));
+        assert_eq!(sampler.select(60, 100), Some(1));
+
+        let mut soft = sampler.note_on(60, 30, FS).unwrap();
+        assert!((soft.next() - 0.2 * 30.0 / 127.0).abs() < 1e-12);
+        let mut loud = sampler.note_on(60, 127, FS).unwrap();
+        assert!((loud.next() - 0.9).abs() < 1e-12);
+
+        let mut sampler = Sampler::new().with_zone(zone(0.5).with_keys(48..=72));
+        assert!(sampler.note_on(80, 100, FS).is_none());
+    }
+
+    #[test]
+    fn notes_are_resampled_to_the_pitch() {
+        let ramp: Vec<f64> = (0..1000).map(|i| i as f64 / 1000.0).collect();
+        let mut sampler = Sampler::new().with_zone(Zone::from_samples(ramp, FS, 60));
+        // an octave up reads twice as fast, and ends in half the time
+        let voice = sampler.note_on(72, 127, FS).unwrap();
+        let out: Vec<f64> = voice.until_exhausted().collect();
+        assert_eq!(out.len(), 500);
+        assert!((out[100] - 0.2).abs() < 1e-12);
+        // a fifth down, rendered at the half of the sampling rate of the sample
+        let voice = sampler.note_on(53, 127, FS / 2.0).unwrap();
+        assert!(
+            (voice.take(2).last().unwrap() - 2.0 * 2f64.powf(-7.0 / 12.0) / 1000.0).abs() < 1e-12
+        );
+    }
+
+    #[test]
+    fn round_robin_cycles_for_each_key() {
+        let mut sampler = Sampler::new()
+            .with_zone(zone(0.1).with_keys(60..=60))
+            .with_zone(zone(0.2).with_keys(60..=60))
+            .with_zone(zone(0.3).with_keys(60..=60))
+            .with_zone(zone(0.5).with_keys(61..=62))
+            .with_zone(zone(0.6).with_keys(61..=62));
+
+        let mut played = |key: u8| {
+            let zone = sampler.select(key, 127).unwrap();
+            sampler.note_on(key, 127, FS).unwrap();
+            zone
+        };
+        let sequence: Vec<usize> = (0..7).map(|_| played(60)).collect();
+        assert_eq!(sequence, [0, 1, 2, 0, 1, 2, 0]);
+        // the keys sharing the zones keep their own turns
+        assert_eq!(
+            [played(61), played(61), played(62), played(61)],
+            [3, 4, 3, 3]
+        );
+    }
+}
@@ -0,0 +1,244 @@
+//! Catching NaN and infinity before they spread: a single non-finite sample
+//! from a bad parameter poisons every filter after it (turning the output
+//! into silence, or into full-scale garbage on some backends), and it's hard
+//! to tell where it came from.
+//!
+//! Wrap the stages of a chain with `Sanitize` (or build the chain by `Chain`,
+//! which does it after each stage). The first non-finite sample is recorded
+//! with the label of the stage and its index, and `Player` reports it when
+//! the stream ends (see `take_non_finite()`).
+
+use dasp::Signal;
+use std::sync::Mutex;
+
+/// Where the first non-finite sample appeared.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NonFinite {
+    /// The label of the `Sanitize` that found it.
+    pub label: String,
+    /// The index of the sample among those of the stage.
+    pub index: usize,
+}
+
+// the first one found by any `Sanitize` since the last `take_non_finite()`
+static NON_FINITE: Mutex<Option<NonFinite>> = Mutex::new(None);
+
+/// Takes the first non-finite sample found since the last call, if any.
+pub fn take_non_finite() -> Option<NonFinite> {
+    NON_FINITE.lock().unwrap_or_else(|e| e.into_inner()).take()
+}
+
+/// Replaces the non-finite samples of a stage with 0.0, and records the first
+/// one (unless one is already recorded, e.g. by an earlier stage, which runs
+/// first within a frame). This locks only on the first one, so it's cheap
+/// enough for the audio thread. Works on both `Signal`s and iterators.
+pub struct Sanitize<S> {
+    signal: S,
+    label: String,
+    enabled: bool,
+    index: usize,
+    found: bool,
+}
+
+impl<S> Sanitize<S> {
+    pub fn new(signal: S, label: &str) -> Self {
+        Self {
+            signal,
+            label: label.to_string(),
+            enabled: true,
+            index: 0,
+            found: false,
+        }
+    }
+
+    /// Passes the samples as they are when disabled.
+    pub fn with_enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self
+    }
+
+    fn check(&mut self, x: f64) -> f64 {
+        let index = self.index;
+        self.index += 1;
+        if !self.enabled || x.is_finite() {
+            return x;
+        }
+
+        if !self.found {
+            self.found = true;
+            let mut first = NON_FINITE.lock().unwrap_or_else(|e| e.into_inner());
+            first.get_or_insert_with(|| NonFinite {
+                label: self.label.clone(),
+                index,
+            });
+        }
+        0.0
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for Sanitize<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        self.check(x)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.signal.is_exhausted()
+    }
+}
+
+impl<S: Iterator<Item = f64>> Iterator for Sanitize<S> {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let x = self.signal.next()?;
+        Some(self.check(x))
+    }
+}
+
+/// Builds a chain of stages, inserting a labeled `Sanitize` after each. They
+/// are enabled in the debug builds by default, and can be enabled in the
+/// release builds by `with_sanitize(true)` right after `new()`.
+///
+/// ```text
+/// let chain = Chain::new(osc, "osc")
+///     .then("filter", |s| Filter::new(s, ...))
+///     .then("reverb", |s| Reverb::new(s, ...))
+///     .build();
+/// ```
+pub struct Chain<S> {
+    signal: Sanitize<S>,
+    enabled: bool,
+}
+
+impl<S: Signal<Frame = f64>> Chain<S> {
+    pub fn new(source: S, label: &str) -> Self {
+        let enabled = cfg!(debug_assertions);
+        Self {
+            signal: Sanitize::new(source, label).with_enabled(enabled),
+            enabled,
+        }
+    }
+
+    /// Enables or disables the sanitizers of the last stage and the stages
+    /// appended after this, so call this right after `new()`.
+    pub fn with_sanitize(mut self, enabled: bool) -> Self {
+        self.signal.enabled = enabled;
+        self.enabled = enabled;
+        self
+    }
+
+    /// Appends a stage, which takes the chain so far.
+    pub fn then<T, F>(self, label: &str, stage: F) -> Chain<T>
+    where
+        T: Signal<Frame = f64>,
+        F: FnOnce(Sanitize<S>) -> T,
+    {
+        Chain {
+            signal: Sanitize::new(stage(self.signal), label).with_enabled(self.enabled),
+            enabled: self.enabled,
+        }
+    }
+
+    pub fn build(self) -> Sanitize<S> {
+        self.signal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FirFilter;
+    use crate::units::Hz;
+
+    const FS: f64 = 48000.0;
+
+    // the report is global, so the tests that make one take turns
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    fn sine() -> impl Signal<Frame = f64> {
+        dasp::signal::rate(FS).const_hz(440.0).sine().scale_amp(0.5)
+    }
+
+    // a stage that turns the sample at `at` into NaN
+    struct NanAt<S> {
+        signal: S,
+        at: usize,
+        index: usize,
+    }
+
+    impl<S: Signal<Frame = f64>> Signal for NanAt<S> {
+        type Frame = f64;
+
+        fn next(&mut self) -> Self::Frame {
+            let x = self.signal.next();
+            self.index += 1;
+            if self.index - 1 == self.at {
+                f64::NAN
+            } else {
+                x
+            }
+        }
+    }
+
+    fn chain(enabled: bool) -> impl Signal<Frame = f64> {
+        Chain::new(sine(), "osc")
+            .with_sanitize(enabled)
+            .then("bad", |s| NanAt {
+                signal: s,
+                at: 1000,
+                index: 0,
+            })
+            .then("lpf", |s| FirFilter::low_pass(s, FS, Hz(2000.0), 31))
+            .build()
+    }
+
+    #[test]
+    fn finds_the_stage_and_the_index_of_a_nan() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        take_non_finite();
+
+        let out: Vec<f64> = chain(true).take(10000).collect();
+        assert!(out.iter().all(|x| x.is_finite()));
+        assert_eq!(
+            take_non_finite(),
+            Some(NonFinite {
+                label: "bad".to_string(),
+                index: 1000,
+            })
+        );
+        // reported once
+        assert_eq!(take_non_finite(), None);
+
+        // and the filter after it isn't poisoned
+        let tail = &out[9000..];
+        let rms = (tail.iter().map(|x| x * x).sum::<f64>() / tail.len() as f64).sqrt();
+        assert!(rms > 0.3, "{rms}");
+    }
+
+    #[test]
+    fn disabled_chain_passes_the_nan() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        take_non_finite();
+
+        let out: Vec<f64> = chain(false).take(10000).collect();
+        assert!(out.iter().any(|x| !x.is_finite()));
+        assert_eq!(take_non_finite(), None);
+    }
+
+    #[test]
+    fn sanitizes_iterators_too() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        take_non_finite();
+
+        let input = [0.1, f64::INFINITY, 0.2, f64::NAN];
+        let out: Vec<f64> = Sanitize::new(input.into_iter(), "input").collect();
+        assert_eq!(out, [0.1, 0.0, 0.2, 0.0]);
+        assert_eq!(
+            take_non_finite().map(|x| (x.label, x.index)),
+            Some(("input".to_string(), 1))
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Scheduling the changes of parameters ahead of time from the control
+//! thread, like `AudioParam` of the Web Audio API (`setValueAtTime()`,
+//! `linearRampToValueAtTime()`, and `exponentialRampToValueAtTime()`), and
+//! rendering them sample-accurately on the audio thread.
+//!
+//! The times are in frames since the audio side started rendering, i.e. the
+//! number of the calls to `ScheduledParam::next_value()` before the frame.
+
+use crate::units::Frames;
+use std::collections::{HashMap, VecDeque};
+use std::sync::mpsc;
+
+/// The error on scheduling an event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScheduleError {
+    /// No parameter of the name was added to the `Scheduler`.
+    UnknownParam(String),
+    /// The value must be finite.
+    NonFinite(f64),
+    /// An exponential ramp can't reach 0.
+    ExpRampToZero,
+    /// The audio side of the parameter was dropped.
+    Disconnected,
+}
+
+impl std::fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownParam(name) => write!(f, "unknown parameter: {name}"),
+            Self::NonFinite(value) => write!(f, "invalid value: {value}"),
+            Self::ExpRampToZero => write!(f, "an exponential ramp can't reach 0"),
+            Self::Disconnected => write!(f, "the parameter is no longer rendered"),
+        }
+    }
+}
+
+impl std::error::Error for ScheduleError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Shape {
+    /// Jumps to the value at the time.
+    Set,
+    /// Ramps linearly from the previous event to the value at the time.
+    Linear,
+    /// Ramps exponentially from the previous event to the value at the time.
+    Exponential,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Event {
+    time: usize,
+    value: f64,
+    shape: Shape,
+}
+
+/// The control side of the scheduled parameters. Add the parameters by
+/// `add()` and hand the returned `ScheduledParam`s to the audio thread, and
+/// then schedule the events by the names.
+///
+/// An event scheduled at the same time as an earlier one replaces it (the
+/// last writer wins). An event in the middle of a ramp splits it; the ramp
+/// then ends at the new event, and the next ramp starts from it.
+#[derive(Debug, Default)]
+pub struct Scheduler {
+    params: HashMap<String, mpsc::Sender<Event>>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a parameter with the initial value, and returns its audio side.
+    pub fn add(&mut self, name: &str, value: f64) -> ScheduledParam {
+        let (tx, rx) = mpsc::channel();
+        self.params.insert(name.to_string(), tx);
+        ScheduledParam::new(rx, value)
+    }
+
+    /// Jumps to `value` at `time`.
+    pub fn set_value_at(&self, param: &str, time: Frames, value: f64) -> Result<(), ScheduleError> {
+        self.send(param, time, value, Shape::Set)
+    }
+
+    /// Ramps linearly from the previous event to `value` at `end`.
+    pub fn linear_ramp_to(
+        &self,
+        param: &str,
+        end: Frames,
+        value: f64,
+    ) -> Result<(), ScheduleError> {
+        self.send(param, end, value, Shape::Linear)
+    }
+
+    /// Ramps exponentially from the previous event to `value` at `end`,
+    /// which sounds even for the frequencies and the gains. `value` must not
+    /// be 0. If the previous value is 0 or of the opposite sign, this holds
+    /// the previous value and jumps at `end`, as the Web Audio API does.
+    pub fn exp_ramp_to(&self, param: &str, end: Frames, value: f64) -> Result<(), ScheduleError> {
+        if value == 0.0 {
+            return Err(ScheduleError::ExpRampToZero);
+        }
+        self.send(param, end, value, Shape::Exponential)
+    }
+
+    fn send(
+        &self,
+        param: &str,
+        time: Frames,
+        value: f64,
+        shape: Shape,
+    ) -> Result<(), ScheduleError> {
+        if !value.is_finite() {
+            return Err(ScheduleError::NonFinite(value));
+        }
+        let tx = self
+            .params
+            .get(param)
+            .ok_or_else(|| ScheduleError::UnknownParam(param.to_string()))?;
+        tx.send(Event {
+            time: time.0,
+            value,
+            shape,
+        })
+        .map_err(|_| ScheduleError::Disconnected)
+    }
+}
+
+// the events that can be pending without allocating on the audio thread
+const EVENT_CAPACITY: usize = 64;
+
+/// The audio side of a parameter of a `Scheduler`, which renders the
+/// scheduled events.
+pub struct ScheduledParam {
+    rx: mpsc::Receiver<Event>,
+    // the last event that has passed, where the next ramp starts
+    anchor: Event,
+    pending: VecDeque<Event>,
+    cur_frame: usize,
+}
+
+impl ScheduledParam {
+    fn new(rx: mpsc::Receiver<Event>, value: f64) -> Self {
+        Self {
+            rx,
+            anchor: Event {
+                time: 0,
+                value,
+                shape: Shape::Set,
+            },
+            pending: VecDeque::with_capacity(EVENT_CAPACITY),
+            cur_frame: 0,
+        }
+    }
+
+    /// The value on the current frame, and proceeds to the next frame.
+    pub fn next_value(&mut self) -> f64 {
+        while let Ok(event) = self.rx.try_recv() {
+            self.insert(event);
+        }
+
+        while let Some(event) = self.pending.front() {
+            if event.time > self.cur_frame {
+                break;
+            }
+            self.anchor = *event;
+            self.pending.pop_front();
+        }
+
+        let value = match self.pending.front() {
+            Some(next) => self.interpolate(next),
+            None => self.anchor.value,
+        };
+        self.cur_frame += 1;
+        value
+    }
+
+    fn insert(&mut self, event: Event) {
+        let i = self.pending.partition_point(|e| e.time < event.time);
+        match self.pending.get_mut(i) {
+            Some(e) if e.time == event.time => *e = event,
+            _ => self.pending.insert(i, event),
+        }
+    }
+
+    fn interpolate(&self, next: &Event) -> f64 {
+        let (v0, v1) = (self.anchor.value, next.value);
+        // the anchor is in the past and the next event is in the future
+        let t = (self.cur_frame - self.anchor.time) as f64 / (next.time - self.anchor.time) as f64;
+        match next.shape {
+            Shape::Set => v0,
+            Shape::Linear => v0 + (v1 - v0) * t,
+            Shape::Exponential if v0 * v1 > 0.0 => v0 * (v1 / v0).powf(t),
+            Shape::Exponential => v0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(param: &mut ScheduledParam, len: usize) -> Vec<f64> {
+        (0..len).map(|_| param.next_value()).collect()
+    }
+
+    #[test]
+    fn hits_each_breakpoint() {
+        let mut scheduler = Scheduler::new();
+        let mut param = scheduler.add("cutoff", 100.0);
+        scheduler
+            .linear_ramp_to("cutoff", Frames(100), 200.0)
+            .unwrap();
+        scheduler.exp_ramp_to("cutoff", Frames(300), 800.0).unwrap();
+        scheduler.set_value_at("cutoff", Frames(400), 50.0).unwrap();
+        let values = render(&mut param, 500);
+
+        assert_eq!(values[0], 100.0);
+        assert_eq!(values[50], 150.0);
+        assert_eq!(values[100], 200.0);
+        // halfway in an exponential ramp is the geometric mean
+        assert!((values[200] - 400.0).abs() < 1e-9);
+        assert_eq!(values[300], 800.0);
+        assert_eq!(values[399], 800.0);
+        assert_eq!(values[400], 50.0);
+        assert!(values[..300].windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn last_writer_wins() {
+        let mut scheduler = Scheduler::new();
+        let mut param = scheduler.add("mix", 0.0);
+        scheduler.linear_ramp_to("mix", Frames(100), 1.0).unwrap();
+        // supersedes the ramp above
+        scheduler.linear_ramp_to("mix", Frames(100), 0.5).unwrap();
+        // splits the ramp; it ends here and the next one starts here
+        scheduler.set_value_at("mix", Frames(50), 0.8).unwrap();
+        let values = render(&mut param, 200);
+
+        assert_eq!(values[49], 0.0);
+        assert_eq!(values[50], 0.8);
+        assert!((values[75] - 0.65).abs() < 1e-12);
+        assert_eq!(values[100], 0.5);
+        assert_eq!(values[199], 0.5);
+    }
+
+    #[test]
+    fn rejects_invalid_events() {
+        let mut scheduler = Scheduler::new();
+        let param = scheduler.add("gain", 1.0);
+        assert_eq!(
+            scheduler.exp_ramp_to("gain", Frames(10), 0.0),
+            Err(ScheduleError::ExpRampToZero)
+        );
+        assert_eq!(
+            scheduler
+                .set_value_at("gain", Frames(10), f64::NAN)
+                .unwrap_err()
+                .to_string(),
+            "invalid value: NaN"
+        );
+        assert_eq!(
+            scheduler.set_value_at("pan", Frames(10), 0.0),
+            Err(ScheduleError::UnknownParam("pan".to_string()))
+        );
+        drop(param);
+        assert_eq!(
+            scheduler.set_value_at("gain", Frames(10), 0.5),
+            Err(ScheduleError::Disconnected)
+        );
+    }
+}
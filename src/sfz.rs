@@ -0,0 +1,347 @@
+//! Loading the instruments of the SFZ format into a `Sampler`. Only a subset
+//! is understood: the `<control>`, `<global>`, `<group>`, and `<region>`
+//! headers, and these opcodes (the others are ignored):
+//!
+//! - `sample`, relative to the SFZ file (and `default_path` of `<control>`)
+//! - `key`, `lokey`, `hikey`, `pitch_keycenter`, as numbers or note names
+//!   (`c4` is 60)
+//! - `lovel`, `hivel`
+//! - `loop_mode` (`no_loop` and `one_shot` disable the loop of the WAV),
+//!   `loop_start`, `loop_end` (inclusive, as in the format)
+//!
+//! The opcodes of `<global>` and `<group>` are the defaults of the regions
+//! that follow them.
+
+use crate::sampler::{Sampler, Zone};
+use crate::units::Frames;
+use crate::wav;
+use anyhow::{anyhow, bail};
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+/// A region of an SFZ file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    /// The path of the sample as written, with `default_path` prepended.
+    pub sample: PathBuf,
+    pub keys: RangeInclusive<u8>,
+    pub velocities: RangeInclusive<u8>,
+    /// The key the sample sounds at as recorded.
+    pub root: u8,
+    /// `Some(None)` disables the loop; `None` uses the loop of the WAV, if
+    /// any.
+    pub loop_points: Option<Option<(Frames, Frames)>>,
+}
+
+/// Reads the SFZ file and the samples of its regions.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Sampler, anyhow::Error> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let regions = parse(&std::fs::read_to_string(path)?)?;
+
+    let mut sampler = Sampler::new();
+    for region in regions {
+        let sample_path = dir.join(&region.sample);
+        let wav = wav::read(&sample_path)
+            .map_err(|e| anyhow!("failed to read {}: {e}", sample_path.display()))?;
+        let mut zone = Zone::new(&wav, region.root)
+            .with_keys(region.keys)
+            .with_velocities(region.velocities);
+        if let Some(loop_points) = region.loop_points {
+            zone = zone.with_loop_points(loop_points);
+        }
+        sampler = sampler.with_zone(zone);
+    }
+    Ok(sampler)
+}
+
+/// Parses the text of an SFZ file into the regions, in the order written.
+pub fn parse(text: &str) -> Result<Vec<Region>, anyhow::Error> {
+    let text = strip_comments(text);
+
+    let mut default_path = String::new();
+    let mut global = HashMap::new();
+    let mut group = HashMap::new();
+    let mut regions = vec![];
+    // the header the opcodes are for, and the opcodes of the region
+    let mut header = None;
+    let mut region: Option<HashMap<&str, &str>> = None;
+
+    for token in tokenize(&text)? {
+        match token {
+            Token::Header(name) => {
+                if let Some(opcodes) = region.take() {
+                    regions.push(to_region(&opcodes, &default_path)?);
+                }
+                match name {
+                    "global" => {
+                        global.clear();
+                        group.clear();
+                    }
+                    "group" => group = global.clone(),
+                    "region" => region = Some(group.clone()),
+                    _ => {}
+                }
+                header = Some(name);
+            }
+            Token::Opcode(name, value) => match header {
+                Some("control") if name == "default_path" => default_path = value.to_string(),
+                Some("global") => {
+                    global.insert(name, value);
+                    group.insert(name, value);
+                }
+                Some("group") => {
+                    group.insert(name, value);
+                }
+                Some("region") => {
+                    if let Some(opcodes) = region.as_mut() {
+                        opcodes.insert(name, value);
+                    }
+                }
+                Some(_) => {}
+                None => bail!("opcode {name} before any header"),
+            },
+        }
+    }
+    if let Some(opcodes) = region {
+        regions.push(to_region(&opcodes, &default_path)?);
+    }
+    Ok(regions)
+}
+
+fn to_region(opcodes: &HashMap<&str, &str>, default_path: &str) -> Result<Region, anyhow::Error> {
+    let get = |name| opcodes.get(name).copied();
+    let key_of = |name| get(name).map(parse_key).transpose();
+    let velocity_of = |name| {
+        get(name)
+            .map(|v| {
+                v.parse::<u8>()
+                    .ok()
+                    .filter(|v| *v <= 127)
+                    .ok_or_else(|| anyhow!("invalid velocity for {name}: {v}"))
+            })
+            .transpose()
+    };
+    let frames_of = |name| {
+        get(name)
+            .map(|v| {
+                v.parse::<usize>()
+                    .map_err(|_| anyhow!("invalid frames for {name}: {v}"))
+            })
+            .transpose()
+    };
+
+    let sample = get("sample").ok_or_else(|| anyhow!("a region without sample"))?;
+    // the paths are written with backslashes on Windows
+    let sample = PathBuf::from(format!("{default_path}{sample}").replace('\\', "/"));
+
+    // `key` sets all of the three
+    let key = key_of("key")?;
+    let lokey = key_of("lokey")?.or(key).unwrap_or(0);
+    let hikey = key_of("hikey")?.or(key).unwrap_or(127);
+    let root = key_of("pitch_keycenter")?.or(key).unwrap_or(60);
+
+    let lovel = velocity_of("lovel")?.unwrap_or(0);
+    let hivel = velocity_of("hivel")?.unwrap_or(127);
+
+    let loop_points = match get("loop_mode") {
+        Some("no_loop" | "one_shot") => Some(None),
+        _ => match (frames_of("loop_start")?, frames_of("loop_end")?) {
+            (Some(start), Some(end)) => {
+                let end = end
+                    .checked_add(1)
+                    .ok_or_else(|| anyhow!("invalid frames for loop_end: {end}"))?;
+                Some(Some((Frames(start), Frames(end))))
+            }
+            _ => None,
+        },
+    };
+
+    Ok(Region {
+        sample,
+        keys: lokey..=hikey,
+        velocities: lovel..=hivel,
+        root,
+        loop_points,
+    })
+}
+
+/// Parses a MIDI key as a number (0 to 127) or a note name like `c4`, `f#3`,
+/// or `eb5`, where `c4` is 60.
+fn parse_key(value: &str) -> Result<u8, anyhow::Error> {
+    let invalid = || anyhow!("invalid key: {value}");
+    if let Ok(key) = value.parse::<u8>() {
+        return (key <= 127).then_some(key).ok_or_else(invalid);
+    }
+
+    let lower = value.to_ascii_lowercase();
+    let mut chars = lower.chars();
+    let semitone = match chars.next() {
+        Some('c') => 0,
+        Some('d') => 2,
+        Some('e') => 4,
+        Some('f') => 5,
+        Some('g') => 7,
+        Some('a') => 9,
+        Some('b') => 11,
+        _ => return Err(invalid()),
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.strip_prefix('#') {
+        Some(octave) => (1, octave),
+        None => match rest.strip_prefix('b') {
+            Some(octave) => (-1, octave),
+            None => (0, rest),
+        },
+    };
+    let octave: i32 = octave.parse().map_err(|_| invalid())?;
+    let key = (octave + 1) * 12 + semitone + accidental;
+    u8::try_from(key)
+        .ok()
+        .filter(|key| *key <= 127)
+        .ok_or_else(invalid)
+}
+
+enum Token<'a> {
+    Header(&'a str),
+    Opcode(&'a str, &'a str),
+}
+
+/// Splits the text into the headers and the opcodes. A value runs until the
+/// next opcode or header, so the paths of the samples can contain spaces.
+fn tokenize(text: &str) -> Result<Vec<Token<'_>>, anyhow::Error> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('<') {
+            let end = after
+                .find('>')
+                .ok_or_else(|| anyhow!("unclosed header: <{after}"))?;
+            tokens.push(Token::Header(after[..end].trim()));
+            rest = after[end + 1..].trim_start();
+            continue;
+        }
+
+        let eq = rest
+            .find('=')
+            .ok_or_else(|| anyhow!("expected an opcode: {rest}"))?;
+        let name = rest[..eq].trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            bail!("invalid opcode: {}", &rest[..eq]);
+        }
+        let value = &rest[eq + 1..];
+        let end = value_end(value);
+        tokens.push(Token::Opcode(name, value[..end].trim()));
+        rest = value[end..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// the end of the value, i.e. the start of the next header, or the whitespace
+// before the next `name=`
+fn value_end(value: &str) -> usize {
+    let header = value.find('<').unwrap_or(value.len());
+    let mut end = header;
+    for (i, c) in value[..header].char_indices() {
+        if !c.is_whitespace() {
+            continue;
+        }
+        let next = value[i..].trim_start();
+        let word_end = next
+            .find(|c: char| c.is_whitespace() || c == '=' || c == '<')
+            .unwrap_or(next.len());
+        if next[word_end..].starts_with('=') && word_end > 0 {
+            end = i;
+            break;
+        }
+    }
+    end
+}
+
+/// Removes the `//` and `/* */` comments.
+fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    loop {
+        let line = rest.find("//");
+        let block = rest.find("/*");
+        match (line, block) {
+            // whichever comes first
+            (Some(i), b) if b.is_none_or(|b| i < b) => {
+                out.push_str(&rest[..i]);
+                rest = rest[i..].find('\n').map_or("", |end| &rest[i + end..]);
+            }
+            (_, Some(i)) => {
+                out.push_str(&rest[..i]);
+                out.push(' ');
+                rest = rest[i + 2..]
+                    .find("*/")
+                    .map_or("", |end| &rest[i + 2 + end + 2..]);
+            }
+            _ => {
+                out.push_str(rest);
+                return out;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_follow_the_opcodes() {
+        let text = "
+            // a piano of two samples
+            <control> default_path=samples\\
+            <global> lovel=10 loop_mode=no_loop
+            <group> hivel=100
+            <region> sample=low c.wav lokey=c3 hikey=b3 pitch_keycenter=60
+            <region> sample=high.wav key=72 loop_mode=loop_continuous loop_start=100 loop_end=199
+            /* a layer of the loudest notes */
+            <group>
+            <region> sample=loud.wav lovel=101
+        ";
+        let regions = parse(text).unwrap();
+        assert_eq!(
+            regions,
+            [
+                Region {
+                    sample: PathBuf::from("samples/low c.wav"),
+                    keys: 48..=59,
+                    velocities: 10..=100,
+                    root: 60,
+                    loop_points: Some(None),
+                },
+                Region {
+                    sample: PathBuf::from("samples/high.wav"),
+                    keys: 72..=72,
+                    velocities: 10..=100,
+                    root: 72,
+                    loop_points: Some(Some((Frames(100), Frames(200)))),
+                },
+                Region {
+                    sample: PathBuf::from("samples/loud.wav"),
+                    keys: 0..=127,
+                    velocities: 101..=127,
+                    root: 60,
+                    loop_points: Some(None),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_opcodes() {
+        assert!(parse("<region> sample=a.wav key=128").is_err());
+        assert!(parse("<region> sample=a.wav hivel=200").is_err());
+        assert!(parse("<region> key=60").is_err());
+        assert!(parse("sample=a.wav <region>").is_err());
+        // the exclusive end would overflow
+        let text = format!("<region> sample=a.wav loop_start=0 loop_end={}", usize::MAX);
+        let err = parse(&text).unwrap_err();
+        assert!(err.to_string().contains("loop_end"), "{err}");
+    }
+}
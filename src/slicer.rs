@@ -0,0 +1,314 @@
+//! A slicer, like the ones of the samplers for breakbeats: a loop is chopped
+//! into slices (evenly, or at the onsets of its hits), and a sequence plays
+//! them in a new order, each optionally reversed or pitched.
+
+use crate::analysis::detect_onsets;
+use crate::units::Frames;
+use crate::wav::Wav;
+use dasp::Signal;
+use std::sync::Arc;
+
+/// A loop chopped into slices, which are contiguous and cover the whole
+/// loop.
+#[derive(Clone, Debug)]
+pub struct Slicer {
+    samples: Arc<[f64]>,
+    fs: f64, // sampling rate of the loop
+    // the start and the end (exclusive) of each slice
+    slices: Vec<(Frames, Frames)>,
+}
+
+impl Slicer {
+    /// The loop of the WAV (mixed down to mono) as a single slice.
+    pub fn new(wav: &Wav) -> Self {
+        Self::from_samples(wav.to_mono(), wav.fs)
+    }
+
+    pub fn from_samples(samples: Vec<f64>, fs: f64) -> Self {
+        let len = samples.len();
+        Self {
+            samples: samples.into(),
+            fs,
+            slices: vec![(Frames(0), Frames(len))],
+        }
+    }
+
+    /// Chops the loop into `n` slices of the equal length (which differ by a
+    /// frame at most, if the length isn't divisible by `n`).
+    pub fn with_equal_slices(mut self, n: usize) -> Self {
+        let len = self.samples.len();
+        let n = n.clamp(1, len.max(1));
+        self.slices = (0..n)
+            .map(|i| (Frames(i * len / n), Frames((i + 1) * len / n)))
+            .collect();
+        self
+    }
+
+    /// Chops the loop at the onsets detected by `detect_onsets()` with the
+    /// FFT size of `fft_size` (see `OnsetDetector`; the positions are
+    /// accurate within about an eighth of it). The first slice starts at the
+    /// start of the loop, so it includes anything before the first onset.
+    pub fn with_onset_slices(mut self, fft_size: usize) -> Self {
+        let len = self.samples.len();
+        let mut starts: Vec<usize> = detect_onsets(&self.samples, self.fs, fft_size)
+            .into_iter()
+            .map(|onset| onset.0)
+            .filter(|&start| start < len)
+            .collect();
+        match starts.first_mut() {
+            Some(first) => *first = 0,
+            None => starts.push(0),
+        }
+        let ends = starts.iter().skip(1).copied().chain(std::iter::once(len));
+        self.slices = starts
+            .iter()
+            .zip(ends)
+            .map(|(&start, end)| (Frames(start), Frames(end)))
+            .collect();
+        self
+    }
+
+    pub fn slices(&self) -> &[(Frames, Frames)] {
+        &self.slices
+    }
+
+    /// The number of the slices.
+    pub fn len(&self) -> usize {
+        self.slices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slices.is_empty()
+    }
+
+    /// Plays a slice at the sampling rate of `fs`. It ends at the end of the
+    /// slice (or the start, if reversed), never running into the next one.
+    /// Returns `None` if the slice doesn't exist.
+    pub fn play(&self, step: &SliceStep, fs: f64) -> Option<SliceVoice> {
+        let &(start, end) = self.slices.get(step.slice)?;
+        Some(SliceVoice {
+            samples: self.samples.clone(),
+            start: start.0,
+            len: end.0 - start.0,
+            reverse: step.reverse,
+            pos: 0.0,
+            step: 2.0_f64.powf(step.semitones / 12.0) * self.fs / fs,
+        })
+    }
+
+    /// Plays the slices of the steps of `step_length` at the sampling rate of
+    /// `fs`, and keeps silent after the end of the sequence. A step of `None`
+    /// is a rest, which lets the previous slice sound to its end; otherwise,
+    /// the slice sounding is cut when the next step starts (like the hits of
+    /// a drum machine). An unknown slice is a rest too.
+    ///
+    /// With all the slices in order, unreversed and unpitched, and the step
+    /// as long as the slices, this reconstructs the loop.
+    pub fn sequence(
+        &self,
+        steps: Vec<Option<SliceStep>>,
+        step_length: Frames,
+        fs: f64,
+    ) -> impl Signal<Frame = f64> {
+        Sequence {
+            slicer: self.clone(),
+            fs,
+            steps: steps.into_iter(),
+            step_length: step_length.0.max(1),
+            voice: None,
+            cur_frame: 0,
+        }
+    }
+}
+
+/// A step of a sequence of slices.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SliceStep {
+    /// The index of the slice.
+    pub slice: usize,
+    /// Plays the slice backwards, from its end to its start.
+    pub reverse: bool,
+    /// The pitch in semitones, which changes the speed (and the length) too.
+    pub semitones: f64,
+}
+
+impl SliceStep {
+    /// A step playing the slice forwards at the original pitch.
+    pub fn new(slice: usize) -> Self {
+        Self {
+            slice,
+            reverse: false,
+            semitones: 0.0,
+        }
+    }
+
+    pub fn with_reverse(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+
+    pub fn with_pitch(mut self, semitones: f64) -> Self {
+        self.semitones = semitones;
+        self
+    }
+}
+
+/// A slice being played by `Slicer::play()`. The slice is read at the speed
+/// of the pitch with the linear interpolation.
+pub struct SliceVoice {
+    samples: Arc<[f64]>,
+    start: usize,
+    len: usize,
+    reverse: bool,
+    pos: f64, // the position in the slice, in the frames of the loop
+    step: f64,
+}
+
+impl SliceVoice {
+    // the `i`-th sample of the slice in the order played, or 0.0 beyond it
+    fn sample(&self, i: usize) -> f64 {
+        if i >= self.len {
+            return 0.0;
+        }
+        let i = if self.reverse { self.len - 1 - i } else { i };
+        self.samples[self.start + i]
+    }
+}
+
+impl Signal for SliceVoice {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        if self.is_exhausted() {
+            return 0.0;
+        }
+
+        let i = self.pos.floor() as usize;
+        let frac = self.pos - i as f64;
+        let x = (1.0 - frac) * self.sample(i) + frac * self.sample(i + 1);
+        self.pos += self.step;
+        x
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.pos >= self.len as f64
+    }
+}
+
+struct Sequence {
+    slicer: Slicer,
+    fs: f64,
+    steps: std::vec::IntoIter<Option<SliceStep>>,
+    step_length: usize,
+    voice: Option<SliceVoice>,
+    cur_frame: usize,
+}
+
+impl Signal for Sequence {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        // proceed to the next step
+        if self.cur_frame.is_multiple_of(self.step_length) {
+            let step = self.steps.next().flatten();
+            if let Some(voice) = step.and_then(|step| self.slicer.play(&step, self.fs)) {
+                self.voice = Some(voice);
+            }
+        }
+        self.cur_frame += 1;
+
+        match &mut self.voice {
+            Some(voice) => voice.next(),
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    // a bar of decaying noise bursts at `hits`
+    fn drum_loop(hits: &[usize], len: usize) -> Vec<f64> {
+        let mut samples = vec![0.0; len];
+        let mut seed: u32 = 1;
+        for &start in hits {
+            for (i, x) in samples[start..].iter_mut().take(4800).enumerate() {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                let noise = seed as f64 / u32::MAX as f64 * 2.0 - 1.0;
+                *x = noise * (-(i as f64) / FS / 0.02).exp();
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn identity_sequence_reconstructs_the_loop() {
+        let samples = drum_loop(&[0, 12000, 24000, 36000], 48000);
+        let slicer = Slicer::from_samples(samples.clone(), FS).with_equal_slices(8);
+        assert_eq!(slicer.len(), 8);
+        let steps = (0..8).map(|i| Some(SliceStep::new(i))).collect();
+        let out: Vec<f64> = slicer
+            .sequence(steps, Frames(6000), FS)
+            .take(48000)
+            .collect();
+        assert_eq!(out, samples);
+    }
+
+    #[test]
+    fn slice_plays_exactly_its_region() {
+        let samples: Vec<f64> = (0..100).map(|i| i as f64).collect();
+        let slicer = Slicer::from_samples(samples, FS).with_equal_slices(4);
+
+        let voice = slicer.play(&SliceStep::new(2), FS).unwrap();
+        let out: Vec<f64> = voice.until_exhausted().collect();
+        assert_eq!(out, (50..75).map(|i| i as f64).collect::<Vec<_>>());
+
+        let voice = slicer
+            .play(&SliceStep::new(2).with_reverse(true), FS)
+            .unwrap();
+        let out: Vec<f64> = voice.until_exhausted().collect();
+        assert_eq!(out, (50..75).rev().map(|i| i as f64).collect::<Vec<_>>());
+
+        // an octave up is twice as fast
+        let voice = slicer
+            .play(&SliceStep::new(1).with_pitch(12.0), FS)
+            .unwrap();
+        let out: Vec<f64> = voice.until_exhausted().collect();
+        assert_eq!(out.len(), 13);
+        assert!(out
+            .iter()
+            .zip((25..50).step_by(2))
+            .all(|(&x, i)| (x - i as f64).abs() < 1e-9));
+
+        assert!(slicer.play(&SliceStep::new(4), FS).is_none());
+    }
+
+    #[test]
+    fn onsets_of_the_hits_are_found_within_10_ms() {
+        let hits = [4800, 21000, 37500];
+        let samples = drum_loop(&hits, 48000);
+        let tolerance = 480;
+
+        let onsets = detect_onsets(&samples, FS, 1024);
+        assert_eq!(onsets.len(), hits.len(), "{onsets:?}");
+        for (onset, hit) in onsets.iter().zip(hits) {
+            assert!(onset.0.abs_diff(hit) <= tolerance, "{onset:?} for {hit}");
+        }
+
+        // the first slice includes the silence before the first hit
+        let slicer = Slicer::from_samples(samples, FS).with_onset_slices(1024);
+        let slices = slicer.slices();
+        assert_eq!(slices.len(), 3);
+        assert_eq!(slices[0].0, Frames(0));
+        assert_eq!(slices[2].1, Frames(48000));
+        for (slice, hit) in slices[1..].iter().zip(&hits[1..]) {
+            assert!(
+                slice.0 .0.abs_diff(*hit) <= tolerance,
+                "{slice:?} for {hit}"
+            );
+        }
+    }
+}
@@ -0,0 +1,1764 @@
+//! Songs: tracks of step patterns played by simple synth voices, with
+//! automation lanes sweeping their parameters over the bars, read from TOML
+//! files like `songs/sweep.toml`:
+//!
+//! ```toml
+//! bpm = 120
+//! bars = 16
+//!
+//! [[tracks]]
+//! name = "bass"
+//! waveform = "saw"
+//! pattern = "C2 . C2 . Eb2 . . G1"
+//!
+//! [[automation]]
+//! param = "bass.cutoff"
+//! mode = "exponential"
+//! points = [{ at = "1:1", value = 300 }, { at = "9:1", value = 5000 }]
+//! ```
+//!
+//! A pattern is a list of steps separated by spaces: a note (e.g. `C2`,
+//! `F#3`, or `Bb4`; `C4` is the MIDI key 60), `-` to hold the previous note
+//! for one more step, or `.` for a rest. It loops until the end of the song.
+//!
+//! Each track has the parameters `<name>.cutoff` (the cutoff of its low-pass
+//! in Hz) and `<name>.level` (its gain) in the `ParamSet` of `Song::params()`,
+//! which the automation lanes (and anything else, e.g. the OSC server) set
+//! while playing.
+//!
+//! The mix of the tracks goes through the effects of the `[[master]]` tables
+//! in order, named by `type` (see `MasterEffect`):
+//!
+//! ```toml
+//! [[master]]
+//! type = "compressor"
+//! threshold = -18
+//! ratio = 3
+//!
+//! [[master]]
+//! type = "limiter"
+//! ceiling = -1
+//! ```
+//!
+//! With the `live-reload` feature, `watch()` reloads the file on every change
+//! while playing, swapping in the new version at the next bar.
+
+use crate::core::biquad::Biquad;
+use crate::core::envelope::EnvelopeFollower;
+use crate::core::multiosc::Waveform;
+use crate::core::smooth::SmoothedParam;
+use crate::effects::{GainComputer, Limiter};
+use crate::params::{AtomicF64, ParamSet};
+use crate::poly::{sort_events, PolySynth, TimedEvent, VoiceEvent};
+use crate::units::{Db, Frames, Hz, Ms};
+use anyhow::{anyhow, bail, Context};
+use dasp::Signal;
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// the number of the frames for which the automation lanes are evaluated
+// once, and the size of the blocks of `Signal::next()`
+const BLOCK: usize = 64;
+
+// the time for the parameters to glide to the values set, so that the steps
+// of the blocks don't make zipper noise
+const PARAM_SMOOTHING: Ms = Ms(10.0);
+
+const Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+/// A song: tracks of patterns of the steps of `1 / steps_per_bar` of a bar
+/// (of 4/4), played for `bars` at `bpm`.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Song {
+    pub bpm: f64,
+    pub bars: usize,
+    /// 16 (sixteenth notes) by default.
+    pub steps_per_bar: usize,
+    pub tracks: Vec<Track>,
+    pub automation: Vec<AutomationLane>,
+    /// The effects on the mix of the tracks, in order.
+    pub master: Vec<MasterEffect>,
+}
+
+impl Default for Song {
+    fn default() -> Self {
+        Self {
+            bpm: 120.0,
+            bars: 0,
+            steps_per_bar: 16,
+            tracks: vec![],
+            automation: vec![],
+            master: vec![],
+        }
+    }
+}
+
+/// A track of a song, played by a `PolySynth` through a low-pass.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Track {
+    pub name: String,
+    pub waveform: TrackWaveform,
+    pub pattern: String,
+    /// The length of a note relative to the step (0.0 to 1.0); a note held
+    /// by `-` lasts the whole steps before its last one.
+    pub gate: f64,
+    pub level: f64,
+    /// -1.0 (left) to 1.0 (right)
+    pub pan: f64,
+    /// The initial cutoff of the low-pass in Hz.
+    pub cutoff: f64,
+    /// The attack and the release of the notes in milliseconds.
+    pub attack: f64,
+    pub release: f64,
+    /// Another track above this one, whose level turns this one down by
+    /// `duck_depth` (0.0 to 1.0) at most, e.g. a pad ducked by the kick.
+    pub duck_by: Option<String>,
+    pub duck_depth: f64,
+}
+
+impl Default for Track {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            waveform: TrackWaveform::default(),
+            pattern: String::new(),
+            gate: 0.5,
+            level: 0.3,
+            pan: 0.0,
+            cutoff: 2000.0,
+            attack: 5.0,
+            release: 100.0,
+            duck_by: None,
+            duck_depth: 0.8,
+        }
+    }
+}
+
+/// The waveforms of the tracks, named as in the song files.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackWaveform {
+    Sine,
+    Triangle,
+    #[default]
+    Saw,
+    Square,
+}
+
+impl From<TrackWaveform> for Waveform {
+    fn from(waveform: TrackWaveform) -> Self {
+        match waveform {
+            TrackWaveform::Sine => Waveform::Sine,
+            TrackWaveform::Triangle => Waveform::Triangle,
+            TrackWaveform::Saw => Waveform::Saw,
+            TrackWaveform::Square => Waveform::Pulse,
+        }
+    }
+}
+
+/// The effects of the master chain, named by `type` in the song files. An
+/// unknown name is an error listing these.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MasterEffect {
+    Eq(EqSettings),
+    Compressor(CompressorSettings),
+    Limiter(LimiterSettings),
+}
+
+/// A high-pass and a high shelf. Either is skipped if 0.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct EqSettings {
+    /// The cutoff of the high-pass in Hz.
+    pub low_cut: f64,
+    /// The corner of the high shelf in Hz, and its gain in dB.
+    pub high_shelf: f64,
+    pub high_gain: f64,
+}
+
+impl Default for EqSettings {
+    fn default() -> Self {
+        Self {
+            low_cut: 0.0,
+            high_shelf: 8000.0,
+            high_gain: 0.0,
+        }
+    }
+}
+
+/// A `Compressor` keyed by the louder channel, so that both channels are
+/// compressed by the same gain.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressorSettings {
+    /// The threshold in dB.
+    pub threshold: f64,
+    pub ratio: f64,
+    /// The attack and the release in milliseconds.
+    pub attack: f64,
+    pub release: f64,
+}
+
+impl Default for CompressorSettings {
+    fn default() -> Self {
+        Self {
+            threshold: -18.0,
+            ratio: 3.0,
+            attack: 10.0,
+            release: 100.0,
+        }
+    }
+}
+
+/// A `Limiter` linked across the channels.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimiterSettings {
+    /// The ceiling in dB.
+    pub ceiling: f64,
+    /// The release in milliseconds.
+    pub release: f64,
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        Self {
+            ceiling: -1.0,
+            release: 50.0,
+        }
+    }
+}
+
+impl MasterEffect {
+    /// The name of the effect in the song files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            MasterEffect::Eq(_) => "eq",
+            MasterEffect::Compressor(_) => "compressor",
+            MasterEffect::Limiter(_) => "limiter",
+        }
+    }
+
+    fn validate(&self) -> Result<(), anyhow::Error> {
+        let is_time = |ms: f64| ms >= 0.0 && ms.is_finite();
+        match self {
+            MasterEffect::Eq(eq) => {
+                if !(eq.low_cut >= 0.0 && eq.low_cut.is_finite()) {
+                    bail!("invalid low_cut: {}", eq.low_cut);
+                }
+                if !(eq.high_shelf > 0.0 && eq.high_shelf.is_finite()) {
+                    bail!("invalid high_shelf: {}", eq.high_shelf);
+                }
+                if !eq.high_gain.is_finite() {
+                    bail!("invalid high_gain: {}", eq.high_gain);
+                }
+            }
+            MasterEffect::Compressor(comp) => {
+                if !comp.threshold.is_finite() {
+                    bail!("invalid threshold: {}", comp.threshold);
+                }
+                if !(comp.ratio >= 1.0 && comp.ratio.is_finite()) {
+                    bail!("ratio must be 1 or more: {}", comp.ratio);
+                }
+                if !(is_time(comp.attack) && is_time(comp.release)) {
+                    bail!(
+                        "invalid attack or release: {}, {}",
+                        comp.attack,
+                        comp.release
+                    );
+                }
+            }
+            MasterEffect::Limiter(limiter) => {
+                if !limiter.ceiling.is_finite() {
+                    bail!("invalid ceiling: {}", limiter.ceiling);
+                }
+                if !is_time(limiter.release) {
+                    bail!("invalid release: {}", limiter.release);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A step of a pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatternStep {
+    /// Starts a note of the MIDI key.
+    Note(u8),
+    /// Holds the previous note (`-`).
+    Hold,
+    /// `.`
+    Rest,
+}
+
+impl Track {
+    /// Parses the pattern.
+    pub fn steps(&self) -> Result<Vec<PatternStep>, anyhow::Error> {
+        self.pattern
+            .split_whitespace()
+            .map(|token| match token {
+                "-" => Ok(PatternStep::Hold),
+                "." => Ok(PatternStep::Rest),
+                _ => parse_note(token)
+                    .map(PatternStep::Note)
+                    .ok_or_else(|| anyhow!("invalid step: {token}")),
+            })
+            .collect()
+    }
+}
+
+/// The MIDI key of a note name such as `C4` (60), `F#3`, or `Bb-1`.
+pub fn parse_note(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    let pitch_class: i32 = match chars.next()? {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    let (accidental, octave) = match rest.strip_prefix('#') {
+        Some(octave) => (1, octave),
+        None => match rest.strip_prefix('b') {
+            Some(octave) => (-1, octave),
+            None => (0, rest),
+        },
+    };
+    let octave: i32 = octave.parse().ok()?;
+    let key = (octave + 1) * 12 + pitch_class + accidental;
+    u8::try_from(key).ok().filter(|&key| key <= 127)
+}
+
+/// A lane of breakpoints of a parameter, interpolated between them and held
+/// before the first and after the last.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AutomationLane {
+    pub param: String,
+    #[serde(default)]
+    pub mode: Interpolation,
+    pub points: Vec<Breakpoint>,
+}
+
+/// How an automation lane goes from a breakpoint to the next.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    /// Jumps at the next breakpoint.
+    Step,
+    #[default]
+    Linear,
+    /// Changes by the same ratio per time, which sounds even for
+    /// frequency-like parameters (e.g. a cutoff). The values must be
+    /// positive.
+    Exponential,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Breakpoint {
+    pub at: Position,
+    pub value: f64,
+}
+
+/// The time of a breakpoint: seconds from the start of the song, or a string
+/// of `"bar:step"` counted from 1 (e.g. `"9:1"`, or just `"9"`, is the
+/// start of the 9th bar).
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Position {
+    Seconds(f64),
+    BarStep(String),
+}
+
+/// The lengths of the steps and bars of a song at a sampling rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timing {
+    pub fs: f64, // sampling rate
+    pub step_length: Frames,
+    pub steps_per_bar: usize,
+}
+
+impl Timing {
+    pub fn bar_length(&self) -> Frames {
+        Frames(self.step_length.0 * self.steps_per_bar)
+    }
+}
+
+impl Position {
+    /// The frame of the position.
+    pub fn to_frame(&self, timing: &Timing) -> Result<f64, anyhow::Error> {
+        match self {
+            Self::Seconds(seconds) if *seconds >= 0.0 => Ok(seconds * timing.fs),
+            Self::Seconds(seconds) => bail!("negative time: {seconds}"),
+            Self::BarStep(s) => {
+                let (bar, step) = s.split_once(':').unwrap_or((s, "1"));
+                let parse = |n: &str| n.trim().parse::<usize>().ok().filter(|&n| n >= 1);
+                let (Some(bar), Some(step)) = (parse(bar), parse(step)) else {
+                    bail!("invalid position: {s} (bar:step, counted from 1)");
+                };
+                if step > timing.steps_per_bar {
+                    bail!(
+                        "invalid position: {s} (a bar has {} steps)",
+                        timing.steps_per_bar
+                    );
+                }
+                let steps = (bar - 1) * timing.steps_per_bar + step - 1;
+                Ok((steps * timing.step_length.0) as f64)
+            }
+        }
+    }
+}
+
+impl AutomationLane {
+    /// The lane with the positions in frames, checking that the breakpoints
+    /// are in order (and positive for the exponential interpolation).
+    pub fn curve(&self, timing: &Timing) -> Result<LaneCurve, anyhow::Error> {
+        if self.points.is_empty() {
+            bail!("no breakpoints");
+        }
+        let points = self
+            .points
+            .iter()
+            .map(|p| Ok((p.at.to_frame(timing)?, p.value)))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        if points.windows(2).any(|w| w[1].0 < w[0].0) {
+            bail!("the breakpoints are not in order");
+        }
+        if self.mode == Interpolation::Exponential && points.iter().any(|&(_, v)| v <= 0.0) {
+            bail!("the values of the exponential interpolation must be positive");
+        }
+        Ok(LaneCurve {
+            mode: self.mode,
+            points,
+        })
+    }
+}
+
+/// An automation lane ready to evaluate, made by `AutomationLane::curve()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LaneCurve {
+    mode: Interpolation,
+    // the frames and the values, in order
+    points: Vec<(f64, f64)>,
+}
+
+impl LaneCurve {
+    /// The value at the frame. Before the first breakpoint it's the value of
+    /// the first, and after the last it's the value of the last. At two
+    /// breakpoints of the same time, the value jumps to the later one.
+    pub fn value_at(&self, frame: f64) -> f64 {
+        let i = self.points.partition_point(|&(f, _)| f <= frame);
+        if i == 0 {
+            return self.points[0].1;
+        }
+        if i == self.points.len() {
+            return self.points[i - 1].1;
+        }
+
+        let (f0, v0) = self.points[i - 1];
+        let (f1, v1) = self.points[i];
+        let t = (frame - f0) / (f1 - f0);
+        match self.mode {
+            Interpolation::Step => v0,
+            Interpolation::Linear => v0 + (v1 - v0) * t,
+            Interpolation::Exponential => v0 * (v1 / v0).powf(t),
+        }
+    }
+}
+
+impl Song {
+    /// Parses and validates a song.
+    pub fn from_toml(s: &str) -> Result<Self, anyhow::Error> {
+        let song: Self = toml::from_str(s)?;
+        song.validate()?;
+        Ok(song)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_toml(&s).with_context(|| format!("invalid song: {}", path.display()))
+    }
+
+    /// Checks everything `SongPlayer::new()` would fail on, at any sampling
+    /// rate.
+    pub fn validate(&self) -> Result<(), anyhow::Error> {
+        if !(self.bpm > 0.0 && self.bpm.is_finite()) {
+            bail!("invalid bpm: {}", self.bpm);
+        }
+        if self.bars == 0 {
+            bail!("bars must be at least 1");
+        }
+        if self.steps_per_bar == 0 {
+            bail!("steps_per_bar must be at least 1");
+        }
+
+        let params = self.params();
+        for (i, track) in self.tracks.iter().enumerate() {
+            if track.name.is_empty() {
+                bail!("the track {} has no name", i + 1);
+            }
+            if self.tracks[..i].iter().any(|t| t.name == track.name) {
+                bail!("duplicate track: {}", track.name);
+            }
+            let steps = track
+                .steps()
+                .with_context(|| format!("track {}", track.name))?;
+            if steps.is_empty() {
+                bail!("track {}: the pattern is empty", track.name);
+            }
+            if !(track.cutoff > 0.0 && track.cutoff.is_finite()) {
+                bail!("track {}: invalid cutoff: {}", track.name, track.cutoff);
+            }
+            if let Some(key) = &track.duck_by {
+                // which also rules out the loops of the ducking
+                if !self.tracks[..i].iter().any(|t| &t.name == key) {
+                    bail!(
+                        "track {}: duck_by must be a track above it: {key}",
+                        track.name
+                    );
+                }
+            }
+        }
+
+        for (i, effect) in self.master.iter().enumerate() {
+            effect
+                .validate()
+                .with_context(|| format!("master effect {} ({})", i + 1, effect.name()))?;
+        }
+
+        let timing = self.timing(48000.0);
+        for lane in &self.automation {
+            if params.get(&lane.param).is_none() {
+                bail!("automation of an unknown parameter: {}", lane.param);
+            }
+            lane.curve(&timing)
+                .with_context(|| format!("automation of {}", lane.param))?;
+        }
+        Ok(())
+    }
+
+    pub fn timing(&self, fs: f64) -> Timing {
+        Timing {
+            fs,
+            step_length: Ms::from_note(self.bpm, 1.0 / self.steps_per_bar as f64).to_frames(fs),
+            steps_per_bar: self.steps_per_bar,
+        }
+    }
+
+    /// The length of the bars (without the release of the last notes).
+    pub fn length(&self, fs: f64) -> Frames {
+        Frames(self.timing(fs).bar_length().0 * self.bars)
+    }
+
+    /// The parameters of the tracks at their initial values.
+    pub fn params(&self) -> ParamSet {
+        let mut params = ParamSet::new();
+        for track in &self.tracks {
+            params.add(&format!("{}.cutoff", track.name), track.cutoff);
+            params.add(&format!("{}.level", track.name), track.level);
+        }
+        params
+    }
+}
+
+/// Plays a song, and ends after the release of the last notes.
+///
+/// It's rendered in blocks by `render_block()`, or by `next()` of `Signal`
+/// which renders blocks of 64 frames. The notes start and end at their
+/// exact frames within the blocks, and the automation lanes are evaluated
+/// every 64 frames from the start, whatever the size of the blocks, and
+/// written to the parameters, which the tracks read then. So the output
+/// doesn't depend on the size of the blocks.
+///
+/// A new version of the song can be swapped in while playing by a
+/// `Reloader` of `reloader()`.
+///
+/// To render a whole song offline on many threads, use `render()`.
+pub struct SongPlayer {
+    timing: Timing,
+    tracks: Vec<TrackVoice>,
+    master: MasterChain,
+    // the output of each track for a chunk
+    bufs: Vec<Vec<[f64; 2]>>,
+    length: usize,
+    tail: usize,
+    // the frame to render next
+    cur_frame: usize,
+    // the block rendered for `next()`, and the position in it
+    block: Vec<[f64; 2]>,
+    block_pos: usize,
+    // the update sent by the `Reloader`, and the one waiting for the next bar
+    updates: Arc<Mutex<Option<SongUpdate>>>,
+    pending: Option<SongUpdate>,
+}
+
+impl SongPlayer {
+    /// `params` should be from `song.params()`, possibly shared with another
+    /// thread.
+    pub fn new(song: &Song, fs: f64, params: &ParamSet) -> Result<Self, anyhow::Error> {
+        let SongUpdate {
+            timing,
+            patterns,
+            lanes,
+            master,
+            length,
+            tail,
+        } = SongUpdate::new(song, fs, params)?;
+        let tracks = song
+            .tracks
+            .iter()
+            .zip(patterns.into_iter().zip(lanes))
+            .map(|(track, (pattern, lanes))| {
+                let param = |name: &str| lookup(params, &format!("{}.{name}", track.name));
+                Ok(TrackVoice::new(
+                    pattern,
+                    lanes,
+                    &timing,
+                    param("cutoff")?,
+                    param("level")?,
+                ))
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        Ok(Self {
+            timing,
+            bufs: vec![vec![[0.0; 2]; BLOCK]; tracks.len()],
+            tracks,
+            master,
+            length,
+            tail,
+            cur_frame: 0,
+            block: vec![[0.0; 2]; BLOCK],
+            block_pos: BLOCK,
+            updates: Arc::default(),
+            pending: None,
+        })
+    }
+
+    /// A `Reloader` of the file of `song`, which this player is playing,
+    /// with the same `params`.
+    pub fn reloader<P: Into<PathBuf>>(
+        &self,
+        path: P,
+        song: Song,
+        params: Arc<ParamSet>,
+    ) -> Reloader {
+        Reloader {
+            path: path.into(),
+            song,
+            fs: self.timing.fs,
+            params,
+            updates: self.updates.clone(),
+        }
+    }
+
+    /// The length of the song including the release of the last notes.
+    pub fn total_length(&self) -> Frames {
+        Frames(self.length + self.tail)
+    }
+
+    /// Whether `render_block()` has rendered the whole song.
+    pub fn is_finished(&self) -> bool {
+        self.cur_frame >= self.length + self.tail
+    }
+
+    /// The frame to render next, counted from the start of the song at the
+    /// current tempo.
+    pub fn position(&self) -> Frames {
+        Frames(self.cur_frame)
+    }
+
+    /// Whether an update from the `Reloader` is waiting for the next bar.
+    pub fn has_pending_update(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Renders the next `out.len()` frames into `out`, the mix of the tracks
+    /// through the master chain. After the end of the song, it renders
+    /// silence (and the tails of the master effects, if any).
+    pub fn render_block(&mut self, out: &mut [[f64; 2]]) {
+        out.fill([0.0; 2]);
+        let mut pos = 0;
+        while pos < out.len() {
+            // a newer update replaces the one waiting
+            if let Some(update) = self.updates.try_lock().ok().and_then(|mut u| u.take()) {
+                self.pending = Some(update);
+            }
+            let bar_length = self.timing.bar_length().0.max(1);
+            if self.cur_frame.is_multiple_of(bar_length) {
+                if let Some(update) = self.pending.take() {
+                    self.swap(update);
+                }
+            }
+
+            // up to the next evaluation of the lanes, or the next bar for the
+            // update waiting
+            let mut len = (BLOCK - self.cur_frame % BLOCK).min(out.len() - pos);
+            if self.pending.is_some() {
+                let bar_length = self.timing.bar_length().0.max(1);
+                len = len.min(bar_length - self.cur_frame % bar_length);
+            }
+            let chunk = &mut out[pos..pos + len];
+            for (i, track) in self.tracks.iter_mut().enumerate() {
+                // the tracks ducking this one are above it
+                let (above, rest) = self.bufs.split_at_mut(i);
+                let key = track.pattern.duck.map(|(key, _)| &above[key][..len]);
+                let buf = &mut rest[0][..len];
+                track.render(self.cur_frame, self.length, key, buf);
+                mix(chunk, buf);
+            }
+            pos += len;
+            self.cur_frame += len;
+        }
+        self.master.process(out);
+    }
+
+    // Swaps in the update at the start of a bar, keeping the position in
+    // bars even if the tempo has changed.
+    fn swap(&mut self, update: SongUpdate) {
+        let bar = self.cur_frame / self.timing.bar_length().0.max(1);
+        let frame = bar * update.timing.bar_length().0;
+        let updates = update.patterns.into_iter().zip(update.lanes);
+        for (track, (pattern, lanes)) in self.tracks.iter_mut().zip(updates) {
+            track.swap(pattern, lanes, &update.timing, self.cur_frame, frame);
+        }
+        // the effects unchanged keep their states
+        if update.master.effects != self.master.effects {
+            self.master = update.master;
+        }
+        self.timing = update.timing;
+        self.length = update.length;
+        self.tail = update.tail;
+        self.cur_frame = frame;
+    }
+}
+
+impl Signal for SongPlayer {
+    type Frame = [f64; 2];
+
+    fn next(&mut self) -> Self::Frame {
+        if self.block_pos == self.block.len() {
+            let mut block = std::mem::take(&mut self.block);
+            self.render_block(&mut block);
+            self.block = block;
+            self.block_pos = 0;
+        }
+        self.block_pos += 1;
+        self.block[self.block_pos - 1]
+    }
+
+    fn is_exhausted(&self) -> bool {
+        // the frames returned by `next()` so far
+        let played = self
+            .cur_frame
+            .saturating_sub(self.block.len() - self.block_pos);
+        played >= self.length + self.tail
+    }
+}
+
+/// The result of `render()`.
+pub struct Rendered {
+    pub frames: Vec<[f64; 2]>,
+    /// The number of the threads used, which is 1 if the tracks depend on
+    /// each other.
+    pub threads: usize,
+    /// The sum of the times each track took, or the whole time if rendered
+    /// on one thread.
+    pub track_time: Duration,
+    pub wall_time: Duration,
+}
+
+impl Rendered {
+    /// How many times faster the rendering was than on one thread
+    /// (approximately, as the time of mixing isn't counted).
+    pub fn speedup(&self) -> f64 {
+        self.track_time.as_secs_f64() / self.wall_time.as_secs_f64()
+    }
+}
+
+/// Renders the whole song (with the release of the last notes) offline.
+/// With `threads` more than 1, each track is rendered to its own buffer on a
+/// pool of the threads, and they are mixed in the order of the tracks, so
+/// the output is identical to the one of `SongPlayer` whatever the number of
+/// the threads. Each track has its own state, including the parameters it
+/// reads.
+///
+/// The master chain is applied to the mix afterwards, on one thread.
+///
+/// If a track depends on another by `duck_by`, the song is rendered on one
+/// thread instead.
+pub fn render(song: &Song, fs: f64, threads: usize) -> Result<Rendered, anyhow::Error> {
+    let start = Instant::now();
+    let mut player = SongPlayer::new(song, fs, &song.params())?;
+    let len = player.total_length().0;
+    let mut frames = vec![[0.0; 2]; len];
+
+    let depends = player.tracks.iter().any(|t| t.pattern.duck.is_some());
+    if threads <= 1 || depends {
+        player.render_block(&mut frames);
+        let time = start.elapsed();
+        return Ok(Rendered {
+            frames,
+            threads: 1,
+            track_time: time,
+            wall_time: time,
+        });
+    }
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()?;
+    let length = player.length;
+    let stems: Vec<(Vec<[f64; 2]>, Duration)> = pool.install(|| {
+        player
+            .tracks
+            .par_iter_mut()
+            .map(|track| {
+                let start = Instant::now();
+                let mut stem = vec![[0.0; 2]; len];
+                // the same chunks as `SongPlayer::render_block()`
+                for (i, chunk) in stem.chunks_mut(BLOCK).enumerate() {
+                    track.render(i * BLOCK, length, None, chunk);
+                }
+                (stem, start.elapsed())
+            })
+            .collect()
+    });
+    for (stem, _) in &stems {
+        mix(&mut frames, stem);
+    }
+    player.master.process(&mut frames);
+
+    Ok(Rendered {
+        frames,
+        threads,
+        track_time: stems.iter().map(|(_, time)| *time).sum(),
+        wall_time: start.elapsed(),
+    })
+}
+
+fn mix(out: &mut [[f64; 2]], track: &[[f64; 2]]) {
+    for (y, x) in out.iter_mut().zip(track) {
+        y[0] += x[0];
+        y[1] += x[1];
+    }
+}
+
+fn lookup(params: &ParamSet, name: &str) -> Result<Arc<AtomicF64>, anyhow::Error> {
+    params
+        .get(name)
+        .cloned()
+        .ok_or_else(|| anyhow!("no such parameter: {name}"))
+}
+
+/// The effects of the master chain, applied to the frames in place.
+struct MasterChain {
+    effects: Vec<MasterEffect>,
+    stages: Vec<MasterStage>,
+}
+
+enum MasterStage {
+    // the filters of the channels, in order
+    Eq(Vec<[Biquad; 2]>),
+    Compressor(GainComputer),
+    Limiter(Limiter),
+}
+
+impl MasterChain {
+    fn new(effects: &[MasterEffect], fs: f64) -> Self {
+        let cutoff = |fc: f64| Hz(fc.min(fs * 0.45));
+        let stages = effects
+            .iter()
+            .map(|effect| match effect {
+                MasterEffect::Eq(eq) => {
+                    let mut filters = vec![];
+                    if eq.low_cut > 0.0 {
+                        let fc = cutoff(eq.low_cut);
+                        filters.push([(); 2].map(|_| Biquad::high_pass(fs, fc, Q)));
+                    }
+                    if eq.high_gain != 0.0 {
+                        let (fc, gain) = (cutoff(eq.high_shelf), Db(eq.high_gain));
+                        filters.push([(); 2].map(|_| Biquad::high_shelf(fs, fc, Q, gain)));
+                    }
+                    MasterStage::Eq(filters)
+                }
+                MasterEffect::Compressor(comp) => MasterStage::Compressor(
+                    GainComputer::new(fs, Db(comp.threshold), comp.ratio)
+                        .with_times(Ms(comp.attack), Ms(comp.release)),
+                ),
+                MasterEffect::Limiter(limiter) => {
+                    MasterStage::Limiter(Limiter::new(fs, Db(limiter.ceiling), Ms(limiter.release)))
+                }
+            })
+            .collect();
+        Self {
+            effects: effects.to_vec(),
+            stages,
+        }
+    }
+
+    fn process(&mut self, frames: &mut [[f64; 2]]) {
+        for stage in &mut self.stages {
+            for frame in frames.iter_mut() {
+                match stage {
+                    MasterStage::Eq(filters) => {
+                        for filters in filters.iter_mut() {
+                            for (x, filter) in frame.iter_mut().zip(filters) {
+                                *x = filter.process(*x);
+                            }
+                        }
+                    }
+                    MasterStage::Compressor(computer) => {
+                        let key = frame[0].abs().max(frame[1].abs());
+                        let gain = Db(-computer.process(key).0).to_gain();
+                        *frame = frame.map(|x| x * gain);
+                    }
+                    MasterStage::Limiter(limiter) => {
+                        let gain = limiter.gain(frame[0].abs().max(frame[1].abs()));
+                        *frame = frame.map(|x| x * gain);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A version of a song prepared off the audio thread, for `SongPlayer` to
+/// start with or to swap in.
+struct SongUpdate {
+    timing: Timing,
+    patterns: Vec<TrackPattern>,
+    // the lanes of the parameters of each track
+    lanes: Vec<Vec<(LaneCurve, Arc<AtomicF64>)>>,
+    master: MasterChain,
+    length: usize,
+    tail: usize,
+}
+
+impl SongUpdate {
+    fn new(song: &Song, fs: f64, params: &ParamSet) -> Result<Self, anyhow::Error> {
+        song.validate()?;
+        let timing = song.timing(fs);
+        let patterns = song
+            .tracks
+            .iter()
+            .map(|track| TrackPattern::new(track, song))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        let mut lanes: Vec<_> = song.tracks.iter().map(|_| vec![]).collect();
+        for lane in &song.automation {
+            // all the parameters are of the tracks
+            let Some(i) = song.tracks.iter().position(|t| {
+                lane.param
+                    .strip_prefix(&t.name)
+                    .is_some_and(|p| p.starts_with('.'))
+            }) else {
+                bail!("automation of an unknown parameter: {}", lane.param);
+            };
+            lanes[i].push((lane.curve(&timing)?, lookup(params, &lane.param)?));
+        }
+        let tail = song
+            .tracks
+            .iter()
+            .map(|t| Ms(t.release).to_frames(fs).0)
+            .max()
+            .unwrap_or(0);
+
+        Ok(Self {
+            timing,
+            patterns,
+            lanes,
+            master: MasterChain::new(&song.master, fs),
+            length: song.length(fs).0,
+            tail,
+        })
+    }
+}
+
+/// Reloads the file of a song playing by a `SongPlayer` (see
+/// `SongPlayer::reloader()`), e.g. on every change of the file.
+pub struct Reloader {
+    path: PathBuf,
+    song: Song,
+    fs: f64, // sampling rate
+    params: Arc<ParamSet>,
+    updates: Arc<Mutex<Option<SongUpdate>>>,
+}
+
+impl Reloader {
+    /// Reads the file again, and if it has changed, sends the new version to
+    /// the player, which swaps it in at the start of the next bar, keeping
+    /// the position in bars. The initial cutoffs and levels of the tracks
+    /// that have changed are set to the parameters immediately.
+    ///
+    /// Returns whether the file has changed. If the file is invalid, or
+    /// adds, removes, or reorders the tracks, the error is returned and the
+    /// player keeps playing the current version.
+    pub fn reload(&mut self) -> Result<bool, anyhow::Error> {
+        let song = Song::load(&self.path)?;
+        if song == self.song {
+            return Ok(false);
+        }
+        let names = |song: &Song| {
+            song.tracks
+                .iter()
+                .map(|t| t.name.clone())
+                .collect::<Vec<_>>()
+        };
+        if names(&song) != names(&self.song) {
+            bail!("the tracks can't be added, removed, or reordered while playing");
+        }
+        let update = SongUpdate::new(&song, self.fs, &self.params)?;
+
+        for (old, new) in self.song.tracks.iter().zip(&song.tracks) {
+            if new.cutoff != old.cutoff {
+                self.params.set(&format!("{}.cutoff", new.name), new.cutoff);
+            }
+            if new.level != old.level {
+                self.params.set(&format!("{}.level", new.name), new.level);
+            }
+        }
+        *self.updates.lock().unwrap() = Some(update);
+        self.song = song;
+        Ok(true)
+    }
+
+    /// The version of the song sent last.
+    pub fn song(&self) -> &Song {
+        &self.song
+    }
+}
+
+/// Reloads the song by the `Reloader` on every change of its file, printing
+/// the errors, until the returned watcher is dropped. The directory of the
+/// file is watched, as many editors save a file by replacing it.
+#[cfg(feature = "live-reload")]
+pub fn watch(mut reloader: Reloader) -> Result<notify::RecommendedWatcher, anyhow::Error> {
+    use notify::Watcher;
+
+    let path = reloader.path.clone();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let Ok(event) = event else {
+            return;
+        };
+        let is_song = event
+            .paths
+            .iter()
+            .any(|p| p.file_name() == path.file_name());
+        if !is_song || !(event.kind.is_modify() || event.kind.is_create()) {
+            return;
+        }
+        match reloader.reload() {
+            Ok(true) => eprintln!("reloaded {}; swapping in at the next bar", path.display()),
+            Ok(false) => {}
+            Err(e) => eprintln!("{e:#}; keeping the current version"),
+        }
+    })?;
+    watcher.watch(&dir, notify::RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+// the number of the voices of each track, enough for the releases to overlap
+const TRACK_VOICES: usize = 8;
+
+// the settings of a track that a new version of the song can change
+struct TrackPattern {
+    steps: Vec<PatternStep>,
+    gate: f64,
+    waveform: Waveform,
+    attack: Ms,
+    release: Ms,
+    pan: [f64; 2],
+    // the index of the track ducking this one, and the depth
+    duck: Option<(usize, f64)>,
+}
+
+impl TrackPattern {
+    fn new(track: &Track, song: &Song) -> Result<Self, anyhow::Error> {
+        let duck = match &track.duck_by {
+            Some(key) => {
+                let i = song
+                    .tracks
+                    .iter()
+                    .position(|t| &t.name == key)
+                    .ok_or_else(|| anyhow!("no such track: {key}"))?;
+                Some((i, track.duck_depth.clamp(0.0, 1.0)))
+            }
+            None => None,
+        };
+        // the equal-power pan law, normalized to 1.0 at the center as the
+        // voices are centered already
+        let angle = (track.pan.clamp(-1.0, 1.0) + 1.0) * std::f64::consts::FRAC_PI_4;
+        Ok(Self {
+            steps: track.steps()?,
+            gate: track.gate.clamp(0.0, 1.0),
+            waveform: track.waveform.into(),
+            attack: Ms(track.attack),
+            release: Ms(track.release),
+            pan: [angle.cos(), angle.sin()].map(|g| g * std::f64::consts::SQRT_2),
+            duck,
+        })
+    }
+}
+
+// how fast the ducking follows the level of the other track
+const DUCK_ATTACK: Ms = Ms(1.0);
+const DUCK_RELEASE: Ms = Ms(150.0);
+
+struct TrackVoice {
+    fs: f64, // sampling rate
+    pattern: TrackPattern,
+    lanes: Vec<(LaneCurve, Arc<AtomicF64>)>,
+    // whether the lanes should be evaluated before the next frame even if
+    // it's not on the grid of the blocks, i.e. after a swap
+    lanes_due: bool,
+    ducker: EnvelopeFollower,
+    step_length: usize,
+    synth: PolySynth,
+    lpf: [Biquad; 2],
+    cutoff_param: Arc<AtomicF64>,
+    level_param: Arc<AtomicF64>,
+    cutoff: SmoothedParam,
+    level: SmoothedParam,
+    // the frames and the keys of the Note Offs to come
+    note_offs: Vec<(usize, u8)>,
+    // the buffers for each segment, allocated once
+    events: Vec<TimedEvent>,
+    buf: Vec<[f64; 2]>,
+}
+
+impl TrackVoice {
+    fn new(
+        pattern: TrackPattern,
+        lanes: Vec<(LaneCurve, Arc<AtomicF64>)>,
+        timing: &Timing,
+        cutoff_param: Arc<AtomicF64>,
+        level_param: Arc<AtomicF64>,
+    ) -> Self {
+        let fs = timing.fs;
+        let cutoff = cutoff_param.get();
+        Self {
+            fs,
+            step_length: timing.step_length.0.max(1),
+            synth: PolySynth::new(fs, pattern.waveform, TRACK_VOICES)
+                .with_envelope(pattern.attack, pattern.release),
+            pattern,
+            lanes,
+            lanes_due: false,
+            ducker: EnvelopeFollower::new(fs, DUCK_ATTACK, DUCK_RELEASE),
+            lpf: [(); 2].map(|_| Biquad::low_pass(fs, Hz(cutoff), Q)),
+            cutoff: SmoothedParam::new(fs, PARAM_SMOOTHING, cutoff),
+            level: SmoothedParam::new(fs, PARAM_SMOOTHING, level_param.get()),
+            cutoff_param,
+            level_param,
+            note_offs: Vec::with_capacity(TRACK_VOICES),
+            events: Vec::with_capacity(2 * TRACK_VOICES),
+            buf: vec![[0.0; 2]; BLOCK],
+        }
+    }
+
+    // Swaps in the pattern at `from`, which becomes `to` on the new timing.
+    // The notes sounding keep their lengths.
+    fn swap(
+        &mut self,
+        pattern: TrackPattern,
+        lanes: Vec<(LaneCurve, Arc<AtomicF64>)>,
+        timing: &Timing,
+        from: usize,
+        to: usize,
+    ) {
+        self.synth.set_waveform(pattern.waveform);
+        self.synth.set_envelope(pattern.attack, pattern.release);
+        self.pattern = pattern;
+        self.lanes = lanes;
+        self.lanes_due = true;
+        self.step_length = timing.step_length.0.max(1);
+        for (frame, _) in &mut self.note_offs {
+            *frame = *frame - from + to;
+        }
+    }
+
+    fn read_params(&mut self) {
+        let cutoff = self.cutoff_param.get().clamp(1.0, self.fs * 0.45);
+        self.cutoff.set_target(cutoff);
+        self.level.set_target(self.level_param.get());
+    }
+
+    // the length of the note starting at the step, including the steps held
+    fn note_length(&self, step: usize) -> usize {
+        let steps = &self.pattern.steps;
+        let held = (1..steps.len())
+            .take_while(|i| steps[(step + i) % steps.len()] == PatternStep::Hold)
+            .count();
+        let gate = (self.pattern.gate * self.step_length as f64) as usize;
+        (held * self.step_length + gate).max(1)
+    }
+
+    // Renders the frames from `from` into `out`, which doesn't cross the
+    // grid of the blocks. No notes start at or after `length`. `key` is the
+    // output of the track ducking this one, of the same frames.
+    fn render(
+        &mut self,
+        from: usize,
+        length: usize,
+        key: Option<&[[f64; 2]]>,
+        out: &mut [[f64; 2]],
+    ) {
+        if from.is_multiple_of(BLOCK) || self.lanes_due {
+            for (curve, param) in &self.lanes {
+                param.set(curve.value_at(from as f64));
+            }
+            self.read_params();
+            self.lanes_due = false;
+        }
+
+        let to = from + out.len();
+        self.events.clear();
+
+        // the steps starting in the segment
+        let steps = self.pattern.steps.len();
+        let mut step = from.div_ceil(self.step_length);
+        while step * self.step_length < to.min(length) {
+            let frame = step * self.step_length;
+            if let PatternStep::Note(key) = self.pattern.steps[step % steps] {
+                self.events.push(TimedEvent {
+                    offset: frame - from,
+                    event: VoiceEvent::NoteOn { key, velocity: 1.0 },
+                });
+                let off = frame + self.note_length(step % steps);
+                self.note_offs.push((off, key));
+            }
+            step += 1;
+        }
+        // the ends of the notes in the segment, including the ones just
+        // started
+        let events = &mut self.events;
+        self.note_offs.retain(|&(frame, key)| {
+            if frame < to {
+                events.push(TimedEvent {
+                    offset: frame - from,
+                    event: VoiceEvent::NoteOff { key },
+                });
+            }
+            frame >= to
+        });
+        sort_events(&mut self.events);
+
+        let buf = &mut self.buf[..out.len()];
+        self.synth.render_block(buf, &self.events);
+        for (i, (y, x)) in out.iter_mut().zip(buf.iter()).enumerate() {
+            let duck = match (self.pattern.duck, key) {
+                (Some((_, depth)), Some(key)) => {
+                    let level = self.ducker.process(0.5 * (key[i][0] + key[i][1]).abs());
+                    1.0 - depth * level.min(1.0)
+                }
+                _ => 1.0,
+            };
+            if self.cutoff.is_gliding() {
+                let coefs = Biquad::low_pass(self.fs, Hz(self.cutoff.next_value()), Q);
+                for lpf in &mut self.lpf {
+                    lpf.set_coefficients(&coefs);
+                }
+            }
+            let level = duck * self.level.next_value();
+            for ch in 0..2 {
+                y[ch] = self.pattern.pan[ch] * level * self.lpf[ch].process(x[ch]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    fn timing() -> Timing {
+        // 120 BPM: a step of a sixteenth note is 0.125 s
+        Song::default().timing(FS)
+    }
+
+    fn curve(mode: &str, points: &str) -> LaneCurve {
+        let lane: AutomationLane = toml::from_str(&format!(
+            "param = \"x\"\nmode = \"{mode}\"\npoints = {points}"
+        ))
+        .unwrap();
+        lane.curve(&timing()).unwrap()
+    }
+
+    #[test]
+    fn positions_of_bars_and_seconds() {
+        let timing = timing();
+        let frame = |at: &str| Position::BarStep(at.to_string()).to_frame(&timing).unwrap();
+        assert_eq!(frame("1:1"), 0.0);
+        assert_eq!(frame("1:2"), 6000.0);
+        assert_eq!(frame("2"), 96000.0);
+        assert_eq!(frame("9:1"), 8.0 * 96000.0);
+        assert_eq!(Position::Seconds(0.5).to_frame(&timing).unwrap(), 24000.0);
+        assert!(Position::BarStep("0:1".to_string())
+            .to_frame(&timing)
+            .is_err());
+        assert!(Position::BarStep("1:17".to_string())
+            .to_frame(&timing)
+            .is_err());
+    }
+
+    #[test]
+    fn lane_at_breakpoints_and_between() {
+        let lane = curve(
+            "linear",
+            r#"[{ at = 1.0, value = 10 }, { at = 2.0, value = 20 }, { at = "3:1", value = 0 }]"#,
+        );
+        // at the breakpoints exactly
+        assert_eq!(lane.value_at(FS), 10.0);
+        assert_eq!(lane.value_at(2.0 * FS), 20.0);
+        assert_eq!(lane.value_at(4.0 * FS), 0.0);
+        // between them
+        assert_eq!(lane.value_at(1.5 * FS), 15.0);
+        assert_eq!(lane.value_at(3.0 * FS), 10.0);
+        // held before the first and after the last
+        assert_eq!(lane.value_at(0.0), 10.0);
+        assert_eq!(lane.value_at(100.0 * FS), 0.0);
+
+        let step = curve(
+            "step",
+            r#"[{ at = 1.0, value = 10 }, { at = 2.0, value = 20 }]"#,
+        );
+        assert_eq!(step.value_at(1.99 * FS), 10.0);
+        assert_eq!(step.value_at(2.0 * FS), 20.0);
+    }
+
+    #[test]
+    fn exponential_lane_is_even_in_ratio() {
+        let lane = curve(
+            "exponential",
+            r#"[{ at = 0.0, value = 300 }, { at = 4.0, value = 4800 }]"#,
+        );
+        // doubles every second
+        for (seconds, value) in [(1.0, 600.0), (2.0, 1200.0), (3.0, 2400.0)] {
+            assert!((lane.value_at(seconds * FS) - value).abs() < 1e-9);
+        }
+
+        let lane: AutomationLane = toml::from_str(
+            "param = \"x\"\nmode = \"exponential\"\npoints = [{ at = 0, value = 0 }]",
+        )
+        .unwrap();
+        assert!(lane.curve(&timing()).is_err());
+    }
+
+    #[test]
+    fn parses_notes() {
+        assert_eq!(parse_note("C4"), Some(60));
+        assert_eq!(parse_note("A4"), Some(69));
+        assert_eq!(parse_note("F#3"), Some(54));
+        assert_eq!(parse_note("Bb1"), Some(34));
+        assert_eq!(parse_note("C-1"), Some(0));
+        assert_eq!(parse_note("H2"), None);
+        assert_eq!(parse_note("C"), None);
+        assert_eq!(parse_note("G9"), Some(127));
+        assert_eq!(parse_note("A9"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_songs() {
+        let track = "[[tracks]]\nname = \"bass\"\npattern = \"C2 . - .\"\n";
+        assert!(Song::from_toml(&format!("bars = 1\n{track}")).is_ok());
+        // no bars
+        assert!(Song::from_toml(track).is_err());
+        // an invalid step
+        assert!(Song::from_toml("bars = 1\n[[tracks]]\nname = \"a\"\npattern = \"C2 X\"").is_err());
+        // an unknown parameter
+        let lane = "[[automation]]\nparam = \"lead.cutoff\"\npoints = [{ at = 0, value = 1 }]";
+        let err = Song::from_toml(&format!("bars = 1\n{track}{lane}")).unwrap_err();
+        assert!(err.to_string().contains("lead.cutoff"), "{err}");
+        // an unknown field
+        assert!(Song::from_toml("bars = 1\ntempo = 120").is_err());
+        // ducked by itself, or by a track below
+        let ducked = format!("{track}duck_by = \"bass\"\n");
+        assert!(Song::from_toml(&format!("bars = 1\n{ducked}")).is_err());
+        let below = format!(
+            "bars = 1\n{}{track}",
+            ducked.replace("\"bass\"\np", "\"pad\"\np")
+        );
+        assert!(Song::from_toml(&below).is_err(), "{below}");
+    }
+
+    #[test]
+    fn demo_song_is_valid() {
+        let song = Song::from_toml(include_str!("../songs/sweep.toml")).unwrap();
+        assert_eq!(song.length(FS).0, 16 * 96000);
+    }
+
+    #[test]
+    fn lanes_write_to_params() {
+        let song = Song::from_toml(
+            r#"
+            bars = 2
+
+            [[tracks]]
+            name = "bass"
+            pattern = "C2 - . C2"
+
+            [[automation]]
+            param = "bass.cutoff"
+            mode = "exponential"
+            points = [{ at = "1:1", value = 300 }, { at = "2:1", value = 5000 }]
+            "#,
+        )
+        .unwrap();
+        let params = song.params();
+        let cutoff = params.get("bass.cutoff").unwrap().clone();
+        let mut player = SongPlayer::new(&song, FS, &params).unwrap();
+
+        let bar = song.timing(FS).bar_length().0;
+        let first: Vec<[f64; 2]> = (0..bar / 2).map(|_| player.next()).collect();
+        assert!(first.iter().any(|&[l, _]| l != 0.0));
+        // halfway through the sweep, evaluated at the start of the last block
+        let halfway = 300.0 * (5000.0_f64 / 300.0).powf(0.5);
+        assert!(
+            (cutoff.get() / halfway - 1.0).abs() < 0.01,
+            "{}",
+            cutoff.get()
+        );
+
+        let rest: Vec<[f64; 2]> = player.until_exhausted().collect();
+        assert_eq!(cutoff.get(), 5000.0);
+        assert!(rest.iter().flatten().all(|x| x.is_finite()));
+        assert_eq!(
+            bar / 2 + rest.len(),
+            song.length(FS).0 + Ms(100.0).to_frames(FS).0
+        );
+    }
+
+    #[test]
+    fn block_sizes_render_identically() {
+        // the steps and the notes end in the middle of the blocks
+        let song = Song::from_toml(
+            r#"
+            bpm = 133
+            bars = 2
+
+            [[tracks]]
+            name = "bass"
+            pattern = "C2 . Eb2 C3 - . G1 ."
+            gate = 0.37
+
+            [[tracks]]
+            name = "lead"
+            waveform = "square"
+            pattern = "G4 - - Bb4 . C5 . ."
+            release = 300
+
+            [[automation]]
+            param = "bass.cutoff"
+            mode = "exponential"
+            points = [{ at = "1:1", value = 300 }, { at = "2:5", value = 5000 }]
+            "#,
+        )
+        .unwrap();
+
+        let render = |block: usize| {
+            let mut player = SongPlayer::new(&song, FS, &song.params()).unwrap();
+            let mut out = vec![[0.0; 2]; player.total_length().0];
+            for chunk in out.chunks_mut(block) {
+                player.render_block(chunk);
+            }
+            assert!(player.is_finished());
+            out
+        };
+        let reference = render(64);
+        assert!(reference.iter().any(|&f| f != [0.0; 2]));
+        for block in [512, 4096] {
+            assert!(render(block) == reference, "block size {block}");
+        }
+    }
+
+    // a song of a track playing a note of `key` on the first 15 steps of
+    // each bar
+    fn held_note(bpm: f64, key: &str, cutoff: f64) -> String {
+        format!(
+            r#"
+            bpm = {bpm}
+            bars = 4
+
+            [[tracks]]
+            name = "lead"
+            waveform = "sine"
+            pattern = "{key} - - - - - - - - - - - - - - ."
+            cutoff = {cutoff}
+            release = 5
+            "#
+        )
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("{name}-{}.toml", std::process::id()))
+    }
+
+    fn pitch_of(player: &mut SongPlayer, len: usize) -> f64 {
+        let mut out = vec![[0.0; 2]; len];
+        player.render_block(&mut out);
+        let mono: Vec<f64> = out.iter().map(|&[l, _]| l).collect();
+        crate::analysis::detect_pitch(&mono, FS)
+            .unwrap()
+            .frequency
+            .0
+    }
+
+    #[test]
+    fn reload_swaps_at_the_next_bar() {
+        let path = temp_file("reload-swaps");
+        let old = held_note(120.0, "A4", 8000.0);
+        std::fs::write(&path, &old).unwrap();
+        let song = Song::from_toml(&old).unwrap();
+        let params = Arc::new(song.params());
+        let mut player = SongPlayer::new(&song, FS, &params).unwrap();
+        let mut reloader = player.reloader(&path, song, params.clone());
+
+        let mut out = vec![[0.0; 2]; 1000];
+        player.render_block(&mut out);
+        // an octave higher, and half the tempo
+        std::fs::write(&path, held_note(60.0, "A5", 9000.0)).unwrap();
+        assert!(reloader.reload().unwrap());
+        assert!(!reloader.reload().unwrap());
+        // the cutoff is set immediately
+        assert_eq!(params.get("lead.cutoff").unwrap().get(), 9000.0);
+
+        // the first bar of 2 s at 120 BPM is still the old one
+        let mut out = vec![[0.0; 2]; 80000 - 1000];
+        player.render_block(&mut out);
+        assert!((pitch_of(&mut player, 4096) - 440.0).abs() < 1.0);
+        let mut out = vec![[0.0; 2]; 96000 - 84096 - 1];
+        player.render_block(&mut out);
+        assert!(player.has_pending_update());
+
+        // swapped at the second bar, which starts at 4 s at 60 BPM
+        let mut out = vec![[0.0; 2]; 2];
+        player.render_block(&mut out);
+        assert!(!player.has_pending_update());
+        assert_eq!(player.position(), Frames(192000 + 1));
+        assert!((pitch_of(&mut player, 4096) - 880.0).abs() < 2.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn broken_reload_keeps_the_song() {
+        let path = temp_file("reload-broken");
+        let old = held_note(120.0, "A4", 8000.0);
+        std::fs::write(&path, &old).unwrap();
+        let song = Song::from_toml(&old).unwrap();
+        let params = Arc::new(song.params());
+        let mut player = SongPlayer::new(&song, FS, &params).unwrap();
+        let mut reloader = player.reloader(&path, song.clone(), params);
+
+        let added = format!("{old}\n[[tracks]]\nname = \"bass\"\npattern = \"C2\"");
+        for broken in [
+            "bars = ".to_string(),
+            held_note(120.0, "H4", 8000.0),
+            old.replace("lead", "solo"),
+            added,
+        ] {
+            std::fs::write(&path, broken).unwrap();
+            assert!(reloader.reload().is_err());
+            assert_eq!(reloader.song(), &song);
+        }
+
+        let mut out = vec![[0.0; 2]; 100000];
+        player.render_block(&mut out);
+        assert!(!player.has_pending_update());
+        assert!((pitch_of(&mut player, 4096) - 440.0).abs() < 1.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn four_tracks(duck: &str) -> Song {
+        Song::from_toml(&format!(
+            r#"
+            bpm = 128
+            bars = 2
+
+            [[tracks]]
+            name = "kick"
+            waveform = "sine"
+            pattern = "C2 . . . C2 . . ."
+            level = 0.8
+
+            [[tracks]]
+            name = "bass"
+            pattern = "C2 . Eb2 C3 - . G1 ."
+
+            [[tracks]]
+            name = "pad"
+            waveform = "triangle"
+            pattern = "G3 - - - - - - - Bb3 - - - - - - -"
+            {duck}
+
+            [[tracks]]
+            name = "lead"
+            waveform = "square"
+            pattern = "G4 - - Bb4 . C5 . ."
+            pan = 0.5
+
+            [[automation]]
+            param = "bass.cutoff"
+            mode = "exponential"
+            points = [{{ at = "1:1", value = 300 }}, {{ at = "2:5", value = 5000 }}]
+
+            [[automation]]
+            param = "lead.level"
+            points = [{{ at = 0, value = 0 }}, {{ at = 1.5, value = 0.3 }}]
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn threads_render_identically() {
+        let song = four_tracks("");
+        let serial = render(&song, FS, 1).unwrap();
+        let parallel = render(&song, FS, 4).unwrap();
+        assert_eq!(parallel.threads, 4);
+        assert!(serial.frames.iter().any(|&f| f != [0.0; 2]));
+        assert!(parallel.frames == serial.frames);
+
+        // the same as played
+        let mut player = SongPlayer::new(&song, FS, &song.params()).unwrap();
+        let played: Vec<[f64; 2]> = player.by_ref().until_exhausted().collect();
+        assert!(played == serial.frames);
+    }
+
+    #[test]
+    fn ducking_renders_serially() {
+        let ducked = render(&four_tracks("duck_by = \"kick\""), FS, 4).unwrap();
+        assert_eq!(ducked.threads, 1);
+
+        let plain = render(&four_tracks(""), FS, 4).unwrap();
+        let energy = |frames: &[[f64; 2]]| frames.iter().map(|f| f[0] * f[0]).sum::<f64>();
+        assert!(energy(&ducked.frames) < energy(&plain.frames));
+    }
+
+    #[test]
+    fn master_chain_follows_the_toml() {
+        let song = Song::from_toml(
+            r#"
+            bars = 1
+
+            [[master]]
+            type = "eq"
+            low_cut = 40
+            high_gain = 2
+
+            [[master]]
+            type = "compressor"
+            threshold = -12
+            ratio = 4
+
+            [[master]]
+            type = "limiter"
+            ceiling = -0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            song.master,
+            [
+                MasterEffect::Eq(EqSettings {
+                    low_cut: 40.0,
+                    high_shelf: 8000.0,
+                    high_gain: 2.0,
+                }),
+                MasterEffect::Compressor(CompressorSettings {
+                    threshold: -12.0,
+                    ratio: 4.0,
+                    ..Default::default()
+                }),
+                MasterEffect::Limiter(LimiterSettings {
+                    ceiling: -0.5,
+                    release: 50.0,
+                }),
+            ]
+        );
+
+        let chain = MasterChain::new(&song.master, FS);
+        assert!(matches!(
+            chain.stages[..],
+            [
+                MasterStage::Eq(ref filters),
+                MasterStage::Compressor(_),
+                MasterStage::Limiter(_),
+            ] if filters.len() == 2
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_master_effects() {
+        let err = Song::from_toml("bars = 1\n[[master]]\ntype = \"reverb\"").unwrap_err();
+        for name in ["reverb", "eq", "compressor", "limiter"] {
+            assert!(err.to_string().contains(name), "{err}");
+        }
+        // an unknown setting, and an invalid one
+        assert!(Song::from_toml("bars = 1\n[[master]]\ntype = \"eq\"\ngain = 1").is_err());
+        let err = Song::from_toml("bars = 1\n[[master]]\ntype = \"compressor\"\nratio = 0.5")
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("master effect 1 (compressor)"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn empty_master_is_a_passthrough() {
+        let mut noise = crate::core::noise::Noise::new(1);
+        let input: Vec<[f64; 2]> = (0..4800)
+            .map(|_| [noise.next_sample(), noise.next_sample()])
+            .collect();
+        // no effects, or an eq of no cut and no gain
+        for effects in [vec![], vec![MasterEffect::Eq(EqSettings::default())]] {
+            let mut frames = input.clone();
+            MasterChain::new(&effects, FS).process(&mut frames);
+            assert!(frames == input);
+        }
+    }
+
+    #[test]
+    fn limiter_on_the_master_holds_the_ceiling() {
+        let master = "duck_by = \"kick\"\n[[master]]\ntype = \"limiter\"\nceiling = -12";
+        let plain = render(&four_tracks("duck_by = \"kick\""), FS, 1).unwrap();
+        let limited = render(&four_tracks(master), FS, 1).unwrap();
+        let peak = |frames: &[[f64; 2]]| {
+            frames
+                .iter()
+                .flatten()
+                .fold(0.0, |peak: f64, x| peak.max(x.abs()))
+        };
+        let ceiling = Db(-12.0).to_gain();
+        assert!(peak(&plain.frames) > ceiling);
+        assert!(peak(&limited.frames) <= ceiling);
+
+        // applied after the tracks rendered on the threads too
+        let master = "[[master]]\ntype = \"limiter\"\nceiling = -12";
+        let song = four_tracks(master);
+        let serial = render(&song, FS, 1).unwrap();
+        let parallel = render(&song, FS, 4).unwrap();
+        assert_eq!(parallel.threads, 4);
+        assert!(parallel.frames == serial.frames);
+        assert!(peak(&parallel.frames) <= ceiling);
+    }
+}
@@ -0,0 +1,779 @@
+//! Positioning sources in space. Azimuths are in degrees, counterclockwise
+//! from the front as seen from above (i.e. 90 is the left, -90 is the right).
+
+use crate::convolution::Convolver;
+use crate::core::smooth::{SmoothedParam, SMOOTHING};
+use crate::effects::DelayLine;
+use crate::latency::Latency;
+use crate::units::{Hz, Ms};
+use dasp::{Frame, Signal};
+use std::f64::consts::PI;
+
+/// Pans a mono source over `N` speakers on a horizontal circle by 2D vector
+/// base amplitude panning (VBAP): the source is reproduced by the pair of
+/// adjacent speakers enclosing its direction, with the gains whose vectors
+/// sum to the direction of the source, normalized to the constant power.
+/// The gains glide over 20 ms when the azimuth is changed.
+///
+/// c.f. Pulkki, V. (1997). Virtual sound source positioning using vector base
+/// amplitude panning.
+pub struct SurroundPanner<S: Signal<Frame = f64>, const N: usize> {
+    signal: S,
+    // the speakers sorted by the azimuth, with their original indices
+    speakers: [(f64, usize); N],
+    gains: [SmoothedParam; N],
+}
+
+impl<S: Signal<Frame = f64>, const N: usize> SurroundPanner<S, N> {
+    /// `speakers` are the azimuths of the speakers in the order of the output
+    /// channels, e.g. `[45.0, -45.0, 135.0, -135.0]` for a quadraphonic
+    /// setup. The speakers must be at least 2, and the adjacent ones must be
+    /// less than 180 degrees apart.
+    pub fn new(signal: S, fs: f64, speakers: [f64; N], azimuth: f64) -> Self {
+        assert!(N >= 2, "at least 2 speakers are needed");
+
+        let speakers = sort_speakers(speakers);
+        let gains = vbap_gains(&speakers, azimuth).map(|g| SmoothedParam::new(fs, SMOOTHING, g));
+        Self {
+            signal,
+            speakers,
+            gains,
+        }
+    }
+
+    pub fn set_azimuth(&mut self, azimuth: f64) {
+        let targets = vbap_gains(&self.speakers, azimuth);
+        for (gain, target) in self.gains.iter_mut().zip(targets) {
+            gain.set_target(target);
+        }
+    }
+
+    /// The current gains of the channels.
+    pub fn gains(&self) -> [f64; N] {
+        self.gains.map(|g| g.value())
+    }
+}
+
+/// The azimuths of the speakers sorted, with their original indices.
+fn sort_speakers<const N: usize>(speakers: [f64; N]) -> [(f64, usize); N] {
+    let mut sorted = [(0.0, 0); N];
+    for (i, (s, &azimuth)) in sorted.iter_mut().zip(&speakers).enumerate() {
+        *s = (azimuth.rem_euclid(360.0), i);
+    }
+    sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+    sorted
+}
+
+/// The gains of 2D VBAP over the sorted speakers (see `SurroundPanner`).
+fn vbap_gains<const N: usize>(speakers: &[(f64, usize); N], azimuth: f64) -> [f64; N] {
+    let azimuth = azimuth.rem_euclid(360.0);
+
+    // the pair of the adjacent speakers that encloses the azimuth; the last
+    // pair wraps around 360 degrees
+    let k = speakers
+        .iter()
+        .rposition(|&(a, _)| a <= azimuth)
+        .unwrap_or(N - 1);
+    let (a1, i1) = speakers[k];
+    let (a2, i2) = speakers[(k + 1) % N];
+
+    // solve p = g1 l1 + g2 l2 for the gains
+    let (p, l1, l2) = (unit(azimuth), unit(a1), unit(a2));
+    let det = l1[0] * l2[1] - l1[1] * l2[0];
+    let g1 = (p[0] * l2[1] - p[1] * l2[0]) / det;
+    let g2 = (l1[0] * p[1] - l1[1] * p[0]) / det;
+    let norm = g1.hypot(g2);
+
+    let mut gains = [0.0; N];
+    gains[i1] = g1 / norm;
+    gains[i2] = g2 / norm;
+    gains
+}
+
+/// The unit vector toward the azimuth.
+fn unit(azimuth: f64) -> [f64; 2] {
+    let theta = azimuth.to_radians();
+    [theta.cos(), theta.sin()]
+}
+
+impl<S: Signal<Frame = f64>, const N: usize> Signal for SurroundPanner<S, N>
+where
+    [f64; N]: Frame<Sample = f64>,
+{
+    type Frame = [f64; N];
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        let mut frame = [0.0; N];
+        for (y, gain) in frame.iter_mut().zip(&mut self.gains) {
+            *y = gain.next_value() * x;
+        }
+        frame
+    }
+}
+
+/// The unit vector toward the azimuth and the elevation (upward), as `[x, y,
+/// z]` where x is the front and y is the left.
+fn unit_3d(azimuth: f64, elevation: f64) -> [f64; 3] {
+    let (theta, phi) = (azimuth.to_radians(), elevation.to_radians());
+    [theta.cos() * phi.cos(), theta.sin() * phi.cos(), phi.sin()]
+}
+
+/// Encodes a mono source into the first-order ambisonic B-format with the
+/// traditional (FuMa) convention; the frame is `[W, X, Y, Z]` where `W` is the
+/// omnidirectional component scaled by `1/sqrt(2)` and `X`, `Y` and `Z` are
+/// the figure-of-eight components toward the front, the left and the top.
+/// The gains glide over 20 ms when the direction is changed.
+pub struct AmbisonicEncoder<S: Signal<Frame = f64>> {
+    signal: S,
+    gains: [SmoothedParam; 4],
+}
+
+impl<S: Signal<Frame = f64>> AmbisonicEncoder<S> {
+    /// `elevation` is in degrees, positive upward.
+    pub fn new(signal: S, fs: f64, azimuth: f64, elevation: f64) -> Self {
+        let gains =
+            b_format_gains(azimuth, elevation).map(|g| SmoothedParam::new(fs, SMOOTHING, g));
+        Self { signal, gains }
+    }
+
+    pub fn set_direction(&mut self, azimuth: f64, elevation: f64) {
+        let targets = b_format_gains(azimuth, elevation);
+        for (gain, target) in self.gains.iter_mut().zip(targets) {
+            gain.set_target(target);
+        }
+    }
+
+    /// The current gains of `W`, `X`, `Y` and `Z`.
+    pub fn gains(&self) -> [f64; 4] {
+        self.gains.map(|g| g.value())
+    }
+}
+
+/// The gains of `W`, `X`, `Y` and `Z` toward the direction.
+fn b_format_gains(azimuth: f64, elevation: f64) -> [f64; 4] {
+    let [x, y, z] = unit_3d(azimuth, elevation);
+    [std::f64::consts::FRAC_1_SQRT_2, x, y, z]
+}
+
+impl<S: Signal<Frame = f64>> Signal for AmbisonicEncoder<S> {
+    type Frame = [f64; 4];
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        let mut frame = [0.0; 4];
+        for (y, gain) in frame.iter_mut().zip(&mut self.gains) {
+            *y = gain.next_value() * x;
+        }
+        frame
+    }
+}
+
+/// Decodes a first-order B-format signal (as `AmbisonicEncoder` produces) to
+/// `N` speakers by the basic (projection) decoder: each speaker plays what a
+/// virtual microphone pointing to it would pick up. This assumes a regular
+/// array, i.e. the speakers evenly spaced on a circle or on a sphere, at the
+/// same distance from the listener.
+///
+/// If all the speakers are on the horizontal plane, `Z` is ignored.
+pub struct AmbisonicDecoder<S: Signal<Frame = [f64; 4]>, const N: usize> {
+    signal: S,
+    // the gains of W, X, Y and Z for each speaker
+    matrix: [[f64; 4]; N],
+}
+
+impl<S: Signal<Frame = [f64; 4]>, const N: usize> AmbisonicDecoder<S, N> {
+    /// `speakers` are the pairs of the azimuth and the elevation of the
+    /// speakers in the order of the output channels.
+    pub fn new(signal: S, speakers: [(f64, f64); N]) -> Self {
+        assert!(N >= 4, "at least 4 speakers are needed");
+
+        // the first-order components need the weight of the dimension (2 for
+        // a circle, 3 for a sphere) so that the velocity vector of the
+        // reproduced field points to the source with the magnitude 1
+        let dim = if speakers.iter().all(|&(_, e)| e == 0.0) {
+            2.0
+        } else {
+            3.0
+        };
+        let matrix = speakers.map(|(a, e)| {
+            let [x, y, z] = unit_3d(a, e);
+            [
+                std::f64::consts::SQRT_2 / N as f64,
+                dim * x / N as f64,
+                dim * y / N as f64,
+                dim * z / N as f64,
+            ]
+        });
+
+        Self { signal, matrix }
+    }
+}
+
+impl<S: Signal<Frame = [f64; 4]>, const N: usize> Signal for AmbisonicDecoder<S, N>
+where
+    [f64; N]: Frame<Sample = f64>,
+{
+    type Frame = [f64; N];
+
+    fn next(&mut self) -> Self::Frame {
+        let b = self.signal.next();
+        self.matrix
+            .map(|row| row.iter().zip(&b).map(|(g, x)| g * x).sum())
+    }
+}
+
+/// A pair of head-related impulse responses (HRIRs) measured (or modeled)
+/// for a direction.
+pub struct Hrir {
+    pub azimuth: f64,
+    pub elevation: f64,
+    pub left: Vec<f64>,
+    pub right: Vec<f64>,
+}
+
+/// A set of HRIRs over directions, like what a SOFA file contains.
+pub struct HrtfSet {
+    fs: f64, // sampling rate
+    hrirs: Vec<Hrir>,
+}
+
+// the radius of the head in meters and the speed of sound in m/s
+const HEAD_RADIUS: f64 = 0.0875;
+const SPEED_OF_SOUND: f64 = 343.0;
+
+// the half width of the windowed sinc used for the fractional delays
+const SINC_HALF_WIDTH: usize = 16;
+
+impl HrtfSet {
+    /// `hrirs` must be sampled at `fs`, and must not be empty.
+    pub fn new(fs: f64, hrirs: Vec<Hrir>) -> Self {
+        assert!(!hrirs.is_empty(), "at least 1 pair of HRIRs is needed");
+        Self { fs, hrirs }
+    }
+
+    /// A small built-in set modeled by a rigid sphere, every 5 degrees in
+    /// azimuth and 15 degrees in elevation. Each ear gets the delay by the
+    /// path around the head (Woodworth's formula) and the head shadow, a
+    /// one-pole one-zero filter that boosts the highs up to 6 dB when the
+    /// sound comes from the side of the ear and cuts them when it comes from
+    /// the other side. There is no pinna, so the front and the back (and the
+    /// elevation) are hardly distinguishable.
+    ///
+    /// c.f. Brown, C. P., & Duda, R. O. (1998). A structural model for binaural
+    /// sound synthesis.
+    pub fn spherical_head(fs: f64) -> Self {
+        let mut hrirs = vec![];
+        for elevation in (-45..=90).step_by(15) {
+            for azimuth in (-180..180).step_by(5) {
+                let (azimuth, elevation) = (azimuth as f64, elevation as f64);
+                let source = unit_3d(azimuth, elevation);
+                let ear_ir = |ear: f64| {
+                    let ear = unit_3d(ear, 0.0);
+                    let cos: f64 = source.iter().zip(&ear).map(|(a, b)| a * b).sum();
+                    spherical_head_ir(fs, cos.clamp(-1.0, 1.0).acos())
+                };
+                hrirs.push(Hrir {
+                    azimuth,
+                    elevation,
+                    left: ear_ir(90.0),
+                    right: ear_ir(-90.0),
+                });
+            }
+        }
+        Self::new(fs, hrirs)
+    }
+
+    pub fn fs(&self) -> f64 {
+        self.fs
+    }
+
+    /// The HRIRs of the direction closest to the given one.
+    pub fn nearest(&self, azimuth: f64, elevation: f64) -> &Hrir {
+        let target = unit_3d(azimuth, elevation);
+        let closeness = |h: &Hrir| -> f64 {
+            let u = unit_3d(h.azimuth, h.elevation);
+            u.iter().zip(&target).map(|(a, b)| a * b).sum()
+        };
+        self.hrirs
+            .iter()
+            .max_by(|a, b| closeness(a).total_cmp(&closeness(b)))
+            .expect("HrtfSet should not be empty")
+    }
+}
+
+/// The impulse response of the spherical head for an ear, where `theta` is
+/// the angle in radians between the ear and the source.
+fn spherical_head_ir(fs: f64, theta: f64) -> Vec<f64> {
+    // the delay by Woodworth's formula, offset so that it is 0 when the
+    // source is right in front of the ear
+    let a_c = HEAD_RADIUS / SPEED_OF_SOUND;
+    let delay = if theta < PI / 2.0 {
+        a_c * (1.0 - theta.cos())
+    } else {
+        a_c * (1.0 + theta - PI / 2.0)
+    };
+    let delay = SINC_HALF_WIDTH as f64 + delay * fs;
+
+    // the fractional delay by a Hann-windowed sinc
+    let len = Ms(4.0).to_frames(fs).0.max(4 * SINC_HALF_WIDTH);
+    let mut ir: Vec<f64> = (0..len)
+        .map(|n| {
+            let t = n as f64 - delay;
+            if t.abs() >= SINC_HALF_WIDTH as f64 {
+                return 0.0;
+            }
+            let window = 0.5 + 0.5 * (PI * t / SINC_HALF_WIDTH as f64).cos();
+            let sinc = if t == 0.0 {
+                1.0
+            } else {
+                (PI * t).sin() / (PI * t)
+            };
+            window * sinc
+        })
+        .collect();
+
+    // the head shadow (alpha s + beta) / (s + beta), transformed by the
+    // bilinear transform
+    let alpha_min = 0.1;
+    let theta_min = 150.0_f64.to_radians();
+    let alpha = 1.0 + alpha_min / 2.0 + (1.0 - alpha_min / 2.0) * (theta / theta_min * PI).cos();
+    let beta = 2.0 * SPEED_OF_SOUND / HEAD_RADIUS;
+    let k = 2.0 * fs;
+    let (b0, b1) = (alpha * k + beta, beta - alpha * k);
+    let (a0, a1) = (k + beta, beta - k);
+
+    let (mut x1, mut y1) = (0.0, 0.0);
+    for x in ir.iter_mut() {
+        let y = (b0 * *x + b1 * x1 - a1 * y1) / a0;
+        (x1, y1) = (*x, y);
+        *x = y;
+    }
+    ir
+}
+
+/// Renders a mono source at a direction for headphones, by convolving it with
+/// the HRIRs of the direction closest to it in an `HrtfSet`.
+pub struct BinauralRenderer<S: Signal<Frame = f64>> {
+    signal: S,
+    left: Convolver,
+    right: Convolver,
+}
+
+impl<S: Signal<Frame = f64>> BinauralRenderer<S> {
+    pub fn new(signal: S, hrtf: &HrtfSet, azimuth: f64, elevation: f64) -> Self {
+        let hrir = hrtf.nearest(azimuth, elevation);
+        Self {
+            signal,
+            left: Convolver::from_ir(&hrir.left, hrtf.fs),
+            right: Convolver::from_ir(&hrir.right, hrtf.fs),
+        }
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for BinauralRenderer<S> {
+    type Frame = [f64; 2];
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        [self.left.process(x), self.right.process(x)]
+    }
+}
+
+impl<S: Signal<Frame = f64>> Latency for BinauralRenderer<S> {
+    /// Only the latency of the convolution; the delay built in the HRIRs is
+    /// not included.
+    fn latency_frames(&self) -> usize {
+        self.left.latency_frames()
+    }
+}
+
+// the distance within which `MovingSource` is not attenuated, in meters
+const REFERENCE_DISTANCE: f64 = 1.0;
+
+/// Places a mono source on a trajectory around the listener at the origin,
+/// with the effects of the distance:
+///
+/// - the gain is inversely proportional to the distance (beyond 1 m)
+/// - the air absorbs the highs more as the distance increases, modeled
+///   roughly by a one-pole low-pass whose cutoff falls from 20 kHz at 0 m to
+///   about 2 kHz at 100 m
+/// - the sound takes time to travel, which is a delay line whose length
+///   follows the distance; the doppler shift comes out of this naturally,
+///   i.e. the pitch rises when the source approaches and falls when it
+///   recedes (as the delay is computed from the current position rather
+///   than the position at the time of the emission, the shift is slightly
+///   smaller than the exact one, e.g. by 0.8% at 30 m/s)
+///
+/// The direction is not rendered; feed the output to a panner for that.
+pub struct MovingSource<S: Signal<Frame = f64>, P: Signal<Frame = [f64; 3]>> {
+    signal: S,
+    trajectory: P,
+    fs: f64, // sampling rate
+    delay: DelayLine,
+    max_delay: f64,
+    low_pass: f64,
+}
+
+impl<S: Signal<Frame = f64>, P: Signal<Frame = [f64; 3]>> MovingSource<S, P> {
+    /// `trajectory` is the position `[x, y, z]` of the source in meters on
+    /// each frame, where x is the front, y is the left and z is the top.
+    /// The source is clamped at `max_distance` in meters as far as the delay
+    /// is concerned.
+    pub fn new(signal: S, trajectory: P, fs: f64, max_distance: f64) -> Self {
+        let max_delay = (max_distance / SPEED_OF_SOUND * fs).ceil();
+        Self {
+            signal,
+            trajectory,
+            fs,
+            delay: DelayLine::new(max_delay as usize + 2),
+            max_delay,
+            low_pass: 0.0,
+        }
+    }
+}
+
+impl<S: Signal<Frame = f64>, P: Signal<Frame = [f64; 3]>> Signal for MovingSource<S, P> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        let [px, py, pz] = self.trajectory.next();
+        let distance = (px * px + py * py + pz * pz).sqrt();
+
+        self.delay.push(x);
+        let delay = (distance / SPEED_OF_SOUND * self.fs).min(self.max_delay);
+        let delayed = self.delay.tap_fractional(1.0 + delay);
+
+        let cutoff = Hz(20000.0 * 10.0 / (10.0 + distance))
+            .normalized(self.fs)
+            .min(0.5);
+        let coef = (-2.0 * PI * cutoff).exp();
+        self.low_pass = delayed + coef * (self.low_pass - delayed);
+
+        self.low_pass * REFERENCE_DISTANCE / distance.max(REFERENCE_DISTANCE)
+    }
+}
+
+/// The azimuths of the quadraphonic speakers in the order of the channels:
+/// front left, front right, rear left, and rear right.
+pub const QUAD_SPEAKERS: [f64; 4] = [45.0, -45.0, 135.0, -135.0];
+
+/// Places a mono track at a position around the listener at the origin on
+/// the horizontal plane, as a simple image of a room:
+///
+/// - the gain is inversely proportional to the distance, i.e. -6 dB per
+///   doubling, and 1 within the minimum distance (1 m by default)
+/// - for stereo (`new()`), the pan follows the azimuth with the equal-power
+///   pan law; a source on the left is fully on the left, and one behind is
+///   panned like the one in front mirrored
+/// - for quad (`quad()`), the source is panned between the adjacent pair of
+///   `QUAD_SPEAKERS` as `SurroundPanner` does, with the constant power
+/// - optionally, the sound is delayed by the time it takes to travel the
+///   distance (see `with_pre_delay()`)
+///
+/// Unlike `MovingSource`, the air absorption is not modeled.
+pub struct Placement<S: Signal<Frame = f64>, P: Signal<Frame = [f64; 2]>, const N: usize = 2> {
+    signal: S,
+    position: P,
+    fs: f64, // sampling rate
+    min_distance: f64,
+    // the delay line and the maximum delay in frames
+    pre_delay: Option<(DelayLine, f64)>,
+    // the sorted speakers for the pairwise panning, or None for stereo
+    speakers: Option<[(f64, usize); N]>,
+}
+
+impl<S: Signal<Frame = f64>, P: Signal<Frame = [f64; 2]>> Placement<S, P> {
+    /// `position` is the position `[x, y]` of the track in meters on each
+    /// frame, where x is the front and y is the left.
+    pub fn new(signal: S, position: P, fs: f64) -> Self {
+        Self::with_speakers(signal, position, fs, None)
+    }
+}
+
+impl<S: Signal<Frame = f64>, P: Signal<Frame = [f64; 2]>> Placement<S, P, 4> {
+    /// Renders to the quadraphonic speakers, `[FL, FR, RL, RR]`. See `new()`
+    /// for `position`.
+    pub fn quad(signal: S, position: P, fs: f64) -> Self {
+        Self::with_speakers(signal, position, fs, Some(sort_speakers(QUAD_SPEAKERS)))
+    }
+}
+
+impl<S: Signal<Frame = f64>, P: Signal<Frame = [f64; 2]>, const N: usize> Placement<S, P, N> {
+    fn with_speakers(signal: S, position: P, fs: f64, speakers: Option<[(f64, usize); N]>) -> Self {
+        Self {
+            signal,
+            position,
+            fs,
+            min_distance: REFERENCE_DISTANCE,
+            pre_delay: None,
+            speakers,
+        }
+    }
+
+    /// Sets the distance in meters within which the gain stays 1, so that a
+    /// source close to the listener doesn't get infinitely loud.
+    pub fn with_min_distance(mut self, min_distance: f64) -> Self {
+        assert!(min_distance > 0.0, "min_distance must be positive");
+        self.min_distance = min_distance;
+        self
+    }
+
+    /// Delays the track by `distance / 343 m/s`, clamped at `max_distance`
+    /// in meters. As the delay follows a moving source, the pitch shifts as
+    /// the doppler effect.
+    pub fn with_pre_delay(mut self, max_distance: f64) -> Self {
+        let max_delay = (max_distance / SPEED_OF_SOUND * self.fs).ceil();
+        self.pre_delay = Some((DelayLine::new(max_delay as usize + 2), max_delay));
+        self
+    }
+}
+
+impl<S: Signal<Frame = f64>, P: Signal<Frame = [f64; 2]>, const N: usize> Signal
+    for Placement<S, P, N>
+where
+    [f64; N]: Frame<Sample = f64>,
+{
+    type Frame = [f64; N];
+
+    fn next(&mut self) -> Self::Frame {
+        let x = self.signal.next();
+        let [px, py] = self.position.next();
+        let distance = px.hypot(py);
+
+        let x = match &mut self.pre_delay {
+            Some((delay_line, max_delay)) => {
+                delay_line.push(x);
+                let delay = (distance / SPEED_OF_SOUND * self.fs).min(*max_delay);
+                delay_line.tap_fractional(1.0 + delay)
+            }
+            None => x,
+        };
+        let x = x * self.min_distance / distance.max(self.min_distance);
+
+        let azimuth = py.atan2(px);
+        let gains = match &self.speakers {
+            Some(speakers) => vbap_gains(speakers, azimuth.to_degrees()),
+            None => {
+                // -1 (left) to 1 (right), and the equal-power pan law
+                let pan = -azimuth.sin();
+                let theta = (pan + 1.0) * PI / 4.0;
+                let mut gains = [0.0; N];
+                gains[0] = theta.cos();
+                gains[1] = theta.sin();
+                gains
+            }
+        };
+        gains.map(|g| g * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{Db, Frames};
+    use dasp::signal;
+
+    const FS: f64 = 48000.0;
+
+    fn sine() -> impl Signal<Frame = f64> {
+        signal::rate(FS).const_hz(440.0).sine()
+    }
+
+    #[test]
+    fn vbap_at_a_speaker_uses_only_that_speaker() {
+        let speakers = [45.0, -45.0, 135.0, -135.0];
+        for (i, &azimuth) in speakers.iter().enumerate() {
+            let mut panner = SurroundPanner::new(sine(), FS, speakers, azimuth);
+            let mut energy = [0.0; 4];
+            for _ in 0..4800 {
+                for (e, y) in energy.iter_mut().zip(panner.next()) {
+                    *e += y * y;
+                }
+            }
+            let total: f64 = energy.iter().sum();
+            for (j, e) in energy.iter().enumerate() {
+                if j == i {
+                    assert!((e / total - 1.0).abs() < 1e-12, "{azimuth}: {energy:?}");
+                } else {
+                    assert!(e / total < 1e-12, "{azimuth}: {energy:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ambisonic_front_is_on_x_and_w_is_omnidirectional() {
+        let front = AmbisonicEncoder::new(sine(), FS, 0.0, 0.0).gains();
+        assert_eq!(front[1], 1.0);
+        assert!(front[2].abs() < 1e-15 && front[3].abs() < 1e-15);
+
+        let directions = [(0.0, 0.0), (90.0, 0.0), (-135.0, 30.0), (60.0, -90.0)];
+        for (azimuth, elevation) in directions {
+            let mut encoder = AmbisonicEncoder::new(sine(), FS, azimuth, elevation);
+            let mut reference = sine();
+            for _ in 0..100 {
+                let [w, ..] = encoder.next();
+                assert_eq!(w, std::f64::consts::FRAC_1_SQRT_2 * reference.next());
+            }
+            // X is the largest toward the front
+            let [_, x, ..] = encoder.gains();
+            assert!(x <= front[1]);
+        }
+    }
+
+    #[test]
+    fn binaural_source_on_the_right_is_louder_and_earlier_on_the_right() {
+        let hrtf = HrtfSet::spherical_head(FS);
+        let impulse = signal::from_iter(std::iter::once(1.0).chain(std::iter::repeat(0.0)));
+        let mut renderer = BinauralRenderer::new(impulse, &hrtf, -90.0, 0.0);
+        let out: Vec<[f64; 2]> = (0..4800).map(|_| renderer.next()).collect();
+
+        let energy = |ch: usize| out.iter().map(|f| f[ch] * f[ch]).sum::<f64>();
+        let peak = |ch: usize| {
+            (0..out.len())
+                .max_by(|&a, &b| out[a][ch].abs().total_cmp(&out[b][ch].abs()))
+                .unwrap()
+        };
+        let ild = Db::from_gain((energy(1) / energy(0)).sqrt()).0;
+        assert!(ild > 3.0, "ILD: {ild} dB");
+        // Woodworth's formula gives about 0.65 ms for the side
+        let itd = Frames(peak(0) - peak(1)).to_ms(FS).0;
+        assert!(itd > 0.5 && itd < 0.8, "ITD: {itd} ms");
+    }
+
+    #[test]
+    fn doppler_raises_the_pitch_approaching_and_lowers_it_receding() {
+        let len = FS as usize;
+        // in m/s
+        let speed = 20.0;
+        // the frequency of the second half of the sine moving at `velocity`
+        // from 30 m in front
+        let freq = |velocity: f64| {
+            let trajectory =
+                signal::from_iter((0..).map(move |n| [30.0 + velocity * n as f64 / FS, 0.0, 0.0]));
+            let mut source = MovingSource::new(sine(), trajectory, FS, 100.0);
+            let y: Vec<f64> = (0..len).map(|_| source.next()).collect();
+            let crossings: Vec<usize> = (len / 2..len)
+                .filter(|&i| y[i - 1] < 0.0 && y[i] >= 0.0)
+                .collect();
+            let span = crossings[crossings.len() - 1] - crossings[0];
+            (crossings.len() - 1) as f64 * FS / span as f64
+        };
+
+        let ratio = speed / SPEED_OF_SOUND;
+        let approaching = freq(-speed) / 440.0;
+        let receding = freq(speed) / 440.0;
+        assert!((approaching - (1.0 + ratio)).abs() < 0.01, "{approaching}");
+        assert!((receding - (1.0 - ratio)).abs() < 0.01, "{receding}");
+    }
+
+    // a constant source at a fixed position
+    fn placed(
+        position: [f64; 2],
+    ) -> Placement<impl Signal<Frame = f64>, impl Signal<Frame = [f64; 2]>> {
+        Placement::new(signal::gen(|| 1.0), signal::gen(move || position), FS)
+    }
+
+    #[test]
+    fn placement_gain_falls_by_6_db_per_doubling() {
+        let power = |[l, r]: [f64; 2]| l * l + r * r;
+        for distance in [1.0, 2.0, 5.0] {
+            let near = power(placed([distance, distance]).next());
+            let far = power(placed([2.0 * distance, 2.0 * distance]).next());
+            let db = 10.0 * (far / near).log10();
+            assert!((db + 6.02).abs() < 0.01, "{distance} m: {db} dB");
+        }
+    }
+
+    #[test]
+    fn placement_on_the_left_is_fully_left() {
+        let [l, r] = placed([0.0, 3.0]).next();
+        assert!(l > 0.0 && r.abs() < 1e-15, "{l}, {r}");
+        let [l, r] = placed([0.0, -3.0]).next();
+        assert!(r > 0.0 && l.abs() < 1e-15, "{l}, {r}");
+    }
+
+    #[test]
+    fn placement_pre_delay_follows_the_distance() {
+        let distance = 10.0;
+        let impulse = signal::from_iter(std::iter::once(1.0).chain(std::iter::repeat(0.0)));
+        let position = signal::gen(move || [distance, 0.0]);
+        let mut placement = Placement::new(impulse, position, FS).with_pre_delay(50.0);
+        let out: Vec<f64> = (0..4800).map(|_| placement.next()[0]).collect();
+        let peak = (0..out.len())
+            .max_by(|&a, &b| out[a].abs().total_cmp(&out[b].abs()))
+            .unwrap();
+        let expected = distance / SPEED_OF_SOUND * FS;
+        assert!(
+            (peak as f64 - expected).abs() <= 1.0,
+            "{peak} != {expected}"
+        );
+    }
+
+    #[test]
+    fn quad_placement_pans_between_adjacent_speakers() {
+        let at = |azimuth: f64| {
+            let [x, y] = unit(azimuth);
+            Placement::quad(signal::gen(|| 1.0), signal::gen(move || [x, y]), FS).next()
+        };
+
+        // front right (-45 degrees, as the azimuth is counterclockwise)
+        let [fl, fr, rl, rr] = at(-45.0);
+        assert!((fr - 1.0).abs() < 1e-12, "{fr}");
+        assert!(fl.abs() < 1e-12 && rl.abs() < 1e-12 && rr.abs() < 1e-12);
+
+        // the front, between FL and FR
+        let [fl, fr, rl, rr] = at(0.0);
+        assert!((fl - fr).abs() < 1e-12, "{fl} != {fr}");
+        assert!(rl.abs() < 1e-12 && rr.abs() < 1e-12);
+    }
+
+    #[test]
+    fn quad_placement_keeps_the_power_while_rotating() {
+        // a turn per second at 1 m
+        let mut n = 0;
+        let position = signal::gen_mut(move || {
+            n += 1;
+            unit(360.0 * n as f64 / FS)
+        });
+        let mut placement = Placement::quad(signal::gen(|| 1.0), position, FS);
+        for _ in 0..FS as usize {
+            let power: f64 = placement.next().iter().map(|g| g * g).sum();
+            let db = 10.0 * power.log10();
+            assert!(db.abs() < 0.5, "{db} dB");
+        }
+    }
+
+    #[test]
+    fn moving_the_panner_glides_the_gains() {
+        let speakers = [45.0, -45.0, 135.0, -135.0];
+        let dc = || signal::from_iter(std::iter::repeat(1.0));
+        let mut panner = SurroundPanner::new(dc(), FS, speakers, 45.0);
+        assert_eq!(panner.next(), [1.0, 0.0, 0.0, 0.0]);
+
+        // from the front left to the front right
+        panner.set_azimuth(-45.0);
+        let frames = Ms(20.0).to_frames(FS).0;
+        let mut prev = [1.0, 0.0, 0.0, 0.0];
+        for i in 0..frames {
+            let frame = panner.next();
+            for (y, p) in frame.iter().zip(prev) {
+                assert!((y - p).abs() < 0.01, "frame {i}: {frame:?}");
+            }
+            prev = frame;
+        }
+        assert_eq!(prev, [0.0, 1.0, 0.0, 0.0]);
+
+        let mut encoder = AmbisonicEncoder::new(dc(), FS, 0.0, 0.0);
+        encoder.set_direction(90.0, 0.0);
+        let [_, x, y, _] = encoder.next();
+        assert!(x > 0.99 && y < 0.01, "{x} {y}");
+        for _ in 1..frames {
+            encoder.next();
+        }
+        let [_, x, y, _] = encoder.gains();
+        assert!(x.abs() < 1e-12 && y == 1.0, "{x} {y}");
+    }
+}
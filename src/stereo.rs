@@ -0,0 +1,209 @@
+use dasp::Signal;
+
+/// An effect that processes a mono signal sample by sample.
+pub trait MonoEffect {
+    fn process(&mut self, x: f64) -> f64;
+
+    /// The delay the effect introduces in frames, which `ParallelMix`
+    /// compensates (and `Latency` reports). 0 by default.
+    fn latency(&self) -> usize {
+        0
+    }
+}
+
+/// An effect that processes a stereo signal frame by frame.
+pub trait StereoEffect {
+    fn process(&mut self, frame: [f64; 2]) -> [f64; 2];
+}
+
+impl<F: FnMut(f64) -> f64> MonoEffect for F {
+    fn process(&mut self, x: f64) -> f64 {
+        self(x)
+    }
+}
+
+impl StereoEffect for Box<dyn StereoEffect> {
+    fn process(&mut self, frame: [f64; 2]) -> [f64; 2] {
+        self.as_mut().process(frame)
+    }
+}
+
+/// Promotes a mono effect to stereo by running two instances for the left
+/// and right channels. The instances don't share any state, so what is fed to
+/// one channel never leaks into the other.
+pub struct Promoted<E: MonoEffect> {
+    left: E,
+    right: E,
+}
+
+impl<E: MonoEffect> Promoted<E> {
+    pub fn new(left: E, right: E) -> Self {
+        Self { left, right }
+    }
+
+    /// Creates the two instances by calling `f` twice.
+    pub fn from_fn(f: impl Fn() -> E) -> Self {
+        Self::new(f(), f())
+    }
+}
+
+impl<E: MonoEffect> StereoEffect for Promoted<E> {
+    fn process(&mut self, [l, r]: [f64; 2]) -> [f64; 2] {
+        [self.left.process(l), self.right.process(r)]
+    }
+}
+
+/// Uses a stereo effect as a mono one by feeding the same input to both
+/// channels and averaging the outputs.
+pub struct MonoSum<E: StereoEffect> {
+    effect: E,
+}
+
+pub fn mono_sum<E: StereoEffect>(effect: E) -> MonoSum<E> {
+    MonoSum { effect }
+}
+
+impl<E: StereoEffect> MonoEffect for MonoSum<E> {
+    fn process(&mut self, x: f64) -> f64 {
+        let [l, r] = self.effect.process([x, x]);
+        (l + r) / 2.0
+    }
+}
+
+/// Applies a `MonoEffect` to a mono `Signal`.
+pub struct ApplyMono<S: Signal<Frame = f64>, E: MonoEffect> {
+    signal: S,
+    effect: E,
+}
+
+impl<S: Signal<Frame = f64>, E: MonoEffect> ApplyMono<S, E> {
+    pub fn new(signal: S, effect: E) -> Self {
+        Self { signal, effect }
+    }
+}
+
+impl<S: Signal<Frame = f64>, E: MonoEffect> Signal for ApplyMono<S, E> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        self.effect.process(self.signal.next())
+    }
+}
+
+/// Applies a `StereoEffect` to a stereo `Signal`.
+pub struct ApplyStereo<S: Signal<Frame = [f64; 2]>, E: StereoEffect> {
+    signal: S,
+    effect: E,
+}
+
+impl<S: Signal<Frame = [f64; 2]>, E: StereoEffect> ApplyStereo<S, E> {
+    pub fn new(signal: S, effect: E) -> Self {
+        Self { signal, effect }
+    }
+}
+
+impl<S: Signal<Frame = [f64; 2]>, E: StereoEffect> Signal for ApplyStereo<S, E> {
+    type Frame = [f64; 2];
+
+    fn next(&mut self) -> Self::Frame {
+        self.effect.process(self.signal.next())
+    }
+}
+
+/// Encodes left/right to mid/side: `m = (l + r) / 2` and `s = (l - r) / 2`.
+pub fn ms_encode([l, r]: [f64; 2]) -> [f64; 2] {
+    [(l + r) / 2.0, (l - r) / 2.0]
+}
+
+/// Decodes mid/side to left/right; the inverse of `ms_encode()`.
+pub fn ms_decode([m, s]: [f64; 2]) -> [f64; 2] {
+    [m + s, m - s]
+}
+
+/// Processes the mid (what is common to both channels) and the side (the
+/// difference) separately, e.g. compressing the mid only, or widening the
+/// stereo image by boosting the side with `MsProcessor::new(|m| m, |s| s * 1.5)`.
+/// Use `ApplyStereo` to apply this to a `Signal`.
+pub struct MsProcessor<M: MonoEffect, E: MonoEffect> {
+    mid: M,
+    side: E,
+}
+
+impl<M: MonoEffect, E: MonoEffect> MsProcessor<M, E> {
+    pub fn new(mid: M, side: E) -> Self {
+        Self { mid, side }
+    }
+}
+
+impl<M: MonoEffect, E: MonoEffect> StereoEffect for MsProcessor<M, E> {
+    fn process(&mut self, frame: [f64; 2]) -> [f64; 2] {
+        let [m, s] = ms_encode(frame);
+        ms_decode([self.mid.process(m), self.side.process(s)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::DelayLine;
+
+    #[test]
+    fn promoted_channels_are_independent() {
+        // a feedback delay, whose state would leak if it were shared
+        let mut delay = Promoted::from_fn(|| {
+            let mut line = DelayLine::new(100);
+            move |x: f64| {
+                let y = x + 0.5 * line.tap(100);
+                line.push(y);
+                y
+            }
+        });
+
+        let out: Vec<[f64; 2]> = (0..1000)
+            .map(|i| delay.process([if i == 0 { 1.0 } else { 0.0 }, 0.0]))
+            .collect();
+        // echoes on the left every 100 frames, and nothing on the right
+        assert_eq!(out[100][0], 0.5);
+        assert_eq!(out[200][0], 0.25);
+        assert!(out.iter().all(|&[_, r]| r == 0.0));
+    }
+
+    #[test]
+    fn ms_round_trip_is_identity() {
+        for frame in [[1.0, 0.0], [0.0, -1.0], [0.3, 0.7], [-0.25, 0.125]] {
+            let [l, r] = ms_decode(ms_encode(frame));
+            assert!((l - frame[0]).abs() < 1e-15 && (r - frame[1]).abs() < 1e-15);
+        }
+    }
+
+    #[test]
+    fn side_boost_widens_the_image() {
+        // partially correlated channels
+        let frames: Vec<[f64; 2]> = (0..1000)
+            .map(|i| {
+                let t = i as f64 * 0.05;
+                [t.sin(), 0.5 * t.sin() + 0.5 * (1.7 * t).sin()]
+            })
+            .collect();
+        // the correlation of the channels; 1.0 for mono
+        let correlation = |frames: &[[f64; 2]]| {
+            let lr: f64 = frames.iter().map(|[l, r]| l * r).sum();
+            let ll: f64 = frames.iter().map(|[l, _]| l * l).sum();
+            let rr: f64 = frames.iter().map(|[_, r]| r * r).sum();
+            lr / (ll * rr).sqrt()
+        };
+
+        let mut unity = MsProcessor::new(|m| m, |s| s);
+        let same: Vec<[f64; 2]> = frames.iter().map(|&f| unity.process(f)).collect();
+        assert!((correlation(&same) - correlation(&frames)).abs() < 1e-12);
+
+        let mut wide = MsProcessor::new(|m| m, |s| s * 2.0);
+        let widened: Vec<[f64; 2]> = frames.iter().map(|&f| wide.process(f)).collect();
+        assert!(
+            correlation(&widened) < correlation(&frames) - 0.1,
+            "{} -> {}",
+            correlation(&frames),
+            correlation(&widened)
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use crate::units::{Db, Frames, Ms};
+use dasp::Signal;
+
+/// A hint whether a `Signal` keeps sounding after its input stops (e.g. reverb,
+/// delay, or a plucked string).
+pub trait HasTail {
+    fn has_tail(&self) -> bool;
+}
+
+const DEFAULT_THRESHOLD: Db = Db(-80.0);
+const DEFAULT_WINDOW: Ms = Ms(50.0);
+const DEFAULT_MAX_TAIL: Ms = Ms(10_000.0);
+
+/// Takes `nominal_frames` frames from a signal, and then keeps pulling frames
+/// until the output stays below the threshold for the window, or the length
+/// of the tail reaches the cap.
+pub struct TailUntilSilent<S: Signal<Frame = f64>> {
+    signal: S,
+    nominal_frames: usize,
+    threshold: f64,
+    window_frames: usize,
+    max_tail_frames: usize,
+    cur_frame: usize,
+    silent_frames: usize,
+}
+
+impl<S: Signal<Frame = f64>> TailUntilSilent<S> {
+    pub fn new(signal: S, nominal_frames: Frames, fs: f64) -> Self {
+        Self {
+            signal,
+            nominal_frames: nominal_frames.0,
+            threshold: DEFAULT_THRESHOLD.to_gain(),
+            window_frames: DEFAULT_WINDOW.to_frames(fs).0,
+            max_tail_frames: DEFAULT_MAX_TAIL.to_frames(fs).0,
+            cur_frame: 0,
+            silent_frames: 0,
+        }
+    }
+
+    /// Sets the threshold of the silence in dBFS (default: -80 dB).
+    pub fn with_threshold(mut self, threshold: Db) -> Self {
+        self.threshold = threshold.to_gain();
+        self
+    }
+
+    /// Sets how long the output needs to stay silent to end (default: 50 ms).
+    pub fn with_window(mut self, window: Frames) -> Self {
+        self.window_frames = window.0;
+        self
+    }
+
+    /// Sets the maximum length of the tail (default: 10 seconds).
+    pub fn with_max_tail(mut self, max_tail: Frames) -> Self {
+        self.max_tail_frames = max_tail.0;
+        self
+    }
+}
+
+impl<S: Signal<Frame = f64>> Iterator for TailUntilSilent<S> {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // tail phase
+        if self.cur_frame >= self.nominal_frames
+            && (self.silent_frames >= self.window_frames
+                || self.cur_frame - self.nominal_frames >= self.max_tail_frames)
+        {
+            return None;
+        }
+
+        self.cur_frame += 1;
+
+        let out = self.signal.next();
+        if out.abs() < self.threshold {
+            self.silent_frames += 1;
+        } else {
+            self.silent_frames = 0;
+        }
+
+        Some(out)
+    }
+}
+
+/// Takes `nominal_frames` frames from a signal, followed by its tail if the
+/// signal reports it has one.
+pub fn take_with_tail<S>(signal: S, nominal_frames: Frames, fs: f64) -> TailUntilSilent<S>
+where
+    S: Signal<Frame = f64> + HasTail,
+{
+    let has_tail = signal.has_tail();
+    let tail = TailUntilSilent::new(signal, nominal_frames, fs);
+    if has_tail {
+        tail
+    } else {
+        tail.with_max_tail(Frames(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::convolution::{exponential_decay_ir, Convolution};
+    use crate::core::karplus::KarplusStrong;
+    use crate::units::Hz;
+
+    const FS: f64 = 48000.0;
+
+    // a constant, which may say it has a tail
+    struct Dc {
+        value: f64,
+        has_tail: bool,
+    }
+
+    impl Signal for Dc {
+        type Frame = f64;
+
+        fn next(&mut self) -> Self::Frame {
+            self.value
+        }
+    }
+
+    impl HasTail for Dc {
+        fn has_tail(&self) -> bool {
+            self.has_tail
+        }
+    }
+
+    #[test]
+    fn pluck_through_reverb_rings_out_to_silence() {
+        // a pluck at the frame 0, the last note
+        let mut string = KarplusStrong::<1024>::new(FS, Hz(330.0), 0.5, Ms(300.0), 1).unwrap();
+        string.pluck();
+        let pluck = dasp::signal::gen_mut(move || string.process());
+        let ir = exponential_decay_ir(FS, Ms(2000.0), 1);
+        let reverb = Convolution::from_ir(pluck, &ir, FS);
+
+        let nominal = Ms(500.0).to_frames(FS);
+        let out: Vec<f64> = take_with_tail(reverb, nominal, FS).collect();
+        let last_note = 0;
+        assert!(
+            out.len() - last_note >= Ms(1500.0).to_frames(FS).0,
+            "{}",
+            out.len()
+        );
+        // ended before the cap
+        assert!(out.len() < nominal.0 + DEFAULT_MAX_TAIL.to_frames(FS).0);
+
+        let end = &out[out.len() - Ms(10.0).to_frames(FS).0..];
+        let threshold = 10.0_f64.powf(-80.0 / 20.0);
+        assert!(end.iter().all(|x| x.abs() < threshold));
+    }
+
+    #[test]
+    fn never_silent_signal_stops_at_the_cap() {
+        let fs = 100.0;
+        // 10 seconds of the tail by default
+        let dc = Dc {
+            value: 0.5,
+            has_tail: true,
+        };
+        assert_eq!(take_with_tail(dc, Frames(10), fs).count(), 10 + 1000);
+
+        let sine = dasp::signal::rate(FS).const_hz(440.0).sine();
+        let tail = TailUntilSilent::new(sine, Frames(1000), FS).with_max_tail(Frames(2000));
+        assert_eq!(tail.count(), 3000);
+    }
+
+    #[test]
+    fn tailless_signal_stops_at_the_nominal_length() {
+        let dc = Dc {
+            value: 0.5,
+            has_tail: false,
+        };
+        assert_eq!(take_with_tail(dc, Frames(1234), FS).count(), 1234);
+    }
+}
@@ -0,0 +1,232 @@
+//! The transport of a sequence played along live: a count-in of clicks (the
+//! pre-roll) before the sequence starts, so that the player can come in on
+//! the first beat, and a `Capture` of the input aligned with the sequence,
+//! starting where that beat lands in the recording.
+
+use crate::units::{Frames, Hz, Ms};
+use dasp::Signal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// the click of the count-in: a decaying cosine (so it starts at its peak),
+// higher on the first beat
+const CLICK_FREQ: Hz = Hz(1000.0);
+const CLICK_ACCENT_FREQ: Hz = Hz(1500.0);
+const CLICK_LENGTH: Ms = Ms(30.0);
+const CLICK_DECAY: Ms = Ms(5.0);
+const CLICK_LEVEL: f64 = 0.5;
+
+/// What the transport is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TransportState {
+    /// Counting in; the sequence hasn't started yet.
+    PreRoll,
+    Playing,
+}
+
+/// Plays a count-in of `beats` clicks at the tempo (one bar of 4/4 by
+/// default), and then the sequence, whose first frame lands exactly on the
+/// beat after the last click. The sequence isn't pulled during the count-in,
+/// so it starts from its own frame 0.
+pub struct CountIn<S: Signal<Frame = f64>> {
+    signal: S,
+    fs: f64, // sampling rate
+    beat: usize,
+    beats: usize,
+    cur_frame: usize,
+    // whether the sequence has started, shared with the control thread
+    playing: Arc<AtomicBool>,
+}
+
+impl<S: Signal<Frame = f64>> CountIn<S> {
+    pub fn new(signal: S, fs: f64, bpm: f64) -> Self {
+        Self {
+            signal,
+            fs,
+            beat: Ms::from_note(bpm, 0.25).to_frames(fs).0.max(1),
+            beats: 4,
+            cur_frame: 0,
+            playing: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Sets the number of the clicks, e.g. 3 for a bar of 3/4. 0 starts the
+    /// sequence at once.
+    pub fn with_beats(mut self, beats: usize) -> Self {
+        self.beats = beats;
+        self
+    }
+
+    /// The length of the count-in, i.e. where the sequence starts.
+    pub fn length(&self) -> Frames {
+        Frames(self.beat * self.beats)
+    }
+
+    /// A handle to see the state from another thread (e.g. a UI), which
+    /// stays valid after this is moved to the audio thread.
+    pub fn state_handle(&self) -> TransportHandle {
+        TransportHandle(self.playing.clone())
+    }
+
+    pub fn state(&self) -> TransportState {
+        self.state_handle().state()
+    }
+
+    fn click(&self) -> f64 {
+        let beat = self.cur_frame / self.beat;
+        let t = (self.cur_frame % self.beat) as f64 / self.fs;
+        if t * 1000.0 >= CLICK_LENGTH.0 {
+            return 0.0;
+        }
+        let freq = if beat == 0 {
+            CLICK_ACCENT_FREQ
+        } else {
+            CLICK_FREQ
+        };
+        let decay = (-t * 1000.0 / CLICK_DECAY.0).exp();
+        CLICK_LEVEL * decay * (2.0 * std::f64::consts::PI * freq.0 * t).cos()
+    }
+}
+
+impl<S: Signal<Frame = f64>> Signal for CountIn<S> {
+    type Frame = f64;
+
+    fn next(&mut self) -> Self::Frame {
+        if self.cur_frame < self.length().0 {
+            let y = self.click();
+            self.cur_frame += 1;
+            return y;
+        }
+        self.playing.store(true, Ordering::Relaxed);
+        self.signal.next()
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.cur_frame >= self.length().0 && self.signal.is_exhausted()
+    }
+}
+
+/// The state of a `CountIn` seen from another thread.
+#[derive(Clone, Debug)]
+pub struct TransportHandle(Arc<AtomicBool>);
+
+impl TransportHandle {
+    pub fn state(&self) -> TransportState {
+        if self.0.load(Ordering::Relaxed) {
+            TransportState::Playing
+        } else {
+            TransportState::PreRoll
+        }
+    }
+}
+
+/// Where the first beat after the count-in lands in a recording of the input
+/// made along with the output, both started at the same time (the first
+/// click is the first frame written to the output).
+///
+/// The player hears the output `output_latency` late (as reported by the
+/// device, e.g. the difference of the timestamps of the playback and the
+/// callback of cpal), so playing in time with what they hear, bar 1 beat 1
+/// comes in at `count_in + output_latency`. Drop this many frames from the
+/// start of the recording to align it with the sequence, as `Capture` does.
+pub fn capture_offset(count_in: Frames, output_latency: Frames) -> Frames {
+    Frames(count_in.0 + output_latency.0)
+}
+
+/// Records the input played along with a `CountIn`, started at the same time
+/// as it: the frames before `capture_offset()` are dropped, so that the
+/// frame 0 of the recording is bar 1 beat 1 as played, aligned with the
+/// frame 0 of the sequence. All the memory is allocated up front, so this is
+/// safe on the audio thread; the input beyond the capacity is dropped too.
+pub struct Capture {
+    skip: usize,
+    frames: Vec<f64>,
+}
+
+impl Capture {
+    /// `count_in` is the `length()` of the `CountIn`, and `capacity` the
+    /// longest recording after it.
+    pub fn new(count_in: Frames, output_latency: Frames, capacity: Frames) -> Self {
+        Self {
+            skip: capture_offset(count_in, output_latency).0,
+            frames: Vec::with_capacity(capacity.0),
+        }
+    }
+
+    /// Records a frame of the input.
+    pub fn push(&mut self, x: f64) {
+        if self.skip > 0 {
+            self.skip -= 1;
+        } else if self.frames.len() < self.frames.capacity() {
+            self.frames.push(x);
+        }
+    }
+
+    /// The recording so far, from bar 1 beat 1.
+    pub fn recording(&self) -> &[f64] {
+        &self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+    const LATENCY: Frames = Frames(256);
+
+    // a sequence distinguishable from the clicks
+    fn sequence() -> impl Signal<Frame = f64> {
+        dasp::signal::gen(|| 0.25)
+    }
+
+    // the output of the count-in as heard, `LATENCY` late
+    fn heard(count_in: &mut CountIn<impl Signal<Frame = f64>>, len: usize) -> Vec<f64> {
+        let out = (0..len - LATENCY.0).map(|_| count_in.next());
+        std::iter::repeat_n(0.0, LATENCY.0).chain(out).collect()
+    }
+
+    #[test]
+    fn sequence_starts_a_bar_after_the_first_click() {
+        let mut count_in = CountIn::new(sequence(), FS, 120.0);
+        let bar = count_in.length().0;
+        assert_eq!(bar, 96000);
+        let handle = count_in.state_handle();
+        assert_eq!(handle.state(), TransportState::PreRoll);
+
+        let heard = heard(&mut count_in, bar + 1000);
+        assert_eq!(handle.state(), TransportState::Playing);
+        let first_click = heard.iter().position(|&x| x != 0.0).unwrap();
+        let first_frame = heard.iter().position(|&x| x == 0.25).unwrap();
+        assert_eq!(first_click, LATENCY.0);
+        assert_eq!(first_frame - first_click, bar);
+        // and the clicks are on the beats
+        for beat in 0..4 {
+            assert_eq!(heard[first_click + beat * bar / 4], CLICK_LEVEL);
+        }
+    }
+
+    #[test]
+    fn capture_starts_on_the_first_beat() {
+        let mut count_in = CountIn::new(sequence(), FS, 120.0).with_beats(3);
+        let count_in_length = count_in.length();
+        assert_eq!(
+            capture_offset(count_in_length, LATENCY),
+            Frames(72000 + 256)
+        );
+
+        // the player plays back what they hear, in time
+        let mut capture = Capture::new(count_in_length, LATENCY, Frames(500));
+        for x in heard(&mut count_in, 80000) {
+            capture.push(x);
+        }
+        assert_eq!(capture.recording(), [0.25; 500]);
+    }
+
+    #[test]
+    fn no_count_in_plays_at_once() {
+        let mut count_in = CountIn::new(sequence(), FS, 120.0).with_beats(0);
+        assert_eq!(count_in.next(), 0.25);
+        assert_eq!(count_in.state(), TransportState::Playing);
+    }
+}
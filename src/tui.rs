@@ -0,0 +1,223 @@
+//! A live screen in the terminal showing the transport, the level, and the
+//! parameters, controlled by the keys. Enable with `--features tui`.
+//!
+//! The mapping of the keys and the layout of the screen are plain functions
+//! (`action()` and `layout()`), so the drawing by `Screen` is the only part
+//! that touches the terminal.
+
+use crate::units::{Db, Hz};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, style, terminal};
+use std::io::Write;
+use std::time::Duration;
+
+/// The interval of redrawing the screen, i.e. about 15 fps.
+pub const REFRESH: Duration = Duration::from_millis(66);
+
+// the range of the level meter
+const METER_FLOOR: Db = Db(-60.0);
+
+/// What a key does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Mutes or unmutes the output (the stream keeps running).
+    TogglePause,
+    BpmUp,
+    BpmDown,
+    CutoffUp,
+    CutoffDown,
+    NextPreset,
+    /// Fades out and ends.
+    Quit,
+}
+
+/// The action of the key, if any: space pauses, `+` and `-` change the BPM,
+/// the arrow keys change the cutoff, `p` loads the next preset, and `q` (or
+/// Esc, or Ctrl-C, which doesn't interrupt in the raw mode) quits.
+pub fn action(key: &KeyEvent) -> Option<Action> {
+    if key.kind == KeyEventKind::Release {
+        return None;
+    }
+    if key.modifiers.contains(KeyModifiers::CONTROL) {
+        return (key.code == KeyCode::Char('c')).then_some(Action::Quit);
+    }
+
+    match key.code {
+        KeyCode::Char(' ') => Some(Action::TogglePause),
+        // `=` is `+` without the shift on most keyboards
+        KeyCode::Char('+' | '=') => Some(Action::BpmUp),
+        KeyCode::Char('-') => Some(Action::BpmDown),
+        KeyCode::Up | KeyCode::Right => Some(Action::CutoffUp),
+        KeyCode::Down | KeyCode::Left => Some(Action::CutoffDown),
+        KeyCode::Char('p') => Some(Action::NextPreset),
+        KeyCode::Char('q') | KeyCode::Esc => Some(Action::Quit),
+        _ => None,
+    }
+}
+
+/// What the screen shows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Status {
+    /// The time played, in seconds.
+    pub position: f64,
+    pub paused: bool,
+    pub bpm: f64,
+    pub cutoff: Hz,
+    pub preset: String,
+    /// The peak level (gain) of the output.
+    pub level: f64,
+}
+
+/// The lines of the screen for the terminal of `width` columns. The meter
+/// fills the width left by its label and value.
+pub fn layout(status: &Status, width: usize) -> Vec<String> {
+    let minutes = (status.position / 60.0).floor();
+    let seconds = status.position - 60.0 * minutes;
+    let transport = if status.paused { "paused " } else { "playing" };
+
+    let db = Db::from_gain(status.level).0.max(METER_FLOOR.0);
+    let label = "level  ";
+    let value = if db <= METER_FLOOR.0 {
+        "   -inf dB".to_string()
+    } else {
+        format!(" {db:6.1} dB")
+    };
+    let bar_width = width.saturating_sub(label.len() + value.len() + 2);
+    let filled = ((1.0 - db / METER_FLOOR.0) * bar_width as f64).round() as usize;
+    let filled = filled.min(bar_width);
+    let meter = format!(
+        "{label}[{}{}]{value}",
+        "#".repeat(filled),
+        " ".repeat(bar_width - filled)
+    );
+
+    vec![
+        format!(
+            "{transport}  {minutes:02.0}:{seconds:04.1}  {:5.1} BPM",
+            status.bpm
+        ),
+        format!(
+            "cutoff {:7.1} Hz  preset: {}",
+            status.cutoff.0, status.preset
+        ),
+        meter,
+        String::new(),
+        "space: pause  +/-: BPM  arrows: cutoff  p: preset  q: quit".to_string(),
+    ]
+}
+
+/// The terminal in the raw mode on the alternate screen, which is restored on
+/// drop.
+pub struct Screen {
+    out: std::io::Stdout,
+}
+
+impl Screen {
+    pub fn new() -> Result<Self, anyhow::Error> {
+        let mut out = std::io::stdout();
+        terminal::enable_raw_mode()?;
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(Self { out })
+    }
+
+    /// The number of the columns.
+    pub fn width(&self) -> usize {
+        terminal::size().map_or(80, |(columns, _)| columns as usize)
+    }
+
+    /// Replaces the screen with the lines.
+    pub fn draw(&mut self, lines: &[String]) -> Result<(), anyhow::Error> {
+        queue!(self.out, terminal::Clear(terminal::ClearType::All))?;
+        for (row, line) in lines.iter().enumerate() {
+            queue!(self.out, cursor::MoveTo(0, row as u16), style::Print(line))?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Waits for a key up to `timeout` (e.g. `REFRESH`), and returns its
+    /// action, if any.
+    pub fn poll_action(&self, timeout: Duration) -> Result<Option<Action>, anyhow::Error> {
+        if !event::poll(timeout)? {
+            return Ok(None);
+        }
+        match event::read()? {
+            Event::Key(key) => Ok(action(&key)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        // nothing can be done on errors here
+        let _ = execute!(self.out, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn keys_map_to_actions() {
+        let none = KeyModifiers::NONE;
+        let cases = [
+            (KeyCode::Char(' '), Some(Action::TogglePause)),
+            (KeyCode::Char('+'), Some(Action::BpmUp)),
+            (KeyCode::Char('='), Some(Action::BpmUp)),
+            (KeyCode::Char('-'), Some(Action::BpmDown)),
+            (KeyCode::Up, Some(Action::CutoffUp)),
+            (KeyCode::Left, Some(Action::CutoffDown)),
+            (KeyCode::Char('p'), Some(Action::NextPreset)),
+            (KeyCode::Char('q'), Some(Action::Quit)),
+            (KeyCode::Esc, Some(Action::Quit)),
+            (KeyCode::Char('x'), None),
+        ];
+        for (code, expected) in cases {
+            assert_eq!(action(&key(code, none)), expected, "{code:?}");
+        }
+        let ctrl = KeyModifiers::CONTROL;
+        assert_eq!(action(&key(KeyCode::Char('c'), ctrl)), Some(Action::Quit));
+        assert_eq!(action(&key(KeyCode::Char(' '), ctrl)), None);
+
+        let mut release = key(KeyCode::Char(' '), none);
+        release.kind = KeyEventKind::Release;
+        assert_eq!(action(&release), None);
+    }
+
+    #[test]
+    fn layout_fills_the_width() {
+        let mut status = Status {
+            position: 83.25,
+            paused: false,
+            bpm: 120.0,
+            cutoff: Hz(1500.0),
+            preset: "bright".to_string(),
+            level: 1.0,
+        };
+        let lines = layout(&status, 60);
+        assert_eq!(lines[0], "playing  01:23.2  120.0 BPM");
+        assert_eq!(lines[1], "cutoff  1500.0 Hz  preset: bright");
+        assert_eq!(lines[2].len(), 60);
+        assert!(lines[2].starts_with("level  [####"));
+        assert!(lines[2].ends_with("]    0.0 dB"));
+
+        // -30 dB is half of the meter (of 40 columns)
+        status.paused = true;
+        status.level = Db(-30.0).to_gain();
+        let lines = layout(&status, 59);
+        assert!(lines[0].starts_with("paused "));
+        let bar = &lines[2]["level  [".len()..lines[2].find(']').unwrap()];
+        assert_eq!(bar.len(), 40);
+        assert_eq!(bar.matches('#').count(), 20);
+
+        status.level = 0.0;
+        assert!(layout(&status, 60)[2].ends_with("   -inf dB"));
+    }
+}
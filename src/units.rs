@@ -0,0 +1,182 @@
+//! Thin newtypes for the units of parameters, so that e.g. milliseconds can't
+//! be passed where frames are expected.
+//!
+//! Conversions to `Frames` round to the nearest frame, and exactly half a frame
+//! rounds up (away from zero), e.g. 0.5 frames -> 1 frame and 1.5 frames -> 2
+//! frames. Negative durations become 0 frames.
+//!
+//! A few constructors have raw-unit convenience forms suffixed with `_raw`
+//! (e.g. `Biquad::low_pass_raw()`), which take bare numbers instead.
+
+use crate::core::math;
+
+/// Frequency in hertz
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Hz(pub f64);
+
+/// Duration in milliseconds
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Ms(pub f64);
+
+/// Level in decibels (relative to 1.0)
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Db(pub f64);
+
+/// Duration in frames (samples per channel)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Frames(pub usize);
+
+fn round_to_frames(frames: f64) -> Frames {
+    if frames <= 0.0 || frames.is_nan() {
+        return Frames(0);
+    }
+    Frames(math::round(frames) as usize)
+}
+
+impl Hz {
+    /// The length of one period.
+    pub fn period(self) -> Ms {
+        Ms(1000.0 / self.0)
+    }
+
+    /// The frequency in cycles per sample.
+    pub fn normalized(self, fs: f64) -> f64 {
+        self.0 / fs
+    }
+
+    /// The frequency of the MIDI note number in the equal temperament with
+    /// A4 (69) = 440 Hz. `note` can be fractional.
+    pub fn from_midi_note(note: f64) -> Self {
+        Self(440.0 * math::powf(2.0, (note - 69.0) / 12.0))
+    }
+
+    /// The (fractional) MIDI note number of the frequency; the inverse of
+    /// `from_midi_note()`.
+    pub fn to_midi_note(self) -> f64 {
+        69.0 + 12.0 * math::log2(self.0 / 440.0)
+    }
+}
+
+impl Ms {
+    /// The length of a note value (in whole notes, e.g. 1/8, or 3/16 for the
+    /// dotted eighth) at the tempo of `bpm` quarter notes per minute.
+    pub fn from_note(bpm: f64, value: f64) -> Self {
+        Self(60_000.0 / bpm * 4.0 * value)
+    }
+
+    pub fn to_frames(self, fs: f64) -> Frames {
+        round_to_frames(self.0 * fs / 1000.0)
+    }
+
+    pub fn to_seconds(self) -> f64 {
+        self.0 / 1000.0
+    }
+}
+
+impl Db {
+    pub fn to_gain(self) -> f64 {
+        math::powf(10.0, self.0 / 20.0)
+    }
+
+    pub fn from_gain(gain: f64) -> Self {
+        Self(20.0 * math::log10(math::abs(gain)))
+    }
+}
+
+impl Frames {
+    pub fn to_ms(self, fs: f64) -> Ms {
+        Ms(self.0 as f64 * 1000.0 / fs)
+    }
+}
+
+/// A note value for the rates synced to the tempo; e.g. 1/8T (the eighth
+/// triplet) is `NoteDivision::Triplet(8)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoteDivision {
+    /// 1/n of a whole note, e.g. `Straight(4)` for the quarter note.
+    Straight(u32),
+    /// 1/n dotted, i.e. 1.5 times as long.
+    Dotted(u32),
+    /// 1/n triplet, i.e. 2/3 as long.
+    Triplet(u32),
+}
+
+impl NoteDivision {
+    /// The length in whole notes, as `Ms::from_note()` takes.
+    pub fn value(self) -> f64 {
+        match self {
+            Self::Straight(n) => 1.0 / n as f64,
+            Self::Dotted(n) => 1.5 / n as f64,
+            Self::Triplet(n) => 2.0 / 3.0 / n as f64,
+        }
+    }
+
+    /// The length at the tempo of `bpm` quarter notes per minute.
+    pub fn to_ms(self, bpm: f64) -> Ms {
+        Ms::from_note(bpm, self.value())
+    }
+}
+
+/// The rate of an LFO or another modulator, either free or synced to the
+/// tempo as a cycle per note value. The rate must be positive.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LfoRate {
+    Hz(f64),
+    Division(NoteDivision),
+}
+
+impl LfoRate {
+    /// The frequency at the tempo of `bpm` quarter notes per minute (which
+    /// doesn't matter for `LfoRate::Hz`).
+    pub fn to_hz(self, bpm: f64) -> Hz {
+        match self {
+            Self::Hz(hz) => Hz(hz),
+            Self::Division(division) => Hz(1000.0 / division.to_ms(bpm).0),
+        }
+    }
+
+    /// The length of a cycle in frames.
+    pub fn cycle(self, fs: f64, bpm: f64) -> Frames {
+        match self {
+            Self::Hz(hz) => Hz(hz).period().to_frames(fs),
+            Self::Division(division) => division.to_ms(bpm).to_frames(fs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_frames_round_up() {
+        let fs = 1000.0; // a frame per millisecond
+        assert_eq!(Ms(0.5).to_frames(fs), Frames(1));
+        assert_eq!(Ms(1.5).to_frames(fs), Frames(2));
+        assert_eq!(Ms(2.5).to_frames(fs), Frames(3));
+        assert_eq!(Ms(2.4999).to_frames(fs), Frames(2));
+        assert_eq!(Ms(0.0).to_frames(fs), Frames(0));
+        assert_eq!(Ms(-1.5).to_frames(fs), Frames(0));
+        assert_eq!(Ms(f64::NAN).to_frames(fs), Frames(0));
+        assert_eq!(Hz(2000.0 / 3.0).period().to_frames(fs), Frames(2));
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        for fs in [44100.0, 48000.0] {
+            for frames in [0, 1, 441, 12345] {
+                assert_eq!(Frames(frames).to_ms(fs).to_frames(fs), Frames(frames));
+            }
+        }
+        assert_eq!(Ms(20.0).to_frames(44100.0), Frames(882));
+    }
+
+    #[test]
+    fn quarter_note_lfo_at_120_bpm_is_half_a_second() {
+        let rate = LfoRate::Division(NoteDivision::Straight(4));
+        assert_eq!(rate.to_hz(120.0), Hz(2.0));
+        assert_eq!(rate.cycle(48000.0, 120.0), Frames(24000));
+        // and the free rate ignores the tempo
+        assert_eq!(LfoRate::Hz(2.0).cycle(48000.0, 90.0), Frames(24000));
+    }
+}
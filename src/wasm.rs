@@ -0,0 +1,125 @@
+//! The bindings for running the DSP core in the browser, e.g. in an
+//! `AudioWorkletProcessor`. Only `core` is used, so this builds for
+//! `wasm32-unknown-unknown` without cpal:
+//!
+//! ```text
+//! cargo rustc --release --lib --crate-type cdylib --target wasm32-unknown-unknown \
+//!     --no-default-features --features wasm
+//! wasm-bindgen --target web --out-dir web/pkg \
+//!     target/wasm32-unknown-unknown/release/sound_programming_practice.wasm
+//! ```
+//!
+//! and then serve `web/` (see `web/index.html`).
+
+use crate::core::envelope::Env;
+use crate::core::math;
+use crate::core::phasor::Phasor;
+use crate::units::{Hz, Ms};
+use wasm_bindgen::prelude::*;
+
+// the patch of the ch3-melody example
+#[rustfmt::skip]
+const TRACK1: [f64; 8] = [659.26, 587.33, 523.25, 493.88, 440.00, 392.00, 440.00, 493.88];
+#[rustfmt::skip]
+const TRACK2: [f64; 8] = [261.63, 196.00, 220.00, 164.81, 174.61, 130.81, 174.61, 196.00];
+const STEP: Ms = Ms(1000.0);
+const ATTACK: Ms = Ms(20.0);
+const RELEASE: Ms = Ms(20.0);
+
+// the level of each track, so that the sum stays within [-1.0, 1.0]
+const LEVEL: f64 = 0.4;
+
+/// The two sine tracks of ch3-melody, repeating forever.
+#[wasm_bindgen]
+pub struct Melody {
+    fs: f64, // sampling rate
+    tracks: [Phasor; 2],
+    env: Env,
+    step: usize,
+}
+
+#[wasm_bindgen]
+impl Melody {
+    /// `sample_rate` is that of the `AudioContext`.
+    #[wasm_bindgen(constructor)]
+    pub fn new(sample_rate: f64) -> Self {
+        let mut melody = Self {
+            fs: sample_rate,
+            tracks: [Phasor::new(), Phasor::new()],
+            env: Env::new(
+                STEP.to_frames(sample_rate),
+                ATTACK.to_frames(sample_rate),
+                RELEASE.to_frames(sample_rate),
+            ),
+            step: 0,
+        };
+        melody.set_step(0);
+        melody
+    }
+
+    /// Fills the block (e.g. of 128 frames on an `AudioWorkletProcessor`)
+    /// with the next frames.
+    pub fn render_block(&mut self, out: &mut [f32]) {
+        for x in out.iter_mut() {
+            let level = match self.env.next_level() {
+                Some(level) => level,
+                // proceed to the next step
+                None => {
+                    self.set_step((self.step + 1) % TRACK1.len());
+                    self.env.retrigger();
+                    self.env.next_level().unwrap_or(0.0)
+                }
+            };
+
+            let mut y = 0.0;
+            for track in &mut self.tracks {
+                y += math::sin(2.0 * core::f64::consts::PI * track.phase());
+                track.advance();
+            }
+            *x = (LEVEL * level * y) as f32;
+        }
+    }
+
+    // the tracks of ch3-melody play their sequences from the end
+    fn set_step(&mut self, step: usize) {
+        self.step = step;
+        let i = TRACK1.len() - 1 - step;
+        self.tracks[0].set_freq(self.fs, Hz(TRACK1[i]));
+        self.tracks[1].set_freq(self.fs, Hz(TRACK2[i]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f64 = 48000.0;
+
+    #[test]
+    fn plays_the_sequences_from_the_end() {
+        let mut melody = Melody::new(FS);
+        let step = STEP.to_frames(FS).0;
+        let mut out = vec![0.0; step];
+        // into the middle of the first step
+        melody.render_block(&mut out[..step / 2]);
+        for i in (0..TRACK1.len()).rev().chain([TRACK1.len() - 1]) {
+            let freqs = melody.tracks.each_ref().map(|t| t.increment() * FS);
+            assert!((freqs[0] - TRACK1[i]).abs() < 1e-6, "step {i}: {freqs:?}");
+            assert!((freqs[1] - TRACK2[i]).abs() < 1e-6, "step {i}: {freqs:?}");
+            melody.render_block(&mut out);
+        }
+    }
+
+    #[test]
+    fn blocks_are_not_silent_and_bounded() {
+        let mut melody = Melody::new(FS);
+        let mut block = [0.0; 128];
+        let mut peak: f32 = 0.0;
+        for _ in 0..(FS as usize / 128) {
+            melody.render_block(&mut block);
+            assert!(block.iter().all(|x| x.is_finite() && x.abs() <= 1.0));
+            peak = block.iter().fold(peak, |peak, x| peak.max(x.abs()));
+        }
+        assert!(peak > 0.1, "{peak}");
+    }
+}
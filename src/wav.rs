@@ -0,0 +1,472 @@
+//! Reading WAV files, e.g. impulse responses, and writing the rendered
+//! audio, optionally with the loop points for samplers.
+
+use crate::units::Frames;
+use anyhow::{anyhow, bail};
+use dasp::Signal;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The content of a WAV file. The samples are converted to `f64` within
+/// [-1.0, 1.0] and interleaved.
+pub struct Wav {
+    pub fs: f64,
+    pub channels: usize,
+    pub samples: Vec<f64>,
+    /// The start and the end (exclusive) of the loop, in the `smpl` chunk.
+    pub loop_points: Option<(Frames, Frames)>,
+    /// The metadata of the Broadcast Wave Format, in the `bext` chunk.
+    pub bext: Option<Bext>,
+}
+
+impl Wav {
+    /// The number of frames (samples per channel).
+    pub fn len(&self) -> usize {
+        self.samples.len() / self.channels
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// The average of the channels.
+    pub fn to_mono(&self) -> Vec<f64> {
+        self.samples
+            .chunks_exact(self.channels)
+            .map(|frame| frame.iter().sum::<f64>() / self.channels as f64)
+            .collect()
+    }
+}
+
+/// The broadcast audio extension (the `bext` chunk) of the Broadcast Wave
+/// Format (BWF, EBU Tech 3285), which carries the session metadata of a stem.
+/// The texts must be ASCII.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bext {
+    /// Up to 256 characters.
+    pub description: String,
+    /// The name of the originator, up to 32 characters.
+    pub originator: String,
+    /// The reference of the originator (e.g. the name of the session), up to
+    /// 32 characters.
+    pub originator_reference: String,
+    /// `yyyy-mm-dd`, or empty.
+    pub origination_date: String,
+    /// `hh:mm:ss`, or empty.
+    pub origination_time: String,
+    /// The position of the first frame on the timeline of the session, in
+    /// frames since midnight.
+    pub time_reference: u64,
+}
+
+// the lengths of the text fields of `bext`
+const BEXT_TEXTS: [usize; 5] = [256, 32, 32, 10, 8];
+// the size of `bext` without the coding history (of version 1)
+const BEXT_SIZE: usize = 602;
+const BEXT_VERSION: u16 = 1;
+
+impl Bext {
+    fn texts(&self) -> [&str; 5] {
+        [
+            &self.description,
+            &self.originator,
+            &self.originator_reference,
+            &self.origination_date,
+            &self.origination_time,
+        ]
+    }
+
+    fn to_bytes(&self) -> Result<Vec<u8>, anyhow::Error> {
+        let mut b = Vec::with_capacity(BEXT_SIZE);
+        for (text, len) in self.texts().into_iter().zip(BEXT_TEXTS) {
+            if !text.is_ascii() || text.len() > len {
+                bail!("invalid text in bext (must be ASCII of {len} bytes at most): {text}");
+            }
+            b.extend_from_slice(text.as_bytes());
+            b.resize(b.len() + len - text.len(), 0);
+        }
+        // `0` stands for a digit
+        for (text, pattern, format) in [
+            (&self.origination_date, "0000-00-00", "yyyy-mm-dd"),
+            (&self.origination_time, "00:00:00", "hh:mm:ss"),
+        ] {
+            let matches = text.len() == pattern.len()
+                && text.bytes().zip(pattern.bytes()).all(|(c, p)| match p {
+                    b'0' => c.is_ascii_digit(),
+                    _ => c == p,
+                });
+            if !text.is_empty() && !matches {
+                bail!("invalid date or time in bext (must be {format}): {text}");
+            }
+        }
+        b.extend_from_slice(&self.time_reference.to_le_bytes());
+        b.extend_from_slice(&BEXT_VERSION.to_le_bytes());
+        // the UMID and the reserved bytes
+        b.resize(BEXT_SIZE, 0);
+        Ok(b)
+    }
+
+    fn parse(body: &[u8]) -> Self {
+        let mut texts = vec![];
+        let mut pos = 0;
+        for len in BEXT_TEXTS {
+            // the texts are padded with NULs unless they fill the field
+            let field = &body[pos..pos + len];
+            let end = field.iter().position(|&c| c == 0).unwrap_or(len);
+            texts.push(String::from_utf8_lossy(&field[..end]).into_owned());
+            pos += len;
+        }
+        let time_reference = u64::from_le_bytes(body[pos..pos + 8].try_into().unwrap());
+        let [description, originator, originator_reference, origination_date, origination_time] =
+            texts.try_into().unwrap();
+        Self {
+            description,
+            originator,
+            originator_reference,
+            origination_date,
+            origination_time,
+            time_reference,
+        }
+    }
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Reads a WAV file of integer PCM (8, 16, 24, or 32 bits) or float (32 or 64
+/// bits).
+pub fn read<P: AsRef<Path>>(path: P) -> Result<Wav, anyhow::Error> {
+    let bytes = std::fs::read(path)?;
+    parse(&bytes)
+}
+
+fn parse(bytes: &[u8]) -> Result<Wav, anyhow::Error> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        bail!("not a WAV file");
+    }
+
+    let u16_at = |b: &[u8], i: usize| u16::from_le_bytes([b[i], b[i + 1]]);
+    let u32_at = |b: &[u8], i: usize| u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]]);
+
+    let mut format = None;
+    let mut data = None;
+    let mut loop_points = None;
+    let mut bext = None;
+
+    // walk through the chunks; each chunk is padded to an even length
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let size = u32_at(bytes, pos + 4) as usize;
+        let body = &bytes[pos + 8..(pos + 8 + size).min(bytes.len())];
+
+        match id {
+            b"fmt " if body.len() >= 16 => {
+                let mut tag = u16_at(body, 0);
+                if tag == WAVE_FORMAT_EXTENSIBLE && body.len() >= 26 {
+                    // the first 2 bytes of the subformat GUID is the actual tag
+                    tag = u16_at(body, 24);
+                }
+                let channels = u16_at(body, 2) as usize;
+                let fs = u32_at(body, 4) as f64;
+                let bits = u16_at(body, 14) as usize;
+                format = Some((tag, channels, fs, bits));
+            }
+            b"data" => data = Some(body),
+            b"bext" if body.len() >= BEXT_SIZE => bext = Some(Bext::parse(body)),
+            // only the first loop is read; the end in the chunk is inclusive
+            b"smpl" if body.len() >= SMPL_HEADER_SIZE + SMPL_LOOP_SIZE && u32_at(body, 28) > 0 => {
+                let start = u32_at(body, SMPL_HEADER_SIZE + 8) as usize;
+                let end = u32_at(body, SMPL_HEADER_SIZE + 12) as usize;
+                loop_points = Some((Frames(start), Frames(end + 1)));
+            }
+            _ => {}
+        }
+
+        pos += 8 + size + size % 2;
+    }
+
+    let (tag, channels, fs, bits) = format.ok_or_else(|| anyhow!("no fmt chunk"))?;
+    let data = data.ok_or_else(|| anyhow!("no data chunk"))?;
+    if channels == 0 {
+        bail!("the number of channels is 0");
+    }
+
+    let width = bits / 8;
+    let chunks = data.chunks_exact(width.max(1));
+    let mut samples: Vec<f64> = match (tag, bits) {
+        (WAVE_FORMAT_PCM, 8) => chunks.map(|b| (b[0] as f64 - 128.0) / 128.0).collect(),
+        (WAVE_FORMAT_PCM, 16) => chunks
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f64 / 32768.0)
+            .collect(),
+        (WAVE_FORMAT_PCM, 24) => chunks
+            // put the 24 bits on the upper side of i32 to extend the sign
+            .map(|b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / 8388608.0)
+            .collect(),
+        (WAVE_FORMAT_PCM, 32) => chunks
+            .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2147483648.0)
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 32) => chunks
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64)
+            .collect(),
+        (WAVE_FORMAT_IEEE_FLOAT, 64) => chunks
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+            .collect(),
+        _ => bail!("unsupported format: tag {tag}, {bits} bits"),
+    };
+
+    // drop the incomplete frame at the end, if any
+    samples.truncate(samples.len() / channels * channels);
+
+    Ok(Wav {
+        fs,
+        channels,
+        samples,
+        loop_points,
+        bext,
+    })
+}
+
+const SMPL_HEADER_SIZE: usize = 36;
+const SMPL_LOOP_SIZE: usize = 24;
+// the MIDI note played at the original pitch, i.e. middle C
+const SMPL_UNITY_NOTE: u32 = 60;
+
+/// Writes a WAV file of 32-bit float. If `wav.loop_points` is set, a `smpl`
+/// chunk is added with a forward loop of the range, which samplers and DAWs
+/// recognize. If `wav.bext` is set, a `bext` chunk is added, which makes the
+/// file a Broadcast Wave.
+pub fn write<P: AsRef<Path>>(path: P, wav: &Wav) -> Result<(), anyhow::Error> {
+    let bytes = to_bytes(wav)?;
+    std::fs::File::create(path)?.write_all(&bytes)?;
+    Ok(())
+}
+
+fn to_bytes(wav: &Wav) -> Result<Vec<u8>, anyhow::Error> {
+    if wav.channels == 0 {
+        bail!("the number of channels is 0");
+    }
+    let frames = wav.len();
+    let smpl = match wav.loop_points {
+        Some((start, end)) => {
+            if start.0 >= end.0 || end.0 > frames {
+                bail!(
+                    "invalid loop points: {}..{} of {frames} frames",
+                    start.0,
+                    end.0
+                );
+            }
+            Some((start.0 as u32, end.0 as u32 - 1))
+        }
+        None => None,
+    };
+
+    let channels = wav.channels as u16;
+    let block_align = channels * 4;
+    let data_size = wav.samples.len() * 4;
+    let smpl_size = SMPL_HEADER_SIZE + SMPL_LOOP_SIZE;
+
+    let mut b = Vec::with_capacity(8 + 4 + (8 + BEXT_SIZE) + (8 + 16) + (8 + data_size));
+    b.extend_from_slice(b"RIFF");
+    // the size of the RIFF chunk, filled at the end
+    b.extend_from_slice(&0u32.to_le_bytes());
+    b.extend_from_slice(b"WAVE");
+
+    // BWF puts bext before fmt
+    if let Some(bext) = &wav.bext {
+        let body = bext.to_bytes()?;
+        b.extend_from_slice(b"bext");
+        b.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        b.extend_from_slice(&body);
+    }
+
+    b.extend_from_slice(b"fmt ");
+    b.extend_from_slice(&16u32.to_le_bytes());
+    b.extend_from_slice(&WAVE_FORMAT_IEEE_FLOAT.to_le_bytes());
+    b.extend_from_slice(&channels.to_le_bytes());
+    b.extend_from_slice(&(wav.fs as u32).to_le_bytes());
+    b.extend_from_slice(&(wav.fs as u32 * u32::from(block_align)).to_le_bytes());
+    b.extend_from_slice(&block_align.to_le_bytes());
+    b.extend_from_slice(&32u16.to_le_bytes());
+
+    b.extend_from_slice(b"data");
+    b.extend_from_slice(&(data_size as u32).to_le_bytes());
+    for &x in &wav.samples {
+        b.extend_from_slice(&(x as f32).to_le_bytes());
+    }
+
+    if let Some((start, end)) = smpl {
+        let sample_period = (1e9 / wav.fs).round() as u32; // in nanoseconds
+        b.extend_from_slice(b"smpl");
+        b.extend_from_slice(&(smpl_size as u32).to_le_bytes());
+        for field in [
+            0, // manufacturer
+            0, // product
+            sample_period,
+            SMPL_UNITY_NOTE,
+            0, // pitch fraction
+            0, // SMPTE format
+            0, // SMPTE offset
+            1, // the number of loops
+            0, // the size of the sampler data
+            // the loop
+            0, // cue point ID
+            0, // type (forward)
+            start,
+            end, // inclusive
+            0,   // fraction
+            0,   // play count (infinite)
+        ] {
+            b.extend_from_slice(&u32::to_le_bytes(field));
+        }
+    }
+
+    let riff_size = b.len() as u32 - 8;
+    b[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(b)
+}
+
+/// Renders the first `frames` frames of each track to `<dir>/<name>.wav`
+/// (mono), so that the tracks can be imported into a DAW as stems and mixed
+/// there. All the files start at the same time and have the same length, and
+/// their sum is the mix. Include the tails (e.g. of the reverbs) in `frames`.
+/// Returns the paths of the files.
+pub fn render_stems<S, P>(
+    tracks: Vec<(&str, S)>,
+    frames: Frames,
+    fs: f64,
+    dir: P,
+) -> Result<Vec<PathBuf>, anyhow::Error>
+where
+    S: Signal<Frame = f64>,
+    P: AsRef<Path>,
+{
+    std::fs::create_dir_all(&dir)?;
+    let mut paths = vec![];
+    for (name, track) in tracks {
+        let path = dir.as_ref().join(format!("{name}.wav"));
+        let wav = Wav {
+            fs,
+            channels: 1,
+            samples: track.take(frames.0).collect(),
+            loop_points: None,
+            bext: None,
+        };
+        write(&path, &wav)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wav(samples: Vec<f64>) -> Wav {
+        Wav {
+            fs: 48000.0,
+            channels: 1,
+            samples,
+            loop_points: None,
+            bext: None,
+        }
+    }
+
+    // the body of the chunk of the id
+    fn chunk<'a>(bytes: &'a [u8], id: &[u8]) -> Option<&'a [u8]> {
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let size = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+            if &bytes[pos..pos + 4] == id {
+                return Some(&bytes[pos + 8..pos + 8 + size]);
+            }
+            pos += 8 + size + size % 2;
+        }
+        None
+    }
+
+    #[test]
+    fn writes_the_loop_points() {
+        let mut looped = wav(vec![0.25; 1000]);
+        looped.loop_points = Some((Frames(100), Frames(900)));
+        let bytes = to_bytes(&looped).unwrap();
+
+        // one forward loop, whose end is inclusive
+        let smpl = chunk(&bytes, b"smpl").unwrap();
+        let field = |i: usize| u32::from_le_bytes(smpl[i * 4..i * 4 + 4].try_into().unwrap());
+        assert_eq!(smpl.len(), SMPL_HEADER_SIZE + SMPL_LOOP_SIZE);
+        assert_eq!(field(7), 1);
+        assert_eq!((field(10), field(11), field(12)), (0, 100, 899));
+
+        let parsed = parse(&bytes).unwrap();
+        assert_eq!(parsed.loop_points, Some((Frames(100), Frames(900))));
+        assert_eq!(parsed.samples, looped.samples);
+        assert!(chunk(&to_bytes(&wav(vec![0.0; 10])).unwrap(), b"smpl").is_none());
+
+        looped.loop_points = Some((Frames(900), Frames(1001)));
+        assert!(to_bytes(&looped).is_err());
+    }
+
+    #[test]
+    fn bext_round_trips() {
+        let mut stem = wav(vec![0.5, -0.5, 0.25]);
+        let bext = Bext {
+            description: "drums".to_string(),
+            originator: "sound-programming-practice".to_string(),
+            originator_reference: "session-1".to_string(),
+            origination_date: "2024-05-06".to_string(),
+            origination_time: "12:34:56".to_string(),
+            // 1 hour at 48 kHz
+            time_reference: 172_800_000,
+        };
+        stem.bext = Some(bext.clone());
+        let bytes = to_bytes(&stem).unwrap();
+
+        // before fmt, of the fixed size
+        assert_eq!(&bytes[12..16], b"bext");
+        assert_eq!(chunk(&bytes, b"bext").unwrap().len(), BEXT_SIZE);
+        assert_eq!(parse(&bytes).unwrap().bext, Some(bext.clone()));
+
+        for (date, time) in [("2024/05/06", ""), ("", "12:34"), ("", "12:3x:56")] {
+            stem.bext = Some(Bext {
+                origination_date: date.to_string(),
+                origination_time: time.to_string(),
+                ..bext.clone()
+            });
+            assert!(to_bytes(&stem).is_err(), "{date} {time}");
+        }
+        stem.bext = Some(Bext {
+            originator: "x".repeat(33),
+            ..bext
+        });
+        assert!(to_bytes(&stem).is_err());
+    }
+
+    #[test]
+    fn stems_sum_to_the_mix() {
+        let fs = 48000.0;
+        let track = |freq: f64| dasp::signal::rate(fs).const_hz(freq).sine().scale_amp(0.4);
+        let dir = std::env::temp_dir().join(format!("stems-{}", std::process::id()));
+        let paths = render_stems(
+            vec![("low", track(220.0)), ("high", track(660.0))],
+            Frames(4800),
+            fs,
+            &dir,
+        )
+        .unwrap();
+        let stems: Vec<Wav> = paths.iter().map(|path| read(path).unwrap()).collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(paths[0].file_name().unwrap(), "low.wav");
+        assert_eq!(stems[0].len(), 4800);
+        assert_eq!(stems[1].len(), stems[0].len());
+
+        let mix: Vec<f64> = track(220.0).add_amp(track(660.0)).take(4800).collect();
+        for (i, &x) in mix.iter().enumerate() {
+            // in the precision of the 32-bit float
+            let sum = stems[0].samples[i] + stems[1].samples[i];
+            assert!((sum - x).abs() < 1e-6, "frame {i}: {sum} != {x}");
+        }
+    }
+}
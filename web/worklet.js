@@ -0,0 +1,24 @@
+// The AudioWorkletProcessor rendering the melody by the wasm module.
+
+import { initSync, Melody } from "./pkg/sound_programming_practice.js";
+
+class MelodyProcessor extends AudioWorkletProcessor {
+  constructor(options) {
+    super();
+    initSync({ module: options.processorOptions.wasm });
+    // `sampleRate` is a global of the AudioWorkletGlobalScope
+    this.melody = new Melody(sampleRate);
+  }
+
+  process(inputs, outputs) {
+    const [left, ...rest] = outputs[0];
+    this.melody.render_block(left);
+    // the melody is mono
+    for (const channel of rest) {
+      channel.set(left);
+    }
+    return true;
+  }
+}
+
+registerProcessor("melody", MelodyProcessor);